@@ -0,0 +1,35 @@
+-- Outbound relay targets (ListenBrainz, webhooks)
+CREATE TABLE IF NOT EXISTS relays (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('listenbrainz', 'webhook')),
+  label TEXT,
+  url TEXT,
+  token TEXT,
+  enabled BOOLEAN NOT NULL DEFAULT true,
+  created_at BIGINT NOT NULL,
+  last_success_at BIGINT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Listens waiting to be forwarded to a relay target
+CREATE TABLE IF NOT EXISTS relay_queue (
+  id BIGSERIAL PRIMARY KEY,
+  relay_id BIGINT NOT NULL,
+  payload JSONB NOT NULL,
+  created_at BIGINT NOT NULL,
+  FOREIGN KEY (relay_id) REFERENCES relays(id) ON DELETE CASCADE
+);
+
+-- Recent delivery failures per relay target
+CREATE TABLE IF NOT EXISTS relay_errors (
+  id BIGSERIAL PRIMARY KEY,
+  relay_id BIGINT NOT NULL,
+  message TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  FOREIGN KEY (relay_id) REFERENCES relays(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_relays_user_id ON relays(user_id);
+CREATE INDEX IF NOT EXISTS idx_relay_queue_relay_id ON relay_queue(relay_id, id);
+CREATE INDEX IF NOT EXISTS idx_relay_errors_relay_id ON relay_errors(relay_id, created_at DESC);
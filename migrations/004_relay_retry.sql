@@ -0,0 +1,7 @@
+-- Retry bookkeeping for queued relay deliveries
+ALTER TABLE relay_queue ADD COLUMN status TEXT NOT NULL DEFAULT 'pending' CHECK (status IN ('pending', 'dead'));
+ALTER TABLE relay_queue ADD COLUMN attempts INT NOT NULL DEFAULT 0;
+ALTER TABLE relay_queue ADD COLUMN next_attempt_at BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE relay_queue ADD COLUMN last_error TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_relay_queue_due ON relay_queue(status, next_attempt_at);
@@ -0,0 +1,9 @@
+-- Relay secrets are stored encrypted with SECRET_KEY. Plaintext tokens from
+-- before this migration can't be decrypted, so they are cleared.
+ALTER TABLE relays RENAME COLUMN token TO secret;
+UPDATE relays SET secret = NULL;
+
+-- Event filter: forward now-playing updates as well as scrobbles
+ALTER TABLE relays ADD COLUMN include_now_playing BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE relay_queue ADD COLUMN event TEXT NOT NULL DEFAULT 'scrobble' CHECK (event IN ('scrobble', 'now_playing'));
@@ -0,0 +1,22 @@
+-- Instance-wide announcements created by admins
+CREATE TABLE IF NOT EXISTS announcements (
+  id BIGSERIAL PRIMARY KEY,
+  title TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_by BIGINT,
+  created_at BIGINT NOT NULL,
+  expires_at BIGINT,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL
+);
+
+-- Which users have read which announcements
+CREATE TABLE IF NOT EXISTS announcement_reads (
+  announcement_id BIGINT NOT NULL,
+  user_id BIGINT NOT NULL,
+  read_at BIGINT NOT NULL,
+  PRIMARY KEY (announcement_id, user_id),
+  FOREIGN KEY (announcement_id) REFERENCES announcements(id) ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_announcements_expires_at ON announcements(expires_at);
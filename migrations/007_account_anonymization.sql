@@ -0,0 +1,2 @@
+-- When a user anonymized their account (NULL for regular accounts)
+ALTER TABLE users ADD COLUMN anonymized_at BIGINT;
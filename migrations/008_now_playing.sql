@@ -0,0 +1,14 @@
+-- Latest now-playing report per user
+CREATE TABLE IF NOT EXISTS now_playing (
+  user_id BIGINT PRIMARY KEY,
+  artist TEXT NOT NULL,
+  track TEXT NOT NULL,
+  album TEXT,
+  duration BIGINT,
+  started_at BIGINT NOT NULL,
+  promoted BOOLEAN NOT NULL DEFAULT false,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Opt-in: turn now-playing reports that never get scrobbled into scrobbles
+ALTER TABLE users ADD COLUMN auto_promote_now_playing BOOLEAN NOT NULL DEFAULT false;
@@ -0,0 +1,10 @@
+-- Retention summary: for each signup month, how many of those users
+-- scrobbled in each later month. Rebuilt by the cohorts job.
+CREATE TABLE IF NOT EXISTS user_cohorts (
+  cohort_month DATE NOT NULL,
+  activity_month DATE NOT NULL,
+  cohort_size BIGINT NOT NULL,
+  active_users BIGINT NOT NULL,
+  computed_at BIGINT NOT NULL,
+  PRIMARY KEY (cohort_month, activity_month)
+);
@@ -0,0 +1,23 @@
+-- Archived top artists/tracks for each completed week and month
+CREATE TABLE IF NOT EXISTS chart_snapshots (
+  user_id BIGINT NOT NULL,
+  period TEXT NOT NULL CHECK (period IN ('week', 'month')),
+  kind TEXT NOT NULL CHECK (kind IN ('artists', 'tracks')),
+  period_start BIGINT NOT NULL,
+  period_end BIGINT NOT NULL,
+  entries JSONB NOT NULL,
+  computed_at BIGINT NOT NULL,
+  PRIMARY KEY (user_id, period, kind, period_start),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Progress of the last snapshot backfill requested by each user
+CREATE TABLE IF NOT EXISTS chart_backfills (
+  user_id BIGINT PRIMARY KEY,
+  status TEXT NOT NULL CHECK (status IN ('running', 'done', 'failed')),
+  snapshots BIGINT NOT NULL DEFAULT 0,
+  error TEXT,
+  started_at BIGINT NOT NULL,
+  finished_at BIGINT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- Durations looked up from recording metadata for scrobbles that arrived
+-- without one. The submitted `duration` is never overwritten.
+ALTER TABLE scrobs ADD COLUMN duration_estimated BIGINT;
+
+-- Cached recording lookups; duration is NULL when no match was found
+CREATE TABLE IF NOT EXISTS recording_durations (
+  artist TEXT NOT NULL,
+  track TEXT NOT NULL,
+  duration BIGINT,
+  looked_up_at BIGINT NOT NULL,
+  PRIMARY KEY (artist, track)
+);
+
+CREATE INDEX IF NOT EXISTS idx_scrobs_missing_duration ON scrobs(artist, track)
+  WHERE duration IS NULL AND duration_estimated IS NULL;
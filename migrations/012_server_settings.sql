@@ -0,0 +1,10 @@
+-- Instance-wide settings managed through /admin/settings (single row)
+CREATE TABLE IF NOT EXISTS server_settings (
+  id SMALLINT PRIMARY KEY DEFAULT 1 CHECK (id = 1),
+  reserved_usernames TEXT[] NOT NULL DEFAULT ARRAY['admin', 'administrator', 'root', 'system', 'support', 'api', 'scrob'],
+  banned_words TEXT[] NOT NULL DEFAULT '{}',
+  min_public_account_age_days INTEGER NOT NULL DEFAULT 0,
+  updated_at BIGINT
+);
+
+INSERT INTO server_settings (id) VALUES (1) ON CONFLICT (id) DO NOTHING;
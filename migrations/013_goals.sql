@@ -0,0 +1,27 @@
+-- Per-user notification feed
+CREATE TABLE IF NOT EXISTS notifications (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  kind TEXT NOT NULL,
+  message TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  read_at BIGINT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_notifications_user ON notifications(user_id, created_at DESC);
+
+-- Listening goals, evaluated per calendar period (UTC)
+CREATE TABLE IF NOT EXISTS goals (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  metric TEXT NOT NULL CHECK (metric IN ('scrobbles', 'artists', 'new_artists', 'tracks', 'listening_hours')),
+  period TEXT NOT NULL CHECK (period IN ('week', 'month', 'year')),
+  target BIGINT NOT NULL CHECK (target > 0),
+  created_at BIGINT NOT NULL,
+  -- Start of the last period in which the user was notified of reaching the goal
+  reached_period_start BIGINT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_goals_user ON goals(user_id);
@@ -0,0 +1,44 @@
+-- Days whose scrobbles changed after the fact (deletes, edits, backdated
+-- inserts). The dirty-days job recomputes aggregates covering them.
+-- No foreign key: rows are also written while a user's scrobbles are being
+-- deleted along with the user.
+CREATE TABLE IF NOT EXISTS dirty_days (
+  user_id BIGINT NOT NULL,
+  day DATE NOT NULL,
+  marked_at BIGINT NOT NULL,
+  PRIMARY KEY (user_id, day)
+);
+
+CREATE OR REPLACE FUNCTION mark_dirty_days() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP IN ('UPDATE', 'DELETE') THEN
+    INSERT INTO dirty_days (user_id, day, marked_at)
+    SELECT DISTINCT user_id, (to_timestamp(timestamp) AT TIME ZONE 'UTC')::date, EXTRACT(EPOCH FROM now())::BIGINT
+    FROM old_rows
+    ON CONFLICT (user_id, day) DO UPDATE SET marked_at = EXCLUDED.marked_at;
+  END IF;
+
+  IF TG_OP IN ('UPDATE', 'INSERT') THEN
+    -- Live scrobbles only touch today, which no aggregate covers yet
+    INSERT INTO dirty_days (user_id, day, marked_at)
+    SELECT DISTINCT user_id, (to_timestamp(timestamp) AT TIME ZONE 'UTC')::date, EXTRACT(EPOCH FROM now())::BIGINT
+    FROM new_rows
+    WHERE TG_OP = 'UPDATE' OR (to_timestamp(timestamp) AT TIME ZONE 'UTC')::date < (now() AT TIME ZONE 'UTC')::date
+    ON CONFLICT (user_id, day) DO UPDATE SET marked_at = EXCLUDED.marked_at;
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scrobs_dirty_insert AFTER INSERT ON scrobs
+  REFERENCING NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_dirty_days();
+
+CREATE TRIGGER scrobs_dirty_update AFTER UPDATE ON scrobs
+  REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_dirty_days();
+
+CREATE TRIGGER scrobs_dirty_delete AFTER DELETE ON scrobs
+  REFERENCING OLD TABLE AS old_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_dirty_days();
@@ -0,0 +1,9 @@
+-- Request tokens for the Last.fm desktop auth flow (auth.getToken). A token
+-- is authorized by a signed-in user, then exchanged for a session key with
+-- auth.getSession. Session keys are regular api_tokens labelled 'lastfm'.
+CREATE TABLE IF NOT EXISTS lastfm_auth_tokens (
+  token TEXT PRIMARY KEY,
+  user_id BIGINT,
+  created_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,15 @@
+-- Sessions handed out by the legacy Audioscrobbler 1.2 handshake. Clients
+-- authenticate with one of the user's API tokens as the password, so a
+-- session dies with the token it was derived from.
+CREATE TABLE IF NOT EXISTS audioscrobbler_sessions (
+  session_id TEXT PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  token_id BIGINT NOT NULL,
+  client TEXT,
+  created_at BIGINT NOT NULL,
+  last_used_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+  FOREIGN KEY (token_id) REFERENCES api_tokens(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_audioscrobbler_sessions_user_id ON audioscrobbler_sessions(user_id);
@@ -0,0 +1,5 @@
+-- Album artist as submitted by the client, so compilations can be charted
+-- as one album instead of one entry per track artist
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS album_artist TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_scrobs_user_album ON scrobs(user_id, album) WHERE album IS NOT NULL;
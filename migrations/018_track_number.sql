@@ -0,0 +1,2 @@
+-- Position of the track on its album, as submitted by the client
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS track_number INTEGER;
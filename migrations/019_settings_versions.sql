@@ -0,0 +1,6 @@
+-- Version counters for optimistic concurrency on settings updates. Clients
+-- get the version as an ETag and send it back in If-Match.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS settings_version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS settings_updated_at BIGINT;
+
+ALTER TABLE server_settings ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,13 @@
+-- Tracks a user has marked as favorites
+CREATE TABLE IF NOT EXISTS loved_tracks (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  artist TEXT NOT NULL,
+  track TEXT NOT NULL,
+  album TEXT,
+  -- MusicBrainz recording id, when the client knows it
+  recording_mbid TEXT,
+  loved_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE,
+  UNIQUE (user_id, artist, track)
+);
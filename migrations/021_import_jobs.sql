@@ -0,0 +1,30 @@
+-- Listening history imported from other services. The uploaded file stays
+-- in IMPORT_DIR until the import worker has processed it.
+CREATE TABLE IF NOT EXISTS import_jobs (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  -- lastfm, listenbrainz or spotify
+  format TEXT NOT NULL,
+  -- pending, running, completed or failed
+  status TEXT NOT NULL DEFAULT 'pending',
+  file_name TEXT NOT NULL,
+  bytes BIGINT NOT NULL,
+  bytes_processed BIGINT NOT NULL DEFAULT 0,
+  processed BIGINT NOT NULL DEFAULT 0,
+  imported BIGINT NOT NULL DEFAULT 0,
+  duplicates BIGINT NOT NULL DEFAULT 0,
+  -- Entries that aren't listens (podcasts, plays too short to count)
+  skipped BIGINT NOT NULL DEFAULT 0,
+  rejected BIGINT NOT NULL DEFAULT 0,
+  -- The first rejection reasons; capped so a broken file can't bloat the row
+  errors TEXT[] NOT NULL DEFAULT '{}',
+  -- Why the whole import failed
+  error TEXT,
+  created_at BIGINT NOT NULL,
+  started_at BIGINT,
+  finished_at BIGINT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_import_jobs_user_id ON import_jobs(user_id, created_at DESC);
+CREATE INDEX IF NOT EXISTS idx_import_jobs_pending ON import_jobs(id) WHERE status = 'pending';
@@ -0,0 +1,11 @@
+-- Per-user preferences that don't affect authentication. A user without a
+-- row gets the column defaults.
+CREATE TABLE IF NOT EXISTS user_settings (
+  user_id BIGINT PRIMARY KEY,
+  display_name TEXT,
+  -- IANA zone name, checked against pg_timezone_names
+  timezone TEXT NOT NULL DEFAULT 'UTC',
+  -- Chart period used when a request doesn't name one
+  default_chart_period TEXT NOT NULL DEFAULT 'overall',
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- What each API token may be used for: `scrobble` (submitting listens and
+-- now playing), `read` (history and stats) and `admin` (account and token
+-- management). Existing tokens keep full access.
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{scrobble,read,admin}';
@@ -0,0 +1,2 @@
+-- Optional expiry for API tokens (unix seconds). NULL never expires.
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS expires_at BIGINT;
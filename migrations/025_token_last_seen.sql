@@ -0,0 +1,4 @@
+-- Where each token was last used from, for GET /sessions. The IP comes from
+-- X-Forwarded-For / X-Real-IP, so it is only as trustworthy as the proxy.
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS last_ip TEXT;
+ALTER TABLE api_tokens ADD COLUMN IF NOT EXISTS last_user_agent TEXT;
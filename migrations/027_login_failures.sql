@@ -0,0 +1,9 @@
+-- Failed password logins, keyed by `user:<username>` or `ip:<address>`.
+-- Once a key has too many recent failures it is locked until
+-- `locked_until`, doubling with each further failure.
+CREATE TABLE IF NOT EXISTS login_failures (
+  key TEXT PRIMARY KEY,
+  failures INTEGER NOT NULL,
+  last_failure_at BIGINT NOT NULL,
+  locked_until BIGINT
+);
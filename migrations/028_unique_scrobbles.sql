@@ -0,0 +1,12 @@
+-- A listen is identified by user, artist, track and timestamp. The duplicate
+-- check used to run before the insert, so concurrent retries could both get
+-- through; drop those copies (keeping the first) and let the index decide.
+DELETE FROM scrobs a
+USING scrobs b
+WHERE a.user_id = b.user_id
+  AND a.artist = b.artist
+  AND a.track = b.track
+  AND a.timestamp = b.timestamp
+  AND a.id > b.id;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_scrobs_unique_listen ON scrobs(user_id, artist, track, timestamp);
@@ -0,0 +1,15 @@
+-- Changes users made to their own scrobbles. Values are JSON snapshots of
+-- the editable fields; new_values is NULL when the scrobble was deleted.
+-- No foreign key on scrob_id so the history outlives a deleted scrobble.
+CREATE TABLE IF NOT EXISTS scrob_edits (
+  id BIGSERIAL PRIMARY KEY,
+  scrob_id BIGINT NOT NULL,
+  user_id BIGINT NOT NULL,
+  action TEXT NOT NULL CHECK (action IN ('edit', 'delete')),
+  old_values JSONB NOT NULL,
+  new_values JSONB,
+  edited_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_scrob_edits_scrob ON scrob_edits(scrob_id, edited_at);
@@ -0,0 +1,18 @@
+-- Per-user corrections applied to incoming scrobbles before they are
+-- stored, in id order. A rewrite replaces the matched value (regex rules
+-- can use $1-style groups); a block drops the scrobble.
+CREATE TABLE IF NOT EXISTS scrobble_rules (
+  id BIGSERIAL PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  field TEXT NOT NULL CHECK (field IN ('artist', 'track', 'album')),
+  match_type TEXT NOT NULL CHECK (match_type IN ('exact', 'regex')),
+  pattern TEXT NOT NULL,
+  action TEXT NOT NULL CHECK (action IN ('rewrite', 'block')),
+  -- Required for rewrites, NULL for blocks
+  replacement TEXT,
+  enabled BOOLEAN NOT NULL DEFAULT true,
+  created_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_scrobble_rules_user ON scrobble_rules(user_id);
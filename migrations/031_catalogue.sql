@@ -0,0 +1,109 @@
+-- Shared catalogue of artists, albums and tracks. Scrobbles keep their text
+-- columns as submitted and point at the catalogue, which a trigger fills in
+-- on every insert and edit; stats group by the ids instead of the text.
+CREATE TABLE IF NOT EXISTS artists (
+  id BIGSERIAL PRIMARY KEY,
+  name TEXT NOT NULL UNIQUE
+);
+
+-- Albums belong to their album artist (else the track artist), with the
+-- spellings of "Various Artists" merged, as in the album charts
+CREATE TABLE IF NOT EXISTS albums (
+  id BIGSERIAL PRIMARY KEY,
+  artist_id BIGINT NOT NULL REFERENCES artists(id),
+  name TEXT NOT NULL,
+  UNIQUE (artist_id, name)
+);
+
+CREATE TABLE IF NOT EXISTS tracks (
+  id BIGSERIAL PRIMARY KEY,
+  artist_id BIGINT NOT NULL REFERENCES artists(id),
+  name TEXT NOT NULL,
+  UNIQUE (artist_id, name)
+);
+
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS artist_id BIGINT REFERENCES artists(id);
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS track_id BIGINT REFERENCES tracks(id);
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS album_id BIGINT REFERENCES albums(id);
+
+CREATE OR REPLACE FUNCTION catalogue_album_artist(artist TEXT, album_artist TEXT) RETURNS TEXT AS $$
+  SELECT CASE
+    WHEN lower(btrim(album_artist)) IN ('various artists', 'various', 'va', 'v.a.') THEN 'Various Artists'
+    ELSE COALESCE(NULLIF(btrim(album_artist), ''), artist)
+  END
+$$ LANGUAGE sql IMMUTABLE;
+
+-- Backfill, one row per distinct name. Only the ids change, so the days
+-- don't need to be marked dirty.
+ALTER TABLE scrobs DISABLE TRIGGER scrobs_dirty_update;
+
+INSERT INTO artists (name)
+SELECT artist FROM scrobs
+UNION
+SELECT catalogue_album_artist(artist, album_artist) FROM scrobs WHERE btrim(album) <> ''
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO tracks (artist_id, name)
+SELECT DISTINCT a.id, s.track
+FROM scrobs s
+JOIN artists a ON a.name = s.artist
+ON CONFLICT (artist_id, name) DO NOTHING;
+
+INSERT INTO albums (artist_id, name)
+SELECT DISTINCT a.id, btrim(s.album)
+FROM scrobs s
+JOIN artists a ON a.name = catalogue_album_artist(s.artist, s.album_artist)
+WHERE btrim(s.album) <> ''
+ON CONFLICT (artist_id, name) DO NOTHING;
+
+UPDATE scrobs s
+SET artist_id = a.id, track_id = t.id
+FROM artists a
+JOIN tracks t ON t.artist_id = a.id
+WHERE a.name = s.artist AND t.name = s.track;
+
+UPDATE scrobs s
+SET album_id = al.id
+FROM albums al
+JOIN artists a ON a.id = al.artist_id
+WHERE btrim(s.album) <> ''
+  AND a.name = catalogue_album_artist(s.artist, s.album_artist)
+  AND al.name = btrim(s.album);
+
+ALTER TABLE scrobs ENABLE TRIGGER scrobs_dirty_update;
+
+ALTER TABLE scrobs ALTER COLUMN artist_id SET NOT NULL;
+ALTER TABLE scrobs ALTER COLUMN track_id SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_scrobs_user_artist_id ON scrobs(user_id, artist_id);
+CREATE INDEX IF NOT EXISTS idx_scrobs_user_track_id ON scrobs(user_id, track_id);
+CREATE INDEX IF NOT EXISTS idx_scrobs_user_album_id ON scrobs(user_id, album_id) WHERE album_id IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION link_scrob_catalogue() RETURNS trigger AS $$
+DECLARE
+  album_artist_name TEXT;
+  album_artist_id BIGINT;
+BEGIN
+  INSERT INTO artists (name) VALUES (NEW.artist) ON CONFLICT (name) DO NOTHING;
+  SELECT id INTO NEW.artist_id FROM artists WHERE name = NEW.artist;
+
+  INSERT INTO tracks (artist_id, name) VALUES (NEW.artist_id, NEW.track) ON CONFLICT (artist_id, name) DO NOTHING;
+  SELECT id INTO NEW.track_id FROM tracks WHERE artist_id = NEW.artist_id AND name = NEW.track;
+
+  IF NEW.album IS NULL OR btrim(NEW.album) = '' THEN
+    NEW.album_id := NULL;
+  ELSE
+    album_artist_name := catalogue_album_artist(NEW.artist, NEW.album_artist);
+    INSERT INTO artists (name) VALUES (album_artist_name) ON CONFLICT (name) DO NOTHING;
+    SELECT id INTO album_artist_id FROM artists WHERE name = album_artist_name;
+
+    INSERT INTO albums (artist_id, name) VALUES (album_artist_id, btrim(NEW.album)) ON CONFLICT (artist_id, name) DO NOTHING;
+    SELECT id INTO NEW.album_id FROM albums WHERE artist_id = album_artist_id AND name = btrim(NEW.album);
+  END IF;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scrobs_link_catalogue BEFORE INSERT OR UPDATE OF artist, track, album, album_artist ON scrobs
+  FOR EACH ROW EXECUTE FUNCTION link_scrob_catalogue();
@@ -0,0 +1,141 @@
+-- MusicBrainz ids submitted with scrobbles. The catalogue matches on them
+-- before names, so different spellings of one artist, track or album land on
+-- the same row, and same-named ones with different ids stay apart.
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS artist_mbid TEXT;
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS release_mbid TEXT;
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS recording_mbid TEXT;
+
+ALTER TABLE artists ADD COLUMN IF NOT EXISTS mbid TEXT UNIQUE;
+ALTER TABLE albums ADD COLUMN IF NOT EXISTS mbid TEXT UNIQUE;
+ALTER TABLE tracks ADD COLUMN IF NOT EXISTS mbid TEXT UNIQUE;
+
+-- Names only need to be unique among the rows without an id
+ALTER TABLE artists DROP CONSTRAINT IF EXISTS artists_name_key;
+ALTER TABLE albums DROP CONSTRAINT IF EXISTS albums_artist_id_name_key;
+ALTER TABLE tracks DROP CONSTRAINT IF EXISTS tracks_artist_id_name_key;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_artists_name ON artists(name) WHERE mbid IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_albums_artist_name ON albums(artist_id, name) WHERE mbid IS NULL;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_tracks_artist_name ON tracks(artist_id, name) WHERE mbid IS NULL;
+CREATE INDEX IF NOT EXISTS idx_artists_name_all ON artists(name);
+CREATE INDEX IF NOT EXISTS idx_albums_artist_name_all ON albums(artist_id, name);
+CREATE INDEX IF NOT EXISTS idx_tracks_artist_name_all ON tracks(artist_id, name);
+
+-- The row for an id, else the first time an id is seen it claims the row
+-- matched by name so far. Without an id, a name matches the row without one,
+-- falling back to the oldest row with one.
+CREATE OR REPLACE FUNCTION catalogue_artist(artist_name TEXT, artist_mbid TEXT) RETURNS BIGINT AS $$
+DECLARE
+  artist_id BIGINT;
+BEGIN
+  IF artist_mbid IS NOT NULL THEN
+    SELECT id INTO artist_id FROM artists WHERE mbid = artist_mbid;
+    IF artist_id IS NULL THEN
+      UPDATE artists SET mbid = artist_mbid WHERE name = artist_name AND mbid IS NULL RETURNING id INTO artist_id;
+    END IF;
+    IF artist_id IS NULL THEN
+      INSERT INTO artists (name, mbid) VALUES (artist_name, artist_mbid) ON CONFLICT (mbid) DO NOTHING;
+      SELECT id INTO artist_id FROM artists WHERE mbid = artist_mbid;
+    END IF;
+    RETURN artist_id;
+  END IF;
+
+  SELECT id INTO artist_id FROM artists WHERE name = artist_name ORDER BY mbid IS NOT NULL, id LIMIT 1;
+  IF artist_id IS NULL THEN
+    INSERT INTO artists (name) VALUES (artist_name) ON CONFLICT (name) WHERE mbid IS NULL DO NOTHING;
+    SELECT id INTO artist_id FROM artists WHERE name = artist_name AND mbid IS NULL;
+  END IF;
+  RETURN artist_id;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION catalogue_track(track_artist_id BIGINT, track_name TEXT, track_mbid TEXT) RETURNS BIGINT AS $$
+DECLARE
+  track_id BIGINT;
+BEGIN
+  IF track_mbid IS NOT NULL THEN
+    SELECT id INTO track_id FROM tracks WHERE mbid = track_mbid;
+    IF track_id IS NULL THEN
+      UPDATE tracks SET mbid = track_mbid
+      WHERE artist_id = track_artist_id AND name = track_name AND mbid IS NULL
+      RETURNING id INTO track_id;
+    END IF;
+    IF track_id IS NULL THEN
+      INSERT INTO tracks (artist_id, name, mbid) VALUES (track_artist_id, track_name, track_mbid) ON CONFLICT (mbid) DO NOTHING;
+      SELECT id INTO track_id FROM tracks WHERE mbid = track_mbid;
+    END IF;
+    RETURN track_id;
+  END IF;
+
+  SELECT id INTO track_id FROM tracks
+  WHERE artist_id = track_artist_id AND name = track_name
+  ORDER BY mbid IS NOT NULL, id LIMIT 1;
+  IF track_id IS NULL THEN
+    INSERT INTO tracks (artist_id, name) VALUES (track_artist_id, track_name)
+    ON CONFLICT (artist_id, name) WHERE mbid IS NULL DO NOTHING;
+    SELECT id INTO track_id FROM tracks WHERE artist_id = track_artist_id AND name = track_name AND mbid IS NULL;
+  END IF;
+  RETURN track_id;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION catalogue_album(album_artist_id BIGINT, album_name TEXT, album_mbid TEXT) RETURNS BIGINT AS $$
+DECLARE
+  album_id BIGINT;
+BEGIN
+  IF album_mbid IS NOT NULL THEN
+    SELECT id INTO album_id FROM albums WHERE mbid = album_mbid;
+    IF album_id IS NULL THEN
+      UPDATE albums SET mbid = album_mbid
+      WHERE artist_id = album_artist_id AND name = album_name AND mbid IS NULL
+      RETURNING id INTO album_id;
+    END IF;
+    IF album_id IS NULL THEN
+      INSERT INTO albums (artist_id, name, mbid) VALUES (album_artist_id, album_name, album_mbid) ON CONFLICT (mbid) DO NOTHING;
+      SELECT id INTO album_id FROM albums WHERE mbid = album_mbid;
+    END IF;
+    RETURN album_id;
+  END IF;
+
+  SELECT id INTO album_id FROM albums
+  WHERE artist_id = album_artist_id AND name = album_name
+  ORDER BY mbid IS NOT NULL, id LIMIT 1;
+  IF album_id IS NULL THEN
+    INSERT INTO albums (artist_id, name) VALUES (album_artist_id, album_name)
+    ON CONFLICT (artist_id, name) WHERE mbid IS NULL DO NOTHING;
+    SELECT id INTO album_id FROM albums WHERE artist_id = album_artist_id AND name = album_name AND mbid IS NULL;
+  END IF;
+  RETURN album_id;
+END;
+$$ LANGUAGE plpgsql;
+
+-- A recording or release found by id keeps the artist it was catalogued
+-- under, which wins over this scrobble's spelling
+CREATE OR REPLACE FUNCTION link_scrob_catalogue() RETURNS trigger AS $$
+DECLARE
+  album_artist_name TEXT;
+  album_artist_id BIGINT;
+BEGIN
+  NEW.artist_id := catalogue_artist(NEW.artist, NEW.artist_mbid);
+  NEW.track_id := catalogue_track(NEW.artist_id, NEW.track, NEW.recording_mbid);
+
+  IF NEW.album IS NULL OR btrim(NEW.album) = '' THEN
+    NEW.album_id := NULL;
+  ELSE
+    album_artist_name := catalogue_album_artist(NEW.artist, NEW.album_artist);
+    IF album_artist_name = NEW.artist THEN
+      album_artist_id := NEW.artist_id;
+    ELSE
+      album_artist_id := catalogue_artist(album_artist_name, NULL);
+    END IF;
+    NEW.album_id := catalogue_album(album_artist_id, btrim(NEW.album), NEW.release_mbid);
+  END IF;
+
+  RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS scrobs_link_catalogue ON scrobs;
+CREATE TRIGGER scrobs_link_catalogue
+  BEFORE INSERT OR UPDATE OF artist, track, album, album_artist, artist_mbid, release_mbid, recording_mbid ON scrobs
+  FOR EACH ROW EXECUTE FUNCTION link_scrob_catalogue();
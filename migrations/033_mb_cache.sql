@@ -0,0 +1,18 @@
+-- MusicBrainz matches for scrobbled text, looked up in the background when
+-- METADATA_LOOKUP is on. All the match columns are NULL when nothing matched.
+CREATE TABLE IF NOT EXISTS mb_cache (
+  artist TEXT NOT NULL,
+  track TEXT NOT NULL,
+  -- Trimmed album, '' for scrobbles without one
+  album TEXT NOT NULL,
+  recording_mbid TEXT,
+  recording_name TEXT,
+  artist_mbid TEXT,
+  artist_name TEXT,
+  release_mbid TEXT,
+  release_name TEXT,
+  looked_up_at BIGINT NOT NULL,
+  PRIMARY KEY (artist, track, album)
+);
+
+CREATE INDEX IF NOT EXISTS idx_scrobs_missing_mbid ON scrobs(artist, track) WHERE recording_mbid IS NULL;
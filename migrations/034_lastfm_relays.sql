@@ -0,0 +1,3 @@
+-- Last.fm relays store the user's session key as their secret
+ALTER TABLE relays DROP CONSTRAINT IF EXISTS relays_kind_check;
+ALTER TABLE relays ADD CONSTRAINT relays_kind_check CHECK (kind IN ('lastfm', 'listenbrainz', 'webhook'));
@@ -0,0 +1,13 @@
+-- MPD servers scrob watches on a user's behalf when MPD_WATCHER is on. The
+-- password is encrypted with SECRET_KEY like relay secrets.
+CREATE TABLE IF NOT EXISTS mpd_watchers (
+  user_id BIGINT PRIMARY KEY,
+  host TEXT NOT NULL,
+  port INTEGER NOT NULL,
+  password TEXT,
+  enabled BOOLEAN NOT NULL DEFAULT true,
+  updated_at BIGINT NOT NULL,
+  last_connected_at BIGINT,
+  last_error TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,24 @@
+-- Spotify accounts linked through /connect/spotify, polled for recently
+-- played tracks. Tokens are encrypted with SECRET_KEY.
+CREATE TABLE IF NOT EXISTS spotify_links (
+  user_id BIGINT PRIMARY KEY,
+  spotify_user_id TEXT NOT NULL,
+  display_name TEXT,
+  access_token TEXT NOT NULL,
+  refresh_token TEXT NOT NULL,
+  expires_at BIGINT NOT NULL,
+  -- played_at (unix ms) of the newest play seen, sent as `after`
+  cursor_ms BIGINT,
+  linked_at BIGINT NOT NULL,
+  last_polled_at BIGINT,
+  last_error TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Links in flight between /connect/spotify and the callback
+CREATE TABLE IF NOT EXISTS spotify_link_states (
+  state TEXT PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  created_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
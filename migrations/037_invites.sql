@@ -0,0 +1,16 @@
+-- Closed registration: signups need an invite code minted by an admin
+ALTER TABLE server_settings ADD COLUMN IF NOT EXISTS registration_open BOOLEAN NOT NULL DEFAULT true;
+
+-- Single-use invite codes from /admin/invites
+CREATE TABLE IF NOT EXISTS invites (
+  code TEXT PRIMARY KEY,
+  note TEXT,
+  created_by BIGINT,
+  created_at BIGINT NOT NULL,
+  expires_at BIGINT,
+  -- Set when the code is redeemed at signup
+  used_by BIGINT,
+  used_at BIGINT,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL,
+  FOREIGN KEY (used_by) REFERENCES users(id) ON DELETE SET NULL
+);
@@ -0,0 +1,8 @@
+-- Operational settings changed at runtime through /admin/settings. NULL
+-- falls back to the environment (SCROBBLE_MAX_BATCH, SCROBBLE_RATE_LIMIT,
+-- SCROBBLE_RATE_LIMIT_WINDOW); a NULL retention keeps scrobbles forever.
+ALTER TABLE server_settings
+  ADD COLUMN IF NOT EXISTS scrobble_max_batch INTEGER CHECK (scrobble_max_batch > 0),
+  ADD COLUMN IF NOT EXISTS scrobble_rate_limit INTEGER CHECK (scrobble_rate_limit >= 0),
+  ADD COLUMN IF NOT EXISTS scrobble_rate_limit_window INTEGER CHECK (scrobble_rate_limit_window > 0),
+  ADD COLUMN IF NOT EXISTS retention_days INTEGER CHECK (retention_days > 0);
@@ -0,0 +1,7 @@
+-- Bans set through /admin/users/{id}/ban. A ban is in force from
+-- banned_at until banned_until (NULL = until lifted); the account and its
+-- data are kept.
+ALTER TABLE users
+  ADD COLUMN IF NOT EXISTS banned_at BIGINT,
+  ADD COLUMN IF NOT EXISTS banned_until BIGINT,
+  ADD COLUMN IF NOT EXISTS ban_reason TEXT;
@@ -0,0 +1,3 @@
+-- The admin dashboard's daily series count scrobbles by when they were
+-- received, over the last few months
+CREATE INDEX IF NOT EXISTS idx_scrobs_created_at ON scrobs(created_at);
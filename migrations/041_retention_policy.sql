@@ -0,0 +1,13 @@
+-- retention_days now overrides SCROB_RETENTION_DAYS like the other runtime
+-- settings: NULL uses the environment and 0 keeps scrobbles forever.
+-- retention_scope limits the purge to banned users; users can opt out of it
+-- in their preferences.
+ALTER TABLE server_settings DROP CONSTRAINT IF EXISTS server_settings_retention_days_check;
+ALTER TABLE server_settings ADD CONSTRAINT server_settings_retention_days_check CHECK (retention_days >= 0);
+
+ALTER TABLE server_settings
+  ADD COLUMN IF NOT EXISTS retention_scope TEXT NOT NULL DEFAULT 'all'
+    CHECK (retention_scope IN ('all', 'banned'));
+
+ALTER TABLE user_settings
+  ADD COLUMN IF NOT EXISTS retention_opt_out BOOLEAN NOT NULL DEFAULT false;
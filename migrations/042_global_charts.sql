@@ -0,0 +1,7 @@
+-- Server-wide charts at /charts/global/*. Users opt in; admins can switch
+-- the feature off altogether.
+ALTER TABLE user_settings
+  ADD COLUMN IF NOT EXISTS include_in_global_charts BOOLEAN NOT NULL DEFAULT false;
+
+ALTER TABLE server_settings
+  ADD COLUMN IF NOT EXISTS global_charts_enabled BOOLEAN NOT NULL DEFAULT true;
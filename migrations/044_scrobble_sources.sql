@@ -0,0 +1,4 @@
+-- Which client or integration submitted each scrobble (X-Scrob-Client, the
+-- `client` field, or the compatibility API / sync that stored it). NULL for
+-- scrobbles stored before this was tracked or sent without one.
+ALTER TABLE scrobs ADD COLUMN IF NOT EXISTS source TEXT;
@@ -0,0 +1,19 @@
+-- Discord webhooks users post listening updates to. The URL carries the
+-- webhook token, so it is encrypted with SECRET_KEY like relay secrets.
+-- The last_* columns mark what was already posted: the local start of the
+-- summarized day and week, and the highest milestone announced.
+CREATE TABLE IF NOT EXISTS discord_webhooks (
+  user_id BIGINT PRIMARY KEY,
+  url TEXT NOT NULL,
+  daily_summary BOOLEAN NOT NULL DEFAULT true,
+  milestones BOOLEAN NOT NULL DEFAULT true,
+  weekly_top_artist BOOLEAN NOT NULL DEFAULT true,
+  enabled BOOLEAN NOT NULL DEFAULT true,
+  updated_at BIGINT NOT NULL,
+  last_daily_summary BIGINT,
+  last_weekly_top_artist BIGINT,
+  last_milestone BIGINT,
+  last_success_at BIGINT,
+  last_error TEXT,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
@@ -0,0 +1,22 @@
+-- Optional email address per user. Only a verified address can receive
+-- password resets, and each verified address belongs to one account.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email TEXT;
+ALTER TABLE users ADD COLUMN IF NOT EXISTS email_verified_at BIGINT;
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_verified_email
+  ON users (lower(email)) WHERE email_verified_at IS NOT NULL;
+
+-- Single-use tokens mailed for address verification and password resets.
+-- Only the SHA-256 of the token is stored; `email` is the address a
+-- verification token was sent to.
+CREATE TABLE IF NOT EXISTS email_tokens (
+  token_hash TEXT PRIMARY KEY,
+  user_id BIGINT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('verify', 'reset')),
+  email TEXT NOT NULL,
+  created_at BIGINT NOT NULL,
+  expires_at BIGINT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_tokens_user_id ON email_tokens(user_id, kind);
@@ -0,0 +1,47 @@
+-- Precomputed top artists/tracks/albums per user and rolling period, so
+-- chart requests don't scan every scrobble. `range_from` is the period start
+-- the entries were computed for; it moves when a new day starts.
+-- Any change to a user's scrobbles sets `stale_since`, and the chart cache
+-- job recomputes the entry. Entries nobody reads for a week are dropped.
+CREATE TABLE IF NOT EXISTS chart_cache (
+  user_id BIGINT NOT NULL,
+  kind TEXT NOT NULL CHECK (kind IN ('artists', 'tracks', 'albums')),
+  period TEXT NOT NULL,
+  range_from BIGINT NOT NULL,
+  entries JSONB NOT NULL,
+  computed_at BIGINT NOT NULL,
+  read_at BIGINT NOT NULL,
+  stale_since BIGINT,
+  PRIMARY KEY (user_id, kind, period),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_chart_cache_stale ON chart_cache(stale_since) WHERE stale_since IS NOT NULL;
+
+CREATE OR REPLACE FUNCTION mark_chart_cache_stale() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP IN ('UPDATE', 'DELETE') THEN
+    UPDATE chart_cache SET stale_since = EXTRACT(EPOCH FROM now())::BIGINT
+    WHERE stale_since IS NULL AND user_id IN (SELECT user_id FROM old_rows);
+  END IF;
+
+  IF TG_OP IN ('UPDATE', 'INSERT') THEN
+    UPDATE chart_cache SET stale_since = EXTRACT(EPOCH FROM now())::BIGINT
+    WHERE stale_since IS NULL AND user_id IN (SELECT user_id FROM new_rows);
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scrobs_chart_cache_insert AFTER INSERT ON scrobs
+  REFERENCING NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_chart_cache_stale();
+
+CREATE TRIGGER scrobs_chart_cache_update AFTER UPDATE ON scrobs
+  REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_chart_cache_stale();
+
+CREATE TRIGGER scrobs_chart_cache_delete AFTER DELETE ON scrobs
+  REFERENCING OLD TABLE AS old_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION mark_chart_cache_stale();
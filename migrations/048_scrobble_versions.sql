@@ -0,0 +1,41 @@
+-- Per-user counter bumped by every statement that changes the user's
+-- scrobbles. ETags on responses built from scrobbles (recent, charts) are
+-- derived from it, so edits and deletes change them as well as new listens.
+-- No foreign key, as with dirty_days: rows are also written while a user's
+-- scrobbles are being deleted along with the user.
+CREATE TABLE IF NOT EXISTS scrobble_versions (
+  user_id BIGINT PRIMARY KEY,
+  version BIGINT NOT NULL
+);
+
+CREATE OR REPLACE FUNCTION bump_scrobble_versions() RETURNS trigger AS $$
+BEGIN
+  IF TG_OP = 'INSERT' THEN
+    INSERT INTO scrobble_versions (user_id, version)
+    SELECT DISTINCT user_id, 1 FROM new_rows
+    ON CONFLICT (user_id) DO UPDATE SET version = scrobble_versions.version + 1;
+  ELSIF TG_OP = 'DELETE' THEN
+    INSERT INTO scrobble_versions (user_id, version)
+    SELECT DISTINCT user_id, 1 FROM old_rows
+    ON CONFLICT (user_id) DO UPDATE SET version = scrobble_versions.version + 1;
+  ELSE
+    INSERT INTO scrobble_versions (user_id, version)
+    SELECT user_id, 1 FROM old_rows UNION SELECT user_id, 1 FROM new_rows
+    ON CONFLICT (user_id) DO UPDATE SET version = scrobble_versions.version + 1;
+  END IF;
+
+  RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER scrobs_version_insert AFTER INSERT ON scrobs
+  REFERENCING NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION bump_scrobble_versions();
+
+CREATE TRIGGER scrobs_version_update AFTER UPDATE ON scrobs
+  REFERENCING OLD TABLE AS old_rows NEW TABLE AS new_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION bump_scrobble_versions();
+
+CREATE TRIGGER scrobs_version_delete AFTER DELETE ON scrobs
+  REFERENCING OLD TABLE AS old_rows
+  FOR EACH STATEMENT EXECUTE FUNCTION bump_scrobble_versions();
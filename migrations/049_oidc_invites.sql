@@ -0,0 +1,3 @@
+-- The invite code a login was started with, redeemed if the callback has to
+-- create an account while registration is closed
+ALTER TABLE oidc_login_states ADD COLUMN IF NOT EXISTS invite_code TEXT;
@@ -37,7 +37,7 @@ echo ""
 echo "Logging in to get token..."
 
 # Login to get token
-LOGIN_RESPONSE=$(curl -s -X POST "$API_URL/login" \
+LOGIN_RESPONSE=$(curl -s -X POST "$API_URL/api/v1/login" \
   -H "Content-Type: application/json" \
   -d "{\"username\":\"$USERNAME\",\"password\":\"$PASSWORD\"}")
 
@@ -174,7 +174,7 @@ SCROBBLES='[
   }
 ]'
 
-RESPONSE=$(curl -s -X POST "$API_URL/scrob" \
+RESPONSE=$(curl -s -X POST "$API_URL/api/v1/scrob" \
   -H "Authorization: Bearer $TOKEN" \
   -H "Content-Type: application/json" \
   -d "$SCROBBLES")
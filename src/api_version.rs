@@ -0,0 +1,49 @@
+//! REST API versioning. The JSON API is served under `/api/v1`. Additive
+//! changes (new endpoints, new response fields) stay in v1; a breaking change
+//! to a response shape goes into a new `/api/vN` prefix served next to the
+//! old one, so existing clients keep working until the old version is retired.
+//! Clients discover the versions in `GET /api/info`.
+//!
+//! Protocol endpoints (ListenBrainz, Last.fm, Audioscrobbler, Subsonic,
+//! webhooks, OAuth callbacks, embeds, health) keep fixed paths, since their
+//! URLs are configured in other software.
+
+use axum::{
+  extract::Request,
+  http::{header, HeaderValue},
+  middleware::Next,
+  response::Response,
+};
+
+/// Version served at `/api/<CURRENT>`
+pub const CURRENT: &str = "v1";
+/// Versions this server answers, oldest first
+pub const SUPPORTED: &[&str] = &["v1"];
+/// `Deprecation` value for the unprefixed paths: 2026-10-16 as unix time
+const LEGACY_DEPRECATED_AT: &str = "@1792108800";
+
+/// Tag responses with the API version that produced them
+pub async fn tag(request: Request, next: Next) -> Response {
+  let mut response = next.run(request).await;
+  response
+    .headers_mut()
+    .insert("api-version", HeaderValue::from_static(CURRENT));
+  response
+}
+
+/// Mark responses on the pre-`/api/v1` paths as deprecated (RFC 9745) and
+/// link to the same endpoint under the current version
+pub async fn deprecate_legacy(request: Request, next: Next) -> Response {
+  let path = request.uri().path();
+  // `/api/overview` was the one route already under `/api`
+  let path = path.strip_prefix("/api").unwrap_or(path);
+  let successor = HeaderValue::from_str(&format!("</api/{}{}>; rel=\"successor-version\"", CURRENT, path));
+
+  let mut response = next.run(request).await;
+  let headers = response.headers_mut();
+  headers.insert("deprecation", HeaderValue::from_static(LEGACY_DEPRECATED_AT));
+  if let Ok(successor) = successor {
+    headers.insert(header::LINK, successor);
+  }
+  response
+}
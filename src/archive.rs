@@ -0,0 +1,374 @@
+//! Portable account archives, used to move an account between instances
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  auth,
+  db::DbPool,
+  goals,
+  policy::ContentPolicy,
+  preferences::{self, Preferences, CHART_PERIODS},
+  routes::scrobble::{normalize_mbid, normalize_source, validate_scrobble, ScrobbleRequest},
+};
+
+pub const FORMAT: &str = "scrob-account";
+pub const VERSION: u32 = 1;
+
+/// Scrobbles are inserted in chunks of this size when restoring
+const RESTORE_BATCH_SIZE: usize = 5000;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AccountArchive {
+  pub format: String,
+  pub version: u32,
+  pub exported_at: i64,
+  pub username: String,
+  pub settings: ArchivedSettings,
+  #[serde(default)]
+  pub goals: Vec<ArchivedGoal>,
+  #[serde(default)]
+  pub loved_tracks: Vec<ArchivedLovedTrack>,
+  pub scrobbles: Vec<ArchivedScrobble>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedSettings {
+  pub is_private: bool,
+  pub auto_promote_now_playing: bool,
+  /// Missing from archives made before preferences existed
+  #[serde(default)]
+  pub preferences: Option<Preferences>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedGoal {
+  pub metric: String,
+  pub period: String,
+  pub target: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedLovedTrack {
+  pub artist: String,
+  pub track: String,
+  pub album: Option<String>,
+  pub recording_mbid: Option<String>,
+  pub loved_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchivedScrobble {
+  pub artist: String,
+  pub track: String,
+  pub album: Option<String>,
+  #[serde(default)]
+  pub album_artist: Option<String>,
+  #[serde(default)]
+  pub track_number: Option<i32>,
+  pub duration: Option<i64>,
+  pub timestamp: i64,
+  #[serde(default)]
+  pub artist_mbid: Option<String>,
+  #[serde(default)]
+  pub release_mbid: Option<String>,
+  #[serde(default)]
+  pub recording_mbid: Option<String>,
+  /// Client or integration that first stored it
+  #[serde(default)]
+  pub source: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoreSummary {
+  pub scrobbles_imported: u64,
+  pub scrobbles_skipped: u64,
+  pub goals_imported: u64,
+  /// Invalid on this server, or already set
+  pub goals_skipped: u64,
+  pub loved_tracks_imported: u64,
+}
+
+/// Collect everything needed to recreate the account elsewhere
+pub async fn build(pool: &DbPool, user_id: i64) -> Result<AccountArchive, sqlx::Error> {
+  let user = sqlx::query!(
+    r#"
+    SELECT username, is_private as "is_private: bool", auto_promote_now_playing as "auto_promote_now_playing: bool"
+    FROM users
+    WHERE id = $1
+    "#,
+    user_id
+  )
+  .fetch_one(pool)
+  .await?;
+
+  let preferences = preferences::load(pool, user_id).await?;
+
+  let goals = sqlx::query_as!(
+    ArchivedGoal,
+    "SELECT metric, period, target FROM goals WHERE user_id = $1 ORDER BY created_at",
+    user_id
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let loved_tracks = sqlx::query_as!(
+    ArchivedLovedTrack,
+    r#"
+    SELECT artist, track, album, recording_mbid, loved_at
+    FROM loved_tracks
+    WHERE user_id = $1
+    ORDER BY loved_at
+    "#,
+    user_id
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let scrobbles = sqlx::query_as!(
+    ArchivedScrobble,
+    r#"
+    SELECT
+      artist, track, album, album_artist, track_number, duration, timestamp as "timestamp!",
+      artist_mbid, release_mbid, recording_mbid, source
+    FROM scrobs
+    WHERE user_id = $1
+    ORDER BY timestamp
+    "#,
+    user_id
+  )
+  .fetch_all(pool)
+  .await?;
+
+  Ok(AccountArchive {
+    format: FORMAT.to_string(),
+    version: VERSION,
+    exported_at: chrono::Utc::now().timestamp(),
+    username: user.username,
+    settings: ArchivedSettings {
+      is_private: user.is_private,
+      auto_promote_now_playing: user.auto_promote_now_playing,
+      preferences: Some(preferences),
+    },
+    goals,
+    loved_tracks,
+    scrobbles,
+  })
+}
+
+/// Merge an archive into an existing account. Scrobbles already present
+/// (same artist, track and timestamp) are skipped, so restoring twice is safe.
+pub async fn restore(
+  pool: &DbPool,
+  user_id: i64,
+  archive: &AccountArchive,
+) -> Result<RestoreSummary, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  let policy = ContentPolicy::load(pool).await?;
+
+  let created_at = sqlx::query_scalar!("SELECT created_at FROM users WHERE id = $1", user_id)
+    .fetch_one(pool)
+    .await?;
+
+  // Moving doesn't get around the minimum account age for public profiles
+  let is_private = archive.settings.is_private || !policy.allows_public(created_at, now);
+
+  let mut tx = pool.begin().await?;
+
+  sqlx::query!(
+    r#"
+    UPDATE users
+    SET is_private = $2, auto_promote_now_playing = $3,
+        settings_version = settings_version + 1, settings_updated_at = $4
+    WHERE id = $1
+    "#,
+    user_id,
+    is_private,
+    archive.settings.auto_promote_now_playing,
+    now
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  if let Some(prefs) = &archive.settings.preferences {
+    // The other server may know zones or periods this one doesn't
+    let timezone = if preferences::is_valid_timezone(pool, &prefs.timezone).await? {
+      prefs.timezone.as_str()
+    } else {
+      "UTC"
+    };
+    let period = if CHART_PERIODS.contains(&prefs.default_chart_period.as_str()) {
+      prefs.default_chart_period.as_str()
+    } else {
+      "overall"
+    };
+    let display_name = prefs
+      .display_name
+      .as_deref()
+      .filter(|name| policy.check_text(name).is_ok())
+      .map(|name| name.chars().take(preferences::MAX_DISPLAY_NAME_LEN).collect::<String>());
+
+    sqlx::query!(
+      r#"
+      INSERT INTO user_settings (
+        user_id, display_name, timezone, default_chart_period, retention_opt_out, include_in_global_charts
+      )
+      VALUES ($1, $2, $3, $4, $5, $6)
+      ON CONFLICT (user_id) DO UPDATE
+      SET display_name = EXCLUDED.display_name,
+          timezone = EXCLUDED.timezone,
+          default_chart_period = EXCLUDED.default_chart_period,
+          retention_opt_out = EXCLUDED.retention_opt_out,
+          include_in_global_charts = EXCLUDED.include_in_global_charts
+      "#,
+      user_id,
+      display_name,
+      timezone,
+      period,
+      prefs.retention_opt_out,
+      prefs.include_in_global_charts
+    )
+    .execute(&mut *tx)
+    .await?;
+  }
+
+  // The other server may know metrics or periods this one doesn't
+  let mut goals_imported = 0;
+  for goal in archive.goals.iter().filter(|g| is_valid_goal(g)) {
+    goals_imported += sqlx::query!(
+      r#"
+      INSERT INTO goals (user_id, metric, period, target, created_at)
+      SELECT $1, $2, $3, $4, $5
+      WHERE NOT EXISTS(
+          SELECT 1 FROM goals
+          WHERE user_id = $1 AND metric = $2 AND period = $3 AND target = $4
+      )
+      "#,
+      user_id,
+      goal.metric,
+      goal.period,
+      goal.target,
+      now
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+  }
+
+  let mut loved_tracks_imported = 0;
+  for loved in &archive.loved_tracks {
+    if loved.artist.trim().is_empty() || loved.track.trim().is_empty() {
+      continue;
+    }
+
+    loved_tracks_imported += sqlx::query!(
+      r#"
+      INSERT INTO loved_tracks (user_id, artist, track, album, recording_mbid, loved_at)
+      VALUES ($1, $2, $3, $4, $5, $6)
+      ON CONFLICT (user_id, artist, track) DO NOTHING
+      "#,
+      user_id,
+      loved.artist,
+      loved.track,
+      loved.album,
+      loved.recording_mbid,
+      loved.loved_at
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+  }
+
+  // Archives come from another server, so hold them to the same rules as submissions
+  let valid: Vec<&ArchivedScrobble> = archive.scrobbles.iter().filter(|s| is_valid(s)).collect();
+
+  let mut scrobbles_imported = 0;
+  for chunk in valid.chunks(RESTORE_BATCH_SIZE) {
+    let artists: Vec<&str> = chunk.iter().map(|s| s.artist.as_str()).collect();
+    let tracks: Vec<&str> = chunk.iter().map(|s| s.track.as_str()).collect();
+    let albums: Vec<Option<&str>> = chunk.iter().map(|s| s.album.as_deref()).collect();
+    let album_artists: Vec<Option<&str>> = chunk.iter().map(|s| s.album_artist.as_deref()).collect();
+    let track_numbers: Vec<Option<i32>> = chunk.iter().map(|s| s.track_number).collect();
+    let durations: Vec<Option<i64>> = chunk.iter().map(|s| s.duration).collect();
+    let timestamps: Vec<i64> = chunk.iter().map(|s| s.timestamp).collect();
+    let artist_mbids: Vec<Option<String>> = chunk.iter().map(|s| normalize_mbid(s.artist_mbid.as_deref())).collect();
+    let release_mbids: Vec<Option<String>> = chunk.iter().map(|s| normalize_mbid(s.release_mbid.as_deref())).collect();
+    let recording_mbids: Vec<Option<String>> =
+      chunk.iter().map(|s| normalize_mbid(s.recording_mbid.as_deref())).collect();
+    let sources: Vec<Option<String>> = chunk.iter().map(|s| normalize_source(s.source.as_deref())).collect();
+
+    scrobbles_imported += sqlx::query!(
+      r#"
+      INSERT INTO scrobs (
+        user_id, artist, track, album, album_artist, track_number, duration, timestamp, created_at,
+        artist_mbid, release_mbid, recording_mbid, source
+      )
+      SELECT DISTINCT ON (i.artist, i.track, i.timestamp)
+             $1, i.artist, i.track, i.album, i.album_artist, i.track_number, i.duration, i.timestamp, $9,
+             i.artist_mbid, i.release_mbid, i.recording_mbid, i.source
+      FROM UNNEST(
+        $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::INTEGER[], $7::BIGINT[], $8::BIGINT[],
+        $10::TEXT[], $11::TEXT[], $12::TEXT[], $13::TEXT[]
+      ) AS i(
+        artist, track, album, album_artist, track_number, duration, timestamp,
+        artist_mbid, release_mbid, recording_mbid, source
+      )
+      ON CONFLICT (user_id, artist, track, timestamp) DO NOTHING
+      "#,
+      user_id,
+      &artists as &[&str],
+      &tracks as &[&str],
+      &albums as &[Option<&str>],
+      &album_artists as &[Option<&str>],
+      &track_numbers as &[Option<i32>],
+      &durations as &[Option<i64>],
+      &timestamps,
+      now,
+      &artist_mbids as &[Option<String>],
+      &release_mbids as &[Option<String>],
+      &recording_mbids as &[Option<String>],
+      &sources as &[Option<String>]
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+  }
+
+  tx.commit().await?;
+  auth::invalidate_user(user_id);
+
+  Ok(RestoreSummary {
+    scrobbles_imported,
+    scrobbles_skipped: archive.scrobbles.len() as u64 - scrobbles_imported,
+    goals_imported,
+    goals_skipped: archive.goals.len() as u64 - goals_imported,
+    loved_tracks_imported,
+  })
+}
+
+fn is_valid_goal(goal: &ArchivedGoal) -> bool {
+  goals::METRICS.contains(&goal.metric.as_str()) && goals::PERIODS.contains(&goal.period.as_str()) && goal.target > 0
+}
+
+fn is_valid(scrob: &ArchivedScrobble) -> bool {
+  let (Ok(timestamp), Ok(duration)) = (
+    u64::try_from(scrob.timestamp),
+    scrob.duration.map(u64::try_from).transpose(),
+  ) else {
+    return false;
+  };
+
+  validate_scrobble(&ScrobbleRequest {
+    artist: scrob.artist.clone(),
+    track: scrob.track.clone(),
+    timestamp,
+    album: scrob.album.clone(),
+    album_artist: scrob.album_artist.clone(),
+    duration,
+    track_number: scrob.track_number.and_then(|n| u32::try_from(n).ok()),
+    artist_mbid: scrob.artist_mbid.clone(),
+    release_mbid: scrob.release_mbid.clone(),
+    recording_mbid: scrob.recording_mbid.clone(),
+    source: scrob.source.clone(),
+  })
+  .is_ok()
+}
@@ -0,0 +1,161 @@
+use std::time::Instant;
+
+use axum::{
+  body::{to_bytes, Body},
+  extract::{MatchedPath, Request, State},
+  http::header,
+  middleware::Next,
+  response::Response,
+};
+use serde_json::Value;
+
+use crate::{auth, state::AppState};
+
+/// Field names whose values never make it into the audit log
+const REDACTED_FIELDS: [&str; 8] = [
+  "password",
+  "token",
+  "secret",
+  "api_key",
+  "api_sig",
+  "session_key",
+  "sk",
+  "authorization",
+];
+
+/// Single-letter credential parameters, redacted only on the protocol routes
+/// that use them (by route prefix) so ordinary fields elsewhere stay visible
+const REDACTED_ROUTE_FIELDS: [(&str, &[&str]); 2] = [
+  // Audioscrobbler 1.2 session id
+  ("/audioscrobbler/", &["s"]),
+  // Subsonic password, token and salt
+  ("/rest/", &["p", "t", "s"]),
+];
+
+/// Log method, route, status, latency and user for every request, plus a
+/// sampled, redacted copy of the request body
+pub async fn log_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+  let started = Instant::now();
+  let method = request.method().clone();
+  let route = request
+    .extensions()
+    .get::<MatchedPath>()
+    .map(|p| p.as_str().to_string())
+    .unwrap_or_else(|| request.uri().path().to_string());
+
+  let user_id = match request
+    .headers()
+    .get(header::AUTHORIZATION)
+    .and_then(|h| h.to_str().ok())
+    .and_then(auth::extract_token_from_header)
+  {
+    Some(token) => auth::lookup_token(&state.pool, &token).await.ok().flatten().map(|owner| owner.user_id),
+    None => None,
+  };
+
+  let sampled = state.config.audit_sample_rate > 0.0
+    && rand::random::<f64>() < state.config.audit_sample_rate;
+
+  let (request, body) = if sampled {
+    capture_body(request, &route, state.config.audit_max_body_bytes).await
+  } else {
+    (request, None)
+  };
+
+  let response = next.run(request).await;
+
+  tracing::info!(
+    target: "scrob::audit",
+    method = %method,
+    route = %route,
+    status = response.status().as_u16(),
+    latency_ms = started.elapsed().as_millis() as u64,
+    user_id = ?user_id,
+    body = body.as_deref().unwrap_or(""),
+    "request"
+  );
+
+  response
+}
+
+/// Buffer small JSON/form bodies so they can be logged, then hand them back
+async fn capture_body(request: Request, route: &str, max_bytes: usize) -> (Request, Option<String>) {
+  let content_type = request
+    .headers()
+    .get(header::CONTENT_TYPE)
+    .and_then(|h| h.to_str().ok())
+    .unwrap_or("")
+    .to_string();
+
+  let content_length = request
+    .headers()
+    .get(header::CONTENT_LENGTH)
+    .and_then(|h| h.to_str().ok())
+    .and_then(|v| v.parse::<usize>().ok());
+
+  let is_json = content_type.starts_with("application/json");
+  let is_form = content_type.starts_with("application/x-www-form-urlencoded");
+
+  // Never buffer large or unknown-length uploads just for logging
+  match content_length {
+    Some(0) | None => return (request, None),
+    Some(len) if len > max_bytes => return (request, Some(format!("[{} bytes omitted]", len))),
+    Some(_) if !is_json && !is_form => return (request, Some(format!("[{} body omitted]", content_type))),
+    Some(_) => {}
+  }
+
+  let (parts, body) = request.into_parts();
+  let bytes = match to_bytes(body, max_bytes).await {
+    Ok(bytes) => bytes,
+    Err(_) => return (Request::from_parts(parts, Body::empty()), Some("[unreadable body]".to_string())),
+  };
+
+  let logged = if is_json {
+    match serde_json::from_slice::<Value>(&bytes) {
+      Ok(mut value) => {
+        redact_json(route, &mut value);
+        value.to_string()
+      }
+      Err(_) => "[invalid JSON]".to_string(),
+    }
+  } else {
+    redact_form(route, &String::from_utf8_lossy(&bytes))
+  };
+
+  (Request::from_parts(parts, Body::from(bytes)), Some(logged))
+}
+
+fn is_redacted(route: &str, key: &str) -> bool {
+  let key = key.to_ascii_lowercase();
+  REDACTED_FIELDS.iter().any(|field| key == *field || key.ends_with(&format!("_{}", field)))
+    || REDACTED_ROUTE_FIELDS
+      .iter()
+      .any(|(prefix, fields)| route.starts_with(prefix) && fields.contains(&key.as_str()))
+}
+
+fn redact_json(route: &str, value: &mut Value) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        if is_redacted(route, key) {
+          *value = Value::String("[redacted]".to_string());
+        } else {
+          redact_json(route, value);
+        }
+      }
+    }
+    Value::Array(items) => items.iter_mut().for_each(|item| redact_json(route, item)),
+    _ => {}
+  }
+}
+
+fn redact_form(route: &str, body: &str) -> String {
+  body
+    .split('&')
+    .map(|pair| match pair.split_once('=') {
+      Some((key, _)) if is_redacted(route, key) => format!("{}=[redacted]", key),
+      _ => pair.to_string(),
+    })
+    .collect::<Vec<_>>()
+    .join("&")
+}
@@ -1,17 +1,67 @@
-use crate::db::{models::User, DbPool};
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc, Mutex, OnceLock,
+  },
+  time::Duration,
+};
+
+use crate::{config::Config, db::{self, models::{ApiToken, User}, DbPool}, health, lockout};
 use axum::http::{HeaderMap, StatusCode};
 
+/// What a token may be used for, stored in `api_tokens.scopes`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Submit scrobbles, now playing and loves
+    Scrobble,
+    /// Read history, stats and charts
+    Read,
+    /// Everything else: settings, tokens, the account itself
+    Admin,
+}
+
+impl Scope {
+    pub const ALL: [Scope; 3] = [Scope::Scrobble, Scope::Read, Scope::Admin];
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "scrobble" => Some(Scope::Scrobble),
+            "read" => Some(Scope::Read),
+            "admin" => Some(Scope::Admin),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scope::Scrobble => "scrobble",
+            Scope::Read => "read",
+            Scope::Admin => "admin",
+        }
+    }
+}
+
 /// Authenticated user
 #[derive(Debug, Clone)]
 pub struct AuthUser {
     pub id: i64,
+    #[allow(dead_code)]
     pub username: String,
     pub is_admin: bool,
+    #[allow(dead_code)]
     pub is_private: bool,
 }
 
 impl AuthUser {
+    /// Authenticate with a token that has the `admin` scope
     pub async fn from_headers(pool: &DbPool, headers: &HeaderMap) -> Result<Self, StatusCode> {
+        Self::from_headers_with_scope(pool, headers, Scope::Admin).await
+    }
+
+    /// Authenticate with a token that has `scope`. A valid token without it
+    /// gets 403 rather than 401, so clients don't think they were logged out.
+    pub async fn from_headers_with_scope(pool: &DbPool, headers: &HeaderMap, scope: Scope) -> Result<Self, StatusCode> {
         let auth_header = headers
             .get("authorization")
             .and_then(|h| h.to_str().ok())
@@ -19,10 +69,26 @@ impl AuthUser {
 
         let token = extract_token_from_header(auth_header).ok_or(StatusCode::UNAUTHORIZED)?;
 
-        let user = get_user_by_token(pool, &token)
+        let lookup = token_user(pool, &token, Some(&ClientInfo::from_headers(headers)))
             .await
-            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
-            .ok_or(StatusCode::UNAUTHORIZED)?;
+            .map_err(|e| {
+                if db::is_unavailable(&e) {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            })?;
+
+        // Banned users keep their tokens, which work again once the ban ends
+        let (user, scopes) = match lookup {
+            TokenLookup::Valid(user, scopes) => (user, scopes),
+            TokenLookup::Banned => return Err(StatusCode::FORBIDDEN),
+            TokenLookup::Invalid => return Err(StatusCode::UNAUTHORIZED),
+        };
+
+        if !scopes.iter().any(|s| s == scope.as_str()) {
+            return Err(StatusCode::FORBIDDEN);
+        }
 
         Ok(AuthUser {
             id: user.id,
@@ -33,61 +99,489 @@ impl AuthUser {
     }
 }
 
-/// Extract token from Authorization: Bearer <token> header
+/// Extract token from Authorization: Bearer <token> header. The
+/// `Token <token>` scheme used by ListenBrainz clients is accepted too.
 pub fn extract_token_from_header(auth_header: &str) -> Option<String> {
   auth_header
     .strip_prefix("Bearer ")
+    .or_else(|| auth_header.strip_prefix("Token "))
     .map(|t| t.trim().to_string())
 }
 
-/// Look up user by token
-pub async fn get_user_by_token(pool: &DbPool, token: &str) -> Result<Option<User>, sqlx::Error> {
+/// Longest user agent stored on a token
+const MAX_USER_AGENT_LEN: usize = 256;
+
+/// Where a request came from, recorded on the token it used
+#[derive(Debug, Clone, Default)]
+pub struct ClientInfo {
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+  /// The client address and user agent. `X-Real-IP` is set by
+  /// `net::resolve_client_ip` from the socket peer or a trusted proxy, never
+  /// taken from the client as sent.
+  pub fn from_headers(headers: &HeaderMap) -> Self {
+    let header = |name: &str| {
+      headers
+        .get(name)
+        .and_then(|h| h.to_str().ok())
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+    };
+
+    let ip = header("x-real-ip").map(str::to_string);
+    let user_agent = header("user-agent").map(|v| v.chars().take(MAX_USER_AGENT_LEN).collect());
+
+    ClientInfo { ip, user_agent }
+  }
+}
+
+/// Look up user by token, treating a token without `scope` (or a banned
+/// user's token) as invalid
+pub async fn get_user_by_token(pool: &DbPool, token: &str, scope: Scope) -> Result<Option<User>, sqlx::Error> {
+  Ok(match token_user(pool, token, None).await? {
+    TokenLookup::Valid(user, scopes) if scopes.iter().any(|s| s == scope.as_str()) => Some(*user),
+    _ => None,
+  })
+}
+
+/// Valid tokens cached for `TOKEN_CACHE_TTL`, so authenticated requests
+/// skip the database; unset in the CLI and when the TTL is 0.
+/// `invalidate_user` only reaches this process: a token revoked by the CLI
+/// (`reset-password`), another replica or direct SQL stays usable here until
+/// its entry expires, at most `TOKEN_CACHE_TTL` seconds later.
+static TOKEN_CACHE: OnceLock<TokenCache> = OnceLock::new();
+
+/// How often batched `last_used_at` updates are written
+const TOKEN_FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+const TOKEN_CACHE_CAPACITY: u64 = 10_000;
+
+struct TokenCache {
+  tokens: moka::sync::Cache<String, CachedToken>,
+  /// Bumped by every invalidation, so a lookup that raced one isn't cached
+  generation: AtomicU64,
+  /// Latest use of each token since the last flush
+  pending: Mutex<HashMap<String, TokenUse>>,
+}
+
+#[derive(Clone)]
+struct CachedToken {
+  id: i64,
+  user: Arc<User>,
+  scopes: Arc<Vec<String>>,
+  expires_at: Option<i64>,
+}
+
+struct TokenUse {
+  at: i64,
+  client: ClientInfo,
+}
+
+impl TokenCache {
+  fn record_use(&self, token: &str, at: i64, client: Option<&ClientInfo>) {
+    let client = client.cloned().unwrap_or_default();
+    let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+    let previous = pending.remove(token).map(|u| u.client).unwrap_or_default();
+
+    // Like the direct update, unknown details keep the previous ones
+    let client = ClientInfo {
+      ip: client.ip.or(previous.ip),
+      user_agent: client.user_agent.or(previous.user_agent),
+    };
+    pending.insert(token.to_string(), TokenUse { at, client });
+  }
+}
+
+/// Start caching token lookups for `ttl` and flushing `last_used_at` in
+/// batches. Without it every request reads and updates the token directly.
+pub fn spawn_token_cache(pool: DbPool, ttl: Duration) {
+  if ttl.is_zero() {
+    return;
+  }
+
+  let cache = TokenCache {
+    tokens: moka::sync::Cache::builder()
+      .max_capacity(TOKEN_CACHE_CAPACITY)
+      .time_to_live(ttl)
+      .support_invalidation_closures()
+      .build(),
+    generation: AtomicU64::new(0),
+    pending: Mutex::new(HashMap::new()),
+  };
+  if TOKEN_CACHE.set(cache).is_err() {
+    return;
+  }
+
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(TOKEN_FLUSH_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("token_flush", TOKEN_FLUSH_INTERVAL);
+      if let Err(e) = flush_token_use(&pool).await {
+        tracing::warn!("Failed to record token use: {}", e);
+      }
+    }
+  });
+}
+
+/// Drop cached lookups for a user's tokens. Call after revoking tokens or
+/// changing the user (ban, admin flag, settings, deletion).
+pub fn invalidate_user(user_id: i64) {
+  if let Some(cache) = TOKEN_CACHE.get() {
+    cache.generation.fetch_add(1, Ordering::SeqCst);
+    // Only fails when closures aren't supported, which the builder enables
+    let _ = cache.tokens.invalidate_entries_if(move |_, cached| cached.user.id == user_id);
+  }
+}
+
+/// Write the pending `last_used_at` updates in one statement
+async fn flush_token_use(pool: &DbPool) -> Result<(), sqlx::Error> {
+  let Some(cache) = TOKEN_CACHE.get() else {
+    return Ok(());
+  };
+
+  let batch = std::mem::take(&mut *cache.pending.lock().unwrap_or_else(|e| e.into_inner()));
+  if batch.is_empty() {
+    return Ok(());
+  }
+
+  let mut tokens = Vec::with_capacity(batch.len());
+  let mut used_at = Vec::with_capacity(batch.len());
+  let mut ips = Vec::with_capacity(batch.len());
+  let mut user_agents = Vec::with_capacity(batch.len());
+  for (token, token_use) in &batch {
+    tokens.push(token.clone());
+    used_at.push(token_use.at);
+    ips.push(token_use.client.ip.clone());
+    user_agents.push(token_use.client.user_agent.clone());
+  }
+
+  let result = sqlx::query!(
+    r#"
+    UPDATE api_tokens t
+    SET last_used_at = GREATEST(t.last_used_at, u.used_at),
+        last_ip = COALESCE(u.ip, t.last_ip),
+        last_user_agent = COALESCE(u.user_agent, t.last_user_agent)
+    FROM UNNEST($1::TEXT[], $2::BIGINT[], $3::TEXT[], $4::TEXT[]) AS u(token, used_at, ip, user_agent)
+    WHERE t.token = u.token
+    "#,
+    &tokens,
+    &used_at,
+    &ips as &[Option<String>],
+    &user_agents as &[Option<String>]
+  )
+  .execute(pool)
+  .await;
+
+  // Put the batch back for the next round, unless the token was used again
+  if let Err(e) = result {
+    let mut pending = cache.pending.lock().unwrap_or_else(|e| e.into_inner());
+    for (token, token_use) in batch {
+      pending.entry(token).or_insert(token_use);
+    }
+    return Err(e);
+  }
+
+  Ok(())
+}
+
+enum TokenLookup {
+  /// The token's user and its scopes
+  Valid(Box<User>, Vec<String>),
+  /// A working token whose user is banned
+  Banned,
+  Invalid,
+}
+
+/// Look up the user behind a token along with the token's scopes. `client`
+/// is recorded as where the token was last seen, when known.
+async fn token_user(pool: &DbPool, token: &str, client: Option<&ClientInfo>) -> Result<TokenLookup, sqlx::Error> {
   let now = chrono::Utc::now().timestamp();
 
-  // Find token and verify it's not revoked
+  let cache = TOKEN_CACHE.get();
+  let generation = cache.map(|c| c.generation.load(Ordering::SeqCst));
+  if let Some(cache) = cache {
+    if let Some(cached) = cache.tokens.get(token) {
+      if cached.expires_at.is_none_or(|expires_at| expires_at > now) {
+        cache.record_use(token, now, client);
+        return Ok(TokenLookup::Valid(Box::new((*cached.user).clone()), (*cached.scopes).clone()));
+      }
+      cache.tokens.invalidate(token);
+    }
+  }
+
+  // Find token and verify it's neither revoked nor expired
   let token_row = sqlx::query!(
     r#"
-    SELECT user_id as "user_id!"
-    FROM api_tokens
-    WHERE token = $1 AND revoked = false
+    SELECT t.id as "id!", t.user_id as "user_id!", t.scopes, t.expires_at,
+           (u.banned_at IS NOT NULL AND (u.banned_until IS NULL OR u.banned_until > $2)) as "banned!"
+    FROM api_tokens t
+    JOIN users u ON u.id = t.user_id
+    WHERE t.token = $1 AND t.revoked = false AND (t.expires_at IS NULL OR t.expires_at > $2)
     "#,
-    token
+    token,
+    now
   )
   .fetch_optional(pool)
   .await?;
 
-  let user_id = match token_row {
-    Some(row) => row.user_id,
-    None => return Ok(None),
+  let (token_id, user_id, scopes, expires_at) = match token_row {
+    Some(row) if row.banned => return Ok(TokenLookup::Banned),
+    Some(row) => (row.id, row.user_id, row.scopes, row.expires_at),
+    None => return Ok(TokenLookup::Invalid),
   };
 
-  // Update last_used_at
-  sqlx::query!(
+  // Update last_used_at, keeping the previous client details if these are unknown
+  if let Some(cache) = cache {
+    cache.record_use(token, now, client);
+  } else {
+    let client = client.cloned().unwrap_or_default();
+    sqlx::query!(
+      r#"
+      UPDATE api_tokens
+      SET last_used_at = $1,
+          last_ip = COALESCE($3, last_ip),
+          last_user_agent = COALESCE($4, last_user_agent)
+      WHERE token = $2
+      "#,
+      now,
+      token,
+      client.ip,
+      client.user_agent
+    )
+    .execute(pool)
+    .await?;
+  }
+
+  // Fetch user
+  let user = sqlx::query_as!(
+    User,
     r#"
-    UPDATE api_tokens
-    SET last_used_at = $1
-    WHERE token = $2
+    SELECT id as "id!", username, password_hash, is_admin as "is_admin: bool", is_private as "is_private: bool", created_at as "created_at!", anonymized_at, auto_promote_now_playing as "auto_promote_now_playing: bool", settings_version, settings_updated_at, banned_at, banned_until, ban_reason, email, email_verified_at
+    FROM users
+    WHERE id = $1
     "#,
-    now,
-    token
+    user_id
   )
-  .execute(pool)
+  .fetch_optional(pool)
   .await?;
 
-  // Fetch user
+  let Some(user) = user else {
+    return Ok(TokenLookup::Invalid);
+  };
+
+  if let (Some(cache), Some(generation)) = (cache, generation) {
+    if cache.generation.load(Ordering::SeqCst) == generation {
+      let cached = CachedToken {
+        id: token_id,
+        user: Arc::new(user.clone()),
+        scopes: Arc::new(scopes.clone()),
+        expires_at,
+      };
+      cache.tokens.insert(token.to_string(), cached);
+    }
+  }
+
+  Ok(TokenLookup::Valid(Box::new(user), scopes))
+}
+
+/// A ban in force on an account
+#[derive(Debug, Clone)]
+pub struct Ban {
+  pub reason: Option<String>,
+  /// None until an admin lifts it
+  pub until: Option<i64>,
+}
+
+impl Ban {
+  /// What to tell the banned user
+  pub fn message(&self) -> String {
+    let mut message = match self.until.and_then(|until| chrono::DateTime::from_timestamp(until, 0)) {
+      Some(until) => format!("This account is suspended until {}", until.format("%Y-%m-%d %H:%M UTC")),
+      None => "This account is suspended".to_string(),
+    };
+    if let Some(reason) = &self.reason {
+      message.push_str(": ");
+      message.push_str(reason);
+    }
+    message
+  }
+}
+
+/// The user's current ban, if any
+pub async fn active_ban(pool: &DbPool, user_id: i64) -> Result<Option<Ban>, sqlx::Error> {
+  let ban = sqlx::query_as!(
+    Ban,
+    r#"
+    SELECT ban_reason as reason, banned_until as until
+    FROM users
+    WHERE id = $1 AND banned_at IS NOT NULL AND (banned_until IS NULL OR banned_until > $2)
+    "#,
+    user_id,
+    chrono::Utc::now().timestamp()
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  Ok(ban)
+}
+
+/// Result of a password login
+#[derive(Debug)]
+pub enum LoginResult {
+  Success(User),
+  Invalid,
+  /// Right password, but the account is banned
+  Banned(Ban),
+  /// Too many recent failures; try again after this many seconds
+  Locked(i64),
+}
+
+/// Check a username and password. Every login path (REST, Last.fm mobile
+/// sessions) goes through here so they agree on what counts as a match and
+/// share the brute-force lockout. A hash made with older settings (or
+/// bcrypt) is replaced on success.
+pub async fn authenticate(
+  pool: &DbPool,
+  config: &Config,
+  username: &str,
+  password: &str,
+  ip: Option<&str>,
+) -> Result<LoginResult, sqlx::Error> {
+  // While locked the password isn't even checked, so guesses reveal nothing
+  if let Some(retry_after) = lockout::retry_after(pool, username, ip).await? {
+    return Ok(LoginResult::Locked(retry_after));
+  }
+
+  let user = match verify_login(pool, config, username, password).await? {
+    Some(user) => user,
+    None => {
+      lockout::record_failure(pool, username, ip).await?;
+      return Ok(LoginResult::Invalid);
+    }
+  };
+
+  lockout::record_success(pool, username).await?;
+
+  if let Some(ban) = active_ban(pool, user.id).await? {
+    return Ok(LoginResult::Banned(ban));
+  }
+
+  Ok(LoginResult::Success(user))
+}
+
+async fn verify_login(pool: &DbPool, config: &Config, username: &str, password: &str) -> Result<Option<User>, sqlx::Error> {
   let user = sqlx::query_as!(
     User,
     r#"
-    SELECT id as "id!", username, password_hash, is_admin as "is_admin: bool", is_private as "is_private: bool", created_at as "created_at!"
+    SELECT id as "id!", username, password_hash, is_admin as "is_admin: bool", is_private as "is_private: bool", created_at as "created_at!", anonymized_at, auto_promote_now_playing as "auto_promote_now_playing: bool", settings_version, settings_updated_at, banned_at, banned_until, ban_reason, email, email_verified_at
     FROM users
-    WHERE id = $1
+    WHERE username = $1
     "#,
-    user_id
+    username
   )
   .fetch_optional(pool)
   .await?;
 
-  Ok(user)
+  // Accounts created through SSO (and anonymized ones) have no password
+  let Some(user) = user.filter(|u| !u.password_hash.is_empty()) else {
+    return Ok(None);
+  };
+
+  match verify_password(password, &user.password_hash) {
+    Ok(true) => {}
+    Ok(false) => return Ok(None),
+    Err(e) => {
+      tracing::error!("Unreadable password hash for user {}: {}", user.id, e);
+      return Ok(None);
+    }
+  }
+
+  if needs_rehash(&user.password_hash, config) {
+    match hash_password(password, config) {
+      // Only replace the hash that was checked, in case the password just changed
+      Ok(new_hash) => {
+        sqlx::query!(
+          "UPDATE users SET password_hash = $1 WHERE id = $2 AND password_hash = $3",
+          new_hash,
+          user.id,
+          user.password_hash
+        )
+        .execute(pool)
+        .await?;
+        tracing::info!("Upgraded password hash for user {}", user.id);
+      }
+      Err(e) => tracing::error!("Failed to rehash password for user {}: {}", user.id, e),
+    }
+  }
+
+  Ok(Some(user))
+}
+
+/// Issue a new API token for the user. `label` records what it was issued
+/// for (`session`, `lastfm`, ...); `expires_at` of `None` never expires.
+/// Takes any executor so a token can be issued inside a transaction.
+pub async fn create_token(
+  db: impl sqlx::PgExecutor<'_>,
+  user_id: i64,
+  label: &str,
+  scopes: &[Scope],
+  expires_at: Option<i64>,
+) -> Result<ApiToken, sqlx::Error> {
+  let scopes: Vec<&str> = scopes.iter().map(|s| s.as_str()).collect();
+
+  sqlx::query_as!(
+    ApiToken,
+    r#"
+    INSERT INTO api_tokens (user_id, token, label, created_at, revoked, scopes, expires_at)
+    VALUES ($1, $2, $3, $4, false, $5, $6)
+    RETURNING id, user_id, token, label, created_at, last_used_at, revoked, scopes, expires_at
+    "#,
+    user_id,
+    generate_token(),
+    label,
+    chrono::Utc::now().timestamp(),
+    &scopes as &[&str],
+    expires_at
+  )
+  .fetch_one(db)
+  .await
+}
+
+/// A valid token's id and the user it belongs to
+#[derive(Debug, Clone, Copy)]
+pub struct TokenOwner {
+  pub token_id: i64,
+  pub user_id: i64,
+}
+
+/// Resolve a token without touching `last_used_at`, from the token cache
+/// when it holds the token. Banned users' tokens resolve too.
+pub async fn lookup_token(pool: &DbPool, token: &str) -> Result<Option<TokenOwner>, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  if let Some(cached) = TOKEN_CACHE.get().and_then(|cache| cache.tokens.get(token)) {
+    if cached.expires_at.is_none_or(|expires_at| expires_at > now) {
+      return Ok(Some(TokenOwner {
+        token_id: cached.id,
+        user_id: cached.user.id,
+      }));
+    }
+  }
+
+  sqlx::query_as!(
+    TokenOwner,
+    r#"
+    SELECT id as token_id, user_id as "user_id!"
+    FROM api_tokens
+    WHERE token = $1 AND revoked = false AND (expires_at IS NULL OR expires_at > $2)
+    "#,
+    token,
+    now
+  )
+  .fetch_optional(pool)
+  .await
 }
 
 /// Generate a random API token
@@ -106,12 +600,86 @@ pub fn generate_token() -> String {
   format!("{:x}{}", timestamp, hex::encode(&random_bytes))
 }
 
-/// Hash a password using bcrypt
-pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-  bcrypt::hash(password, bcrypt::DEFAULT_COST)
+fn argon2(config: &Config) -> Result<argon2::Argon2<'static>, argon2::password_hash::Error> {
+  let params = argon2::Params::new(config.argon2_memory_kib, config.argon2_iterations, 1, None)?;
+  Ok(argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params))
 }
 
-/// Verify a password against a hash
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, bcrypt::BcryptError> {
-  bcrypt::verify(password, hash)
+/// Legacy bcrypt hashes, from before Argon2id, start with `$2`
+fn is_bcrypt(hash: &str) -> bool {
+  hash.starts_with("$2")
+}
+
+/// Usernames are 3-20 letters, numbers and underscores
+pub fn check_username(username: &str) -> Result<(), &'static str> {
+  if username.len() < 3 || username.len() > 20 {
+    return Err("Username must be between 3 and 20 characters");
+  }
+
+  if !username.chars().all(|c| c.is_alphanumeric() || c == '_') {
+    return Err("Username can only contain letters, numbers, and underscores");
+  }
+
+  Ok(())
+}
+
+/// Length and complexity rules for new passwords (signup and reset)
+pub fn check_password(password: &str) -> Result<(), &'static str> {
+  if password.len() < 8 {
+    return Err("Password must be at least 8 characters");
+  }
+
+  if password.len() > 72 {
+    return Err("Password must be at most 72 characters");
+  }
+
+  let has_lowercase = password.chars().any(|c| c.is_lowercase());
+  let has_uppercase = password.chars().any(|c| c.is_uppercase());
+  let has_digit = password.chars().any(|c| c.is_numeric());
+
+  if !has_lowercase || !has_uppercase || !has_digit {
+    return Err("Password must contain at least one lowercase letter, one uppercase letter, and one number");
+  }
+
+  Ok(())
+}
+
+/// Hash a password with Argon2id using the configured cost
+pub fn hash_password(password: &str, config: &Config) -> Result<String, argon2::password_hash::Error> {
+  use argon2::password_hash::{PasswordHasher, SaltString};
+
+  let salt = SaltString::generate(&mut rand::rngs::OsRng);
+  Ok(argon2(config)?.hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Verify a password against an Argon2 or legacy bcrypt hash
+pub fn verify_password(password: &str, hash: &str) -> Result<bool, String> {
+  use argon2::password_hash::{PasswordHash, PasswordVerifier};
+
+  if is_bcrypt(hash) {
+    return bcrypt::verify(password, hash).map_err(|e| e.to_string());
+  }
+
+  let parsed = PasswordHash::new(hash).map_err(|e| e.to_string())?;
+  match argon2::Argon2::default().verify_password(password.as_bytes(), &parsed) {
+    Ok(()) => Ok(true),
+    Err(argon2::password_hash::Error::Password) => Ok(false),
+    Err(e) => Err(e.to_string()),
+  }
+}
+
+/// Whether a hash should be replaced: bcrypt, or Argon2 with other settings
+fn needs_rehash(hash: &str, config: &Config) -> bool {
+  if is_bcrypt(hash) {
+    return true;
+  }
+
+  let Ok(parsed) = argon2::password_hash::PasswordHash::new(hash) else {
+    return false;
+  };
+
+  parsed.algorithm != argon2::Algorithm::Argon2id.ident()
+    || argon2::Params::try_from(&parsed)
+      .map(|p| p.m_cost() != config.argon2_memory_kib || p.t_cost() != config.argon2_iterations)
+      .unwrap_or(true)
 }
@@ -0,0 +1,408 @@
+//! Precomputed top artists/tracks/albums for the rolling chart periods.
+//! Chart requests read through `chart_cache`; any change to a user's
+//! scrobbles marks their entries stale (migration 047) and the chart cache
+//! job recomputes them. A stale entry is still served for a few minutes, with
+//! headers saying so, so a busy scrobbler doesn't recompute on every request.
+
+use axum::{
+  http::{header, HeaderMap, HeaderValue},
+  Json,
+};
+use serde::de::DeserializeOwned;
+
+use crate::{
+  db::DbPool,
+  preferences,
+  routes::stats::{period_days, period_starts, TopAlbum, TopArtist, TopTrack},
+};
+
+/// Entries kept per chart; requests can't ask for more
+pub const CACHE_SIZE: i64 = 100;
+/// How long a stale entry is served while the job catches up
+const MAX_STALENESS: i64 = 5 * 60;
+/// A change landing while an entry is computed can be missed, so entries are
+/// recomputed after this long regardless
+const MAX_AGE: i64 = 60 * 60;
+/// `read_at` is only bumped this often, so reads rarely write
+const READ_RESOLUTION: i64 = 60 * 60;
+/// Entries nobody read for this long are dropped rather than refreshed
+const UNREAD_TTL: i64 = 7 * 86400;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChartKind {
+  Artists,
+  Tracks,
+  Albums,
+}
+
+impl ChartKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ChartKind::Artists => "artists",
+      ChartKind::Tracks => "tracks",
+      ChartKind::Albums => "albums",
+    }
+  }
+
+  fn parse(kind: &str) -> Option<Self> {
+    match kind {
+      "artists" => Some(ChartKind::Artists),
+      "tracks" => Some(ChartKind::Tracks),
+      "albums" => Some(ChartKind::Albums),
+      _ => None,
+    }
+  }
+}
+
+/// Where a chart came from, reported in `X-Chart-Cache`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+  /// Cached and up to date
+  Hit,
+  /// Cached, but scrobbles changed since; a refresh is pending
+  Stale,
+  /// Computed for this request and cached
+  Miss,
+  /// An explicit `from`/`to` range, which isn't cached
+  Bypass,
+}
+
+impl Source {
+  fn as_str(self) -> &'static str {
+    match self {
+      Source::Hit => "hit",
+      Source::Stale => "stale",
+      Source::Miss => "miss",
+      Source::Bypass => "bypass",
+    }
+  }
+}
+
+pub struct Chart<T> {
+  pub entries: Vec<T>,
+  pub source: Source,
+  pub computed_at: i64,
+}
+
+/// A chart body with `X-Chart-Cache`, `X-Chart-Computed-At` and `Age` headers
+pub type Cached<T> = (HeaderMap, Json<Vec<T>>);
+
+impl<T> Chart<T> {
+  /// When the cached entries were computed, so a refresh changes the ETag;
+  /// 0 for an uncached range
+  pub fn revision(&self) -> i64 {
+    match self.source {
+      Source::Bypass => 0,
+      _ => self.computed_at,
+    }
+  }
+
+  pub fn with_headers(self) -> Cached<T> {
+    let age = (chrono::Utc::now().timestamp() - self.computed_at).max(0);
+
+    let mut headers = HeaderMap::new();
+    headers.insert("x-chart-cache", HeaderValue::from_static(self.source.as_str()));
+    headers.insert("x-chart-computed-at", HeaderValue::from(self.computed_at));
+    headers.insert(header::AGE, HeaderValue::from(age));
+
+    (headers, Json(self.entries))
+  }
+}
+
+/// The top `limit` entries of a user's chart for `[from, to)`. Rolling
+/// periods (`period` set) read through the cache; explicit ranges are always
+/// computed.
+#[allow(clippy::too_many_arguments)]
+pub async fn top<T: DeserializeOwned>(
+  pool: &DbPool,
+  kind: ChartKind,
+  user_id: i64,
+  period: Option<&str>,
+  from: i64,
+  to: i64,
+  limit: i64,
+  default_duration: i64,
+) -> Result<Chart<T>, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  let Some(period) = period else {
+    let entries = compute(pool, kind, user_id, from, to, limit, default_duration).await?;
+    return Ok(Chart {
+      entries: decode(entries, limit)?,
+      source: Source::Bypass,
+      computed_at: now,
+    });
+  };
+
+  let cached = sqlx::query!(
+    r#"
+    SELECT range_from, entries, computed_at, read_at, stale_since
+    FROM chart_cache
+    WHERE user_id = $1 AND kind = $2 AND period = $3
+    "#,
+    user_id,
+    kind.as_str(),
+    period
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  if let Some(row) = cached {
+    let current = row.range_from == from && now - row.computed_at < MAX_AGE;
+    let source = match row.stale_since {
+      _ if !current => None,
+      None => Some(Source::Hit),
+      Some(since) if now - since < MAX_STALENESS => Some(Source::Stale),
+      Some(_) => None,
+    };
+
+    if let Some(source) = source {
+      if now - row.read_at >= READ_RESOLUTION {
+        sqlx::query!(
+          "UPDATE chart_cache SET read_at = $4 WHERE user_id = $1 AND kind = $2 AND period = $3",
+          user_id,
+          kind.as_str(),
+          period,
+          now
+        )
+        .execute(pool)
+        .await?;
+      }
+
+      return Ok(Chart {
+        entries: decode(row.entries, limit)?,
+        source,
+        computed_at: row.computed_at,
+      });
+    }
+  }
+
+  let entries = compute(pool, kind, user_id, from, to, CACHE_SIZE, default_duration).await?;
+
+  sqlx::query!(
+    r#"
+    INSERT INTO chart_cache (user_id, kind, period, range_from, entries, computed_at, read_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $6)
+    ON CONFLICT (user_id, kind, period) DO UPDATE
+    SET range_from = EXCLUDED.range_from,
+        entries = EXCLUDED.entries,
+        computed_at = EXCLUDED.computed_at,
+        read_at = EXCLUDED.read_at,
+        stale_since = NULL
+    "#,
+    user_id,
+    kind.as_str(),
+    period,
+    from,
+    entries,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(Chart {
+    entries: decode(entries, limit)?,
+    source: Source::Miss,
+    computed_at: now,
+  })
+}
+
+/// Recompute up to `batch` stale entries, longest stale first, after
+/// dropping ones nobody reads. Returns how many were refreshed.
+pub async fn refresh_stale(pool: &DbPool, batch: i64, default_duration: i64) -> Result<u64, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  sqlx::query!("DELETE FROM chart_cache WHERE read_at < $1", now - UNREAD_TTL)
+    .execute(pool)
+    .await?;
+
+  let stale = sqlx::query!(
+    r#"
+    SELECT user_id, kind, period, stale_since as "stale_since!"
+    FROM chart_cache
+    WHERE stale_since IS NOT NULL
+    ORDER BY stale_since
+    LIMIT $1
+    "#,
+    batch
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let mut refreshed = 0;
+  for row in stale {
+    // Clear the marker before computing, so scrobbles landing meanwhile
+    // mark the entry again instead of being lost
+    let claimed = sqlx::query!(
+      r#"
+      UPDATE chart_cache SET stale_since = NULL
+      WHERE user_id = $1 AND kind = $2 AND period = $3 AND stale_since = $4
+      "#,
+      row.user_id,
+      row.kind,
+      row.period,
+      row.stale_since
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    let Some(kind) = ChartKind::parse(&row.kind) else {
+      continue;
+    };
+    if claimed == 0 {
+      continue;
+    }
+    let Some(from) = period_start(pool, row.user_id, &row.period).await? else {
+      continue;
+    };
+
+    let entries = compute(pool, kind, row.user_id, from, i64::MAX, CACHE_SIZE, default_duration).await?;
+
+    sqlx::query!(
+      r#"
+      UPDATE chart_cache
+      SET range_from = $4, entries = $5, computed_at = $6
+      WHERE user_id = $1 AND kind = $2 AND period = $3
+      "#,
+      row.user_id,
+      row.kind,
+      row.period,
+      from,
+      entries,
+      chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+
+    refreshed += 1;
+  }
+
+  Ok(refreshed)
+}
+
+/// Mark every entry stale, for changes the scrobble triggers can't see such
+/// as catalogue renames
+pub async fn mark_all_stale(pool: &DbPool) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    "UPDATE chart_cache SET stale_since = $1 WHERE stale_since IS NULL",
+    chrono::Utc::now().timestamp()
+  )
+  .execute(pool)
+  .await?;
+  Ok(())
+}
+
+/// Current start of a rolling period in the user's timezone
+async fn period_start(pool: &DbPool, user_id: i64, period: &str) -> Result<Option<i64>, sqlx::Error> {
+  if period == "overall" {
+    return Ok(Some(0));
+  }
+  let Some(days) = period_days(period) else {
+    return Ok(None);
+  };
+
+  let preferences = preferences::load(pool, user_id).await?;
+  let (_, from) = period_starts(pool, &preferences.timezone, days).await?;
+  Ok(Some(from))
+}
+
+fn decode<T: DeserializeOwned>(entries: serde_json::Value, limit: i64) -> Result<Vec<T>, sqlx::Error> {
+  let mut entries: Vec<T> = serde_json::from_value(entries).map_err(|e| sqlx::Error::Decode(Box::new(e)))?;
+  entries.truncate(limit.max(0) as usize);
+  Ok(entries)
+}
+
+async fn compute(
+  pool: &DbPool,
+  kind: ChartKind,
+  user_id: i64,
+  from: i64,
+  to: i64,
+  limit: i64,
+  default_duration: i64,
+) -> Result<serde_json::Value, sqlx::Error> {
+  let entries = match kind {
+    ChartKind::Artists => serde_json::to_value(top_artists(pool, user_id, from, to, limit, default_duration).await?),
+    ChartKind::Tracks => serde_json::to_value(top_tracks(pool, user_id, from, to, limit).await?),
+    ChartKind::Albums => serde_json::to_value(top_albums(pool, user_id, from, to, limit).await?),
+  };
+  entries.map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+async fn top_artists(
+  pool: &DbPool,
+  user_id: i64,
+  from: i64,
+  to: i64,
+  limit: i64,
+  default_duration: i64,
+) -> Result<Vec<TopArtist>, sqlx::Error> {
+  sqlx::query_as!(
+    TopArtist,
+    r#"
+    SELECT
+        a.name,
+        COUNT(*) as "count!: i64",
+        SUM(COALESCE(s.duration, s.duration_estimated, $5))::BIGINT as "listening_time!"
+    FROM scrobs s
+    JOIN artists a ON a.id = s.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY a.id
+    ORDER BY COUNT(*) DESC
+    LIMIT $4
+    "#,
+    user_id,
+    from,
+    to,
+    limit,
+    default_duration
+  )
+  .fetch_all(pool)
+  .await
+}
+
+async fn top_tracks(pool: &DbPool, user_id: i64, from: i64, to: i64, limit: i64) -> Result<Vec<TopTrack>, sqlx::Error> {
+  sqlx::query_as!(
+    TopTrack,
+    r#"
+    SELECT a.name as "artist!", t.name as "track!", COUNT(*) as "count!: i64"
+    FROM scrobs s
+    JOIN tracks t ON t.id = s.track_id
+    JOIN artists a ON a.id = t.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY t.id, a.id
+    ORDER BY COUNT(*) DESC
+    LIMIT $4
+    "#,
+    user_id,
+    from,
+    to,
+    limit
+  )
+  .fetch_all(pool)
+  .await
+}
+
+/// Keyed by name + album artist, as described on `routes::stats::top_albums`
+async fn top_albums(pool: &DbPool, user_id: i64, from: i64, to: i64, limit: i64) -> Result<Vec<TopAlbum>, sqlx::Error> {
+  sqlx::query_as!(
+    TopAlbum,
+    r#"
+    SELECT al.name as "album!", a.name as "artist!", COUNT(*) as "count!: i64"
+    FROM scrobs s
+    JOIN albums al ON al.id = s.album_id
+    JOIN artists a ON a.id = al.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY al.id, a.id
+    ORDER BY COUNT(*) DESC, al.name
+    LIMIT $4
+    "#,
+    user_id,
+    from,
+    to,
+    limit
+  )
+  .fetch_all(pool)
+  .await
+}
@@ -0,0 +1,255 @@
+//! Command line. `scrob` (or `scrob serve`) runs the server; the other
+//! subcommands bootstrap and repair an install without going through HTTP.
+
+use std::{
+  io::{BufRead, IsTerminal},
+  path::PathBuf,
+};
+
+use clap::{Parser, Subcommand};
+
+use crate::{
+  auth::{check_password, check_username, hash_password},
+  config::Config,
+  db::{self, DbPool, MIGRATOR},
+  import::{self, ImportFormat},
+  lockout,
+  policy::ContentPolicy,
+};
+
+#[derive(Debug, Parser)]
+#[command(name = "scrob", version, about = "Self-hosted scrobble server")]
+pub struct Cli {
+  /// TOML config file; environment variables override it
+  #[arg(long, global = true, env = "SCROB_CONFIG", value_name = "PATH")]
+  pub config: Option<String>,
+
+  /// Validate the configuration and exit
+  #[arg(long, global = true)]
+  pub check_config: bool,
+
+  #[command(subcommand)]
+  pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+  /// Run the HTTP server (the default)
+  Serve,
+  /// Apply pending database migrations and exit
+  Migrate,
+  /// Create an admin account; the password is prompted for, or read from stdin
+  CreateAdmin { username: String },
+  /// Set a new password, sign out the user's sessions and clear login lockouts
+  ResetPassword { username: String },
+  /// Import an exported listening history for a user and wait for it
+  Import {
+    file: PathBuf,
+    /// Account the listens are imported into
+    #[arg(long)]
+    user: String,
+    #[arg(long, value_parser = ["lastfm", "listenbrainz", "spotify"])]
+    format: String,
+  },
+}
+
+/// Run an administration command; `Serve` is handled by main
+pub async fn run(command: Command, config: &Config) -> Result<(), String> {
+  let pool = db::create_pool(&config.database_url)
+    .await
+    .map_err(|e| format!("Failed to connect to the database: {}", e))?;
+
+  match command {
+    Command::Serve => unreachable!("serve is handled by main"),
+    Command::Migrate => {
+      let latest = MIGRATOR.iter().map(|m| m.version).max().unwrap_or_default();
+      println!("Database schema is up to date (migration {})", latest);
+      Ok(())
+    }
+    Command::CreateAdmin { username } => create_admin(&pool, config, &username).await,
+    Command::ResetPassword { username } => reset_password(&pool, config, &username).await,
+    Command::Import { file, user, format } => import_file(&pool, config, file, &user, &format).await,
+  }
+}
+
+async fn create_admin(pool: &DbPool, config: &Config, username: &str) -> Result<(), String> {
+  check_username(username)?;
+
+  let exists = sqlx::query_scalar!(
+    r#"SELECT EXISTS(SELECT 1 FROM users WHERE username = $1) as "exists!""#,
+    username
+  )
+  .fetch_one(pool)
+  .await
+  .map_err(db_error)?;
+
+  if exists {
+    return Err(format!("User '{}' already exists", username));
+  }
+
+  let password = read_password()?;
+  check_password(&password)?;
+  let password_hash = hash_password(&password, config).map_err(|e| format!("Password hashing error: {}", e))?;
+
+  let now = chrono::Utc::now().timestamp();
+  let policy = ContentPolicy::load(pool).await.map_err(db_error)?;
+
+  let id = sqlx::query_scalar!(
+    r#"
+    INSERT INTO users (username, password_hash, is_admin, is_private, created_at)
+    VALUES ($1, $2, true, $3, $4)
+    RETURNING id as "id!"
+    "#,
+    username,
+    password_hash,
+    !policy.allows_public(now, now),
+    now
+  )
+  .fetch_one(pool)
+  .await
+  .map_err(db_error)?;
+
+  println!("Created admin '{}' (id {}); log in with POST /login", username, id);
+  Ok(())
+}
+
+async fn reset_password(pool: &DbPool, config: &Config, username: &str) -> Result<(), String> {
+  let user_id = sqlx::query_scalar!(
+    "SELECT id FROM users WHERE username = $1 AND anonymized_at IS NULL",
+    username
+  )
+  .fetch_optional(pool)
+  .await
+  .map_err(db_error)?
+  .ok_or_else(|| format!("No user named '{}'", username))?;
+
+  let password = read_password()?;
+  check_password(&password)?;
+  let password_hash = hash_password(&password, config).map_err(|e| format!("Password hashing error: {}", e))?;
+
+  let mut tx = pool.begin().await.map_err(db_error)?;
+
+  sqlx::query!("UPDATE users SET password_hash = $1 WHERE id = $2", password_hash, user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+  sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1 AND kind = 'reset'", user_id)
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+  let revoked = sqlx::query!(
+    "UPDATE api_tokens SET revoked = true WHERE user_id = $1 AND label = 'session' AND revoked = false",
+    user_id
+  )
+  .execute(&mut *tx)
+  .await
+  .map_err(db_error)?
+  .rows_affected();
+
+  tx.commit().await.map_err(db_error)?;
+
+  lockout::record_success(pool, username).await.map_err(db_error)?;
+
+  println!("Password for '{}' reset; {} session(s) signed out", username, revoked);
+  // Running servers only see the revocation once their cached lookups expire
+  if revoked > 0 && config.token_cache_ttl > 0 {
+    println!(
+      "Running servers may accept those sessions for up to {}s more (TOKEN_CACHE_TTL)",
+      config.token_cache_ttl
+    );
+  }
+  Ok(())
+}
+
+async fn import_file(pool: &DbPool, config: &Config, file: PathBuf, username: &str, format: &str) -> Result<(), String> {
+  let format = ImportFormat::parse(format).ok_or_else(|| format!("Unsupported format '{}'", format))?;
+
+  let user_id = sqlx::query_scalar!(
+    "SELECT id FROM users WHERE username = $1 AND anonymized_at IS NULL",
+    username
+  )
+  .fetch_optional(pool)
+  .await
+  .map_err(db_error)?
+  .ok_or_else(|| format!("No user named '{}'", username))?;
+
+  // The worker deletes the upload when it's done, so work on a copy
+  let dir = PathBuf::from(&config.import_dir);
+  tokio::fs::create_dir_all(&dir)
+    .await
+    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+  let file_name = format!("{}-{}.upload", user_id, crate::auth::generate_token());
+  let bytes = tokio::fs::copy(&file, import::upload_path(&dir, &file_name))
+    .await
+    .map_err(|e| format!("Failed to copy {}: {}", file.display(), e))?;
+
+  let job_id = sqlx::query_scalar!(
+    r#"
+    INSERT INTO import_jobs (user_id, format, file_name, bytes, created_at)
+    VALUES ($1, $2, $3, $4, $5)
+    RETURNING id
+    "#,
+    user_id,
+    format.as_str(),
+    file_name,
+    bytes as i64,
+    chrono::Utc::now().timestamp()
+  )
+  .fetch_one(pool)
+  .await
+  .map_err(db_error)?;
+
+  if !import::run_job(pool, &dir, job_id).await.map_err(db_error)? {
+    println!("Import {} was picked up by a running server; follow it with GET /import/{}", job_id, job_id);
+    return Ok(());
+  }
+
+  let job = sqlx::query!(
+    r#"
+    SELECT status, processed, imported, duplicates, skipped, rejected, error
+    FROM import_jobs
+    WHERE id = $1
+    "#,
+    job_id
+  )
+  .fetch_one(pool)
+  .await
+  .map_err(db_error)?;
+
+  println!(
+    "Import {} {}: {} processed, {} imported, {} duplicates, {} skipped, {} rejected",
+    job_id, job.status, job.processed, job.imported, job.duplicates, job.skipped, job.rejected
+  );
+
+  match job.error {
+    Some(error) => Err(error),
+    None => Ok(()),
+  }
+}
+
+/// Prompt twice on a terminal; otherwise take the first line of stdin
+fn read_password() -> Result<String, String> {
+  let stdin = std::io::stdin();
+
+  if stdin.is_terminal() {
+    let password = rpassword::prompt_password("Password: ").map_err(|e| e.to_string())?;
+    let repeated = rpassword::prompt_password("Repeat password: ").map_err(|e| e.to_string())?;
+    if password != repeated {
+      return Err("Passwords don't match".to_string());
+    }
+    return Ok(password);
+  }
+
+  let mut line = String::new();
+  stdin
+    .lock()
+    .read_line(&mut line)
+    .map_err(|e| format!("Failed to read password: {}", e))?;
+  Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn db_error(e: sqlx::Error) -> String {
+  format!("Database error: {}", e)
+}
@@ -1,29 +1,521 @@
-use std::env;
+use std::{collections::HashMap, env};
+
+use crate::net::{self, IpNet};
+
+/// Every setting, by environment variable name. In the config file
+/// `[smtp] host = ".."` and `smtp_host = ".."` both set `SMTP_HOST`.
+const SETTINGS: &[&str] = &[
+  "DATABASE_URL", "PORT", "HOST", "SECRET_KEY", "AUDIT_LOG",
+  "AUDIT_SAMPLE_RATE", "AUDIT_MAX_BODY_BYTES", "HEAVY_CONCURRENCY_LIMIT",
+  "LOAD_SHED_RETRY_AFTER", "RATE_LIMIT", "RATE_LIMIT_WINDOW",
+  "SCROBBLE_RATE_LIMIT", "SCROBBLE_RATE_LIMIT_WINDOW", "SCROBBLE_MAX_BATCH",
+  "SCROBBLE_MAX_BODY_BYTES", "SCROB_RETENTION_DAYS", "DURATION_LOOKUP",
+  "METADATA_LOOKUP", "MPD_WATCHER", "MPD_HOST", "MPD_PORT", "MPD_PASSWORD",
+  "MPD_USER", "DEFAULT_TRACK_DURATION", "MUSICBRAINZ_URL", "PUBLIC_OVERVIEW",
+  "INSTANCE_NAME", "INSTANCE_DESCRIPTION", "LASTFM_API_KEY",
+  "LASTFM_API_SECRET", "LASTFM_RELAY_API_KEY", "LASTFM_RELAY_API_SECRET",
+  "STORAGE_BACKEND", "STORAGE_PATH", "S3_ENDPOINT", "S3_BUCKET", "S3_REGION",
+  "S3_ACCESS_KEY", "S3_SECRET_KEY", "SPOOL_DIR", "SPOOL_MAX_ENTRIES",
+  "IMPORT_DIR", "IMPORT_MAX_BYTES", "OIDC_ISSUER_URL", "OIDC_CLIENT_ID",
+  "OIDC_CLIENT_SECRET", "OIDC_REDIRECT_URL", "SPOTIFY_CLIENT_ID",
+  "SPOTIFY_CLIENT_SECRET", "SPOTIFY_REDIRECT_URL", "ARGON2_MEMORY_KIB",
+  "ARGON2_ITERATIONS", "SMTP_HOST", "SMTP_TLS", "SMTP_PORT", "SMTP_USERNAME",
+  "SMTP_PASSWORD", "SMTP_FROM", "TLS_CERT_PATH", "TLS_KEY_PATH",
+  "TOKEN_CACHE_TTL", "LEGACY_ROUTES", "TRUSTED_PROXIES",
+  "ALLOWED_PRIVATE_NETWORKS",
+];
 
 #[derive(Debug, Clone)]
 pub struct Config {
   pub database_url: String,
   pub port: u16,
   pub host: String,
+  pub secret_key: Option<String>,
+  pub audit_log: bool,
+  pub audit_sample_rate: f64,
+  pub audit_max_body_bytes: usize,
+  pub heavy_concurrency_limit: usize,
+  pub load_shed_retry_after: u64,
+  pub rate_limit: u32,
+  pub rate_limit_window: u64,
+  pub scrobble_rate_limit: u32,
+  pub scrobble_rate_limit_window: u64,
+  pub scrobble_max_batch: usize,
+  pub scrobble_max_body_bytes: usize,
+  /// Delete scrobbles played longer ago than this; none keeps them forever
+  pub retention_days: Option<u32>,
+  pub duration_lookup: bool,
+  /// Fill in MusicBrainz ids and names for scrobbles sent without them
+  pub metadata_lookup: bool,
+  /// Seconds counted for scrobbles without a known duration in listening
+  /// time totals; 0 leaves them out
+  pub default_track_duration: i64,
+  pub musicbrainz_url: String,
+  /// Let users have scrob watch their own MPD server
+  pub mpd_watcher: bool,
+  /// A server-wide MPD instance watched for `mpd_user`
+  pub mpd_host: Option<String>,
+  pub mpd_port: u16,
+  pub mpd_password: Option<String>,
+  pub mpd_user: Option<String>,
+  pub public_overview: bool,
+  pub instance_name: String,
+  pub instance_description: Option<String>,
+  pub lastfm_api_key: Option<String>,
+  pub lastfm_api_secret: Option<String>,
+  /// Key pair scrob signs with when relaying scrobbles to Last.fm
+  pub lastfm_relay_api_key: Option<String>,
+  pub lastfm_relay_api_secret: Option<String>,
+  pub storage_backend: String,
+  pub storage_path: String,
+  pub s3_endpoint: Option<String>,
+  pub s3_bucket: Option<String>,
+  pub s3_region: String,
+  pub s3_access_key: Option<String>,
+  pub s3_secret_key: Option<String>,
+  pub spool_dir: String,
+  pub spool_max_entries: usize,
+  pub import_dir: String,
+  pub import_max_bytes: u64,
+  pub oidc_issuer_url: Option<String>,
+  pub oidc_client_id: Option<String>,
+  pub oidc_client_secret: Option<String>,
+  pub oidc_redirect_url: Option<String>,
+  /// Spotify app used to link accounts for recently-played polling
+  pub spotify_client_id: Option<String>,
+  pub spotify_client_secret: Option<String>,
+  pub spotify_redirect_url: Option<String>,
+  pub argon2_memory_kib: u32,
+  pub argon2_iterations: u32,
+  /// Seconds a token lookup is cached; 0 reads the token on every request.
+  /// Also how long a revocation made outside a server process (the CLI,
+  /// another replica) can take to reach it.
+  pub token_cache_ttl: u64,
+  /// Also serve the REST API at its pre-`/api/v1` paths
+  pub legacy_routes: bool,
+  /// Reverse proxies whose `X-Forwarded-For`/`X-Real-IP` are believed
+  pub trusted_proxies: Vec<IpNet>,
+  /// Private ranges that relays and account moves may still reach
+  pub allowed_private_networks: Vec<IpNet>,
+  /// SMTP relay for verification and password reset mail
+  pub smtp_host: Option<String>,
+  pub smtp_port: u16,
+  /// `starttls` (default), `tls` or `none`
+  pub smtp_tls: String,
+  pub smtp_username: Option<String>,
+  pub smtp_password: Option<String>,
+  pub smtp_from: Option<String>,
+  /// PEM certificate chain and private key; when set, HTTPS is served directly
+  pub tls_cert_path: Option<String>,
+  pub tls_key_path: Option<String>,
 }
 
 impl Config {
-  pub fn from_env() -> Result<Self, String> {
-    let database_url = env::var("DATABASE_URL")
+  /// Settings from the environment, falling back to the TOML file at
+  /// `file` for anything the environment doesn't set
+  pub fn load(file: Option<&str>) -> Result<Self, String> {
+    let vars = Vars {
+      file: match file {
+        Some(path) => read_file(path)?,
+        None => HashMap::new(),
+      },
+    };
+
+    let database_url = vars.var("DATABASE_URL")
       .unwrap_or_else(|_| "postgres://localhost/scrob".to_string());
 
-    let port = env::var("PORT")
+    let port = vars.var("PORT")
       .unwrap_or_else(|_| "3000".to_string())
       .parse()
       .map_err(|e| format!("Invalid PORT: {}", e))?;
 
-    let host = env::var("HOST")
+    let host = vars.var("HOST")
       .unwrap_or_else(|_| "127.0.0.1".to_string());
 
+    let secret_key = vars.var("SECRET_KEY")
+      .ok()
+      .filter(|k| !k.is_empty());
+
+    let audit_log = vars.var("AUDIT_LOG")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(false);
+
+    let audit_sample_rate: f64 = vars.var("AUDIT_SAMPLE_RATE")
+      .unwrap_or_else(|_| "0.0".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid AUDIT_SAMPLE_RATE: {}", e))?;
+
+    if !(0.0..=1.0).contains(&audit_sample_rate) {
+      return Err("AUDIT_SAMPLE_RATE must be between 0.0 and 1.0".to_string());
+    }
+
+    let audit_max_body_bytes = vars.var("AUDIT_MAX_BODY_BYTES")
+      .unwrap_or_else(|_| "4096".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid AUDIT_MAX_BODY_BYTES: {}", e))?;
+
+    let heavy_concurrency_limit = vars.var("HEAVY_CONCURRENCY_LIMIT")
+      .unwrap_or_else(|_| "4".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid HEAVY_CONCURRENCY_LIMIT: {}", e))?;
+
+    if heavy_concurrency_limit == 0 {
+      return Err("HEAVY_CONCURRENCY_LIMIT must be at least 1".to_string());
+    }
+
+    let load_shed_retry_after = vars.var("LOAD_SHED_RETRY_AFTER")
+      .unwrap_or_else(|_| "5".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid LOAD_SHED_RETRY_AFTER: {}", e))?;
+
+    let rate_limit = vars.var("RATE_LIMIT")
+      .unwrap_or_else(|_| "600".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid RATE_LIMIT: {}", e))?;
+
+    let rate_limit_window = vars.var("RATE_LIMIT_WINDOW")
+      .unwrap_or_else(|_| "60".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid RATE_LIMIT_WINDOW: {}", e))?;
+
+    if rate_limit_window == 0 {
+      return Err("RATE_LIMIT_WINDOW must be at least 1 second".to_string());
+    }
+
+    let scrobble_rate_limit = vars.var("SCROBBLE_RATE_LIMIT")
+      .unwrap_or_else(|_| "120".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SCROBBLE_RATE_LIMIT: {}", e))?;
+
+    let scrobble_rate_limit_window = vars.var("SCROBBLE_RATE_LIMIT_WINDOW")
+      .unwrap_or_else(|_| "60".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SCROBBLE_RATE_LIMIT_WINDOW: {}", e))?;
+
+    if scrobble_rate_limit_window == 0 {
+      return Err("SCROBBLE_RATE_LIMIT_WINDOW must be at least 1 second".to_string());
+    }
+
+    let scrobble_max_batch = vars.var("SCROBBLE_MAX_BATCH")
+      .unwrap_or_else(|_| "50".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SCROBBLE_MAX_BATCH: {}", e))?;
+
+    if scrobble_max_batch == 0 {
+      return Err("SCROBBLE_MAX_BATCH must be at least 1".to_string());
+    }
+
+    let scrobble_max_body_bytes = vars.var("SCROBBLE_MAX_BODY_BYTES")
+      .unwrap_or_else(|_| "1048576".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SCROBBLE_MAX_BODY_BYTES: {}", e))?;
+
+    let retention_days = match vars.var("SCROB_RETENTION_DAYS").ok().filter(|v| !v.is_empty()) {
+      Some(days) => Some(
+        days
+          .parse()
+          .map_err(|e| format!("Invalid SCROB_RETENTION_DAYS: {}", e))?,
+      ),
+      None => None,
+    };
+
+    if retention_days == Some(0) {
+      return Err("SCROB_RETENTION_DAYS must be at least 1; leave it unset to keep scrobbles".to_string());
+    }
+
+    let duration_lookup = vars.var("DURATION_LOOKUP")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(false);
+
+    let metadata_lookup = vars.var("METADATA_LOOKUP")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(false);
+
+    let mpd_watcher = vars.var("MPD_WATCHER")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(false);
+
+    let mpd_host = vars.var("MPD_HOST")
+      .ok()
+      .filter(|h| !h.is_empty());
+
+    let mpd_port = vars.var("MPD_PORT")
+      .unwrap_or_else(|_| "6600".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid MPD_PORT: {}", e))?;
+
+    let mpd_password = vars.var("MPD_PASSWORD")
+      .ok()
+      .filter(|p| !p.is_empty());
+
+    let mpd_user = vars.var("MPD_USER")
+      .ok()
+      .filter(|u| !u.is_empty());
+
+    if mpd_host.is_some() != mpd_user.is_some() {
+      return Err("MPD_HOST and MPD_USER must be set together".to_string());
+    }
+
+    let default_track_duration: i64 = vars.var("DEFAULT_TRACK_DURATION")
+      .unwrap_or_else(|_| "210".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid DEFAULT_TRACK_DURATION: {}", e))?;
+
+    if default_track_duration < 0 {
+      return Err("DEFAULT_TRACK_DURATION must not be negative".to_string());
+    }
+
+    let musicbrainz_url = vars.var("MUSICBRAINZ_URL")
+      .unwrap_or_else(|_| "https://musicbrainz.org".to_string());
+
+    let public_overview = vars.var("PUBLIC_OVERVIEW")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(false);
+
+    let instance_name = vars.var("INSTANCE_NAME")
+      .unwrap_or_else(|_| "scrob".to_string());
+
+    let instance_description = vars.var("INSTANCE_DESCRIPTION")
+      .ok()
+      .filter(|d| !d.is_empty());
+
+    let lastfm_api_key = vars.var("LASTFM_API_KEY")
+      .ok()
+      .filter(|k| !k.is_empty());
+
+    let lastfm_api_secret = vars.var("LASTFM_API_SECRET")
+      .ok()
+      .filter(|s| !s.is_empty());
+
+    let lastfm_relay_api_key = vars.var("LASTFM_RELAY_API_KEY")
+      .ok()
+      .filter(|k| !k.is_empty());
+
+    let lastfm_relay_api_secret = vars.var("LASTFM_RELAY_API_SECRET")
+      .ok()
+      .filter(|s| !s.is_empty());
+
+    let storage_backend = vars.var("STORAGE_BACKEND")
+      .unwrap_or_else(|_| "local".to_string());
+
+    let storage_path = vars.var("STORAGE_PATH")
+      .unwrap_or_else(|_| "./data/assets".to_string());
+
+    let s3_endpoint = vars.var("S3_ENDPOINT")
+      .ok()
+      .filter(|e| !e.is_empty());
+
+    let s3_bucket = vars.var("S3_BUCKET")
+      .ok()
+      .filter(|b| !b.is_empty());
+
+    let s3_region = vars.var("S3_REGION")
+      .unwrap_or_else(|_| "us-east-1".to_string());
+
+    let s3_access_key = vars.var("S3_ACCESS_KEY")
+      .ok()
+      .filter(|k| !k.is_empty());
+
+    let s3_secret_key = vars.var("S3_SECRET_KEY")
+      .ok()
+      .filter(|k| !k.is_empty());
+
+    let spool_dir = vars.var("SPOOL_DIR")
+      .unwrap_or_else(|_| "./data/spool".to_string());
+
+    let spool_max_entries = vars.var("SPOOL_MAX_ENTRIES")
+      .unwrap_or_else(|_| "10000".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SPOOL_MAX_ENTRIES: {}", e))?;
+
+    let import_dir = vars.var("IMPORT_DIR")
+      .unwrap_or_else(|_| "./data/imports".to_string());
+
+    let import_max_bytes = vars.var("IMPORT_MAX_BYTES")
+      .unwrap_or_else(|_| "1073741824".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid IMPORT_MAX_BYTES: {}", e))?;
+
+    let oidc_issuer_url = vars.var("OIDC_ISSUER_URL")
+      .ok()
+      .filter(|u| !u.is_empty());
+
+    let oidc_client_id = vars.var("OIDC_CLIENT_ID")
+      .ok()
+      .filter(|i| !i.is_empty());
+
+    let oidc_client_secret = vars.var("OIDC_CLIENT_SECRET")
+      .ok()
+      .filter(|s| !s.is_empty());
+
+    let oidc_redirect_url = vars.var("OIDC_REDIRECT_URL")
+      .ok()
+      .filter(|u| !u.is_empty());
+
+    if oidc_issuer_url.is_some() && (oidc_client_id.is_none() || oidc_redirect_url.is_none()) {
+      return Err("OIDC_ISSUER_URL requires OIDC_CLIENT_ID and OIDC_REDIRECT_URL".to_string());
+    }
+
+    let spotify_client_id = vars.var("SPOTIFY_CLIENT_ID")
+      .ok()
+      .filter(|i| !i.is_empty());
+
+    let spotify_client_secret = vars.var("SPOTIFY_CLIENT_SECRET")
+      .ok()
+      .filter(|s| !s.is_empty());
+
+    let spotify_redirect_url = vars.var("SPOTIFY_REDIRECT_URL")
+      .ok()
+      .filter(|u| !u.is_empty());
+
+    if spotify_client_id.is_some() && (spotify_client_secret.is_none() || spotify_redirect_url.is_none()) {
+      return Err("SPOTIFY_CLIENT_ID requires SPOTIFY_CLIENT_SECRET and SPOTIFY_REDIRECT_URL".to_string());
+    }
+
+    let argon2_memory_kib = vars.var("ARGON2_MEMORY_KIB")
+      .unwrap_or_else(|_| "19456".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid ARGON2_MEMORY_KIB: {}", e))?;
+
+    let argon2_iterations = vars.var("ARGON2_ITERATIONS")
+      .unwrap_or_else(|_| "2".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid ARGON2_ITERATIONS: {}", e))?;
+
+    argon2::Params::new(argon2_memory_kib, argon2_iterations, 1, None)
+      .map_err(|e| format!("Invalid Argon2 settings: {}", e))?;
+
+    let token_cache_ttl = vars.var("TOKEN_CACHE_TTL")
+      .unwrap_or_else(|_| "30".to_string())
+      .parse()
+      .map_err(|e| format!("Invalid TOKEN_CACHE_TTL: {}", e))?;
+
+    let legacy_routes = vars.var("LEGACY_ROUTES")
+      .map(|v| v == "true" || v == "1")
+      .unwrap_or(true);
+
+    let trusted_proxies = net::parse_nets(&vars.var("TRUSTED_PROXIES").unwrap_or_default())
+      .map_err(|e| format!("Invalid TRUSTED_PROXIES: {}", e))?;
+
+    let allowed_private_networks = net::parse_nets(&vars.var("ALLOWED_PRIVATE_NETWORKS").unwrap_or_default())
+      .map_err(|e| format!("Invalid ALLOWED_PRIVATE_NETWORKS: {}", e))?;
+
+    let smtp_host = vars.var("SMTP_HOST")
+      .ok()
+      .filter(|h| !h.is_empty());
+
+    let smtp_tls = vars.var("SMTP_TLS")
+      .unwrap_or_else(|_| "starttls".to_string())
+      .to_lowercase();
+
+    let default_smtp_port = match smtp_tls.as_str() {
+      "starttls" => "587",
+      "tls" => "465",
+      "none" => "25",
+      other => return Err(format!("Invalid SMTP_TLS '{}' (expected starttls, tls or none)", other)),
+    };
+
+    // Empty counts as unset so compose files can pass it through
+    let smtp_port = vars.var("SMTP_PORT")
+      .ok()
+      .filter(|p| !p.is_empty())
+      .unwrap_or_else(|| default_smtp_port.to_string())
+      .parse()
+      .map_err(|e| format!("Invalid SMTP_PORT: {}", e))?;
+
+    let smtp_username = vars.var("SMTP_USERNAME")
+      .ok()
+      .filter(|u| !u.is_empty());
+
+    let smtp_password = vars.var("SMTP_PASSWORD")
+      .ok()
+      .filter(|p| !p.is_empty());
+
+    let smtp_from = vars.var("SMTP_FROM")
+      .ok()
+      .filter(|f| !f.is_empty());
+
+    if smtp_host.is_some() && smtp_from.is_none() {
+      return Err("SMTP_HOST requires SMTP_FROM".to_string());
+    }
+
+    let tls_cert_path = vars.var("TLS_CERT_PATH")
+      .ok()
+      .filter(|p| !p.is_empty());
+
+    let tls_key_path = vars.var("TLS_KEY_PATH")
+      .ok()
+      .filter(|p| !p.is_empty());
+
+    if tls_cert_path.is_some() != tls_key_path.is_some() {
+      return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string());
+    }
+
     Ok(Self {
       database_url,
       port,
       host,
+      secret_key,
+      audit_log,
+      audit_sample_rate,
+      audit_max_body_bytes,
+      heavy_concurrency_limit,
+      load_shed_retry_after,
+      rate_limit,
+      rate_limit_window,
+      scrobble_rate_limit,
+      scrobble_rate_limit_window,
+      scrobble_max_batch,
+      scrobble_max_body_bytes,
+      retention_days,
+      duration_lookup,
+      metadata_lookup,
+      default_track_duration,
+      musicbrainz_url,
+      mpd_watcher,
+      mpd_host,
+      mpd_port,
+      mpd_password,
+      mpd_user,
+      public_overview,
+      instance_name,
+      instance_description,
+      lastfm_api_key,
+      lastfm_api_secret,
+      lastfm_relay_api_key,
+      lastfm_relay_api_secret,
+      storage_backend,
+      storage_path,
+      s3_endpoint,
+      s3_bucket,
+      s3_region,
+      s3_access_key,
+      s3_secret_key,
+      spool_dir,
+      spool_max_entries,
+      import_dir,
+      import_max_bytes,
+      oidc_issuer_url,
+      oidc_client_id,
+      oidc_client_secret,
+      oidc_redirect_url,
+      spotify_client_id,
+      spotify_client_secret,
+      spotify_redirect_url,
+      argon2_memory_kib,
+      argon2_iterations,
+      token_cache_ttl,
+      legacy_routes,
+      trusted_proxies,
+      allowed_private_networks,
+      smtp_host,
+      smtp_port,
+      smtp_tls,
+      smtp_username,
+      smtp_password,
+      smtp_from,
+      tls_cert_path,
+      tls_key_path,
     })
   }
 
@@ -31,3 +523,76 @@ impl Config {
     format!("{}:{}", self.host, self.port)
   }
 }
+
+/// Looks settings up in the environment first, so env overrides the file
+struct Vars {
+  file: HashMap<String, String>,
+}
+
+impl Vars {
+  fn var(&self, key: &str) -> Result<String, env::VarError> {
+    match env::var(key) {
+      Err(env::VarError::NotPresent) => self.file.get(key).cloned().ok_or(env::VarError::NotPresent),
+      found => found,
+    }
+  }
+}
+
+/// Read a TOML config file into settings keyed by environment variable name
+fn read_file(path: &str) -> Result<HashMap<String, String>, String> {
+  let text = std::fs::read_to_string(path)
+    .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+
+  let table: toml::Table = text
+    .parse()
+    .map_err(|e| format!("Invalid config file {}: {}", path, e))?;
+
+  let mut settings = HashMap::new();
+  flatten(path, None, &table, &mut settings)?;
+  Ok(settings)
+}
+
+fn flatten(
+  path: &str,
+  section: Option<&str>,
+  table: &toml::Table,
+  settings: &mut HashMap<String, String>,
+) -> Result<(), String> {
+  for (key, value) in table {
+    let dotted = match section {
+      Some(section) => format!("{}.{}", section, key),
+      None => key.clone(),
+    };
+    let name = dotted.replace(['.', '-'], "_").to_uppercase();
+
+    let value = match value {
+      toml::Value::Table(inner) => {
+        flatten(path, Some(&dotted), inner, settings)?;
+        continue;
+      }
+      toml::Value::String(s) => s.clone(),
+      toml::Value::Integer(i) => i.to_string(),
+      toml::Value::Float(f) => f.to_string(),
+      toml::Value::Boolean(b) => b.to_string(),
+      other => {
+        return Err(format!(
+          "Invalid config file {}: '{}' must be a string, number or boolean, not {}",
+          path,
+          dotted,
+          other.type_str()
+        ))
+      }
+    };
+
+    if !SETTINGS.contains(&name.as_str()) {
+      return Err(format!(
+        "Invalid config file {}: unknown setting '{}' (read as {})",
+        path, dotted, name
+      ));
+    }
+
+    settings.insert(name, value);
+  }
+
+  Ok(())
+}
@@ -0,0 +1,51 @@
+use aes_gcm::{
+  aead::{Aead, AeadCore, KeyInit, OsRng},
+  Aes256Gcm, Nonce,
+};
+use sha2::{Digest, Sha256};
+
+const NONCE_LEN: usize = 12;
+
+/// Encrypts secrets (relay tokens, session keys) before they are stored
+pub struct SecretBox {
+  cipher: Aes256Gcm,
+}
+
+impl SecretBox {
+  /// Derive the encryption key from the configured `SECRET_KEY`
+  pub fn new(secret_key: &str) -> Self {
+    let key = Sha256::digest(secret_key.as_bytes());
+    Self {
+      cipher: Aes256Gcm::new(&key),
+    }
+  }
+
+  /// Encrypt a secret, returning hex encoded nonce + ciphertext
+  pub fn encrypt(&self, plaintext: &str) -> Result<String, String> {
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = self
+      .cipher
+      .encrypt(&nonce, plaintext.as_bytes())
+      .map_err(|e| format!("Encryption failed: {}", e))?;
+
+    let mut out = nonce.to_vec();
+    out.extend_from_slice(&ciphertext);
+    Ok(hex::encode(out))
+  }
+
+  /// Decrypt a secret produced by `encrypt`
+  pub fn decrypt(&self, encoded: &str) -> Result<String, String> {
+    let bytes = hex::decode(encoded).map_err(|_| "Secret is not valid hex".to_string())?;
+    if bytes.len() <= NONCE_LEN {
+      return Err("Secret is too short".to_string());
+    }
+
+    let (nonce, ciphertext) = bytes.split_at(NONCE_LEN);
+    let plaintext = self
+      .cipher
+      .decrypt(Nonce::from_slice(nonce), ciphertext)
+      .map_err(|_| "Failed to decrypt secret (was SECRET_KEY changed?)".to_string())?;
+
+    String::from_utf8(plaintext).map_err(|_| "Secret is not valid UTF-8".to_string())
+  }
+}
@@ -1,17 +1,44 @@
 pub mod models;
 
-use sqlx::postgres::PgPool;
+use std::time::Duration;
+
+use sqlx::{
+  migrate::Migrator,
+  postgres::{PgPool, PgPoolOptions},
+};
 
 pub type DbPool = PgPool;
 
+/// Migrations embedded at build time; /health/ready compares them with the
+/// database
+pub static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Fail fast while the database is unreachable instead of holding requests
+/// for sqlx's default 30 seconds
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(10);
+
 pub async fn create_pool(database_url: &str) -> Result<DbPool, sqlx::Error> {
-  let pool = PgPool::connect(database_url).await?;
+  let pool = PgPoolOptions::new()
+    .acquire_timeout(ACQUIRE_TIMEOUT)
+    .connect(database_url)
+    .await?;
 
   tracing::info!("Running migrations...");
-  sqlx::migrate!("./migrations")
-    .run(&pool)
-    .await?;
+  MIGRATOR.run(&pool).await?;
 
   tracing::info!("Database ready");
   Ok(pool)
 }
+
+/// True for errors caused by the database being unreachable (restart,
+/// network, exhausted pool) rather than by the query itself
+pub fn is_unavailable(e: &sqlx::Error) -> bool {
+  match e {
+    sqlx::Error::Io(_) | sqlx::Error::Tls(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => true,
+    // Class 08 is connection exceptions, 57P0x is shutdown / not yet accepting connections
+    sqlx::Error::Database(db) => db
+      .code()
+      .is_some_and(|code| code.starts_with("08") || code.starts_with("57P0")),
+    _ => false,
+  }
+}
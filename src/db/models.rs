@@ -1,3 +1,6 @@
+// Row types mirror the schema, so not every column is read by every caller
+#![allow(dead_code)]
+
 use sqlx::FromRow;
 
 #[derive(Debug, Clone, FromRow)]
@@ -8,6 +11,15 @@ pub struct User {
   pub is_admin: bool,
   pub is_private: bool,
   pub created_at: i64,
+  pub anonymized_at: Option<i64>,
+  pub auto_promote_now_playing: bool,
+  pub settings_version: i64,
+  pub settings_updated_at: Option<i64>,
+  pub banned_at: Option<i64>,
+  pub banned_until: Option<i64>,
+  pub ban_reason: Option<String>,
+  pub email: Option<String>,
+  pub email_verified_at: Option<i64>,
 }
 
 #[derive(Debug, Clone, FromRow)]
@@ -19,6 +31,8 @@ pub struct ApiToken {
   pub created_at: i64,
   pub last_used_at: Option<i64>,
   pub revoked: bool,
+  pub scopes: Vec<String>,
+  pub expires_at: Option<i64>,
 }
 
 #[derive(Debug, Clone, FromRow)]
@@ -31,6 +45,39 @@ pub struct Scrob {
   pub duration: Option<i64>,
   pub timestamp: i64,
   pub created_at: i64,
+  pub duration_estimated: Option<i64>,
+  pub album_artist: Option<String>,
+  pub track_number: Option<i32>,
+  pub artist_id: i64,
+  pub track_id: i64,
+  pub album_id: Option<i64>,
+  pub artist_mbid: Option<String>,
+  pub release_mbid: Option<String>,
+  pub recording_mbid: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Artist {
+  pub id: i64,
+  pub name: String,
+  pub mbid: Option<String>,
+}
+
+/// Keyed by album artist, or the track artist when none was submitted
+#[derive(Debug, Clone, FromRow)]
+pub struct Album {
+  pub id: i64,
+  pub artist_id: i64,
+  pub name: String,
+  pub mbid: Option<String>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct Track {
+  pub id: i64,
+  pub artist_id: i64,
+  pub name: String,
+  pub mbid: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -45,3 +92,10 @@ pub struct TopTrack {
   pub track: String,
   pub count: i64,
 }
+
+#[derive(Debug, Clone)]
+pub struct TopAlbum {
+  pub album: String,
+  pub artist: String,
+  pub count: i64,
+}
@@ -0,0 +1,391 @@
+//! Discord webhook notifier. Users who set a webhook under /settings/discord
+//! get embeds posted to their channel for yesterday's listening, new
+//! scrobble milestones and last week's top artist, each in their timezone.
+
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use chrono::NaiveDate;
+use serde_json::{json, Value};
+
+use crate::{crypto::SecretBox, db::DbPool, goals, health, preferences, routes::activity::MILESTONES};
+
+const INTERVAL: Duration = Duration::from_secs(15 * 60);
+const EMBED_COLOR: u32 = 0x5865f2;
+
+/// Webhook URLs scrob posts to; anything else is refused so the notifier
+/// can't be pointed at arbitrary hosts
+const WEBHOOK_PREFIXES: [&str; 4] = [
+  "https://discord.com/api/webhooks/",
+  "https://discordapp.com/api/webhooks/",
+  "https://canary.discord.com/api/webhooks/",
+  "https://ptb.discord.com/api/webhooks/",
+];
+
+pub fn is_webhook_url(url: &str) -> bool {
+  !url.contains(char::is_whitespace)
+    && WEBHOOK_PREFIXES
+      .iter()
+      .any(|prefix| url.strip_prefix(prefix).is_some_and(|rest| !rest.is_empty()))
+}
+
+pub fn spawn(pool: DbPool, secrets: Option<Arc<SecretBox>>) {
+  tokio::spawn(async move {
+    let client = reqwest::Client::builder()
+      .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION")))
+      .timeout(Duration::from_secs(10))
+      .build()
+      .expect("failed to build Discord HTTP client");
+
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("discord", INTERVAL);
+      if let Err(e) = notify(&pool, &client, secrets.as_deref()).await {
+        tracing::error!("Discord notifications failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Yesterday and last week in one timezone, with the local midnights of
+/// yesterday, today, last week's Monday and this week's Monday
+#[derive(Debug, Clone, Copy)]
+struct Days {
+  yesterday: NaiveDate,
+  last_week: NaiveDate,
+  bounds: [i64; 4],
+}
+
+async fn local_days(pool: &DbPool, timezone: &str) -> Result<Days, sqlx::Error> {
+  let today = preferences::local_today(pool, timezone).await?;
+  let yesterday = today - chrono::Duration::days(1);
+  let (week_start, _) = goals::period_dates("week", today);
+  let last_week = week_start - chrono::Duration::days(7);
+  let bounds = preferences::local_midnights(pool, timezone, &[yesterday, today, last_week, week_start]).await?;
+  Ok(Days {
+    yesterday,
+    last_week,
+    bounds: [bounds[0], bounds[1], bounds[2], bounds[3]],
+  })
+}
+
+/// Post whatever is due for every enabled webhook. What was posted is only
+/// marked once Discord accepts it, so failed posts are retried next round.
+pub async fn notify(pool: &DbPool, client: &reqwest::Client, secrets: Option<&SecretBox>) -> Result<(), sqlx::Error> {
+  let webhooks = sqlx::query!(
+    r#"
+    SELECT w.user_id, w.url, w.daily_summary, w.milestones, w.weekly_top_artist,
+           w.last_daily_summary, w.last_weekly_top_artist, w.last_milestone,
+           COALESCE(s.display_name, u.username) as "name!",
+           COALESCE(s.timezone, 'UTC') as "timezone!"
+    FROM discord_webhooks w
+    JOIN users u ON u.id = w.user_id
+    LEFT JOIN user_settings s ON s.user_id = w.user_id
+    WHERE w.enabled
+      AND NOT (u.banned_at IS NOT NULL AND (u.banned_until IS NULL OR u.banned_until > $1))
+    "#,
+    chrono::Utc::now().timestamp()
+  )
+  .fetch_all(pool)
+  .await?;
+
+  if webhooks.is_empty() {
+    return Ok(());
+  }
+
+  let Some(secrets) = secrets else {
+    tracing::warn!("SECRET_KEY is not configured, can't decrypt Discord webhooks");
+    return Ok(());
+  };
+
+  let mut days: HashMap<String, Days> = HashMap::new();
+
+  for webhook in webhooks {
+    let url = match secrets.decrypt(&webhook.url) {
+      Ok(url) => url,
+      Err(e) => {
+        tracing::warn!("Can't decrypt Discord webhook for user {}: {}", webhook.user_id, e);
+        continue;
+      }
+    };
+
+    let local = match days.get(&webhook.timezone) {
+      Some(&local) => local,
+      None => {
+        let local = local_days(pool, &webhook.timezone).await?;
+        days.insert(webhook.timezone.clone(), local);
+        local
+      }
+    };
+    let [yesterday, today, last_week, this_week] = local.bounds;
+
+    let mut embeds = Vec::new();
+
+    let daily_due = webhook.daily_summary && webhook.last_daily_summary != Some(yesterday);
+    if daily_due {
+      embeds.extend(daily_summary(pool, webhook.user_id, &webhook.name, local.yesterday, yesterday, today).await?);
+    }
+
+    let weekly_due = webhook.weekly_top_artist && webhook.last_weekly_top_artist != Some(last_week);
+    if weekly_due {
+      embeds.extend(weekly_top_artist(pool, webhook.user_id, &webhook.name, local.last_week, last_week, this_week).await?);
+    }
+
+    // Milestones passed before the webhook was set up aren't announced, and
+    // only the highest is when several are crossed at once (an import)
+    let reached = reached_milestone(pool, webhook.user_id).await?;
+    let milestone_due = webhook.last_milestone.is_none_or(|last| reached > last);
+    if milestone_due && webhook.milestones && webhook.last_milestone.is_some() {
+      embeds.extend(milestone(pool, webhook.user_id, &webhook.name, reached).await?);
+    }
+
+    if !daily_due && !weekly_due && !milestone_due {
+      continue;
+    }
+
+    let posting = !embeds.is_empty();
+    if posting {
+      if let Err((status, e)) = post(client, &url, &embeds).await {
+        tracing::warn!("Discord webhook for user {}: {}", webhook.user_id, e);
+        // Discord answers 401/404 once a webhook is deleted; stop posting
+        let gone = matches!(status, Some(401 | 404));
+        sqlx::query!(
+          "UPDATE discord_webhooks SET last_error = $1, enabled = enabled AND NOT $2 WHERE user_id = $3",
+          e,
+          gone,
+          webhook.user_id
+        )
+        .execute(pool)
+        .await?;
+        continue;
+      }
+    }
+
+    sqlx::query!(
+      r#"
+      UPDATE discord_webhooks
+      SET last_daily_summary = CASE WHEN $2 THEN $3 ELSE last_daily_summary END,
+          last_weekly_top_artist = CASE WHEN $4 THEN $5 ELSE last_weekly_top_artist END,
+          last_milestone = CASE WHEN $6 THEN $7 ELSE last_milestone END,
+          last_success_at = CASE WHEN $8 THEN $9 ELSE last_success_at END,
+          last_error = CASE WHEN $8 THEN NULL ELSE last_error END
+      WHERE user_id = $1
+      "#,
+      webhook.user_id,
+      daily_due,
+      yesterday,
+      weekly_due,
+      last_week,
+      milestone_due,
+      reached,
+      posting,
+      chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+
+    if posting {
+      tracing::info!("Posted {} Discord embed(s) for user {}", embeds.len(), webhook.user_id);
+    }
+  }
+
+  Ok(())
+}
+
+async fn post(client: &reqwest::Client, url: &str, embeds: &[Value]) -> Result<(), (Option<u16>, String)> {
+  let response = client
+    .post(url)
+    .json(&json!({ "username": "scrob", "embeds": embeds }))
+    .send()
+    .await
+    .map_err(|e| (None, format!("Request failed: {}", e)))?;
+
+  let status = response.status();
+  if status.is_success() {
+    return Ok(());
+  }
+
+  let body = response.text().await.unwrap_or_default();
+  Err((
+    Some(status.as_u16()),
+    format!("HTTP {}: {}", status.as_u16(), body.chars().take(200).collect::<String>()),
+  ))
+}
+
+fn embed(title: String, description: String, fields: Vec<Value>) -> Value {
+  json!({
+    "title": title,
+    "description": description,
+    "color": EMBED_COLOR,
+    "fields": fields,
+    "footer": { "text": "scrob" },
+  })
+}
+
+fn field(name: &str, value: String) -> Value {
+  json!({ "name": name, "value": value, "inline": true })
+}
+
+/// Scrobbles, artists, top artist and top track of one day; nothing when
+/// nothing was played
+async fn daily_summary(
+  pool: &DbPool,
+  user_id: i64,
+  name: &str,
+  date: NaiveDate,
+  start: i64,
+  end: i64,
+) -> Result<Option<Value>, sqlx::Error> {
+  let totals = sqlx::query!(
+    r#"
+    SELECT COUNT(*) as "scrobbles!", COUNT(DISTINCT artist_id) as "artists!"
+    FROM scrobs
+    WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+    "#,
+    user_id,
+    start,
+    end
+  )
+  .fetch_one(pool)
+  .await?;
+
+  if totals.scrobbles == 0 {
+    return Ok(None);
+  }
+
+  let top_artist = sqlx::query!(
+    r#"
+    SELECT a.name, COUNT(*) as "count!"
+    FROM scrobs s
+    JOIN artists a ON a.id = s.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY a.id
+    ORDER BY COUNT(*) DESC, a.name
+    LIMIT 1
+    "#,
+    user_id,
+    start,
+    end
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  let top_track = sqlx::query!(
+    r#"
+    SELECT a.name as artist, t.name as track, COUNT(*) as "count!"
+    FROM scrobs s
+    JOIN tracks t ON t.id = s.track_id
+    JOIN artists a ON a.id = t.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY a.id, t.id
+    ORDER BY COUNT(*) DESC, a.name, t.name
+    LIMIT 1
+    "#,
+    user_id,
+    start,
+    end
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  let mut fields = Vec::new();
+  if let Some(artist) = top_artist {
+    fields.push(field("Top artist", format!("{} ({})", artist.name, artist.count)));
+  }
+  if let Some(track) = top_track {
+    fields.push(field("Top track", format!("{} – {} ({})", track.artist, track.track, track.count)));
+  }
+
+  Ok(Some(embed(
+    format!("{}'s listening yesterday", name),
+    format!(
+      "**{}** scrobbles of **{}** artists on {}",
+      totals.scrobbles,
+      totals.artists,
+      date.format("%A %-d %B")
+    ),
+    fields,
+  )))
+}
+
+async fn weekly_top_artist(
+  pool: &DbPool,
+  user_id: i64,
+  name: &str,
+  week: NaiveDate,
+  start: i64,
+  end: i64,
+) -> Result<Option<Value>, sqlx::Error> {
+  let artist = sqlx::query!(
+    r#"
+    SELECT a.name, COUNT(*) as "count!", COUNT(DISTINCT s.track_id) as "tracks!"
+    FROM scrobs s
+    JOIN artists a ON a.id = s.artist_id
+    WHERE s.user_id = $1 AND s.timestamp >= $2 AND s.timestamp < $3
+    GROUP BY a.id
+    ORDER BY COUNT(*) DESC, a.name
+    LIMIT 1
+    "#,
+    user_id,
+    start,
+    end
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  Ok(artist.map(|artist| {
+    embed(
+      format!("{}'s top artist last week", name),
+      format!("**{}** in the week of {}", artist.name, week.format("%-d %B")),
+      vec![
+        field("Scrobbles", artist.count.to_string()),
+        field("Tracks", artist.tracks.to_string()),
+      ],
+    )
+  }))
+}
+
+/// Highest milestone count the user's scrobbles have reached, 0 for none
+async fn reached_milestone(pool: &DbPool, user_id: i64) -> Result<i64, sqlx::Error> {
+  let total = sqlx::query_scalar!(
+    r#"SELECT COUNT(*) as "count!" FROM scrobs WHERE user_id = $1"#,
+    user_id
+  )
+  .fetch_one(pool)
+  .await?;
+
+  Ok(MILESTONES.iter().rev().copied().find(|&count| count <= total).unwrap_or(0))
+}
+
+/// The scrobble that reached milestone `count`, as /stats/milestones counts
+async fn milestone(pool: &DbPool, user_id: i64, name: &str, count: i64) -> Result<Option<Value>, sqlx::Error> {
+  if count == 0 {
+    return Ok(None);
+  }
+
+  let scrobble = sqlx::query!(
+    r#"
+    SELECT artist as "artist!", track as "track!", timestamp as "timestamp!"
+    FROM scrobs
+    WHERE user_id = $1
+    ORDER BY timestamp, id
+    OFFSET $2
+    LIMIT 1
+    "#,
+    user_id,
+    count - 1
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  Ok(scrobble.map(|scrobble| {
+    embed(
+      format!("{} reached {} scrobbles", name, count),
+      format!(
+        "Scrobble #{} was **{}** – {} on <t:{}:f>",
+        count, scrobble.artist, scrobble.track, scrobble.timestamp
+      ),
+      Vec::new(),
+    )
+  }))
+}
@@ -0,0 +1,110 @@
+//! Live listen events for `GET /feed/live`. Writers publish with
+//! `pg_notify`, so scrobbles stored by any instance (or any ingestion path)
+//! reach every subscriber; one listener per process fans them out.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use sqlx::postgres::PgListener;
+use tokio::sync::broadcast;
+
+use crate::{db::DbPool, relay::Listen};
+
+const CHANNEL: &str = "scrob_feed";
+/// Events buffered per subscriber before a slow client starts missing some
+const BUFFER: usize = 256;
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedEventKind {
+  Scrobble,
+  NowPlaying,
+}
+
+impl FeedEventKind {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      FeedEventKind::Scrobble => "scrobble",
+      FeedEventKind::NowPlaying => "now_playing",
+    }
+  }
+}
+
+/// A new scrobble or now-playing report for one user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedEvent {
+  pub user_id: i64,
+  pub kind: FeedEventKind,
+  /// Scrobble id; absent for now-playing reports
+  pub id: Option<i64>,
+  pub listen: Listen,
+}
+
+/// Announce an event to live feed subscribers
+pub async fn publish(
+  pool: &DbPool,
+  user_id: i64,
+  kind: FeedEventKind,
+  id: Option<i64>,
+  listen: &Listen,
+) -> Result<(), sqlx::Error> {
+  let event = FeedEvent {
+    user_id,
+    kind,
+    id,
+    listen: listen.clone(),
+  };
+  let payload = serde_json::to_string(&event).map_err(|e| sqlx::Error::Encode(Box::new(e)))?;
+
+  sqlx::query!("SELECT pg_notify($1, $2)", CHANNEL, payload)
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
+
+/// In-process fan-out of feed events
+#[derive(Clone)]
+pub struct Feed {
+  sender: broadcast::Sender<FeedEvent>,
+}
+
+impl Feed {
+  pub fn subscribe(&self) -> broadcast::Receiver<FeedEvent> {
+    self.sender.subscribe()
+  }
+}
+
+/// Start listening for feed notifications
+pub fn spawn_listener(pool: DbPool) -> Feed {
+  let (sender, _) = broadcast::channel(BUFFER);
+  let feed = Feed { sender: sender.clone() };
+
+  tokio::spawn(async move {
+    loop {
+      if let Err(e) = listen(&pool, &sender).await {
+        tracing::warn!("Live feed listener disconnected: {}", e);
+      }
+      tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+  });
+
+  feed
+}
+
+async fn listen(pool: &DbPool, sender: &broadcast::Sender<FeedEvent>) -> Result<(), sqlx::Error> {
+  let mut listener = PgListener::connect_with(pool).await?;
+  listener.listen(CHANNEL).await?;
+
+  loop {
+    let notification = listener.recv().await?;
+    match serde_json::from_str::<FeedEvent>(notification.payload()) {
+      // Sending only fails when nobody is subscribed
+      Ok(event) => {
+        let _ = sender.send(event);
+      }
+      Err(e) => tracing::error!("Ignoring malformed feed event: {}", e),
+    }
+  }
+}
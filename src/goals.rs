@@ -0,0 +1,146 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+
+use crate::{db::DbPool, preferences};
+
+pub const METRICS: [&str; 5] = ["scrobbles", "artists", "new_artists", "tracks", "listening_hours"];
+pub const PERIODS: [&str; 3] = ["week", "month", "year"];
+
+/// First day and the day after the last of the calendar period containing
+/// `today`. Weeks start on Monday.
+pub fn period_dates(period: &str, today: NaiveDate) -> (NaiveDate, NaiveDate) {
+  match period {
+    "week" => {
+      let start = today - Duration::days(i64::from(today.weekday().num_days_from_monday()));
+      (start, start + Duration::days(7))
+    }
+    "month" => {
+      let start = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
+      let end = if today.month() == 12 {
+        NaiveDate::from_ymd_opt(today.year() + 1, 1, 1)
+      } else {
+        NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1)
+      };
+      (start, end.unwrap())
+    }
+    _ => (
+      NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap(),
+      NaiveDate::from_ymd_opt(today.year() + 1, 1, 1).unwrap(),
+    ),
+  }
+}
+
+/// Start and end (exclusive) of the calendar period containing `now`, in UTC
+pub fn period_bounds(period: &str, now: DateTime<Utc>) -> (i64, i64) {
+  let (start, end) = period_dates(period, now.date_naive());
+  let timestamp = |date: NaiveDate| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp();
+  (timestamp(start), timestamp(end))
+}
+
+/// Start and end (exclusive) of the current calendar period in `timezone`
+pub async fn local_period_bounds(pool: &DbPool, timezone: &str, period: &str) -> Result<(i64, i64), sqlx::Error> {
+  let today = preferences::local_today(pool, timezone).await?;
+  let (start, end) = period_dates(period, today);
+  let bounds = preferences::local_midnights(pool, timezone, &[start, end]).await?;
+  Ok((bounds[0], bounds[1]))
+}
+
+/// Progress towards a goal's metric between `start` and `end`
+pub async fn progress(
+  pool: &DbPool,
+  user_id: i64,
+  metric: &str,
+  start: i64,
+  end: i64,
+) -> Result<i64, sqlx::Error> {
+  match metric {
+    "scrobbles" => {
+      sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        "#,
+        user_id,
+        start,
+        end
+      )
+      .fetch_one(pool)
+      .await
+    }
+    "artists" => {
+      sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT artist_id) as "count!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        "#,
+        user_id,
+        start,
+        end
+      )
+      .fetch_one(pool)
+      .await
+    }
+    "tracks" => {
+      sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT track_id) as "count!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        "#,
+        user_id,
+        start,
+        end
+      )
+      .fetch_one(pool)
+      .await
+    }
+    // Artists first listened to during the period
+    "new_artists" => {
+      sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM (
+            SELECT artist_id
+            FROM scrobs
+            WHERE user_id = $1
+            GROUP BY artist_id
+            HAVING MIN(timestamp) >= $2 AND MIN(timestamp) < $3
+        ) first_listens
+        "#,
+        user_id,
+        start,
+        end
+      )
+      .fetch_one(pool)
+      .await
+    }
+    // Scrobbles without a known duration don't count
+    _ => {
+      sqlx::query_scalar!(
+        r#"
+        SELECT (COALESCE(SUM(COALESCE(duration, duration_estimated)), 0) / 3600)::BIGINT as "hours!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        "#,
+        user_id,
+        start,
+        end
+      )
+      .fetch_one(pool)
+      .await
+    }
+  }
+}
+
+/// Human-readable description used in notifications
+pub fn describe(metric: &str, target: i64, period: &str) -> String {
+  let what = match metric {
+    "scrobbles" => "scrobbles",
+    "artists" => "artists",
+    "new_artists" => "new artists",
+    "tracks" => "tracks",
+    _ => "hours of listening",
+  };
+  format!("{} {} this {}", target, what, period)
+}
@@ -0,0 +1,55 @@
+//! Heartbeats of the periodic background workers, for GET /health/ready.
+//! Each worker beats every round; one that misses several rounds in a row
+//! is reported as stalled.
+
+use std::{collections::BTreeMap, sync::Mutex, time::Duration};
+
+/// Rounds a worker may miss before it counts as stalled
+const MISSED_ROUNDS: u32 = 3;
+/// Extra allowance for rounds that take a while (imports, MusicBrainz lookups)
+const GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+struct Beat {
+  interval: Duration,
+  last: i64,
+}
+
+static WORKERS: Mutex<BTreeMap<&'static str, Beat>> = Mutex::new(BTreeMap::new());
+
+/// Record that worker `name`, which runs every `interval`, started a round
+pub fn beat(name: &'static str, interval: Duration) {
+  let beat = Beat {
+    interval,
+    last: chrono::Utc::now().timestamp(),
+  };
+  WORKERS.lock().unwrap_or_else(|e| e.into_inner()).insert(name, beat);
+}
+
+#[derive(Debug, Clone)]
+pub struct WorkerStatus {
+  pub name: &'static str,
+  pub interval_secs: u64,
+  pub last_beat: i64,
+  pub stalled: bool,
+}
+
+/// Every worker that has started, by name
+pub fn workers() -> Vec<WorkerStatus> {
+  let now = chrono::Utc::now().timestamp();
+
+  WORKERS
+    .lock()
+    .unwrap_or_else(|e| e.into_inner())
+    .iter()
+    .map(|(&name, beat)| {
+      let allowed = (beat.interval * MISSED_ROUNDS + GRACE).as_secs() as i64;
+      WorkerStatus {
+        name,
+        interval_secs: beat.interval.as_secs(),
+        last_beat: beat.last,
+        stalled: now - beat.last > allowed,
+      }
+    })
+    .collect()
+}
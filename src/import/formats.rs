@@ -0,0 +1,417 @@
+//! Streaming parsers for exported listening history. Each one reads the file
+//! incrementally and hands entries to a `Sink`, so memory use doesn't grow
+//! with the size of the export.
+
+use std::io::BufRead;
+
+use chrono::{DateTime, NaiveDateTime};
+use serde::{
+  de::{DeserializeOwned, SeqAccess, Visitor},
+  Deserialize, Deserializer,
+};
+use serde_json::Value;
+
+use crate::routes::scrobble::{validate_scrobble, ScrobbleRequest};
+
+/// Spotify counts a stream after 30 seconds, same as Last.fm's scrobble rule
+const MIN_SPOTIFY_PLAY_MS: u64 = 30_000;
+
+/// One entry of an export
+pub enum Entry {
+  Listen(ScrobbleRequest),
+  /// Not a listen (podcast episode, play too short to count)
+  Skipped,
+  Invalid(String),
+}
+
+impl Entry {
+  /// A listen that passed the same checks as a submission; `location`
+  /// prefixes the rejection reason otherwise
+  fn checked(location: String, scrob: ScrobbleRequest) -> Self {
+    match validate_scrobble(&scrob) {
+      Ok(()) => Entry::Listen(scrob),
+      Err(reason) => Entry::Invalid(format!("{}: {}", location, reason)),
+    }
+  }
+}
+
+/// Receives parsed entries; an error stops parsing
+pub trait Sink {
+  fn push(&mut self, entry: Entry) -> Result<(), String>;
+}
+
+/// Last.fm CSV export. Handles the headerless `artist,album,track,date`
+/// layout of most export tools, and files with a header row naming the
+/// columns (`artist`, `album`, `track`, and `uts` or `date`/`utc_time`).
+pub fn parse_lastfm_csv(mut reader: impl BufRead, sink: &mut dyn Sink) -> Result<(), String> {
+  let mut columns = Columns::HEADERLESS;
+  let mut line_number = 0;
+  let mut first = true;
+
+  while let Some((fields, lines)) = read_csv_record(&mut reader).map_err(|e| format!("Failed to read file: {}", e))? {
+    line_number += lines;
+    if fields.iter().all(|field| field.trim().is_empty()) {
+      continue;
+    }
+
+    if std::mem::take(&mut first) {
+      if let Some(header) = Columns::from_header(&fields) {
+        columns = header;
+        continue;
+      }
+    }
+
+    let entry = match columns.listen(&fields) {
+      Ok(scrob) => Entry::checked(format!("line {}", line_number), scrob),
+      Err(reason) => Entry::Invalid(format!("line {}: {}", line_number, reason)),
+    };
+    sink.push(entry)?;
+  }
+
+  Ok(())
+}
+
+/// Column positions in a Last.fm CSV
+struct Columns {
+  artist: usize,
+  album: Option<usize>,
+  track: usize,
+  /// Unix timestamp column
+  uts: Option<usize>,
+  /// Human-readable date column
+  date: Option<usize>,
+}
+
+impl Columns {
+  const HEADERLESS: Columns = Columns {
+    artist: 0,
+    album: Some(1),
+    track: 2,
+    uts: None,
+    date: Some(3),
+  };
+
+  fn from_header(fields: &[String]) -> Option<Self> {
+    let find = |names: &[&str]| {
+      fields
+        .iter()
+        .position(|field| names.contains(&field.trim().trim_start_matches('\u{feff}').to_lowercase().as_str()))
+    };
+
+    let columns = Columns {
+      artist: find(&["artist", "artist_name", "artist name"])?,
+      album: find(&["album", "album_name", "album name"]),
+      track: find(&["track", "track_name", "track name", "title", "name"])?,
+      uts: find(&["uts", "timestamp", "unix"]),
+      date: find(&["date", "utc_time", "time", "played_at"]),
+    };
+
+    (columns.uts.is_some() || columns.date.is_some()).then_some(columns)
+  }
+
+  fn listen(&self, fields: &[String]) -> Result<ScrobbleRequest, String> {
+    let field = |index: usize| fields.get(index).map(|f| f.trim()).filter(|f| !f.is_empty());
+
+    let timestamp = match (self.uts.and_then(field), self.date.and_then(field)) {
+      (Some(uts), _) => uts.parse::<u64>().map_err(|_| format!("invalid timestamp '{}'", uts))?,
+      (None, Some(date)) => parse_date(date).ok_or_else(|| format!("invalid date '{}'", date))?,
+      (None, None) => return Err("missing date".to_string()),
+    };
+
+    Ok(ScrobbleRequest {
+      artist: field(self.artist).unwrap_or_default().to_string(),
+      track: field(self.track).unwrap_or_default().to_string(),
+      timestamp,
+      album: self.album.and_then(field).map(str::to_string),
+      album_artist: None,
+      duration: None,
+      track_number: None,
+      artist_mbid: None,
+      release_mbid: None,
+      recording_mbid: None,
+      source: None,
+    })
+  }
+}
+
+/// Dates as written by the common Last.fm exporters, all in UTC
+fn parse_date(value: &str) -> Option<u64> {
+  if let Ok(timestamp) = value.parse::<u64>() {
+    return Some(timestamp);
+  }
+
+  if let Ok(date) = DateTime::parse_from_rfc3339(value) {
+    return u64::try_from(date.timestamp()).ok();
+  }
+
+  ["%d %b %Y %H:%M", "%d %b %Y, %H:%M", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+    .and_then(|date| u64::try_from(date.and_utc().timestamp()).ok())
+}
+
+/// Read one CSV record, following quoted fields across line breaks. Returns
+/// the fields and the number of lines consumed, or `None` at end of file.
+fn read_csv_record(reader: &mut impl BufRead) -> std::io::Result<Option<(Vec<String>, usize)>> {
+  let mut fields = Vec::new();
+  let mut field = String::new();
+  let mut in_quotes = false;
+  let mut lines = 0;
+  let mut line = String::new();
+
+  loop {
+    line.clear();
+    if reader.read_line(&mut line)? == 0 {
+      if lines == 0 {
+        return Ok(None);
+      }
+      break;
+    }
+    lines += 1;
+
+    let mut chars = line.trim_end_matches(['\r', '\n']).chars().peekable();
+    while let Some(c) = chars.next() {
+      match (c, in_quotes) {
+        ('"', true) if chars.peek() == Some(&'"') => {
+          field.push('"');
+          chars.next();
+        }
+        ('"', _) => in_quotes = !in_quotes,
+        (',', false) => fields.push(std::mem::take(&mut field)),
+        (c, _) => field.push(c),
+      }
+    }
+
+    if !in_quotes {
+      break;
+    }
+    field.push('\n');
+  }
+
+  fields.push(field);
+  Ok(Some((fields, lines)))
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzListen {
+  listened_at: Option<i64>,
+  track_metadata: ListenBrainzMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+struct ListenBrainzMetadata {
+  artist_name: Option<String>,
+  track_name: Option<String>,
+  release_name: Option<String>,
+  #[serde(default)]
+  additional_info: Value,
+}
+
+/// ListenBrainz export: a JSON array of listens, or the newer JSON Lines
+/// files with one listen per line
+pub fn parse_listenbrainz(mut reader: impl BufRead, sink: &mut dyn Sink) -> Result<(), String> {
+  let convert = |index: usize, value: Value| match serde_json::from_value::<ListenBrainzListen>(value) {
+    Ok(listen) => match listenbrainz_scrobble(listen) {
+      Ok(scrob) => Entry::checked(format!("listen {}", index + 1), scrob),
+      Err(reason) => Entry::Invalid(format!("listen {}: {}", index + 1, reason)),
+    },
+    Err(e) => Entry::Invalid(format!("listen {}: {}", index + 1, e)),
+  };
+
+  if starts_with_array(&mut reader)? {
+    return for_each_element(reader, |index, value| sink.push(convert(index, value)));
+  }
+
+  let mut line = String::new();
+  let mut index = 0;
+  loop {
+    line.clear();
+    if reader.read_line(&mut line).map_err(|e| format!("Failed to read file: {}", e))? == 0 {
+      return Ok(());
+    }
+    if line.trim().is_empty() {
+      continue;
+    }
+
+    let entry = match serde_json::from_str::<Value>(&line) {
+      Ok(value) => convert(index, value),
+      Err(e) => Entry::Invalid(format!("listen {}: {}", index + 1, e)),
+    };
+    sink.push(entry)?;
+    index += 1;
+  }
+}
+
+fn listenbrainz_scrobble(listen: ListenBrainzListen) -> Result<ScrobbleRequest, String> {
+  let info = &listen.track_metadata.additional_info;
+  let number = |key: &str| match info.get(key) {
+    Some(Value::Number(n)) => n.as_u64(),
+    Some(Value::String(s)) => s.trim().parse().ok(),
+    _ => None,
+  };
+
+  let duration = number("duration").or_else(|| number("duration_ms").map(|ms| ms / 1000));
+  let text = |key: &str| info.get(key).and_then(Value::as_str).map(str::to_string);
+
+  Ok(ScrobbleRequest {
+    artist: listen.track_metadata.artist_name.unwrap_or_default(),
+    track: listen.track_metadata.track_name.unwrap_or_default(),
+    timestamp: listen
+      .listened_at
+      .and_then(|t| u64::try_from(t).ok())
+      .ok_or_else(|| "missing listened_at".to_string())?,
+    album: listen.track_metadata.release_name,
+    album_artist: text("release_artist_name"),
+    duration: duration.filter(|d| *d > 0),
+    track_number: number("tracknumber").and_then(|n| u32::try_from(n).ok()),
+    artist_mbid: info
+      .get("artist_mbids")
+      .and_then(Value::as_array)
+      .and_then(|mbids| mbids.first())
+      .and_then(Value::as_str)
+      .map(str::to_string),
+    release_mbid: text("release_mbid"),
+    recording_mbid: text("recording_mbid"),
+    source: None,
+  })
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyStream {
+  /// Extended history: when the stream ended
+  ts: Option<String>,
+  ms_played: Option<u64>,
+  master_metadata_track_name: Option<String>,
+  master_metadata_album_artist_name: Option<String>,
+  master_metadata_album_album_name: Option<String>,
+  /// Account data history (`StreamingHistory*.json`)
+  #[serde(rename = "endTime")]
+  end_time: Option<String>,
+  #[serde(rename = "msPlayed")]
+  ms_played_short: Option<u64>,
+  #[serde(rename = "trackName")]
+  track_name: Option<String>,
+  #[serde(rename = "artistName")]
+  artist_name: Option<String>,
+}
+
+/// Spotify extended streaming history (`Streaming_History_Audio_*.json`).
+/// The shorter account-data `StreamingHistory*.json` files work too.
+pub fn parse_spotify(mut reader: impl BufRead, sink: &mut dyn Sink) -> Result<(), String> {
+  if !starts_with_array(&mut reader)? {
+    return Err("Expected a JSON array of streams".to_string());
+  }
+
+  for_each_element(reader, |index, value| {
+    let entry = match serde_json::from_value::<SpotifyStream>(value) {
+      Ok(stream) => match spotify_entry(stream) {
+        Ok(Entry::Listen(scrob)) => Entry::checked(format!("stream {}", index + 1), scrob),
+        Ok(entry) => entry,
+        Err(reason) => Entry::Invalid(format!("stream {}: {}", index + 1, reason)),
+      },
+      Err(e) => Entry::Invalid(format!("stream {}: {}", index + 1, e)),
+    };
+    sink.push(entry)
+  })
+}
+
+fn spotify_entry(stream: SpotifyStream) -> Result<Entry, String> {
+  let (Some(track), Some(artist)) = (
+    stream.master_metadata_track_name.or(stream.track_name),
+    stream.master_metadata_album_artist_name.or(stream.artist_name),
+  ) else {
+    // Podcast episodes and audiobooks have no track metadata
+    return Ok(Entry::Skipped);
+  };
+
+  let ms_played = stream.ms_played.or(stream.ms_played_short).unwrap_or_default();
+  if ms_played < MIN_SPOTIFY_PLAY_MS {
+    return Ok(Entry::Skipped);
+  }
+
+  let ended = match (stream.ts, stream.end_time) {
+    (Some(ts), _) => DateTime::parse_from_rfc3339(&ts)
+      .map(|date| date.timestamp())
+      .map_err(|_| format!("invalid ts '{}'", ts))?,
+    (None, Some(end_time)) => NaiveDateTime::parse_from_str(&end_time, "%Y-%m-%d %H:%M")
+      .map(|date| date.and_utc().timestamp())
+      .map_err(|_| format!("invalid endTime '{}'", end_time))?,
+    (None, None) => return Err("missing ts".to_string()),
+  };
+
+  // Spotify records when playback ended; scrobbles are timestamped at the start
+  let started = ended - (ms_played / 1000) as i64;
+
+  Ok(Entry::Listen(ScrobbleRequest {
+    artist,
+    track,
+    timestamp: u64::try_from(started).map_err(|_| "invalid ts".to_string())?,
+    album: stream.master_metadata_album_album_name,
+    album_artist: None,
+    duration: None,
+    track_number: None,
+    artist_mbid: None,
+    release_mbid: None,
+    recording_mbid: None,
+    source: None,
+  }))
+}
+
+/// Peek past whitespace (and a BOM) to see whether the document is an array
+fn starts_with_array(reader: &mut impl BufRead) -> Result<bool, String> {
+  loop {
+    let buf = reader.fill_buf().map_err(|e| format!("Failed to read file: {}", e))?;
+    let Some(&first) = buf.first() else {
+      return Ok(false);
+    };
+
+    if buf.starts_with(b"\xef\xbb\xbf") {
+      reader.consume(3);
+    } else if first.is_ascii_whitespace() {
+      reader.consume(1);
+    } else {
+      return Ok(first == b'[');
+    }
+  }
+}
+
+/// Visit the elements of a top-level JSON array one at a time
+fn for_each_element<T, F>(reader: impl BufRead, f: F) -> Result<(), String>
+where
+  T: DeserializeOwned,
+  F: FnMut(usize, T) -> Result<(), String>,
+{
+  struct ElementVisitor<T, F> {
+    f: F,
+    marker: std::marker::PhantomData<T>,
+  }
+
+  impl<'de, T, F> Visitor<'de> for ElementVisitor<T, F>
+  where
+    T: DeserializeOwned,
+    F: FnMut(usize, T) -> Result<(), String>,
+  {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+      formatter.write_str("a JSON array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(mut self, mut seq: A) -> Result<(), A::Error> {
+      let mut index = 0;
+      while let Some(element) = seq.next_element::<T>()? {
+        (self.f)(index, element).map_err(serde::de::Error::custom)?;
+        index += 1;
+      }
+      Ok(())
+    }
+  }
+
+  let mut deserializer = serde_json::Deserializer::from_reader(reader);
+  deserializer
+    .deserialize_seq(ElementVisitor {
+      f,
+      marker: std::marker::PhantomData,
+    })
+    .map_err(|e| format!("Invalid JSON: {}", e))
+}
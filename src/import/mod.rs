@@ -0,0 +1,357 @@
+//! Background imports of listening history exported from other services
+
+pub mod formats;
+
+use std::{
+  io::{BufReader, Read},
+  path::{Path, PathBuf},
+  sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+  },
+  time::Duration,
+};
+
+use tokio::sync::mpsc;
+
+use crate::{
+  db::DbPool,
+  health,
+  routes::scrobble::{normalize_mbid, ScrobbleRequest},
+};
+use formats::{Entry, Sink};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// Entries parsed before they are inserted and progress is saved
+const BATCH_SIZE: usize = 1000;
+/// Parsed batches buffered between the parser thread and the inserts
+const PENDING_BATCHES: usize = 4;
+/// Rejection reasons kept per job
+const MAX_ERRORS: i32 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportFormat {
+  LastfmCsv,
+  ListenBrainz,
+  Spotify,
+}
+
+impl ImportFormat {
+  pub fn parse(value: &str) -> Option<Self> {
+    match value {
+      "lastfm" => Some(ImportFormat::LastfmCsv),
+      "listenbrainz" => Some(ImportFormat::ListenBrainz),
+      "spotify" => Some(ImportFormat::Spotify),
+      _ => None,
+    }
+  }
+
+  pub fn as_str(self) -> &'static str {
+    match self {
+      ImportFormat::LastfmCsv => "lastfm",
+      ImportFormat::ListenBrainz => "listenbrainz",
+      ImportFormat::Spotify => "spotify",
+    }
+  }
+}
+
+/// Where the upload for a job is kept until it has been processed
+pub fn upload_path(dir: &Path, file_name: &str) -> PathBuf {
+  dir.join(file_name)
+}
+
+/// Start the worker that processes pending imports one at a time
+pub fn spawn_worker(pool: DbPool, dir: PathBuf) {
+  tokio::spawn(async move {
+    // Jobs interrupted by a restart start over; duplicates are skipped on insert
+    if let Err(e) = requeue_interrupted(&pool).await {
+      tracing::error!("Failed to requeue interrupted imports: {}", e);
+    }
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("import", POLL_INTERVAL);
+      loop {
+        match run_next(&pool, &dir, None).await {
+          // Long imports would otherwise look like a stalled worker
+          Ok(true) => health::beat("import", POLL_INTERVAL),
+          Ok(false) => break,
+          Err(e) => {
+            tracing::error!("Import worker failed: {}", e);
+            break;
+          }
+        }
+      }
+    }
+  });
+}
+
+async fn requeue_interrupted(pool: &DbPool) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    UPDATE import_jobs
+    SET status = 'pending', started_at = NULL, bytes_processed = 0, processed = 0,
+        imported = 0, duplicates = 0, skipped = 0, rejected = 0, errors = '{}'
+    WHERE status = 'running'
+    "#
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// Process pending job `job_id` right away (`scrob import`); false when it
+/// was already claimed, e.g. by a running server's worker
+pub async fn run_job(pool: &DbPool, dir: &Path, job_id: i64) -> Result<bool, sqlx::Error> {
+  run_next(pool, dir, Some(job_id)).await
+}
+
+/// Claim and process the oldest pending job, or only `job_id`; false when
+/// there was none
+async fn run_next(pool: &DbPool, dir: &Path, job_id: Option<i64>) -> Result<bool, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  let job = sqlx::query!(
+    r#"
+    UPDATE import_jobs
+    SET status = 'running', started_at = $1
+    WHERE id = (
+        SELECT id FROM import_jobs
+        WHERE status = 'pending' AND ($2::BIGINT IS NULL OR id = $2)
+        ORDER BY id
+        LIMIT 1
+        FOR UPDATE SKIP LOCKED
+    )
+    RETURNING id, user_id, format, file_name
+    "#,
+    now,
+    job_id
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  let Some(job) = job else {
+    return Ok(false);
+  };
+
+  let path = upload_path(dir, &job.file_name);
+  tracing::info!("Starting {} import {} for user {}", job.format, job.id, job.user_id);
+
+  let result = match ImportFormat::parse(&job.format) {
+    Some(format) => process(pool, job.id, job.user_id, format, &path).await,
+    None => Err(format!("Unknown import format: {}", job.format)),
+  };
+
+  let (status, error) = match result {
+    Ok(()) => ("completed", None),
+    Err(e) => {
+      tracing::warn!("Import {} failed: {}", job.id, e);
+      ("failed", Some(e))
+    }
+  };
+
+  sqlx::query!(
+    "UPDATE import_jobs SET status = $2, error = $3, finished_at = $4 WHERE id = $1",
+    job.id,
+    status,
+    error,
+    chrono::Utc::now().timestamp()
+  )
+  .execute(pool)
+  .await?;
+
+  if let Err(e) = tokio::fs::remove_file(&path).await {
+    tracing::warn!("Failed to remove import upload {}: {}", path.display(), e);
+  }
+
+  tracing::info!("Import {} {}", job.id, status);
+  Ok(true)
+}
+
+/// Hands parsed entries to the async side in batches
+struct BatchSink {
+  sender: mpsc::Sender<Vec<Entry>>,
+  batch: Vec<Entry>,
+}
+
+impl Sink for BatchSink {
+  fn push(&mut self, entry: Entry) -> Result<(), String> {
+    self.batch.push(entry);
+    if self.batch.len() >= BATCH_SIZE {
+      self.flush()?;
+    }
+    Ok(())
+  }
+}
+
+impl BatchSink {
+  fn flush(&mut self) -> Result<(), String> {
+    if self.batch.is_empty() {
+      return Ok(());
+    }
+    let batch = std::mem::replace(&mut self.batch, Vec::with_capacity(BATCH_SIZE));
+    self.sender.blocking_send(batch).map_err(|_| "Import was cancelled".to_string())
+  }
+}
+
+/// Counts bytes read so progress can be reported against the file size
+struct CountingReader<R> {
+  inner: R,
+  count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+  fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+    let read = self.inner.read(buf)?;
+    self.count.fetch_add(read as u64, Ordering::Relaxed);
+    Ok(read)
+  }
+}
+
+/// Parse the upload on a blocking thread while inserting its batches here
+async fn process(pool: &DbPool, job_id: i64, user_id: i64, format: ImportFormat, path: &Path) -> Result<(), String> {
+  let file = std::fs::File::open(path).map_err(|e| format!("Upload is missing: {}", e))?;
+  let bytes_read = Arc::new(AtomicU64::new(0));
+  let reader = BufReader::new(CountingReader {
+    inner: file,
+    count: bytes_read.clone(),
+  });
+
+  let (sender, mut receiver) = mpsc::channel(PENDING_BATCHES);
+  let parser = tokio::task::spawn_blocking(move || {
+    let mut sink = BatchSink {
+      sender,
+      batch: Vec::with_capacity(BATCH_SIZE),
+    };
+    match format {
+      ImportFormat::LastfmCsv => formats::parse_lastfm_csv(reader, &mut sink),
+      ImportFormat::ListenBrainz => formats::parse_listenbrainz(reader, &mut sink),
+      ImportFormat::Spotify => formats::parse_spotify(reader, &mut sink),
+    }?;
+    sink.flush()
+  });
+
+  while let Some(batch) = receiver.recv().await {
+    let bytes_processed = bytes_read.load(Ordering::Relaxed) as i64;
+    if let Err(e) = import_batch(pool, job_id, user_id, batch, bytes_processed).await {
+      // Dropping the receiver stops the parser at its next batch
+      drop(receiver);
+      let _ = parser.await;
+      return Err(format!("Database error: {}", e));
+    }
+  }
+
+  parser.await.map_err(|e| format!("Import parser crashed: {}", e))??;
+
+  sqlx::query!(
+    "UPDATE import_jobs SET bytes_processed = bytes WHERE id = $1",
+    job_id
+  )
+  .execute(pool)
+  .await
+  .map_err(|e| format!("Database error: {}", e))?;
+
+  Ok(())
+}
+
+/// Insert a batch of listens and add its counts to the job
+async fn import_batch(
+  pool: &DbPool,
+  job_id: i64,
+  user_id: i64,
+  batch: Vec<Entry>,
+  bytes_processed: i64,
+) -> Result<(), sqlx::Error> {
+  let processed = batch.len() as i64;
+  let mut listens: Vec<ScrobbleRequest> = Vec::with_capacity(batch.len());
+  let mut skipped = 0i64;
+  let mut errors: Vec<String> = Vec::new();
+
+  for entry in batch {
+    match entry {
+      Entry::Listen(scrob) => listens.push(scrob),
+      Entry::Skipped => skipped += 1,
+      Entry::Invalid(reason) => errors.push(reason),
+    }
+  }
+
+  let now = chrono::Utc::now().timestamp();
+  let artists: Vec<&str> = listens.iter().map(|s| s.artist.as_str()).collect();
+  let tracks: Vec<&str> = listens.iter().map(|s| s.track.as_str()).collect();
+  let albums: Vec<Option<&str>> = listens.iter().map(|s| s.album.as_deref()).collect();
+  let album_artists: Vec<Option<&str>> = listens.iter().map(|s| s.album_artist.as_deref()).collect();
+  let track_numbers: Vec<Option<i32>> = listens
+    .iter()
+    .map(|s| s.track_number.and_then(|n| i32::try_from(n).ok()))
+    .collect();
+  let durations: Vec<Option<i64>> = listens.iter().map(|s| s.duration.map(|d| d as i64)).collect();
+  let timestamps: Vec<i64> = listens.iter().map(|s| s.timestamp as i64).collect();
+  let artist_mbids: Vec<Option<String>> = listens.iter().map(|s| normalize_mbid(s.artist_mbid.as_deref())).collect();
+  let release_mbids: Vec<Option<String>> = listens.iter().map(|s| normalize_mbid(s.release_mbid.as_deref())).collect();
+  let recording_mbids: Vec<Option<String>> =
+    listens.iter().map(|s| normalize_mbid(s.recording_mbid.as_deref())).collect();
+
+  let mut tx = pool.begin().await?;
+
+  let imported = sqlx::query!(
+    r#"
+    INSERT INTO scrobs (
+      user_id, artist, track, album, album_artist, track_number, duration, timestamp, created_at,
+      artist_mbid, release_mbid, recording_mbid, source
+    )
+    SELECT DISTINCT ON (i.artist, i.track, i.timestamp)
+           $1, i.artist, i.track, i.album, i.album_artist, i.track_number, i.duration, i.timestamp, $9,
+           i.artist_mbid, i.release_mbid, i.recording_mbid, 'import'
+    FROM UNNEST(
+      $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::INTEGER[], $7::BIGINT[], $8::BIGINT[],
+      $10::TEXT[], $11::TEXT[], $12::TEXT[]
+    ) AS i(artist, track, album, album_artist, track_number, duration, timestamp, artist_mbid, release_mbid, recording_mbid)
+    ON CONFLICT (user_id, artist, track, timestamp) DO NOTHING
+    "#,
+    user_id,
+    &artists as &[&str],
+    &tracks as &[&str],
+    &albums as &[Option<&str>],
+    &album_artists as &[Option<&str>],
+    &track_numbers as &[Option<i32>],
+    &durations as &[Option<i64>],
+    &timestamps,
+    now,
+    &artist_mbids as &[Option<String>],
+    &release_mbids as &[Option<String>],
+    &recording_mbids as &[Option<String>]
+  )
+  .execute(&mut *tx)
+  .await?
+  .rows_affected() as i64;
+
+  let rejected = errors.len() as i64;
+  sqlx::query!(
+    r#"
+    UPDATE import_jobs
+    SET processed = processed + $2,
+        imported = imported + $3,
+        duplicates = duplicates + $4,
+        skipped = skipped + $5,
+        rejected = rejected + $6,
+        errors = (errors || $7::TEXT[])[1:$8],
+        bytes_processed = $9
+    WHERE id = $1
+    "#,
+    job_id,
+    processed,
+    imported,
+    listens.len() as i64 - imported,
+    skipped,
+    rejected,
+    &errors,
+    MAX_ERRORS,
+    bytes_processed
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  tx.commit().await
+}
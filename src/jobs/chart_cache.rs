@@ -0,0 +1,22 @@
+use std::time::Duration;
+
+use crate::{chart_cache, db::DbPool, health};
+
+const INTERVAL: Duration = Duration::from_secs(60);
+const BATCH_SIZE: i64 = 200;
+
+/// Refresh chart cache entries whose scrobbles changed
+pub fn spawn(pool: DbPool, default_track_duration: i64) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("chart_cache", INTERVAL);
+      match chart_cache::refresh_stale(&pool, BATCH_SIZE, default_track_duration).await {
+        Ok(0) => {}
+        Ok(refreshed) => tracing::debug!("Refreshed {} chart cache entries", refreshed),
+        Err(e) => tracing::error!("Chart cache refresh failed: {}", e),
+      }
+    }
+  });
+}
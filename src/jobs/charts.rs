@@ -0,0 +1,179 @@
+use crate::db::DbPool;
+
+/// Number of entries kept in each snapshot
+const CHART_SIZE: i64 = 50;
+
+pub const PERIODS: [&str; 2] = ["week", "month"];
+
+/// Run a backfill for one user in the background, recording progress in
+/// `chart_backfills`
+pub fn spawn_backfill(pool: DbPool, user_id: i64) {
+  tokio::spawn(async move {
+    let result = backfill(&pool, user_id).await;
+    let now = chrono::Utc::now().timestamp();
+
+    let (status, snapshots, error) = match result {
+      Ok(snapshots) => ("done", snapshots as i64, None),
+      Err(ref e) => {
+        tracing::error!("Chart backfill for user {} failed: {}", user_id, e);
+        ("failed", 0, Some(e.to_string()))
+      }
+    };
+
+    let update = sqlx::query!(
+      r#"
+      UPDATE chart_backfills
+      SET status = $2, snapshots = $3, error = $4, finished_at = $5
+      WHERE user_id = $1
+      "#,
+      user_id,
+      status,
+      snapshots,
+      error,
+      now
+    )
+    .execute(&pool)
+    .await;
+
+    if let Err(e) = update {
+      tracing::error!("Failed to record chart backfill for user {}: {}", user_id, e);
+    }
+  });
+}
+
+/// Compute snapshots for every completed week and month of a user's history,
+/// replacing any that already exist. Returns the number of snapshots written.
+pub async fn backfill(pool: &DbPool, user_id: i64) -> Result<u64, sqlx::Error> {
+  let mut conn = pool.acquire().await?;
+  let mut written = 0;
+
+  for period in PERIODS {
+    written += write_snapshots(&mut conn, user_id, period, None).await?;
+  }
+
+  tracing::info!("Backfilled {} chart snapshots for user {}", written, user_id);
+  Ok(written)
+}
+
+/// Recompute one completed period after its scrobbles changed, dropping the
+/// snapshot if nothing is left in it
+pub async fn recompute_period(
+  pool: &DbPool,
+  user_id: i64,
+  period: &str,
+  start: i64,
+  end: i64,
+) -> Result<(), sqlx::Error> {
+  let mut tx = pool.begin().await?;
+
+  sqlx::query!(
+    "DELETE FROM chart_snapshots WHERE user_id = $1 AND period = $2 AND period_start = $3",
+    user_id,
+    period,
+    start
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  write_snapshots(&mut tx, user_id, period, Some((start, end))).await?;
+
+  tx.commit().await
+}
+
+/// Upsert artist and track snapshots for completed periods, optionally only
+/// those with scrobbles in `range`
+async fn write_snapshots(
+  conn: &mut sqlx::PgConnection,
+  user_id: i64,
+  period: &str,
+  range: Option<(i64, i64)>,
+) -> Result<u64, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  let (from, until) = range.unzip();
+  let mut written = 0;
+
+  written += sqlx::query!(
+    r#"
+    WITH plays AS (
+        SELECT date_trunc($2, to_timestamp(s.timestamp) AT TIME ZONE 'UTC') as bucket,
+               a.name as artist, COUNT(*) as plays
+        FROM scrobs s
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.user_id = $1
+          AND ($5::BIGINT IS NULL OR s.timestamp >= $5)
+          AND ($6::BIGINT IS NULL OR s.timestamp < $6)
+        GROUP BY bucket, a.id
+    ),
+    ranked AS (
+        SELECT bucket, artist, plays,
+               ROW_NUMBER() OVER (PARTITION BY bucket ORDER BY plays DESC, artist) as rank
+        FROM plays
+        WHERE bucket < date_trunc($2, now() AT TIME ZONE 'UTC')
+    )
+    INSERT INTO chart_snapshots (user_id, period, kind, period_start, period_end, entries, computed_at)
+    SELECT $1, $2, 'artists',
+           EXTRACT(EPOCH FROM bucket)::BIGINT,
+           EXTRACT(EPOCH FROM bucket + ('1 ' || $2)::interval)::BIGINT,
+           jsonb_agg(jsonb_build_object('name', artist, 'count', plays) ORDER BY rank),
+           $3
+    FROM ranked
+    WHERE rank <= $4
+    GROUP BY bucket
+    ON CONFLICT (user_id, period, kind, period_start)
+    DO UPDATE SET entries = EXCLUDED.entries, computed_at = EXCLUDED.computed_at
+    "#,
+    user_id,
+    period,
+    now,
+    CHART_SIZE,
+    from,
+    until
+  )
+  .execute(&mut *conn)
+  .await?
+  .rows_affected();
+
+  written += sqlx::query!(
+    r#"
+    WITH plays AS (
+        SELECT date_trunc($2, to_timestamp(s.timestamp) AT TIME ZONE 'UTC') as bucket,
+               a.name as artist, t.name as track, COUNT(*) as plays
+        FROM scrobs s
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE s.user_id = $1
+          AND ($5::BIGINT IS NULL OR s.timestamp >= $5)
+          AND ($6::BIGINT IS NULL OR s.timestamp < $6)
+        GROUP BY bucket, t.id, a.id
+    ),
+    ranked AS (
+        SELECT bucket, artist, track, plays,
+               ROW_NUMBER() OVER (PARTITION BY bucket ORDER BY plays DESC, artist, track) as rank
+        FROM plays
+        WHERE bucket < date_trunc($2, now() AT TIME ZONE 'UTC')
+    )
+    INSERT INTO chart_snapshots (user_id, period, kind, period_start, period_end, entries, computed_at)
+    SELECT $1, $2, 'tracks',
+           EXTRACT(EPOCH FROM bucket)::BIGINT,
+           EXTRACT(EPOCH FROM bucket + ('1 ' || $2)::interval)::BIGINT,
+           jsonb_agg(jsonb_build_object('artist', artist, 'track', track, 'count', plays) ORDER BY rank),
+           $3
+    FROM ranked
+    WHERE rank <= $4
+    GROUP BY bucket
+    ON CONFLICT (user_id, period, kind, period_start)
+    DO UPDATE SET entries = EXCLUDED.entries, computed_at = EXCLUDED.computed_at
+    "#,
+    user_id,
+    period,
+    now,
+    CHART_SIZE,
+    from,
+    until
+  )
+  .execute(&mut *conn)
+  .await?
+  .rows_affected();
+
+  Ok(written)
+}
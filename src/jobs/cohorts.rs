@@ -0,0 +1,62 @@
+use std::time::Duration;
+
+use crate::{db::DbPool, health};
+
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub fn spawn(pool: DbPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("cohorts", INTERVAL);
+      if let Err(e) = rebuild(&pool).await {
+        tracing::error!("Cohort rebuild failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Recompute the signup-month retention table from scratch
+pub async fn rebuild(pool: &DbPool) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  let mut tx = pool.begin().await?;
+
+  sqlx::query!("DELETE FROM user_cohorts")
+    .execute(&mut *tx)
+    .await?;
+
+  // Activity is based on when scrobbles were recorded, so imported
+  // history doesn't count as activity before signup
+  sqlx::query!(
+    r#"
+    WITH cohorts AS (
+        SELECT id, date_trunc('month', to_timestamp(created_at) AT TIME ZONE 'UTC')::date as cohort_month
+        FROM users
+    ),
+    sizes AS (
+        SELECT cohort_month, COUNT(*) as cohort_size
+        FROM cohorts
+        GROUP BY cohort_month
+    ),
+    activity AS (
+        SELECT DISTINCT user_id, date_trunc('month', to_timestamp(created_at) AT TIME ZONE 'UTC')::date as activity_month
+        FROM scrobs
+    )
+    INSERT INTO user_cohorts (cohort_month, activity_month, cohort_size, active_users, computed_at)
+    SELECT c.cohort_month, a.activity_month, sz.cohort_size, COUNT(*), $1
+    FROM cohorts c
+    JOIN activity a ON a.user_id = c.id AND a.activity_month >= c.cohort_month
+    JOIN sizes sz ON sz.cohort_month = c.cohort_month
+    GROUP BY c.cohort_month, a.activity_month, sz.cohort_size
+    "#,
+    now
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  tx.commit().await?;
+
+  tracing::debug!("Rebuilt user cohorts");
+  Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{collections::BTreeSet, time::Duration};
+
+use chrono::NaiveDate;
+
+use crate::{db::DbPool, goals, health, jobs::charts};
+
+const INTERVAL: Duration = Duration::from_secs(5 * 60);
+const BATCH_SIZE: i64 = 500;
+
+pub fn spawn(pool: DbPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("dirty_days", INTERVAL);
+      if let Err(e) = process(&pool).await {
+        tracing::error!("Dirty day processing failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Recompute archived charts covering days whose scrobbles changed
+pub async fn process(pool: &DbPool) -> Result<(), sqlx::Error> {
+  let marked = sqlx::query!(
+    r#"
+    SELECT d.user_id, d.day, d.marked_at,
+           EXISTS(SELECT 1 FROM chart_snapshots c WHERE c.user_id = d.user_id) as "has_charts!"
+    FROM dirty_days d
+    ORDER BY d.marked_at
+    LIMIT $1
+    "#,
+    BATCH_SIZE
+  )
+  .fetch_all(pool)
+  .await?;
+
+  if marked.is_empty() {
+    return Ok(());
+  }
+
+  // Several dirty days usually fall in the same week or month
+  let mut periods = BTreeSet::new();
+  for row in marked.iter().filter(|row| row.has_charts) {
+    let day = day_start(row.day);
+    for period in charts::PERIODS {
+      let (start, end) = goals::period_bounds(period, day);
+      periods.insert((row.user_id, period, start, end));
+    }
+  }
+
+  for &(user_id, period, start, end) in &periods {
+    charts::recompute_period(pool, user_id, period, start, end).await?;
+  }
+
+  // Keep markers that were touched again while we were working
+  for row in &marked {
+    sqlx::query!(
+      "DELETE FROM dirty_days WHERE user_id = $1 AND day = $2 AND marked_at = $3",
+      row.user_id,
+      row.day,
+      row.marked_at
+    )
+    .execute(pool)
+    .await?;
+  }
+
+  tracing::info!(
+    "Processed {} dirty days, recomputed {} chart periods",
+    marked.len(),
+    periods.len()
+  );
+  Ok(())
+}
+
+fn day_start(day: NaiveDate) -> chrono::DateTime<chrono::Utc> {
+  day.and_hms_opt(0, 0, 0).unwrap().and_utc()
+}
@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::musicbrainz::{self, escape};
+use crate::{db::DbPool, health};
+
+const INTERVAL: Duration = Duration::from_secs(10 * 60);
+const LOOKUPS_PER_RUN: i64 = 100;
+/// Failed lookups are retried after this long, in case metadata was added
+const MISS_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+/// Minimum search score for a recording to be trusted
+const MIN_SCORE: i64 = 90;
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+  #[serde(default)]
+  recordings: Vec<Recording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recording {
+  #[serde(default)]
+  score: i64,
+  /// Length in milliseconds
+  length: Option<i64>,
+}
+
+pub fn spawn(pool: DbPool, musicbrainz_url: String) {
+  tokio::spawn(async move {
+    let client = musicbrainz::client();
+
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("durations", INTERVAL);
+      if let Err(e) = backfill(&pool, &client, &musicbrainz_url).await {
+        tracing::error!("Duration backfill failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Look up uncached recordings that are missing a duration, then apply
+/// everything in the cache to scrobbles without one
+async fn backfill(pool: &DbPool, client: &reqwest::Client, base_url: &str) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  let missing = sqlx::query!(
+    r#"
+    SELECT DISTINCT s.artist, s.track
+    FROM scrobs s
+    LEFT JOIN recording_durations r ON r.artist = s.artist AND r.track = s.track
+    WHERE s.duration IS NULL AND s.duration_estimated IS NULL
+      AND (r.artist IS NULL OR (r.duration IS NULL AND r.looked_up_at < $1))
+    LIMIT $2
+    "#,
+    now - MISS_TTL_SECS,
+    LOOKUPS_PER_RUN
+  )
+  .fetch_all(pool)
+  .await?;
+
+  for row in &missing {
+    let duration = match lookup(client, base_url, &row.artist, &row.track).await {
+      Ok(duration) => duration,
+      Err(e) => {
+        // Leave it uncached so it's tried again next run
+        tracing::warn!("MusicBrainz lookup for {} - {} failed: {}", row.artist, row.track, e);
+        continue;
+      }
+    };
+
+    sqlx::query!(
+      r#"
+      INSERT INTO recording_durations (artist, track, duration, looked_up_at)
+      VALUES ($1, $2, $3, $4)
+      ON CONFLICT (artist, track) DO UPDATE SET duration = $3, looked_up_at = $4
+      "#,
+      row.artist,
+      row.track,
+      duration,
+      chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+  }
+
+  let filled = sqlx::query!(
+    r#"
+    UPDATE scrobs s
+    SET duration_estimated = r.duration
+    FROM recording_durations r
+    WHERE r.artist = s.artist AND r.track = s.track AND r.duration IS NOT NULL
+      AND s.duration IS NULL AND s.duration_estimated IS NULL
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  if filled > 0 {
+    tracing::info!("Estimated durations for {} scrobbles", filled);
+  }
+
+  Ok(())
+}
+
+/// Find the duration in seconds of the best matching recording
+async fn lookup(
+  client: &reqwest::Client,
+  base_url: &str,
+  artist: &str,
+  track: &str,
+) -> Result<Option<i64>, reqwest::Error> {
+  let query = format!("artist:\"{}\" AND recording:\"{}\"", escape(artist), escape(track));
+
+  musicbrainz::throttle().await;
+  let response: SearchResponse = client
+    .get(format!("{}/ws/2/recording", base_url.trim_end_matches('/')))
+    .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", "1")])
+    .send()
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
+
+  Ok(response
+    .recordings
+    .into_iter()
+    .find(|r| r.score >= MIN_SCORE)
+    .and_then(|r| r.length)
+    .map(|ms| (ms + 500) / 1000)
+    .filter(|&secs| secs > 0))
+}
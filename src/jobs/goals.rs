@@ -0,0 +1,86 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::{db::DbPool, goals, health};
+
+const INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+pub fn spawn(pool: DbPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("goals", INTERVAL);
+      if let Err(e) = check(&pool).await {
+        tracing::error!("Goal check failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Notify users of goals reached in the current period, once per period.
+/// Periods follow each user's timezone.
+pub async fn check(pool: &DbPool) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now();
+
+  let pending = sqlx::query!(
+    r#"
+    SELECT g.id as "id!", g.user_id, g.metric, g.period, g.target, g.reached_period_start,
+           COALESCE(s.timezone, 'UTC') as "timezone!"
+    FROM goals g
+    LEFT JOIN user_settings s ON s.user_id = g.user_id
+    "#
+  )
+  .fetch_all(pool)
+  .await?;
+
+  // Most goals share a handful of timezones and periods
+  let mut bounds = HashMap::new();
+
+  for goal in pending {
+    let key = (goal.timezone, goal.period);
+    let (start, end) = match bounds.get(&key) {
+      Some(&range) => range,
+      None => {
+        let range = goals::local_period_bounds(pool, &key.0, &key.1).await?;
+        bounds.insert(key.clone(), range);
+        range
+      }
+    };
+    if goal.reached_period_start == Some(start) {
+      continue;
+    }
+
+    let progress = goals::progress(pool, goal.user_id, &goal.metric, start, end).await?;
+    if progress < goal.target {
+      continue;
+    }
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+      "UPDATE goals SET reached_period_start = $2 WHERE id = $1",
+      goal.id,
+      start
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+      r#"
+      INSERT INTO notifications (user_id, kind, message, created_at)
+      VALUES ($1, 'goal_reached', $2, $3)
+      "#,
+      goal.user_id,
+      format!("Goal reached: {}", goals::describe(&goal.metric, goal.target, &key.1)),
+      now.timestamp()
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    tracing::info!("User {} reached goal {}", goal.user_id, goal.id);
+  }
+
+  Ok(())
+}
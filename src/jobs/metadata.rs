@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+
+use super::musicbrainz::{self, escape};
+use crate::{chart_cache, db::DbPool, health, versioning};
+
+const INTERVAL: Duration = Duration::from_secs(10 * 60);
+const LOOKUPS_PER_RUN: i64 = 100;
+/// Failed lookups are retried after this long, in case metadata was added
+const MISS_TTL_SECS: i64 = 30 * 24 * 60 * 60;
+/// Minimum search score for a recording to be trusted
+const MIN_SCORE: i64 = 90;
+const CANDIDATES: &str = "5";
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+  #[serde(default)]
+  recordings: Vec<Recording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Recording {
+  id: String,
+  #[serde(default)]
+  score: i64,
+  title: String,
+  #[serde(rename = "artist-credit", default)]
+  artist_credit: Vec<Credit>,
+  #[serde(default)]
+  releases: Vec<Release>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Credit {
+  /// The name as credited on this recording
+  name: String,
+  #[serde(default)]
+  joinphrase: String,
+  artist: CreditedArtist,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreditedArtist {
+  id: String,
+  name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Release {
+  id: String,
+  title: String,
+}
+
+/// What a lookup settled on; the release only when the album matched one
+#[derive(Debug, Default)]
+struct Match {
+  recording_mbid: Option<String>,
+  recording_name: Option<String>,
+  artist_mbid: Option<String>,
+  artist_name: Option<String>,
+  release_mbid: Option<String>,
+  release_name: Option<String>,
+}
+
+pub fn spawn(pool: DbPool, musicbrainz_url: String) {
+  tokio::spawn(async move {
+    let client = musicbrainz::client();
+
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("metadata", INTERVAL);
+      if let Err(e) = enrich(&pool, &client, &musicbrainz_url).await {
+        tracing::error!("Metadata enrichment failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Look up uncached artist/track/album combinations of scrobbles without a
+/// recording MBID, then give matching scrobbles the cached ids and the
+/// catalogue the MusicBrainz spellings
+async fn enrich(pool: &DbPool, client: &reqwest::Client, base_url: &str) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  let missing = sqlx::query!(
+    r#"
+    SELECT DISTINCT s.artist, s.track, COALESCE(btrim(s.album), '') as "album!"
+    FROM scrobs s
+    LEFT JOIN mb_cache c
+      ON c.artist = s.artist AND c.track = s.track AND c.album = COALESCE(btrim(s.album), '')
+    WHERE s.recording_mbid IS NULL
+      AND (c.artist IS NULL OR (c.recording_mbid IS NULL AND c.looked_up_at < $1))
+    LIMIT $2
+    "#,
+    now - MISS_TTL_SECS,
+    LOOKUPS_PER_RUN
+  )
+  .fetch_all(pool)
+  .await?;
+
+  for row in &missing {
+    let found = match lookup(client, base_url, &row.artist, &row.track, &row.album).await {
+      Ok(found) => found.unwrap_or_default(),
+      Err(e) => {
+        // Leave it uncached so it's tried again next run
+        tracing::warn!("MusicBrainz lookup for {} - {} failed: {}", row.artist, row.track, e);
+        continue;
+      }
+    };
+
+    sqlx::query!(
+      r#"
+      INSERT INTO mb_cache (
+        artist, track, album, recording_mbid, recording_name, artist_mbid, artist_name,
+        release_mbid, release_name, looked_up_at
+      )
+      VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)
+      ON CONFLICT (artist, track, album) DO UPDATE
+      SET recording_mbid = $4, recording_name = $5, artist_mbid = $6, artist_name = $7,
+          release_mbid = $8, release_name = $9, looked_up_at = $10
+      "#,
+      row.artist,
+      row.track,
+      row.album,
+      found.recording_mbid,
+      found.recording_name,
+      found.artist_mbid,
+      found.artist_name,
+      found.release_mbid,
+      found.release_name,
+      chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+  }
+
+  // Ids sent with a scrobble are kept; the trigger relinks the catalogue
+  let linked = sqlx::query!(
+    r#"
+    UPDATE scrobs s
+    SET recording_mbid = c.recording_mbid,
+        artist_mbid = COALESCE(s.artist_mbid, c.artist_mbid),
+        release_mbid = COALESCE(s.release_mbid, c.release_mbid)
+    FROM mb_cache c
+    WHERE s.recording_mbid IS NULL AND c.recording_mbid IS NOT NULL
+      AND c.artist = s.artist AND c.track = s.track AND c.album = COALESCE(btrim(s.album), '')
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  if linked > 0 {
+    tracing::info!("Linked {} scrobbles to MusicBrainz", linked);
+  }
+
+  rename_catalogue(pool).await
+}
+
+/// Give catalogue entries with a cached MBID its latest MusicBrainz name.
+/// Names are only unique among entries without an id, so this can't clash.
+async fn rename_catalogue(pool: &DbPool) -> Result<(), sqlx::Error> {
+  let mut renamed = sqlx::query!(
+    r#"
+    UPDATE artists a
+    SET name = c.artist_name
+    FROM (
+        SELECT DISTINCT ON (artist_mbid) artist_mbid, artist_name
+        FROM mb_cache
+        WHERE artist_mbid IS NOT NULL
+        ORDER BY artist_mbid, looked_up_at DESC
+    ) c
+    WHERE a.mbid = c.artist_mbid AND a.name <> c.artist_name
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  renamed += sqlx::query!(
+    r#"
+    UPDATE tracks t
+    SET name = c.recording_name
+    FROM (
+        SELECT DISTINCT ON (recording_mbid) recording_mbid, recording_name
+        FROM mb_cache
+        WHERE recording_mbid IS NOT NULL
+        ORDER BY recording_mbid, looked_up_at DESC
+    ) c
+    WHERE t.mbid = c.recording_mbid AND t.name <> c.recording_name
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  renamed += sqlx::query!(
+    r#"
+    UPDATE albums al
+    SET name = c.release_name
+    FROM (
+        SELECT DISTINCT ON (release_mbid) release_mbid, release_name
+        FROM mb_cache
+        WHERE release_mbid IS NOT NULL
+        ORDER BY release_mbid, looked_up_at DESC
+    ) c
+    WHERE al.mbid = c.release_mbid AND al.name <> c.release_name
+    "#
+  )
+  .execute(pool)
+  .await?
+  .rows_affected();
+
+  if renamed > 0 {
+    tracing::info!("Renamed {} catalogue entries to their MusicBrainz names", renamed);
+    chart_cache::mark_all_stale(pool).await?;
+    versioning::bump_all_scrobble_versions(pool).await?;
+  }
+
+  Ok(())
+}
+
+/// Find the best recording whose title and artist match the scrobble apart
+/// from case and punctuation, so a high search score alone can't mislabel
+/// a cover or a namesake
+async fn lookup(
+  client: &reqwest::Client,
+  base_url: &str,
+  artist: &str,
+  track: &str,
+  album: &str,
+) -> Result<Option<Match>, reqwest::Error> {
+  let query = format!("artist:\"{}\" AND recording:\"{}\"", escape(artist), escape(track));
+
+  musicbrainz::throttle().await;
+  let response: SearchResponse = client
+    .get(format!("{}/ws/2/recording", base_url.trim_end_matches('/')))
+    .query(&[("query", query.as_str()), ("fmt", "json"), ("limit", CANDIDATES)])
+    .send()
+    .await?
+    .error_for_status()?
+    .json()
+    .await?;
+
+  let candidates: Vec<Recording> = response
+    .recordings
+    .into_iter()
+    .filter(|r| r.score >= MIN_SCORE && fold(&r.title) == fold(track))
+    .filter(|r| {
+      let credited: String = r.artist_credit.iter().map(|c| format!("{}{}", c.name, c.joinphrase)).collect();
+      let first = r.artist_credit.first().map(|c| c.artist.name.as_str()).unwrap_or_default();
+      fold(&credited) == fold(artist) || fold(first) == fold(artist)
+    })
+    .collect();
+
+  // Prefer a recording that appears on the scrobbled album
+  let release_of = |r: &Recording| {
+    (!album.is_empty())
+      .then(|| r.releases.iter().find(|release| fold(&release.title) == fold(album)))
+      .flatten()
+      .map(|release| (release.id.clone(), release.title.clone()))
+  };
+  let best = candidates
+    .iter()
+    .find(|r| release_of(r).is_some())
+    .or_else(|| candidates.first());
+
+  Ok(best.map(|recording| {
+    let (release_mbid, release_name) = release_of(recording).unzip();
+    let artist = recording.artist_credit.first().map(|c| &c.artist);
+    Match {
+      recording_mbid: Some(recording.id.clone()),
+      recording_name: Some(recording.title.clone()),
+      artist_mbid: artist.map(|a| a.id.clone()),
+      artist_name: artist.map(|a| a.name.clone()),
+      release_mbid,
+      release_name,
+    }
+  }))
+}
+
+/// Lowercase letters and digits only, for comparing names loosely
+fn fold(name: &str) -> String {
+  name.chars().filter(|c| c.is_alphanumeric()).flat_map(char::to_lowercase).collect()
+}
@@ -0,0 +1,30 @@
+//! Periodic background jobs
+
+pub mod chart_cache;
+pub mod charts;
+pub mod cohorts;
+pub mod dirty_days;
+pub mod durations;
+pub mod goals;
+pub mod metadata;
+pub mod musicbrainz;
+pub mod retention;
+
+use crate::{config::Config, db::DbPool, runtime::RuntimeSettings};
+
+/// Start all scheduled jobs
+pub fn spawn_all(pool: DbPool, config: &Config, runtime: &RuntimeSettings) {
+  chart_cache::spawn(pool.clone(), config.default_track_duration);
+  cohorts::spawn(pool.clone());
+  dirty_days::spawn(pool.clone());
+  goals::spawn(pool.clone());
+  retention::spawn(pool.clone(), runtime.clone());
+
+  if config.duration_lookup {
+    durations::spawn(pool.clone(), config.musicbrainz_url.clone());
+  }
+
+  if config.metadata_lookup {
+    metadata::spawn(pool, config.musicbrainz_url.clone());
+  }
+}
@@ -0,0 +1,32 @@
+//! HTTP client and rate limit shared by the MusicBrainz lookup jobs
+
+use std::time::Duration;
+
+use tokio::{sync::Mutex, time::Instant};
+
+/// MusicBrainz allows one request per second per client, across all jobs
+const REQUEST_INTERVAL: Duration = Duration::from_millis(1100);
+
+static LAST_REQUEST: Mutex<Option<Instant>> = Mutex::const_new(None);
+
+pub fn client() -> reqwest::Client {
+  reqwest::Client::builder()
+    .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION"), " ( https://github.com/ducks/scrob )"))
+    .timeout(Duration::from_secs(10))
+    .build()
+    .expect("failed to build MusicBrainz HTTP client")
+}
+
+/// Wait until this process may send its next request
+pub async fn throttle() {
+  let mut last = LAST_REQUEST.lock().await;
+  if let Some(last) = *last {
+    tokio::time::sleep_until(last + REQUEST_INTERVAL).await;
+  }
+  *last = Some(Instant::now());
+}
+
+/// Escape Lucene query syntax inside a quoted term
+pub fn escape(term: &str) -> String {
+  term.replace('\\', "\\\\").replace('"', "\\\"")
+}
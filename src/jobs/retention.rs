@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::{
+  db::DbPool,
+  health,
+  runtime::{RetentionScope, RuntimeSettings},
+};
+
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Rows deleted per statement, so a first run on a large table doesn't hold
+/// one huge transaction
+const BATCH_SIZE: i64 = 5000;
+
+pub fn spawn(pool: DbPool, runtime: RuntimeSettings) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("retention", INTERVAL);
+      if let Err(e) = purge(&pool, &runtime).await {
+        tracing::error!("Retention purge failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Delete scrobbles played more than `retention_days` ago (the
+/// /admin/settings override or `SCROB_RETENTION_DAYS`), for everyone or only
+/// banned users depending on `retention_scope`. Users who opted out in their
+/// preferences are skipped. Imported history older than that goes too.
+pub async fn purge(pool: &DbPool, runtime: &RuntimeSettings) -> Result<(), sqlx::Error> {
+  let limits = runtime.limits();
+  let Some(days) = limits.retention_days else {
+    return Ok(());
+  };
+
+  let now = chrono::Utc::now().timestamp();
+  let cutoff = now - i64::from(days) * 24 * 60 * 60;
+  let banned_only = limits.retention_scope == RetentionScope::Banned;
+  let mut deleted = 0;
+
+  loop {
+    let batch = sqlx::query!(
+      r#"
+      DELETE FROM scrobs
+      WHERE id IN (
+          SELECT s.id
+          FROM scrobs s
+          JOIN users u ON u.id = s.user_id
+          LEFT JOIN user_settings us ON us.user_id = s.user_id
+          WHERE s.timestamp < $1
+            AND NOT COALESCE(us.retention_opt_out, false)
+            AND (NOT $3 OR (u.banned_at IS NOT NULL AND (u.banned_until IS NULL OR u.banned_until > $4)))
+          LIMIT $2
+      )
+      "#,
+      cutoff,
+      BATCH_SIZE,
+      banned_only,
+      now
+    )
+    .execute(pool)
+    .await?
+    .rows_affected();
+
+    deleted += batch;
+    if batch < BATCH_SIZE as u64 {
+      break;
+    }
+  }
+
+  if deleted > 0 {
+    tracing::info!("Deleted {} scrobbles older than {} days", deleted, days);
+  }
+
+  Ok(())
+}
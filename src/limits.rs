@@ -0,0 +1,218 @@
+use std::{
+  collections::HashMap,
+  sync::{
+    atomic::{AtomicI64, AtomicU32, Ordering},
+    Arc, Mutex,
+  },
+};
+
+use axum::{
+  extract::{Request, State},
+  http::{header, HeaderMap, HeaderValue, StatusCode},
+  middleware::Next,
+  response::{IntoResponse, Response},
+  Json,
+};
+use serde_json::json;
+use tokio::sync::Semaphore;
+
+use crate::{
+  auth::{self, extract_token_from_header},
+  db::DbPool,
+};
+
+/// Windows are pruned once this many tokens are being tracked
+const MAX_TRACKED_TOKENS: usize = 10_000;
+
+/// Caps how many requests an endpoint serves at once, shedding the rest
+#[derive(Clone)]
+pub struct ConcurrencyLimit {
+  name: &'static str,
+  semaphore: Arc<Semaphore>,
+  retry_after_secs: u64,
+}
+
+impl ConcurrencyLimit {
+  pub fn new(name: &'static str, permits: usize, retry_after_secs: u64) -> Self {
+    Self {
+      name,
+      semaphore: Arc::new(Semaphore::new(permits)),
+      retry_after_secs,
+    }
+  }
+}
+
+/// Middleware: run the request if a slot is free, otherwise 503 + Retry-After
+pub async fn limit_concurrency(
+  State(limit): State<ConcurrencyLimit>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let Ok(permit) = limit.semaphore.clone().try_acquire_owned() else {
+    tracing::warn!("Shedding request to {}: concurrency limit reached", limit.name);
+    return (
+      StatusCode::SERVICE_UNAVAILABLE,
+      [(header::RETRY_AFTER, limit.retry_after_secs.to_string())],
+      Json(json!({ "error": "Server is busy, please retry later" })),
+    )
+      .into_response();
+  };
+
+  let response = next.run(request).await;
+  drop(permit);
+  response
+}
+
+/// Per-token request counter over fixed windows, keyed by token id so only
+/// tokens that exist take up space. Clones share the counters and the
+/// limit, which can be changed while running (see `set`).
+#[derive(Clone)]
+pub struct RateLimiter {
+  limit: Arc<AtomicU32>,
+  window_secs: Arc<AtomicI64>,
+  windows: Arc<Mutex<HashMap<i64, (i64, u32)>>>,
+}
+
+/// Where a token stands in its current window
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitStatus {
+  pub limit: u32,
+  pub remaining: u32,
+  /// Unix time the current window ends
+  pub reset: i64,
+  /// This request went over the limit
+  pub exceeded: bool,
+}
+
+impl RateLimiter {
+  pub fn new(limit: u32, window_secs: u64) -> Self {
+    Self {
+      limit: Arc::new(AtomicU32::new(limit)),
+      window_secs: Arc::new(AtomicI64::new(window_secs as i64)),
+      windows: Arc::new(Mutex::new(HashMap::new())),
+    }
+  }
+
+  /// Change the limit; a limit of 0 turns enforcement off. Counts carry over
+  /// into the current window.
+  pub fn set(&self, limit: u32, window_secs: u64) {
+    self.limit.store(limit, Ordering::Relaxed);
+    self.window_secs.store(window_secs.max(1) as i64, Ordering::Relaxed);
+  }
+
+  pub fn limit(&self) -> u32 {
+    self.limit.load(Ordering::Relaxed)
+  }
+
+  /// Count a request for the token `token_id` at `now`
+  pub fn hit(&self, token_id: i64, now: i64) -> RateLimitStatus {
+    let limit = self.limit();
+    let window_secs = self.window_secs.load(Ordering::Relaxed);
+    let start = now - now.rem_euclid(window_secs);
+    let mut windows = self.windows.lock().unwrap_or_else(|e| e.into_inner());
+
+    if windows.len() >= MAX_TRACKED_TOKENS {
+      windows.retain(|_, (window_start, _)| *window_start == start);
+    }
+
+    let window = windows.entry(token_id).or_insert((start, 0));
+    if window.0 != start {
+      *window = (start, 0);
+    }
+    window.1 = window.1.saturating_add(1);
+
+    RateLimitStatus {
+      limit,
+      remaining: limit.saturating_sub(window.1),
+      reset: start + window_secs,
+      exceeded: window.1 > limit,
+    }
+  }
+}
+
+impl RateLimitStatus {
+  pub fn apply(&self, headers: &mut HeaderMap) {
+    for (name, value) in [
+      ("x-ratelimit-limit", self.limit as i64),
+      ("x-ratelimit-remaining", self.remaining as i64),
+      ("x-ratelimit-reset", self.reset),
+    ] {
+      headers.insert(name, HeaderValue::from(value));
+    }
+  }
+}
+
+/// The id of the request's bearer token, if it is a valid one. Unknown
+/// tokens aren't counted, so made-up ones can't fill the limiter.
+async fn token_id(pool: &DbPool, headers: &HeaderMap) -> Option<i64> {
+  let token = headers
+    .get(header::AUTHORIZATION)
+    .and_then(|h| h.to_str().ok())
+    .and_then(extract_token_from_header)?;
+
+  match auth::lookup_token(pool, &token).await {
+    Ok(owner) => owner.map(|owner| owner.token_id),
+    // The handler reports the database error
+    Err(_) => None,
+  }
+}
+
+/// Middleware: count token-authenticated requests and report the budget in
+/// `X-RateLimit-*` headers. Nothing is rejected here; clients are expected
+/// to slow down on their own as `Remaining` approaches zero.
+pub async fn rate_limit_headers(
+  State((limiter, pool)): State<(RateLimiter, DbPool)>,
+  request: Request,
+  next: Next,
+) -> Response {
+  let Some(token_id) = token_id(&pool, request.headers()).await else {
+    return next.run(request).await;
+  };
+
+  let status = limiter.hit(token_id, chrono::Utc::now().timestamp());
+  let mut response = next.run(request).await;
+
+  // Banned users' tokens resolve but get 403, and still have a budget. An
+  // enforced limit further in (see `enforce_rate_limit`) reports its own.
+  if response.status() != StatusCode::UNAUTHORIZED && !response.headers().contains_key("x-ratelimit-limit") {
+    status.apply(response.headers_mut());
+  }
+
+  response
+}
+
+/// Middleware: like `rate_limit_headers`, but requests over the limit are
+/// turned away with 429 and `Retry-After` instead of reaching the handler
+pub async fn enforce_rate_limit(
+  State((limiter, pool)): State<(RateLimiter, DbPool)>,
+  request: Request,
+  next: Next,
+) -> Response {
+  if limiter.limit() == 0 {
+    return next.run(request).await;
+  }
+  let Some(token_id) = token_id(&pool, request.headers()).await else {
+    return next.run(request).await;
+  };
+
+  let now = chrono::Utc::now().timestamp();
+  let status = limiter.hit(token_id, now);
+
+  let mut response = if status.exceeded {
+    tracing::warn!("Rate limit exceeded on {}", request.uri().path());
+    (
+      StatusCode::TOO_MANY_REQUESTS,
+      [(header::RETRY_AFTER, (status.reset - now).max(1).to_string())],
+      Json(json!({ "error": "Rate limit exceeded, please slow down" })),
+    )
+      .into_response()
+  } else {
+    next.run(request).await
+  };
+
+  if response.status() != StatusCode::UNAUTHORIZED {
+    status.apply(response.headers_mut());
+  }
+
+  response
+}
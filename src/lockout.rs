@@ -0,0 +1,103 @@
+//! Brute-force protection for password logins: failures are counted per
+//! username and per client IP, and past a threshold further attempts are
+//! refused for a lockout that doubles with each failure.
+
+use crate::db::DbPool;
+
+/// Failures allowed for one username before it is locked
+const FREE_ATTEMPTS_PER_USER: i32 = 5;
+/// Higher for IPs, which can be shared by many people behind a NAT
+const FREE_ATTEMPTS_PER_IP: i32 = 20;
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+/// Failures older than this no longer count
+const FAILURE_WINDOW_SECS: i64 = 24 * 60 * 60;
+
+fn keys(username: &str, ip: Option<&str>) -> Vec<(String, i32)> {
+  let mut keys = vec![(format!("user:{}", username), FREE_ATTEMPTS_PER_USER)];
+  if let Some(ip) = ip {
+    keys.push((format!("ip:{}", ip), FREE_ATTEMPTS_PER_IP));
+  }
+  keys
+}
+
+fn lockout_secs(failures: i32, free_attempts: i32) -> Option<i64> {
+  let over = failures - free_attempts;
+  if over < 0 {
+    return None;
+  }
+  // 30s, 60s, 120s, ... capped at an hour
+  Some(BASE_LOCKOUT_SECS.saturating_mul(1i64 << over.min(20)).min(MAX_LOCKOUT_SECS))
+}
+
+/// Seconds until the username or IP may try again, if either is locked
+pub async fn retry_after(pool: &DbPool, username: &str, ip: Option<&str>) -> Result<Option<i64>, sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  let keys: Vec<String> = keys(username, ip).into_iter().map(|(key, _)| key).collect();
+
+  let locked_until = sqlx::query_scalar!(
+    "SELECT MAX(locked_until) FROM login_failures WHERE key = ANY($1) AND locked_until > $2",
+    &keys,
+    now
+  )
+  .fetch_one(pool)
+  .await?;
+
+  Ok(locked_until.map(|until| until - now))
+}
+
+/// Count a failed login against the username and IP
+pub async fn record_failure(pool: &DbPool, username: &str, ip: Option<&str>) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+  let window_start = now - FAILURE_WINDOW_SECS;
+
+  for (key, free_attempts) in keys(username, ip) {
+    let failures = sqlx::query_scalar!(
+      r#"
+      INSERT INTO login_failures (key, failures, last_failure_at)
+      VALUES ($1, 1, $2)
+      ON CONFLICT (key) DO UPDATE
+      SET failures = CASE WHEN login_failures.last_failure_at < $3 THEN 1 ELSE login_failures.failures + 1 END,
+          last_failure_at = $2
+      RETURNING failures
+      "#,
+      key,
+      now,
+      window_start
+    )
+    .fetch_one(pool)
+    .await?;
+
+    if let Some(secs) = lockout_secs(failures, free_attempts) {
+      tracing::warn!("Locking logins for {} for {}s after {} failures", key, secs, failures);
+      sqlx::query!(
+        "UPDATE login_failures SET locked_until = $2 WHERE key = $1",
+        key,
+        now + secs
+      )
+      .execute(pool)
+      .await?;
+    }
+  }
+
+  // Forget keys that have been quiet for a full window
+  sqlx::query!(
+    "DELETE FROM login_failures WHERE last_failure_at < $1 AND COALESCE(locked_until, 0) < $2",
+    window_start,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// Clear the username's failures after a successful login. The IP's count is
+/// kept so one valid account can't be used to reset it.
+pub async fn record_success(pool: &DbPool, username: &str) -> Result<(), sqlx::Error> {
+  sqlx::query!("DELETE FROM login_failures WHERE key = $1", format!("user:{}", username))
+    .execute(pool)
+    .await?;
+
+  Ok(())
+}
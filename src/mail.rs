@@ -0,0 +1,87 @@
+//! Outgoing mail over SMTP (`SMTP_HOST`), used to verify addresses and send
+//! password reset tokens. Without it those endpoints answer 503.
+
+use std::time::Duration;
+
+use lettre::{
+  message::{header::ContentType, Mailbox},
+  transport::smtp::authentication::Credentials,
+  Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::Config;
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(20);
+pub const MAX_ADDRESS_LEN: usize = 254;
+
+pub struct Mailer {
+  transport: AsyncSmtpTransport<Tokio1Executor>,
+  from: Mailbox,
+  instance_name: String,
+}
+
+impl Mailer {
+  /// The configured SMTP relay, or `None` when `SMTP_HOST` isn't set
+  pub fn from_config(config: &Config) -> Result<Option<Self>, String> {
+    let Some(host) = config.smtp_host.as_deref() else {
+      return Ok(None);
+    };
+
+    let from = config
+      .smtp_from
+      .as_deref()
+      .unwrap_or_default()
+      .parse::<Mailbox>()
+      .map_err(|e| format!("Invalid SMTP_FROM: {}", e))?;
+
+    let builder = match config.smtp_tls.as_str() {
+      "tls" => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+      "none" => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host)),
+      _ => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+    }
+    .map_err(|e| format!("Invalid SMTP_HOST: {}", e))?;
+
+    let mut builder = builder.port(config.smtp_port).timeout(Some(SEND_TIMEOUT));
+    if let Some(username) = &config.smtp_username {
+      let password = config.smtp_password.clone().unwrap_or_default();
+      builder = builder.credentials(Credentials::new(username.clone(), password));
+    }
+
+    Ok(Some(Self {
+      transport: builder.build(),
+      from,
+      instance_name: config.instance_name.clone(),
+    }))
+  }
+
+  /// Send a plain text message; the subject is prefixed with the instance name
+  pub async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), String> {
+    let to = to.parse::<Address>().map_err(|e| format!("Invalid address: {}", e))?;
+
+    let message = Message::builder()
+      .from(self.from.clone())
+      .to(Mailbox::new(None, to))
+      .subject(format!("[{}] {}", self.instance_name, subject))
+      .header(ContentType::TEXT_PLAIN)
+      .body(body)
+      .map_err(|e| format!("Failed to build message: {}", e))?;
+
+    self
+      .transport
+      .send(message)
+      .await
+      .map(|_| ())
+      .map_err(|e| format!("SMTP error: {}", e))
+  }
+}
+
+/// A trimmed address with the domain lowercased, or `None` if it isn't one
+pub fn normalize_address(address: &str) -> Option<String> {
+  let address = address.trim();
+  if address.len() > MAX_ADDRESS_LEN {
+    return None;
+  }
+
+  let parsed = address.parse::<Address>().ok()?;
+  Some(format!("{}@{}", parsed.user(), parsed.domain().to_lowercase()))
+}
@@ -1,35 +1,116 @@
+mod archive;
+mod api_version;
+mod audit;
 mod auth;
+mod chart_cache;
+mod cli;
 mod config;
+mod crypto;
 mod db;
+mod discord;
+mod feed;
+mod goals;
+mod health;
+mod import;
+mod jobs;
+mod limits;
+mod lockout;
+mod mail;
+mod mpd;
+mod net;
+mod now_playing;
+mod oidc;
+mod personal_data;
+mod policy;
+mod preferences;
+mod relay;
 mod routes;
+mod rules;
+mod runtime;
+mod spool;
+mod spotify;
+mod state;
+mod storage;
+mod tls;
+mod versioning;
 
 use axum::{
-    http::StatusCode,
-    response::IntoResponse,
+    extract::DefaultBodyLimit,
+    middleware,
     routing::{get, post},
     Router,
 };
 use tower_http::cors::CorsLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+use std::{net::SocketAddr, sync::Arc};
+
+use clap::Parser;
+use cli::{Cli, Command};
 use config::Config;
+use crypto::SecretBox;
+use limits::{ConcurrencyLimit, RateLimiter};
+use runtime::RuntimeSettings;
+use spool::Spool;
+use state::AppState;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load .env file if present
     let _ = dotenvy::dotenv();
 
-    // Initialize tracing
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
+
+    // Initialize tracing; administration commands only report problems
+    let default_filter = match command {
+        Command::Serve => "scrob=info,tower_http=debug",
+        _ => "scrob=warn",
+    };
     tracing_subscriber::registry()
         .with(
             tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "scrob=info,tower_http=debug".into()),
+                .unwrap_or_else(|_| default_filter.into()),
         )
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    // Load config
-    let config = Config::from_env()?;
+    // Load config: environment variables over the optional TOML file
+    // Empty counts as unset so compose files can pass SCROB_CONFIG through
+    let config_file = cli.config.filter(|path| !path.is_empty());
+    let config = match Config::load(config_file.as_deref()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if cli.check_config {
+        if let Err(e) = check_config(&config).await {
+            eprintln!("Configuration error: {}", e);
+            std::process::exit(1);
+        }
+        match config_file {
+            Some(path) => println!("Configuration OK ({} and environment)", path),
+            None => println!("Configuration OK (environment only)"),
+        }
+        return Ok(());
+    }
+
+    if !matches!(command, Command::Serve) {
+        if let Err(e) = cli::run(command, &config).await {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    serve(config).await
+}
+
+/// Run the HTTP server and background workers until the process is stopped
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+
     tracing::info!("Starting scrob server");
     tracing::info!("Database: {}", config.database_url);
     tracing::info!("Listening on: {}", config.bind_address());
@@ -37,46 +118,322 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Connect to database and run migrations
     let pool = db::create_pool(&config.database_url).await?;
 
-    // Build router
-    let app = Router::new()
+    let secrets = config.secret_key.as_deref().map(|key| Arc::new(SecretBox::new(key)));
+    if secrets.is_none() {
+        tracing::warn!("SECRET_KEY is not set; relay secrets can't be stored");
+    }
+
+    // Verification and password reset mail (SMTP_HOST)
+    let mailer = mail::Mailer::from_config(&config)?.map(Arc::new);
+    if mailer.is_none() {
+        tracing::info!("SMTP_HOST is not set; email verification and password resets are disabled");
+    }
+
+    // Blob storage for media assets
+    let storage = storage::from_config(&config)?;
+    tracing::info!("Asset storage: {}", storage.backend());
+    if let Err(e) = storage::check(storage.as_ref()).await {
+        tracing::warn!("Asset storage is not usable: {}", e);
+    }
+
+    // Scrobbles accepted while the database is unreachable
+    let spool = Arc::new(Spool::new(&config.spool_dir, config.spool_max_entries));
+
+    // Fan-out of new listens for live feed subscribers
+    let feed = feed::spawn_listener(pool.clone());
+
+    // Cache token lookups and batch last_used_at updates (TOKEN_CACHE_TTL)
+    auth::spawn_token_cache(pool.clone(), std::time::Duration::from_secs(config.token_cache_ttl));
+
+    // Limits admins can override in /admin/settings
+    let runtime = RuntimeSettings::new(&config);
+    if let Err(e) = runtime.reload(&pool).await {
+        tracing::warn!("Failed to load server settings, using environment limits: {}", e);
+    }
+    runtime.spawn_refresh(pool.clone());
+
+    let state = AppState {
+        pool: pool.clone(),
+        config: Arc::new(config.clone()),
+        secrets: secrets.clone(),
+        mailer,
+        storage,
+        spool: spool.clone(),
+        feed,
+        runtime: runtime.clone(),
+    };
+
+    // Forward queued listens to relay targets
+    relay::spawn_worker(
+        pool.clone(),
+        secrets.clone(),
+        relay::LastfmApp::from_config(&config),
+        net::Outbound::new(&config.allowed_private_networks),
+    );
+
+    // Scrobble what watched MPD servers play (MPD_HOST, MPD_WATCHER)
+    mpd::spawn(pool.clone(), &config, secrets.clone());
+
+    // Import linked users' Spotify plays (SPOTIFY_CLIENT_ID)
+    spotify::spawn(pool.clone(), &config, secrets.clone());
+
+    // Post listening updates to users' Discord webhooks
+    discord::spawn(pool.clone(), secrets);
+
+    // Replay spooled scrobbles once the database is reachable again
+    spool::spawn_replay(pool.clone(), spool);
+
+    // Process uploaded listening history
+    import::spawn_worker(pool.clone(), config.import_dir.clone().into());
+
+    // Promote now-playing reports that were never scrobbled (opt-in)
+    now_playing::spawn_worker(pool.clone());
+
+    // Scheduled aggregation jobs
+    jobs::spawn_all(pool.clone(), &config, &runtime);
+
+    // Expensive endpoints get their own concurrency cap so they can't
+    // starve scrobble ingestion
+    let heavy = |name: &'static str| {
+        middleware::from_fn_with_state(
+            ConcurrencyLimit::new(name, config.heavy_concurrency_limit, config.load_shed_retry_after),
+            limits::limit_concurrency,
+        )
+    };
+
+    // Scrobble submission is rate limited per token (SCROBBLE_RATE_LIMIT=0
+    // turns this off); the limiter is shared by the routes it covers and
+    // follows changes made in /admin/settings
+    let scrobble_limit = middleware::from_fn_with_state((runtime.scrobble_limiter(), pool.clone()), limits::enforce_rate_limit);
+
+    // The JSON API, served under /api/v1 (see api_version.rs)
+    let api = Router::new()
         // Auth
         .route("/signup", post(routes::signup))
         .route("/login", post(routes::login))
+        .route("/password-reset/request", post(routes::request_password_reset))
+        .route("/password-reset/confirm", post(routes::confirm_password_reset))
+        .route("/auth/oidc/login", get(routes::oidc_login))
+        .route("/tokens", get(routes::list_tokens).post(routes::create_api_token))
+        .route("/tokens/{id}", axum::routing::delete(routes::revoke_token))
+        .route("/tokens/{id}/rotate", post(routes::rotate_token))
+        .route("/sessions", get(routes::list_sessions))
+        .route("/sessions/revoke-all", post(routes::revoke_other_sessions))
         // Scrobbling
-        .route("/now", post(routes::now_playing))
-        .route("/scrob", post(routes::scrobble))
+        .route("/now", get(routes::get_now_playing).post(routes::now_playing).layer(scrobble_limit.clone()))
+        .route(
+            "/scrob",
+            post(routes::scrobble)
+                .layer(DefaultBodyLimit::max(config.scrobble_max_body_bytes))
+                .layer(scrobble_limit),
+        )
+        .route("/feed/live", get(routes::live_feed))
+        .route("/scrobbles/{id}", axum::routing::patch(routes::edit_scrobble).delete(routes::remove_scrobble))
+        .route("/scrobbles/{id}/edits", get(routes::list_scrobble_edits))
+        .route("/library/rename", post(routes::rename_library))
         // Stats
         .route("/recent", get(routes::recent_scrobbles))
-        .route("/top/artists", get(routes::top_artists))
-        .route("/top/tracks", get(routes::top_tracks))
+        .route("/top/artists", get(routes::top_artists).layer(heavy("top_artists")))
+        .route("/top/artists/diff", get(routes::top_artists_diff).layer(heavy("top_artists_diff")))
+        .route("/top/tracks", get(routes::top_tracks).layer(heavy("top_tracks")))
+        .route("/top/albums", get(routes::top_albums).layer(heavy("top_albums")))
+        .route("/track", get(routes::track_detail))
+        .route("/album", get(routes::album_detail).layer(heavy("album_detail")))
+        .route("/library/artists", get(routes::library_artists).layer(heavy("library_artists")))
+        .route("/library/albums", get(routes::library_albums).layer(heavy("library_albums")))
+        .route("/library/tracks", get(routes::library_tracks).layer(heavy("library_tracks")))
+        .route("/stats/overview", get(routes::stats_overview).layer(heavy("stats_overview")))
+        .route("/stats/compare", get(routes::compare_periods).layer(heavy("compare_periods")))
+        .route("/stats/streaks", get(routes::listening_streaks).layer(heavy("listening_streaks")))
+        .route("/stats/milestones", get(routes::listening_milestones).layer(heavy("listening_milestones")))
+        .route("/stats/clock", get(routes::listening_clock).layer(heavy("listening_clock")))
+        .route("/stats/calendar", get(routes::listening_calendar).layer(heavy("listening_calendar")))
+        .route("/stats/on-this-day", get(routes::on_this_day).layer(heavy("on_this_day")))
+        .route("/stats/discoveries", get(routes::discoveries).layer(heavy("discoveries")))
+        .route("/stats/obsessions", get(routes::obsessions).layer(heavy("obsessions")))
+        .route("/stats/sources", get(routes::scrobble_sources).layer(heavy("scrobble_sources")))
+        // Chart archive
+        .route("/charts", get(routes::charts))
+        .route("/charts/backfill", get(routes::chart_backfill_status).post(routes::start_chart_backfill))
+        .route("/charts/global/artists", get(routes::global_top_artists).layer(heavy("global_top_artists")))
+        .route("/charts/global/tracks", get(routes::global_top_tracks).layer(heavy("global_top_tracks")))
         // Public user profiles
         .route("/users/{username}/recent", get(routes::user_recent_scrobbles))
-        .route("/users/{username}/top/artists", get(routes::user_top_artists))
-        .route("/users/{username}/top/tracks", get(routes::user_top_tracks))
+        .route("/users/{username}/now", get(routes::user_now_playing))
+        .route("/users/{username}/top/artists", get(routes::user_top_artists).layer(heavy("user_top_artists")))
+        .route("/users/{username}/top/tracks", get(routes::user_top_tracks).layer(heavy("user_top_tracks")))
+        // Follows
+        .route("/follow/{username}", post(routes::follow_user).delete(routes::unfollow_user))
+        .route("/followers", get(routes::list_followers))
+        .route("/following", get(routes::list_following))
         // Settings
         .route("/settings/privacy", get(routes::get_privacy))
         .route("/settings/privacy", post(routes::update_privacy))
+        .route("/settings/now-playing", get(routes::get_now_playing_settings))
+        .route("/settings/now-playing", post(routes::update_now_playing_settings))
+        .route("/settings/preferences", get(routes::get_preferences).post(routes::update_preferences))
+        .route(
+            "/settings/discord",
+            get(routes::get_discord_webhook).put(routes::put_discord_webhook).delete(routes::delete_discord_webhook),
+        )
+        // Account
+        .route("/account", axum::routing::delete(routes::delete_account))
+        .route("/account/anonymize", post(routes::anonymize_account))
+        .route("/account/email", get(routes::get_email).post(routes::set_email))
+        .route("/account/email/verify", post(routes::verify_email))
+        .route("/account/export", get(routes::export_account).layer(heavy("account_export")))
+        .route("/account/data", get(routes::export_personal_data).layer(heavy("account_data")))
+        .route("/account/move", post(routes::move_account).layer(heavy("account_move")))
+        // Relays
+        .route("/relays", get(routes::list_relays).post(routes::create_relay))
+        .route("/relays/{id}", axum::routing::patch(routes::update_relay).delete(routes::delete_relay))
+        .route("/relays/status", get(routes::relay_status))
+        .route("/relays/retry", post(routes::retry_relays))
+        .route("/relays/lastfm/session", post(routes::lastfm_relay_session))
+        // MPD watcher
+        .route("/mpd", get(routes::get_mpd_watcher).put(routes::put_mpd_watcher).delete(routes::delete_mpd_watcher))
+        // Spotify sync
+        .route("/connect/spotify", get(routes::connect_spotify).delete(routes::disconnect_spotify))
+        .route("/connect/spotify/status", get(routes::spotify_status))
+        // Scrobble rules
+        .route("/rules", get(routes::list_rules).post(routes::create_rule))
+        .route("/rules/{id}", axum::routing::patch(routes::update_rule).delete(routes::delete_rule))
+        // Imports and exports
+        .route("/export", get(routes::export_history).layer(heavy("export_history")))
+        .route("/import", post(routes::create_import))
+        .route("/import/{id}", get(routes::get_import))
+        // Loved tracks
+        .route("/loved", get(routes::list_loved).post(routes::love_track))
+        .route("/loved/{id}", axum::routing::delete(routes::unlove_track))
+        .route("/export/loved", get(routes::export_loved).layer(heavy("export_loved")))
+        // Goals
+        .route("/goals", get(routes::list_goals).post(routes::create_goal))
+        .route("/goals/{id}", axum::routing::delete(routes::delete_goal))
+        // Notifications
+        .route("/notifications", get(routes::notifications))
+        .route("/notifications/{id}/read", post(routes::mark_notification_read))
+        // Announcements
+        .route("/announcements", get(routes::announcements))
+        .route("/announcements/{id}/read", post(routes::mark_announcement_read))
         // Admin
         .route("/admin/users", get(routes::list_users))
         .route("/admin/users/{id}", get(routes::get_user))
         .route("/admin/users/{id}", axum::routing::delete(routes::delete_user))
         .route("/admin/users/{id}/admin", post(routes::toggle_admin))
-        .route("/admin/stats", get(routes::get_stats))
+        .route("/admin/users/{id}/ban", post(routes::ban_user).delete(routes::unban_user))
+        .route("/admin/users/{id}/merge", post(routes::merge_user))
+        .route("/admin/settings", get(routes::get_server_settings).patch(routes::update_server_settings))
+        .route("/admin/stats", get(routes::get_stats).layer(heavy("admin_stats")))
         .route("/admin/scrobbles/{id}", axum::routing::delete(routes::delete_scrobble))
-        // Health check
-        .route("/health", get(health_check))
+        .route("/admin/scrobbles/bulk-delete", post(routes::bulk_delete_scrobbles))
+        .route("/admin/invites", get(routes::list_invites).post(routes::create_invites))
+        .route("/admin/invites/{code}", axum::routing::delete(routes::delete_invite))
+        .route("/admin/announcements", get(routes::list_announcements).post(routes::create_announcement))
+        .route("/admin/announcements/{id}", axum::routing::delete(routes::delete_announcement));
+
+    // Endpoints whose paths are set in other software (scrobbler protocols,
+    // webhooks, OAuth callbacks, embeds, probes) stay unversioned
+    let mut app = Router::new()
+        .nest(
+            &format!("/api/{}", api_version::CURRENT),
+            api.clone()
+                .route("/overview", get(routes::overview).layer(heavy("overview")))
+                .layer(middleware::from_fn(api_version::tag)),
+        )
+        // ListenBrainz-compatible API
+        .route("/1/submit-listens", post(routes::submit_listens))
+        .route("/1/validate-token", get(routes::validate_token))
+        // Last.fm-compatible API
+        .route("/2.0", get(routes::lastfm_api).post(routes::lastfm_api))
+        .route("/2.0/", get(routes::lastfm_api).post(routes::lastfm_api))
+        .route("/lastfm/authorize", post(routes::authorize_lastfm_token))
+        // Legacy Audioscrobbler 1.2 protocol (handshake is `/?hs=true`)
+        .route("/", get(routes::audioscrobbler_handshake))
+        .route("/audioscrobbler/nowplaying", post(routes::audioscrobbler_now_playing))
+        .route("/audioscrobbler/submission", post(routes::audioscrobbler_submission))
+        // Subsonic API (scrobbling only)
+        .route("/rest/ping", get(routes::subsonic_ping).post(routes::subsonic_ping))
+        .route("/rest/ping.view", get(routes::subsonic_ping).post(routes::subsonic_ping))
+        .route("/rest/scrobble", get(routes::subsonic_scrobble).post(routes::subsonic_scrobble))
+        .route("/rest/scrobble.view", get(routes::subsonic_scrobble).post(routes::subsonic_scrobble))
+        .route("/rest/getNowPlaying", get(routes::subsonic_now_playing).post(routes::subsonic_now_playing))
+        .route("/rest/getNowPlaying.view", get(routes::subsonic_now_playing).post(routes::subsonic_now_playing))
+        // Media server webhooks
+        .route("/ingest/jellyfin", post(routes::jellyfin_webhook))
+        // OAuth callbacks, registered with the provider
+        .route("/auth/oidc/callback", get(routes::oidc_callback))
+        .route("/connect/spotify/callback", get(routes::spotify_callback))
+        // Embeds
+        .route("/users/{username}/feed.atom", get(routes::user_atom_feed))
+        .route("/badge/{file}", get(routes::badge))
+        // Server info and API version discovery
+        .route("/api/info", get(routes::server_info))
+        .route("/version", get(routes::version_info))
+        // Health checks; /health is kept for existing probes
+        .route("/health", get(routes::health_live))
+        .route("/health/live", get(routes::health_live))
+        .route("/health/ready", get(routes::health_ready));
+
+    // Pre-/api/v1 paths, marked deprecated (LEGACY_ROUTES=false drops them).
+    // The overview was the one route already under /api.
+    if config.legacy_routes {
+        app = app.merge(
+            api.route("/api/overview", get(routes::overview).layer(heavy("overview")))
+                .layer(middleware::from_fn(api_version::deprecate_legacy)),
+        );
+    }
+
+    // Optional request audit log
+    if config.audit_log {
+        tracing::info!("Audit logging enabled (body sample rate {})", config.audit_sample_rate);
+        app = app.layer(middleware::from_fn_with_state(state.clone(), audit::log_requests));
+    }
+
+    // Advertise per-token request budgets (RATE_LIMIT=0 turns this off)
+    if config.rate_limit > 0 {
+        let limiter = RateLimiter::new(config.rate_limit, config.rate_limit_window);
+        app = app.layer(middleware::from_fn_with_state((limiter, pool.clone()), limits::rate_limit_headers));
+    }
+
+    // Resolve the client address first, so nothing reads a spoofed one
+    let trusted_proxies: Arc<[net::IpNet]> = config.trusted_proxies.clone().into();
+    app = app.layer(middleware::from_fn_with_state(trusted_proxies, net::resolve_client_ip));
+
+    let app = app
         .layer(CorsLayer::permissive())
-        .with_state(pool);
+        .with_state(state);
+
+    // Run server, over HTTPS when TLS_CERT_PATH/TLS_KEY_PATH are set
+    if let (Some(cert_path), Some(key_path)) = (config.tls_cert_path.clone(), config.tls_key_path.clone()) {
+        let tls_config = tls::load(&cert_path, &key_path).await?;
+        tls::spawn_reload(tls_config.clone(), cert_path, key_path);
 
-    // Run server
-    let listener = tokio::net::TcpListener::bind(&config.bind_address()).await?;
-    tracing::info!("REST API: http://{}", config.bind_address());
+        let addr: SocketAddr = tokio::net::lookup_host(config.bind_address())
+            .await?
+            .next()
+            .ok_or("HOST/PORT didn't resolve to an address")?;
+        tracing::info!("REST API: https://{}", addr);
 
-    axum::serve(listener, app).await?;
+        axum_server::bind_rustls(addr, tls_config)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await?;
+    } else {
+        let listener = tokio::net::TcpListener::bind(&config.bind_address()).await?;
+        tracing::info!("REST API: http://{}", config.bind_address());
+
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>()).await?;
+    }
 
     Ok(())
 }
 
-async fn health_check() -> impl IntoResponse {
-    (StatusCode::OK, "OK")
+/// `--check-config`: the checks startup would run, short of connecting to
+/// the database
+async fn check_config(config: &Config) -> Result<(), String> {
+    mail::Mailer::from_config(config)?;
+    storage::from_config(config)?;
+    if let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) {
+        tls::load(cert_path, key_path).await?;
+    }
+    Ok(())
 }
@@ -0,0 +1,479 @@
+//! Built-in MPD watcher, so an MPD server can be scrobbled without running
+//! mpdscribble next to it. One task per watched server idles on the player
+//! subsystem and turns what it sees into now-playing reports and scrobbles.
+
+use std::{
+  collections::{hash_map::Entry, HashMap},
+  sync::Arc,
+  time::Duration,
+};
+
+use tokio::{
+  io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+  net::{
+    tcp::{OwnedReadHalf, OwnedWriteHalf},
+    TcpStream,
+  },
+  task::JoinHandle,
+};
+
+use crate::{
+  config::Config,
+  crypto::SecretBox,
+  db::DbPool,
+  health,
+  net::Outbound,
+  now_playing, relay,
+  routes::scrobble::{is_mbid, normalize_mbid, submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+/// How often the set of watched servers is reloaded
+const SYNC_INTERVAL: Duration = Duration::from_secs(60);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Wait before reconnecting after the connection drops or fails
+const RECONNECT_DELAY: Duration = Duration::from_secs(30);
+/// Longest response line read from a server; tag values stay well below it
+const MAX_LINE_LEN: u64 = 64 * 1024;
+/// Going back to the start of the same song within this many seconds after
+/// playing it further counts as a repeat
+const REPEAT_SLACK_SECS: f64 = 5.0;
+
+/// An MPD server to watch and the user its plays belong to
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Target {
+  user_id: i64,
+  host: String,
+  port: u16,
+  password: Option<String>,
+  /// Configured by the user through /mpd rather than the environment
+  per_user: bool,
+}
+
+/// Start the supervisor that keeps one watcher running per configured server
+pub fn spawn(pool: DbPool, config: &Config, secrets: Option<Arc<SecretBox>>) {
+  let server_wide = config.mpd_host.clone().zip(config.mpd_user.clone());
+  if server_wide.is_none() && !config.mpd_watcher {
+    return;
+  }
+
+  let per_user = config.mpd_watcher;
+  let port = config.mpd_port;
+  let password = config.mpd_password.clone();
+  let outbound = Outbound::new(&config.allowed_private_networks);
+
+  tokio::spawn(async move {
+    let mut running: HashMap<Target, JoinHandle<()>> = HashMap::new();
+
+    let mut interval = tokio::time::interval(SYNC_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("mpd", SYNC_INTERVAL);
+
+      let mut targets = Vec::new();
+      if let Some((host, username)) = &server_wide {
+        match sqlx::query!(
+          r#"
+          SELECT id, (banned_at IS NOT NULL AND (banned_until IS NULL OR banned_until > $2)) as "banned!"
+          FROM users
+          WHERE username = $1
+          "#,
+          username,
+          chrono::Utc::now().timestamp()
+        )
+        .fetch_optional(&pool)
+        .await
+        {
+          // Banned users aren't scrobbled for; the watcher resumes when the ban ends
+          Ok(Some(user)) if user.banned => {}
+          Ok(Some(user)) => targets.push(Target {
+            user_id: user.id,
+            host: host.clone(),
+            port,
+            password: password.clone(),
+            per_user: false,
+          }),
+          Ok(None) => tracing::warn!("MPD_USER {} does not exist", username),
+          Err(e) => tracing::error!("Failed to look up MPD_USER: {}", e),
+        }
+      }
+
+      if per_user {
+        match load_targets(&pool, secrets.as_deref()).await {
+          Ok(loaded) => targets.extend(loaded),
+          Err(e) => {
+            tracing::error!("Failed to load MPD watchers: {}", e);
+            continue;
+          }
+        }
+      }
+
+      // Changed settings show up as a new target, replacing the old watcher
+      running.retain(|target, handle| {
+        let keep = targets.contains(target) && !handle.is_finished();
+        if !keep {
+          handle.abort();
+        }
+        keep
+      });
+
+      for target in targets {
+        if let Entry::Vacant(entry) = running.entry(target) {
+          let handle = tokio::spawn(watch(pool.clone(), entry.key().clone(), outbound.clone()));
+          entry.insert(handle);
+        }
+      }
+    }
+  });
+}
+
+async fn load_targets(pool: &DbPool, secrets: Option<&SecretBox>) -> Result<Vec<Target>, sqlx::Error> {
+  let rows = sqlx::query!(
+    r#"
+    SELECT w.user_id, w.host, w.port, w.password
+    FROM mpd_watchers w
+    JOIN users u ON u.id = w.user_id
+    WHERE w.enabled = true AND (u.banned_at IS NULL OR u.banned_until <= $1) AND u.anonymized_at IS NULL
+    "#,
+    chrono::Utc::now().timestamp()
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let mut targets = Vec::with_capacity(rows.len());
+  for row in rows {
+    let password = match (row.password, secrets) {
+      (None, _) => None,
+      (Some(_), None) => {
+        tracing::warn!("SECRET_KEY is not configured, can't decrypt MPD password for user {}", row.user_id);
+        continue;
+      }
+      (Some(password), Some(secrets)) => match secrets.decrypt(&password) {
+        Ok(password) => Some(password),
+        Err(e) => {
+          tracing::warn!("Can't decrypt MPD password for user {}: {}", row.user_id, e);
+          continue;
+        }
+      },
+    };
+
+    targets.push(Target {
+      user_id: row.user_id,
+      host: row.host,
+      port: row.port as u16,
+      password,
+      per_user: true,
+    });
+  }
+
+  Ok(targets)
+}
+
+/// Watch one server for as long as it stays configured, reconnecting on errors
+async fn watch(pool: DbPool, target: Target, outbound: Outbound) {
+  loop {
+    let result = match Connection::open(&target, &outbound).await {
+      Ok(mut conn) => {
+        record_status(&pool, &target, None).await;
+        follow(&pool, &target, &mut conn).await
+      }
+      Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+      tracing::warn!("MPD watcher for user {} ({}:{}): {}", target.user_id, target.host, target.port, e);
+      record_status(&pool, &target, Some(&e)).await;
+    }
+
+    tokio::time::sleep(RECONNECT_DELAY).await;
+  }
+}
+
+/// Note the connection state on a per-user watcher, for GET /mpd
+async fn record_status(pool: &DbPool, target: &Target, error: Option<&str>) {
+  if !target.per_user {
+    return;
+  }
+
+  let result = match error {
+    None => {
+      sqlx::query!(
+        "UPDATE mpd_watchers SET last_connected_at = $1, last_error = NULL WHERE user_id = $2",
+        chrono::Utc::now().timestamp(),
+        target.user_id
+      )
+      .execute(pool)
+      .await
+    }
+    Some(error) => {
+      sqlx::query!("UPDATE mpd_watchers SET last_error = $1 WHERE user_id = $2", error, target.user_id)
+        .execute(pool)
+        .await
+    }
+  };
+
+  if let Err(e) = result {
+    tracing::error!("Failed to record MPD watcher status: {}", e);
+  }
+}
+
+/// A song as MPD reports it
+#[derive(Debug, Clone)]
+struct Song {
+  artist: String,
+  title: String,
+  album: Option<String>,
+  album_artist: Option<String>,
+  track_number: Option<u32>,
+  duration: Option<i64>,
+  artist_mbid: Option<String>,
+  release_mbid: Option<String>,
+  recording_mbid: Option<String>,
+}
+
+/// The song being played and how long it has actually played
+struct Play {
+  song_id: String,
+  song: Song,
+  started_at: i64,
+  /// Seconds played before the current stretch of playback
+  played: i64,
+  /// When playback last resumed, while it's playing
+  resumed_at: Option<i64>,
+  last_elapsed: f64,
+}
+
+impl Play {
+  fn played(&self, now: i64) -> i64 {
+    self.played + self.resumed_at.map_or(0, |resumed| now - resumed)
+  }
+}
+
+/// Report changes of the player until the connection fails
+async fn follow(pool: &DbPool, target: &Target, conn: &mut Connection) -> Result<(), String> {
+  let mut current: Option<Play> = None;
+
+  loop {
+    let status = conn.command("status").await?;
+    let song = conn.command("currentsong").await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let state = field(&status, "state").unwrap_or("stop");
+    let song_id = field(&status, "songid").filter(|_| state != "stop");
+    let elapsed: f64 = field(&status, "elapsed").and_then(|e| e.parse().ok()).unwrap_or(0.0);
+
+    // The previous play is over when the song changes, stops, or repeats
+    if let Some(play) = &current {
+      let repeated = song_id == Some(play.song_id.as_str())
+        && elapsed < REPEAT_SLACK_SECS
+        && play.last_elapsed > elapsed + REPEAT_SLACK_SECS;
+      if song_id != Some(play.song_id.as_str()) || repeated {
+        if let Some(play) = current.take() {
+          finish(pool, target.user_id, play, now).await.map_err(|e| format!("Database error: {}", e))?;
+        }
+      }
+    }
+
+    match (&mut current, song_id) {
+      (Some(play), Some(_)) => {
+        match (state, play.resumed_at) {
+          ("play", None) => play.resumed_at = Some(now),
+          ("pause", Some(resumed)) => {
+            play.played += now - resumed;
+            play.resumed_at = None;
+          }
+          _ => {}
+        }
+        play.last_elapsed = elapsed;
+      }
+      (None, Some(song_id)) => {
+        if let Some(song) = parse_song(&song) {
+          let playing = state == "play";
+          let play = Play {
+            song_id: song_id.to_string(),
+            started_at: now - elapsed as i64,
+            // Joined mid-song: assume it played from the start
+            played: elapsed as i64,
+            resumed_at: playing.then_some(now),
+            last_elapsed: elapsed,
+            song,
+          };
+
+          if playing {
+            report_now_playing(pool, target.user_id, &play).await.map_err(|e| format!("Database error: {}", e))?;
+          }
+          current = Some(play);
+        }
+      }
+      _ => {}
+    }
+
+    conn.command("idle player").await?;
+  }
+}
+
+async fn report_now_playing(pool: &DbPool, user_id: i64, play: &Play) -> Result<(), sqlx::Error> {
+  let listen = relay::Listen {
+    artist: play.song.artist.clone(),
+    track: play.song.title.clone(),
+    album: play.song.album.clone(),
+    duration: play.song.duration,
+    timestamp: play.started_at,
+  };
+
+  now_playing::record(pool, user_id, &listen).await?;
+
+  if let Err(e) = relay::enqueue_now_playing(pool, user_id, &listen).await {
+    tracing::error!("Failed to queue now-playing for relays: {}", e);
+  }
+
+  Ok(())
+}
+
+/// Scrobble a finished play if enough of it was heard
+async fn finish(pool: &DbPool, user_id: i64, play: Play, now: i64) -> Result<(), sqlx::Error> {
+  now_playing::clear(pool, user_id, &play.song.artist, &play.song.title).await?;
+
+  let played = play.played(now);
+  if let Err(reason) = now_playing::check_threshold(played, play.song.duration) {
+    tracing::debug!("Not scrobbling MPD play for user {}: {}", user_id, reason);
+    return Ok(());
+  }
+
+  let song = play.song;
+  let scrob = ScrobbleRequest {
+    artist: song.artist,
+    track: song.title,
+    timestamp: play.started_at.max(0) as u64,
+    album: song.album,
+    album_artist: song.album_artist,
+    duration: song.duration.map(|d| d as u64),
+    track_number: song.track_number,
+    artist_mbid: song.artist_mbid,
+    release_mbid: song.release_mbid,
+    recording_mbid: song.recording_mbid,
+    source: Some("mpd".to_string()),
+  };
+
+  match submit_scrobble(pool, user_id, &scrob).await? {
+    ScrobbleOutcome::Accepted(id) => tracing::info!("Scrobbled MPD play for user {} as {}", user_id, id),
+    ScrobbleOutcome::Duplicate => {}
+    ScrobbleOutcome::Rejected(reason) => tracing::info!("MPD scrobble for user {} rejected: {}", user_id, reason),
+  }
+
+  Ok(())
+}
+
+/// Songs without artist and title tags (most streams) can't be scrobbled
+fn parse_song(fields: &[(String, String)]) -> Option<Song> {
+  let text = |name: &str| field(fields, name).map(str::trim).filter(|v| !v.is_empty()).map(str::to_string);
+  let mbid = |name: &str| normalize_mbid(field(fields, name)).filter(|id| is_mbid(id));
+
+  Some(Song {
+    artist: text("Artist")?,
+    title: text("Title")?,
+    album: text("Album"),
+    album_artist: text("AlbumArtist"),
+    // "3/12" on some files
+    track_number: text("Track").and_then(|t| t.split('/').next()?.parse().ok()),
+    duration: field(fields, "duration")
+      .or_else(|| field(fields, "Time"))
+      .and_then(|d| d.parse::<f64>().ok())
+      .map(|d| d.round() as i64)
+      .filter(|d| *d > 0),
+    artist_mbid: mbid("MUSICBRAINZ_ARTISTID"),
+    release_mbid: mbid("MUSICBRAINZ_ALBUMID"),
+    // Picard's "MusicBrainz Track Id" tag holds the recording
+    recording_mbid: mbid("MUSICBRAINZ_TRACKID"),
+  })
+}
+
+fn field<'a>(fields: &'a [(String, String)], name: &str) -> Option<&'a str> {
+  fields.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+}
+
+/// A client connection speaking MPD's line protocol
+struct Connection {
+  reader: BufReader<OwnedReadHalf>,
+  writer: OwnedWriteHalf,
+}
+
+impl Connection {
+  /// Connect and log in. Per-user servers only get the addresses `outbound`
+  /// permits; the server-wide `MPD_HOST` is the admin's choice.
+  async fn open(target: &Target, outbound: &Outbound) -> Result<Self, String> {
+    let connect = async {
+      if target.per_user {
+        let addrs = outbound.resolve(&target.host, target.port).await?;
+        TcpStream::connect(addrs.as_slice()).await
+      } else {
+        TcpStream::connect((target.host.as_str(), target.port)).await
+      }
+      .map_err(|e| format!("Connection failed: {}", e))
+    };
+    let stream = tokio::time::timeout(CONNECT_TIMEOUT, connect)
+      .await
+      .map_err(|_| "Connection timed out".to_string())??;
+
+    let (reader, writer) = stream.into_split();
+    let mut conn = Connection {
+      reader: BufReader::new(reader),
+      writer,
+    };
+
+    let greeting = conn.read_line().await?;
+    // What other services send isn't repeated, since GET /mpd shows the error
+    if !greeting.starts_with("OK MPD ") {
+      return Err("Not an MPD server".to_string());
+    }
+
+    if let Some(password) = &target.password {
+      conn.command(&format!("password {}", quote(password))).await?;
+    }
+
+    Ok(conn)
+  }
+
+  /// Send a command and collect the `key: value` lines of its response
+  async fn command(&mut self, command: &str) -> Result<Vec<(String, String)>, String> {
+    self
+      .writer
+      .write_all(format!("{}\n", command).as_bytes())
+      .await
+      .map_err(|e| format!("Write failed: {}", e))?;
+
+    let mut fields = Vec::new();
+    loop {
+      let line = self.read_line().await?;
+      if line == "OK" {
+        return Ok(fields);
+      }
+      if let Some(error) = line.strip_prefix("ACK ") {
+        return Err(format!("MPD error: {}", error));
+      }
+      if let Some((key, value)) = line.split_once(": ") {
+        fields.push((key.to_string(), value.to_string()));
+      }
+    }
+  }
+
+  async fn read_line(&mut self) -> Result<String, String> {
+    let mut line = String::new();
+    let read = (&mut self.reader)
+      .take(MAX_LINE_LEN)
+      .read_line(&mut line)
+      .await
+      .map_err(|e| format!("Read failed: {}", e))?;
+    if read == 0 {
+      return Err("Connection closed".to_string());
+    }
+    if !line.ends_with('\n') && read as u64 >= MAX_LINE_LEN {
+      return Err("Response line too long".to_string());
+    }
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+  }
+}
+
+/// Quote a command argument
+fn quote(arg: &str) -> String {
+  format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,294 @@
+//! Client addresses, and which addresses the server may reach for users.
+//!
+//! By default the client is the socket peer; forwarding headers are only
+//! believed when the peer is one of `TRUSTED_PROXIES`, since anyone can send
+//! them and they feed login lockouts and the `last_ip` shown on sessions.
+//!
+//! Connections to hosts users supply (relays, account moves, MPD watchers)
+//! may only reach public addresses, plus `ALLOWED_PRIVATE_NETWORKS`, so they
+//! can't be pointed at loopback, the LAN or cloud metadata services.
+
+use std::{
+  net::{IpAddr, Ipv4Addr, SocketAddr},
+  sync::Arc,
+};
+
+use axum::{
+  extract::{ConnectInfo, Request, State},
+  http::{HeaderMap, HeaderValue},
+  middleware::Next,
+  response::Response,
+};
+
+/// An address range such as `10.0.0.0/8`, or a single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNet {
+  addr: IpAddr,
+  prefix: u8,
+}
+
+impl IpNet {
+  pub fn parse(value: &str) -> Result<Self, String> {
+    let (addr, prefix) = match value.split_once('/') {
+      Some((addr, prefix)) => (addr, Some(prefix)),
+      None => (value, None),
+    };
+
+    let addr: IpAddr = addr
+      .trim()
+      .parse()
+      .map_err(|_| format!("'{}' is not an IP address or range", value))?;
+    let max = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+      Some(prefix) => prefix
+        .trim()
+        .parse()
+        .ok()
+        .filter(|p| *p <= max)
+        .ok_or_else(|| format!("'{}' has an invalid prefix length", value))?,
+      None => max,
+    };
+
+    Ok(IpNet { addr: addr.to_canonical(), prefix })
+  }
+
+  pub fn contains(&self, ip: IpAddr) -> bool {
+    match (self.addr, ip.to_canonical()) {
+      (IpAddr::V4(net), IpAddr::V4(ip)) => {
+        let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+        net.to_bits() & mask == ip.to_bits() & mask
+      }
+      (IpAddr::V6(net), IpAddr::V6(ip)) => {
+        let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+        net.to_bits() & mask == ip.to_bits() & mask
+      }
+      _ => false,
+    }
+  }
+}
+
+/// Comma-separated addresses and ranges, as in `TRUSTED_PROXIES`
+pub fn parse_nets(value: &str) -> Result<Vec<IpNet>, String> {
+  value
+    .split(',')
+    .map(str::trim)
+    .filter(|v| !v.is_empty())
+    .map(IpNet::parse)
+    .collect()
+}
+
+/// Replace `X-Real-IP` with the resolved client address, which is what
+/// `auth::ClientInfo` reads. Requests without a peer address (no connect
+/// info) lose the header rather than trusting it.
+pub async fn resolve_client_ip(State(trusted): State<Arc<[IpNet]>>, mut request: Request, next: Next) -> Response {
+  let peer = request
+    .extensions()
+    .get::<ConnectInfo<SocketAddr>>()
+    .map(|ConnectInfo(addr)| addr.ip().to_canonical());
+  let client = peer.map(|peer| client_address(peer, request.headers(), &trusted));
+
+  let headers = request.headers_mut();
+  match client.and_then(|ip| HeaderValue::from_str(&ip.to_string()).ok()) {
+    Some(value) => {
+      headers.insert("x-real-ip", value);
+    }
+    None => {
+      headers.remove("x-real-ip");
+    }
+  }
+
+  next.run(request).await
+}
+
+/// The peer, or when it's a trusted proxy, the nearest address in the
+/// forwarding chain that isn't one
+fn client_address(peer: IpAddr, headers: &HeaderMap, trusted: &[IpNet]) -> IpAddr {
+  let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+  if !is_trusted(peer) {
+    return peer;
+  }
+
+  let forwarded: Vec<&str> = headers
+    .get_all("x-forwarded-for")
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .map(str::trim)
+    .collect();
+
+  if forwarded.is_empty() {
+    return headers
+      .get("x-real-ip")
+      .and_then(|v| v.to_str().ok())
+      .and_then(parse_forwarded)
+      .unwrap_or(peer);
+  }
+
+  // Each proxy appends the address it saw, so walk back from the nearest;
+  // an entry that doesn't parse ends the walk at the proxy that added it
+  let mut client = peer;
+  for entry in forwarded.iter().rev() {
+    let Some(ip) = parse_forwarded(entry) else {
+      break;
+    };
+    client = ip;
+    if !is_trusted(ip) {
+      break;
+    }
+  }
+  client
+}
+
+/// An address as proxies write it: bare, or with a port
+fn parse_forwarded(value: &str) -> Option<IpAddr> {
+  let value = value.trim();
+  value
+    .parse::<IpAddr>()
+    .or_else(|_| value.parse::<SocketAddr>().map(|addr| addr.ip()))
+    .ok()
+    .map(|ip| ip.to_canonical())
+}
+
+/// Whether `ip` is a globally routable unicast address
+fn is_public(ip: IpAddr) -> bool {
+  match ip.to_canonical() {
+    IpAddr::V4(ip) => {
+      let [a, b, c, _] = ip.octets();
+      !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", carrier-grade NAT, IETF assignments, benchmarking, reserved
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || (a == 192 && b == 0 && c == 0)
+        || (a == 198 && (18..20).contains(&b))
+        || a >= 240)
+    }
+    IpAddr::V6(ip) => {
+      let segments = ip.segments();
+      // NAT64 reaches the embedded IPv4 address
+      if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return is_public(IpAddr::V4(Ipv4Addr::from_bits(ip.to_bits() as u32)));
+      }
+      !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        || ip.is_unique_local()
+        || ip.is_unicast_link_local()
+        // Documentation and deprecated site-local
+        || (segments[0] == 0x2001 && segments[1] == 0xdb8)
+        || (segments[0] & 0xffc0) == 0xfec0)
+    }
+  }
+}
+
+/// Where requests to user-supplied URLs may go: public addresses and the
+/// configured `ALLOWED_PRIVATE_NETWORKS`
+#[derive(Debug, Clone)]
+pub struct Outbound {
+  allowed: Arc<[IpNet]>,
+}
+
+impl Outbound {
+  pub fn new(allowed: &[IpNet]) -> Self {
+    Self { allowed: allowed.into() }
+  }
+
+  pub fn permits(&self, ip: IpAddr) -> bool {
+    is_public(ip) || self.allowed.iter().any(|net| net.contains(ip))
+  }
+
+  /// Check a URL before storing or fetching it: http(s), and every address
+  /// its host resolves to is permitted
+  pub async fn check_url(&self, url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid URL: {}", e))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+      return Err("URL must start with http:// or https://".to_string());
+    }
+    let host = parsed.host_str().ok_or("URL has no host")?;
+    let port = parsed.port_or_known_default().unwrap_or(80);
+
+    self.resolve(host, port).await.map(|_| ())
+  }
+
+  /// Resolve a host for a plain TCP connection, failing unless every
+  /// address it resolves to is permitted. Connect to the returned addresses
+  /// so the host can't be re-resolved somewhere else in between.
+  pub async fn resolve(&self, host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    if let Some(ip) = literal_ip(host) {
+      return match self.permits(ip) {
+        true => Ok(vec![SocketAddr::new(ip, port)]),
+        false => Err(format!("{} is a private or local address", ip)),
+      };
+    }
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+      .await
+      .map_err(|e| format!("Could not resolve {}: {}", host, e))?
+      .collect();
+    match addrs.iter().find(|addr| !self.permits(addr.ip())) {
+      Some(addr) => Err(format!("{} resolves to a private or local address ({})", host, addr.ip())),
+      None if addrs.is_empty() => Err(format!("Could not resolve {}", host)),
+      None => Ok(addrs),
+    }
+  }
+
+  /// Check a stored URL before each request. Host names are checked when
+  /// they're resolved (see `client_builder`), so only literal addresses are
+  /// looked at here.
+  pub fn check_literal(&self, url: &str) -> Result<(), String> {
+    let host = reqwest::Url::parse(url).ok().and_then(|u| u.host_str().and_then(literal_ip));
+    match host {
+      Some(ip) if !self.permits(ip) => Err(format!("{} is a private or local address", ip)),
+      _ => Ok(()),
+    }
+  }
+
+  /// A client that only connects to permitted addresses, whether a host name
+  /// resolves there later (DNS rebinding) or a redirect points there
+  pub fn client_builder(&self) -> reqwest::ClientBuilder {
+    let redirects = self.clone();
+    reqwest::Client::builder()
+      .dns_resolver(Arc::new(self.clone()))
+      .redirect(reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() >= 10 {
+          return attempt.error("too many redirects");
+        }
+        match redirects.check_literal(attempt.url().as_str()) {
+          Ok(()) => attempt.follow(),
+          Err(e) => attempt.error(e),
+        }
+      }))
+  }
+}
+
+impl reqwest::dns::Resolve for Outbound {
+  fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+    let outbound = self.clone();
+    Box::pin(async move {
+      let host = name.as_str().to_string();
+      let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), 0))
+        .await?
+        .filter(|addr| outbound.permits(addr.ip()))
+        .collect();
+
+      if addrs.is_empty() {
+        return Err(format!("{} resolves to no public address", host).into());
+      }
+      Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+    })
+  }
+}
+
+/// The address in a URL host, which is bracketed for IPv6
+fn literal_ip(host: &str) -> Option<IpAddr> {
+  host
+    .trim_start_matches('[')
+    .trim_end_matches(']')
+    .parse::<IpAddr>()
+    .ok()
+}
@@ -0,0 +1,253 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::{
+  db::DbPool,
+  feed::{self, FeedEventKind},
+  health,
+  relay::Listen,
+  routes::scrobble::{submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+const PROMOTE_INTERVAL: Duration = Duration::from_secs(60);
+/// Slack for players that report the next track slightly before the previous one ends
+const PROMOTE_TOLERANCE_SECS: i64 = 15;
+/// A scrobble this close to the start time counts as the report being scrobbled
+const SCROBBLE_MATCH_SLACK_SECS: i64 = 60;
+/// How long a report without a duration counts as playing
+pub const DEFAULT_TTL_SECS: i64 = 10 * 60;
+/// Tracks shorter than this are never scrobbled by players scrob watches
+const MIN_TRACK_SECS: i64 = 30;
+/// A play this long counts even when it's less than half the track
+const SCROBBLE_AFTER_SECS: i64 = 4 * 60;
+
+/// What a user is currently listening to
+#[derive(Debug, Serialize)]
+pub struct CurrentTrack {
+  pub artist: String,
+  pub track: String,
+  pub album: Option<String>,
+  pub duration: Option<i64>,
+  pub started_at: i64,
+}
+
+struct Entry {
+  user_id: i64,
+  artist: String,
+  track: String,
+  album: Option<String>,
+  duration: i64,
+  started_at: i64,
+}
+
+/// Last.fm's rule for when a play counts: tracks over 30 seconds, played for
+/// half their length or four minutes, whichever comes first
+pub fn check_threshold(played: i64, duration: Option<i64>) -> Result<(), String> {
+  let required = match duration {
+    Some(duration) if duration <= MIN_TRACK_SECS => {
+      return Err(format!("Tracks must be longer than {} seconds", MIN_TRACK_SECS));
+    }
+    Some(duration) => (duration / 2).min(SCROBBLE_AFTER_SECS),
+    None => SCROBBLE_AFTER_SECS,
+  };
+
+  if played < required {
+    return Err(format!("Played {} of the {} seconds needed to scrobble", played, required));
+  }
+
+  Ok(())
+}
+
+/// Store the user's current now-playing report
+pub async fn record(pool: &DbPool, user_id: i64, listen: &Listen) -> Result<(), sqlx::Error> {
+  // The previous report may have finished playing without ever being scrobbled
+  promote_due(pool, Some(user_id), listen.timestamp + PROMOTE_TOLERANCE_SECS).await?;
+
+  sqlx::query!(
+    r#"
+    INSERT INTO now_playing (user_id, artist, track, album, duration, started_at, promoted)
+    VALUES ($1, $2, $3, $4, $5, $6, false)
+    ON CONFLICT (user_id) DO UPDATE
+    SET artist = EXCLUDED.artist,
+        track = EXCLUDED.track,
+        album = EXCLUDED.album,
+        duration = EXCLUDED.duration,
+        started_at = EXCLUDED.started_at,
+        promoted = false
+    "#,
+    user_id,
+    listen.artist,
+    listen.track,
+    listen.album,
+    listen.duration,
+    listen.timestamp
+  )
+  .execute(pool)
+  .await?;
+
+  if let Err(e) = feed::publish(pool, user_id, FeedEventKind::NowPlaying, None, listen).await {
+    tracing::error!("Failed to publish now-playing to the live feed: {}", e);
+  }
+
+  Ok(())
+}
+
+/// Drop the user's report for a track the player stopped, so an unfinished
+/// play isn't promoted to a scrobble later
+pub async fn clear(pool: &DbPool, user_id: i64, artist: &str, track: &str) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    "DELETE FROM now_playing WHERE user_id = $1 AND artist = $2 AND track = $3",
+    user_id,
+    artist,
+    track
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// The user's report if the track is still playing at `now`
+pub async fn current(pool: &DbPool, user_id: i64, now: i64) -> Result<Option<CurrentTrack>, sqlx::Error> {
+  sqlx::query_as!(
+    CurrentTrack,
+    r#"
+    SELECT artist, track, album, duration, started_at
+    FROM now_playing
+    WHERE user_id = $1 AND started_at + COALESCE(duration, $3) > $2
+    "#,
+    user_id,
+    now,
+    DEFAULT_TTL_SECS
+  )
+  .fetch_optional(pool)
+  .await
+}
+
+/// Start the background task that promotes finished now-playing reports and
+/// clears expired ones
+pub fn spawn_worker(pool: DbPool) {
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(PROMOTE_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("now_playing", PROMOTE_INTERVAL);
+      let now = chrono::Utc::now().timestamp();
+      if let Err(e) = promote_due(&pool, None, now).await {
+        tracing::error!("Now-playing promotion failed: {}", e);
+      }
+      if let Err(e) = expire(&pool, now).await {
+        tracing::error!("Now-playing expiry failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Delete reports whose track has finished, keeping ones still waiting to be promoted
+async fn expire(pool: &DbPool, now: i64) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    r#"
+    DELETE FROM now_playing n
+    USING users u
+    WHERE u.id = n.user_id
+      AND n.started_at + COALESCE(n.duration, $2) <= $1
+      AND (n.promoted OR n.duration IS NULL OR NOT u.auto_promote_now_playing)
+    "#,
+    now,
+    DEFAULT_TTL_SECS
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// Promote reports from opted-in users whose track finished by `cutoff`
+async fn promote_due(pool: &DbPool, user_id: Option<i64>, cutoff: i64) -> Result<(), sqlx::Error> {
+  let entries = sqlx::query_as!(
+    Entry,
+    r#"
+    SELECT
+        n.user_id as "user_id!",
+        n.artist,
+        n.track,
+        n.album,
+        n.duration as "duration!",
+        n.started_at as "started_at!"
+    FROM now_playing n
+    JOIN users u ON u.id = n.user_id
+    WHERE u.auto_promote_now_playing = true
+      AND n.promoted = false
+      AND n.duration IS NOT NULL
+      AND n.started_at + n.duration <= $1
+      AND ($2::BIGINT IS NULL OR n.user_id = $2)
+    "#,
+    cutoff,
+    user_id
+  )
+  .fetch_all(pool)
+  .await?;
+
+  for entry in entries {
+    promote(pool, entry).await?;
+  }
+
+  Ok(())
+}
+
+async fn promote(pool: &DbPool, entry: Entry) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    "UPDATE now_playing SET promoted = true WHERE user_id = $1 AND started_at = $2",
+    entry.user_id,
+    entry.started_at
+  )
+  .execute(pool)
+  .await?;
+
+  // The player scrobbled it after all
+  let scrobbled = sqlx::query_scalar!(
+    r#"
+    SELECT EXISTS(
+        SELECT 1 FROM scrobs
+        WHERE user_id = $1 AND artist = $2 AND track = $3 AND timestamp >= $4
+    ) as "exists!"
+    "#,
+    entry.user_id,
+    entry.artist,
+    entry.track,
+    entry.started_at - SCROBBLE_MATCH_SLACK_SECS
+  )
+  .fetch_one(pool)
+  .await?;
+
+  if scrobbled {
+    return Ok(());
+  }
+
+  let scrob = ScrobbleRequest {
+    artist: entry.artist,
+    track: entry.track,
+    timestamp: entry.started_at as u64,
+    album: entry.album,
+    album_artist: None,
+    duration: Some(entry.duration as u64),
+    track_number: None,
+    artist_mbid: None,
+    release_mbid: None,
+    recording_mbid: None,
+    source: Some("now_playing".to_string()),
+  };
+
+  match submit_scrobble(pool, entry.user_id, &scrob).await? {
+    ScrobbleOutcome::Accepted(id) => {
+      tracing::info!("Promoted now-playing for user {} to scrobble {}", entry.user_id, id);
+    }
+    ScrobbleOutcome::Duplicate => {}
+    ScrobbleOutcome::Rejected(reason) => {
+      tracing::warn!("Could not promote now-playing for user {}: {}", entry.user_id, reason);
+    }
+  }
+
+  Ok(())
+}
@@ -0,0 +1,396 @@
+//! OpenID Connect single sign-on (authorization code flow with PKCE)
+
+use std::time::Duration;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{config::Config, db::DbPool, policy::ContentPolicy};
+
+/// How long a login may take between the redirect and the callback
+const LOGIN_STATE_TTL_SECS: i64 = 10 * 60;
+/// Allowed clock difference when checking `exp` and `iat`
+const CLOCK_SKEW_SECS: i64 = 60;
+const SCOPES: &str = "openid profile email";
+
+/// Issuer and client settings, present when `OIDC_ISSUER_URL` is set
+#[derive(Debug, Clone)]
+pub struct OidcSettings {
+  pub issuer: String,
+  pub client_id: String,
+  pub client_secret: Option<String>,
+  pub redirect_url: String,
+}
+
+impl OidcSettings {
+  pub fn from_config(config: &Config) -> Option<Self> {
+    Some(Self {
+      issuer: config.oidc_issuer_url.clone()?.trim_end_matches('/').to_string(),
+      client_id: config.oidc_client_id.clone()?,
+      client_secret: config.oidc_client_secret.clone(),
+      redirect_url: config.oidc_redirect_url.clone()?,
+    })
+  }
+}
+
+/// The parts of the provider's discovery document we use
+#[derive(Debug, Deserialize)]
+struct Provider {
+  issuer: String,
+  authorization_endpoint: String,
+  token_endpoint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Audience {
+  One(String),
+  Many(Vec<String>),
+}
+
+impl Audience {
+  fn contains(&self, client_id: &str) -> bool {
+    match self {
+      Audience::One(aud) => aud == client_id,
+      Audience::Many(auds) => auds.iter().any(|a| a == client_id),
+    }
+  }
+}
+
+/// ID token claims
+#[derive(Debug, Deserialize)]
+pub struct Claims {
+  iss: String,
+  pub sub: String,
+  aud: Audience,
+  exp: i64,
+  nonce: Option<String>,
+  pub preferred_username: Option<String>,
+  pub email: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum OidcError {
+  /// The provider couldn't be reached or answered with something unusable
+  Provider(String),
+  /// The callback doesn't belong to a login we started, or its token is bad
+  Rejected(String),
+  /// The identity is new but registration is closed to it
+  Forbidden(String),
+  Database(sqlx::Error),
+}
+
+impl std::fmt::Display for OidcError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      OidcError::Provider(e) => write!(f, "Identity provider error: {}", e),
+      OidcError::Rejected(e) | OidcError::Forbidden(e) => write!(f, "{}", e),
+      OidcError::Database(e) => write!(f, "Database error: {}", e),
+    }
+  }
+}
+
+impl From<sqlx::Error> for OidcError {
+  fn from(e: sqlx::Error) -> Self {
+    OidcError::Database(e)
+  }
+}
+
+fn http_client() -> Result<reqwest::Client, OidcError> {
+  reqwest::Client::builder()
+    .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION")))
+    .timeout(Duration::from_secs(10))
+    .build()
+    .map_err(|e| OidcError::Provider(format!("Failed to build HTTP client: {}", e)))
+}
+
+async fn discover(client: &reqwest::Client, settings: &OidcSettings) -> Result<Provider, OidcError> {
+  let url = format!("{}/.well-known/openid-configuration", settings.issuer);
+  let provider: Provider = client
+    .get(&url)
+    .send()
+    .await
+    .and_then(|r| r.error_for_status())
+    .map_err(|e| OidcError::Provider(format!("Discovery failed: {}", e)))?
+    .json()
+    .await
+    .map_err(|e| OidcError::Provider(format!("Invalid discovery document: {}", e)))?;
+
+  if provider.issuer.trim_end_matches('/') != settings.issuer {
+    return Err(OidcError::Provider(format!(
+      "Discovery document is for issuer {}, expected {}",
+      provider.issuer, settings.issuer
+    )));
+  }
+
+  Ok(provider)
+}
+
+fn random_string() -> String {
+  URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+}
+
+/// Record a new login attempt and return the provider URL to send the user
+/// to. The invite code is kept for the callback, in case it creates the account.
+pub async fn start_login(
+  pool: &DbPool,
+  settings: &OidcSettings,
+  invite_code: Option<&str>,
+) -> Result<String, OidcError> {
+  let provider = discover(&http_client()?, settings).await?;
+
+  let state = random_string();
+  let nonce = random_string();
+  let code_verifier = random_string();
+  let code_challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()));
+  let now = chrono::Utc::now().timestamp();
+
+  // Abandoned logins are cleared out whenever a new one starts
+  sqlx::query!(
+    "DELETE FROM oidc_login_states WHERE created_at < $1",
+    now - LOGIN_STATE_TTL_SECS
+  )
+  .execute(pool)
+  .await?;
+
+  sqlx::query!(
+    r#"
+    INSERT INTO oidc_login_states (state, nonce, code_verifier, invite_code, created_at)
+    VALUES ($1, $2, $3, $4, $5)
+    "#,
+    state,
+    nonce,
+    code_verifier,
+    invite_code,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  let mut url = reqwest::Url::parse(&provider.authorization_endpoint)
+    .map_err(|e| OidcError::Provider(format!("Invalid authorization endpoint: {}", e)))?;
+  url
+    .query_pairs_mut()
+    .append_pair("response_type", "code")
+    .append_pair("client_id", &settings.client_id)
+    .append_pair("redirect_uri", &settings.redirect_url)
+    .append_pair("scope", SCOPES)
+    .append_pair("state", &state)
+    .append_pair("nonce", &nonce)
+    .append_pair("code_challenge", &code_challenge)
+    .append_pair("code_challenge_method", "S256");
+
+  Ok(url.into())
+}
+
+/// Exchange the callback's code for the user's ID token claims, along with
+/// the invite code the login was started with
+pub async fn finish_login(
+  pool: &DbPool,
+  settings: &OidcSettings,
+  code: &str,
+  state: &str,
+) -> Result<(Claims, Option<String>), OidcError> {
+  let now = chrono::Utc::now().timestamp();
+
+  // Each state can only be used once
+  let login = sqlx::query!(
+    "DELETE FROM oidc_login_states WHERE state = $1 RETURNING nonce, code_verifier, invite_code, created_at",
+    state
+  )
+  .fetch_optional(pool)
+  .await?
+  .filter(|login| now - login.created_at <= LOGIN_STATE_TTL_SECS)
+  .ok_or_else(|| OidcError::Rejected("Unknown or expired login, please start again".to_string()))?;
+
+  let client = http_client()?;
+  let provider = discover(&client, settings).await?;
+
+  let mut form = vec![
+    ("grant_type", "authorization_code"),
+    ("code", code),
+    ("redirect_uri", settings.redirect_url.as_str()),
+    ("client_id", settings.client_id.as_str()),
+    ("code_verifier", login.code_verifier.as_str()),
+  ];
+  if let Some(secret) = &settings.client_secret {
+    form.push(("client_secret", secret.as_str()));
+  }
+
+  let response = client
+    .post(&provider.token_endpoint)
+    .form(&form)
+    .send()
+    .await
+    .map_err(|e| OidcError::Provider(format!("Token request failed: {}", e)))?;
+
+  if !response.status().is_success() {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    return Err(OidcError::Rejected(format!("Identity provider refused the login ({}): {}", status, body)));
+  }
+
+  let tokens: TokenResponse = response
+    .json()
+    .await
+    .map_err(|e| OidcError::Provider(format!("Invalid token response: {}", e)))?;
+
+  // The ID token came straight from the token endpoint over TLS, which
+  // OIDC Core 3.1.3.7 accepts in place of checking its signature
+  let claims = decode_claims(&tokens.id_token)?;
+
+  if claims.iss.trim_end_matches('/') != settings.issuer {
+    return Err(OidcError::Rejected("ID token has the wrong issuer".to_string()));
+  }
+  if !claims.aud.contains(&settings.client_id) {
+    return Err(OidcError::Rejected("ID token is for a different client".to_string()));
+  }
+  if claims.exp + CLOCK_SKEW_SECS < now {
+    return Err(OidcError::Rejected("ID token has expired".to_string()));
+  }
+  if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+    return Err(OidcError::Rejected("ID token nonce does not match the login".to_string()));
+  }
+
+  Ok((claims, login.invite_code))
+}
+
+fn decode_claims(id_token: &str) -> Result<Claims, OidcError> {
+  let payload = id_token
+    .split('.')
+    .nth(1)
+    .ok_or_else(|| OidcError::Rejected("Malformed ID token".to_string()))?;
+  let bytes = URL_SAFE_NO_PAD
+    .decode(payload.trim_end_matches('='))
+    .map_err(|e| OidcError::Rejected(format!("Malformed ID token: {}", e)))?;
+
+  serde_json::from_slice(&bytes).map_err(|e| OidcError::Rejected(format!("Malformed ID token: {}", e)))
+}
+
+/// The user linked to this identity, creating one on first login. As with
+/// signup, creating one needs open registration or a valid invite code.
+pub async fn find_or_create_user(
+  pool: &DbPool,
+  settings: &OidcSettings,
+  claims: &Claims,
+  invite_code: Option<&str>,
+) -> Result<i64, OidcError> {
+  let existing = sqlx::query_scalar!(
+    "SELECT user_id FROM oidc_identities WHERE issuer = $1 AND subject = $2",
+    settings.issuer,
+    claims.sub
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  if let Some(user_id) = existing {
+    return Ok(user_id);
+  }
+
+  let policy = ContentPolicy::load(pool).await?;
+  let invite_code = match invite_code.map(str::trim).filter(|c| !c.is_empty()) {
+    _ if policy.registration_open => None,
+    Some(code) => Some(code),
+    None => return Err(OidcError::Forbidden("Registration is closed; an invite code is required".to_string())),
+  };
+
+  let now = chrono::Utc::now().timestamp();
+  let is_private = !policy.allows_public(now, now);
+
+  let base = username_base(claims, &policy);
+  let mut tx = pool.begin().await?;
+
+  // Retry with a numeric suffix if the name is taken
+  let mut user_id = None;
+  for attempt in 0..10 {
+    let username = if attempt == 0 {
+      base.clone()
+    } else {
+      format!("{}_{}", &base[..base.len().min(15)], rand::random::<u16>() % 10000)
+    };
+
+    // Same rules as signup: first user is admin, no usable password
+    user_id = sqlx::query_scalar!(
+      r#"
+      INSERT INTO users (username, password_hash, is_admin, is_private, created_at)
+      VALUES ($1, '', NOT EXISTS(SELECT 1 FROM users), $2, $3)
+      ON CONFLICT (username) DO NOTHING
+      RETURNING id
+      "#,
+      username,
+      is_private,
+      now
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if user_id.is_some() {
+      break;
+    }
+  }
+
+  let user_id = user_id.ok_or_else(|| OidcError::Rejected("Could not find a free username".to_string()))?;
+
+  // Redeemed with the insert, as in signup, so two logins can't share it
+  if let Some(code) = invite_code {
+    let redeemed = sqlx::query!(
+      r#"
+      UPDATE invites
+      SET used_by = $1, used_at = $2
+      WHERE code = $3 AND used_by IS NULL AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $2)
+      "#,
+      user_id,
+      now,
+      code
+    )
+    .execute(&mut *tx)
+    .await?
+    .rows_affected();
+
+    if redeemed == 0 {
+      return Err(OidcError::Forbidden("Invalid, used or expired invite code".to_string()));
+    }
+  }
+
+  sqlx::query!(
+    "INSERT INTO oidc_identities (issuer, subject, user_id, created_at) VALUES ($1, $2, $3, $4)",
+    settings.issuer,
+    claims.sub,
+    user_id,
+    now
+  )
+  .execute(&mut *tx)
+  .await?;
+
+  tx.commit().await?;
+
+  tracing::info!("Created user {} for OIDC subject {}", user_id, claims.sub);
+  Ok(user_id)
+}
+
+/// A signup-valid username from the provider's preferred username or email
+fn username_base(claims: &Claims, policy: &ContentPolicy) -> String {
+  let source = claims
+    .preferred_username
+    .as_deref()
+    .or_else(|| claims.email.as_deref().and_then(|e| e.split('@').next()))
+    .unwrap_or_default();
+
+  let mut name: String = source
+    .chars()
+    .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+    .take(20)
+    .collect();
+
+  if name.len() < 3 || policy.check_username(&name).is_err() {
+    name = "user".to_string();
+  }
+
+  name
+}
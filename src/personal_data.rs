@@ -0,0 +1,189 @@
+//! Personal data exports: everything stored about a user, for them to keep
+//! or inspect. Unlike the move archive (`archive.rs`) this isn't meant to be
+//! imported anywhere; it covers every table `erase_personal_data` and
+//! `erase_listening_history` wipe (`routes/account.rs`), so a table added
+//! there belongs here too.
+//!
+//! Credentials are described but never included: token and session values,
+//! relay and MPD secrets, Spotify tokens and the Discord webhook URL.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::{
+  db::DbPool,
+  preferences::{self, Preferences},
+};
+
+pub const FORMAT: &str = "scrob-personal-data";
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub struct PersonalData {
+  pub format: String,
+  pub version: u32,
+  pub exported_at: i64,
+  pub account: Value,
+  pub preferences: Preferences,
+  /// Login sessions and API tokens, with where they were last used from
+  pub tokens: Value,
+  pub audioscrobbler_sessions: Value,
+  pub pending_lastfm_auth: Value,
+  pub email_tokens: Value,
+  pub sso_identities: Value,
+  pub login_failures: Value,
+  pub relays: Value,
+  pub discord_webhook: Value,
+  pub spotify_link: Value,
+  pub pending_spotify_links: Value,
+  pub mpd_watcher: Value,
+  pub now_playing: Value,
+  pub loved_tracks: Value,
+  pub goals: Value,
+  pub notifications: Value,
+  pub announcement_reads: Value,
+  pub import_jobs: Value,
+  pub scrobble_rules: Value,
+  pub following: Value,
+  pub followers: Value,
+  pub scrobbles: Value,
+  pub scrobble_edits: Value,
+  pub chart_snapshots: Value,
+}
+
+/// Collect the user's data in one statement, so it's a consistent snapshot
+pub async fn build(pool: &DbPool, user_id: i64) -> Result<PersonalData, sqlx::Error> {
+  let preferences = preferences::load(pool, user_id).await?;
+
+  let data = sqlx::query!(
+    r#"
+    SELECT
+      (SELECT json_build_object(
+         'id', id, 'username', username, 'email', email, 'email_verified_at', email_verified_at,
+         'created_at', created_at, 'is_admin', is_admin, 'is_private', is_private,
+         'auto_promote_now_playing', auto_promote_now_playing, 'settings_updated_at', settings_updated_at,
+         'banned_at', banned_at, 'banned_until', banned_until, 'ban_reason', ban_reason)
+       FROM users u WHERE u.id = $1) as "account!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'id', id, 'label', label, 'scopes', scopes, 'created_at', created_at, 'expires_at', expires_at,
+         'last_used_at', last_used_at, 'last_ip', last_ip, 'last_user_agent', last_user_agent,
+         'revoked', revoked) ORDER BY created_at), '[]')
+       FROM api_tokens WHERE user_id = $1) as "tokens!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'token_id', token_id, 'client', client, 'created_at', created_at,
+         'last_used_at', last_used_at) ORDER BY created_at), '[]')
+       FROM audioscrobbler_sessions WHERE user_id = $1) as "audioscrobbler_sessions!",
+      (SELECT COALESCE(json_agg(json_build_object('created_at', created_at) ORDER BY created_at), '[]')
+       FROM lastfm_auth_tokens WHERE user_id = $1) as "pending_lastfm_auth!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'kind', kind, 'email', email, 'created_at', created_at, 'expires_at', expires_at) ORDER BY created_at), '[]')
+       FROM email_tokens WHERE user_id = $1) as "email_tokens!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'issuer', issuer, 'subject', subject, 'created_at', created_at) ORDER BY created_at), '[]')
+       FROM oidc_identities WHERE user_id = $1) as "sso_identities!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'failures', failures, 'last_failure_at', last_failure_at, 'locked_until', locked_until)), '[]')
+       FROM login_failures WHERE key = 'user:' || (SELECT username FROM users WHERE id = $1)) as "login_failures!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'id', id, 'kind', kind, 'label', label, 'url', url, 'has_secret', secret IS NOT NULL,
+         'include_now_playing', include_now_playing, 'enabled', enabled, 'created_at', created_at,
+         'last_success_at', last_success_at) ORDER BY created_at), '[]')
+       FROM relays WHERE user_id = $1) as "relays!",
+      (SELECT json_build_object(
+         'daily_summary', daily_summary, 'milestones', milestones, 'weekly_top_artist', weekly_top_artist,
+         'enabled', enabled, 'updated_at', updated_at, 'last_success_at', last_success_at,
+         'last_error', last_error)
+       FROM discord_webhooks WHERE user_id = $1) as discord_webhook,
+      (SELECT json_build_object(
+         'spotify_user_id', spotify_user_id, 'display_name', display_name, 'linked_at', linked_at,
+         'last_polled_at', last_polled_at, 'last_error', last_error)
+       FROM spotify_links WHERE user_id = $1) as spotify_link,
+      (SELECT COALESCE(json_agg(json_build_object('created_at', created_at) ORDER BY created_at), '[]')
+       FROM spotify_link_states WHERE user_id = $1) as "pending_spotify_links!",
+      (SELECT json_build_object(
+         'host', host, 'port', port, 'has_password', password IS NOT NULL, 'enabled', enabled,
+         'updated_at', updated_at, 'last_connected_at', last_connected_at, 'last_error', last_error)
+       FROM mpd_watchers WHERE user_id = $1) as mpd_watcher,
+      (SELECT json_build_object(
+         'artist', artist, 'track', track, 'album', album, 'duration', duration, 'started_at', started_at)
+       FROM now_playing WHERE user_id = $1) as now_playing,
+      (SELECT COALESCE(json_agg(json_build_object(
+         'artist', artist, 'track', track, 'album', album, 'recording_mbid', recording_mbid,
+         'loved_at', loved_at) ORDER BY loved_at), '[]')
+       FROM loved_tracks WHERE user_id = $1) as "loved_tracks!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'metric', metric, 'period', period, 'target', target, 'created_at', created_at) ORDER BY created_at), '[]')
+       FROM goals WHERE user_id = $1) as "goals!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'kind', kind, 'message', message, 'created_at', created_at, 'read_at', read_at) ORDER BY created_at), '[]')
+       FROM notifications WHERE user_id = $1) as "notifications!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'announcement_id', announcement_id, 'read_at', read_at) ORDER BY read_at), '[]')
+       FROM announcement_reads WHERE user_id = $1) as "announcement_reads!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'id', id, 'format', format, 'status', status, 'bytes', bytes, 'processed', processed,
+         'imported', imported, 'duplicates', duplicates, 'skipped', skipped, 'rejected', rejected,
+         'errors', errors, 'error', error, 'created_at', created_at, 'started_at', started_at,
+         'finished_at', finished_at) ORDER BY created_at), '[]')
+       FROM import_jobs WHERE user_id = $1) as "import_jobs!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'field', field, 'match_type', match_type, 'pattern', pattern, 'action', action,
+         'replacement', replacement, 'enabled', enabled, 'created_at', created_at) ORDER BY created_at), '[]')
+       FROM scrobble_rules WHERE user_id = $1) as "scrobble_rules!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'username', u.username, 'since', f.created_at) ORDER BY f.created_at), '[]')
+       FROM follows f JOIN users u ON u.id = f.followee_id WHERE f.follower_id = $1) as "following!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'username', u.username, 'since', f.created_at) ORDER BY f.created_at), '[]')
+       FROM follows f JOIN users u ON u.id = f.follower_id WHERE f.followee_id = $1) as "followers!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'id', id, 'artist', artist, 'track', track, 'album', album, 'album_artist', album_artist,
+         'track_number', track_number, 'duration', duration, 'timestamp', timestamp,
+         'artist_mbid', artist_mbid, 'release_mbid', release_mbid, 'recording_mbid', recording_mbid,
+         'source', source, 'created_at', created_at) ORDER BY timestamp), '[]')
+       FROM scrobs WHERE user_id = $1) as "scrobbles!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'scrobble_id', scrob_id, 'action', action, 'old_values', old_values, 'new_values', new_values,
+         'edited_at', edited_at) ORDER BY edited_at), '[]')
+       FROM scrob_edits WHERE user_id = $1) as "scrobble_edits!",
+      (SELECT COALESCE(json_agg(json_build_object(
+         'period', period, 'kind', kind, 'period_start', period_start, 'period_end', period_end,
+         'entries', entries, 'computed_at', computed_at) ORDER BY period_start), '[]')
+       FROM chart_snapshots WHERE user_id = $1) as "chart_snapshots!"
+    "#,
+    user_id
+  )
+  .fetch_one(pool)
+  .await?;
+
+  Ok(PersonalData {
+    format: FORMAT.to_string(),
+    version: VERSION,
+    exported_at: chrono::Utc::now().timestamp(),
+    account: data.account,
+    preferences,
+    tokens: data.tokens,
+    audioscrobbler_sessions: data.audioscrobbler_sessions,
+    pending_lastfm_auth: data.pending_lastfm_auth,
+    email_tokens: data.email_tokens,
+    sso_identities: data.sso_identities,
+    login_failures: data.login_failures,
+    relays: data.relays,
+    discord_webhook: data.discord_webhook.unwrap_or(Value::Null),
+    spotify_link: data.spotify_link.unwrap_or(Value::Null),
+    pending_spotify_links: data.pending_spotify_links,
+    mpd_watcher: data.mpd_watcher.unwrap_or(Value::Null),
+    now_playing: data.now_playing.unwrap_or(Value::Null),
+    loved_tracks: data.loved_tracks,
+    goals: data.goals,
+    notifications: data.notifications,
+    announcement_reads: data.announcement_reads,
+    import_jobs: data.import_jobs,
+    scrobble_rules: data.scrobble_rules,
+    following: data.following,
+    followers: data.followers,
+    scrobbles: data.scrobbles,
+    scrobble_edits: data.scrobble_edits,
+    chart_snapshots: data.chart_snapshots,
+  })
+}
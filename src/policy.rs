@@ -0,0 +1,75 @@
+use crate::db::DbPool;
+
+/// Username and content rules configured by admins in `server_settings`
+#[derive(Debug, Clone)]
+pub struct ContentPolicy {
+  pub reserved_usernames: Vec<String>,
+  pub banned_words: Vec<String>,
+  pub min_public_account_age_days: i32,
+  /// When false, signups need an invite code
+  pub registration_open: bool,
+  /// Whether /charts/global/* is served
+  pub global_charts_enabled: bool,
+}
+
+impl ContentPolicy {
+  pub async fn load(pool: &DbPool) -> Result<Self, sqlx::Error> {
+    let row = sqlx::query!(
+      r#"
+      SELECT reserved_usernames, banned_words, min_public_account_age_days, registration_open,
+             global_charts_enabled
+      FROM server_settings
+      WHERE id = 1
+      "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    Ok(Self {
+      reserved_usernames: row.reserved_usernames,
+      banned_words: row.banned_words,
+      min_public_account_age_days: row.min_public_account_age_days,
+      registration_open: row.registration_open,
+      global_charts_enabled: row.global_charts_enabled,
+    })
+  }
+
+  /// Reject reserved usernames and ones containing a banned word
+  pub fn check_username(&self, username: &str) -> Result<(), String> {
+    let lower = username.to_lowercase();
+
+    if self.reserved_usernames.contains(&lower) {
+      return Err("This username is reserved".to_string());
+    }
+
+    self.check_text(&lower).map_err(|_| "This username is not allowed".to_string())
+  }
+
+  /// Reject text containing a banned word (case-insensitive)
+  pub fn check_text(&self, text: &str) -> Result<(), String> {
+    let lower = text.to_lowercase();
+
+    if self.banned_words.iter().any(|w| lower.contains(w.as_str())) {
+      return Err("Text contains a banned word".to_string());
+    }
+
+    Ok(())
+  }
+
+  /// Whether an account created at `created_at` may have a public profile
+  pub fn allows_public(&self, created_at: i64, now: i64) -> bool {
+    now - created_at >= i64::from(self.min_public_account_age_days) * 24 * 60 * 60
+  }
+}
+
+/// Lowercase, trim, de-duplicate and drop empty entries
+pub fn normalize_list(items: Vec<String>) -> Vec<String> {
+  let mut items: Vec<String> = items
+    .into_iter()
+    .map(|i| i.trim().to_lowercase())
+    .filter(|i| !i.is_empty())
+    .collect();
+  items.sort();
+  items.dedup();
+  items
+}
@@ -0,0 +1,84 @@
+//! Per-user preferences from `user_settings`
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::db::DbPool;
+
+/// Chart periods accepted by `period=` and `default_chart_period`
+pub const CHART_PERIODS: [&str; 5] = ["7day", "1month", "3month", "12month", "overall"];
+pub const MAX_DISPLAY_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preferences {
+  pub display_name: Option<String>,
+  pub timezone: String,
+  pub default_chart_period: String,
+  /// Keep this user's scrobbles when the server prunes old ones
+  #[serde(default)]
+  pub retention_opt_out: bool,
+  /// Count this user's scrobbles in the server-wide charts
+  #[serde(default)]
+  pub include_in_global_charts: bool,
+}
+
+impl Default for Preferences {
+  fn default() -> Self {
+    Self {
+      display_name: None,
+      timezone: "UTC".to_string(),
+      default_chart_period: "overall".to_string(),
+      retention_opt_out: false,
+      include_in_global_charts: false,
+    }
+  }
+}
+
+/// The user's preferences, or the defaults if they never saved any
+pub async fn load(pool: &DbPool, user_id: i64) -> Result<Preferences, sqlx::Error> {
+  let preferences = sqlx::query_as!(
+    Preferences,
+    r#"
+    SELECT display_name, timezone, default_chart_period, retention_opt_out, include_in_global_charts
+    FROM user_settings
+    WHERE user_id = $1
+    "#,
+    user_id
+  )
+  .fetch_optional(pool)
+  .await?;
+
+  Ok(preferences.unwrap_or_default())
+}
+
+/// Whether Postgres knows the zone, so it can be used with `AT TIME ZONE`
+pub async fn is_valid_timezone(pool: &DbPool, timezone: &str) -> Result<bool, sqlx::Error> {
+  sqlx::query_scalar!(
+    r#"SELECT EXISTS(SELECT 1 FROM pg_timezone_names WHERE name = $1) as "exists!""#,
+    timezone
+  )
+  .fetch_one(pool)
+  .await
+}
+
+/// Today's date in `timezone`
+pub async fn local_today(pool: &DbPool, timezone: &str) -> Result<NaiveDate, sqlx::Error> {
+  sqlx::query_scalar!(r#"SELECT (now() AT TIME ZONE $1)::date as "today!""#, timezone)
+    .fetch_one(pool)
+    .await
+}
+
+/// Unix time of the midnight starting each of `dates` in `timezone`, in order
+pub async fn local_midnights(pool: &DbPool, timezone: &str, dates: &[NaiveDate]) -> Result<Vec<i64>, sqlx::Error> {
+  sqlx::query_scalar!(
+    r#"
+    SELECT EXTRACT(EPOCH FROM d.day::timestamp AT TIME ZONE $2)::bigint as "timestamp!"
+    FROM UNNEST($1::date[]) WITH ORDINALITY AS d(day, n)
+    ORDER BY d.n
+    "#,
+    dates,
+    timezone
+  )
+  .fetch_all(pool)
+  .await
+}
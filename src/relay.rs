@@ -0,0 +1,483 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::types::Json;
+
+use crate::{
+  config::Config,
+  crypto::SecretBox,
+  db::DbPool,
+  health,
+  net::Outbound,
+  routes::lastfm::{sign, Params},
+};
+
+const LISTENBRAINZ_API_ROOT: &str = "https://api.listenbrainz.org";
+const LASTFM_API_ROOT: &str = "https://ws.audioscrobbler.com/2.0/";
+const DELIVERY_INTERVAL: Duration = Duration::from_secs(30);
+const DELIVERY_BATCH: i64 = 100;
+const ERRORS_KEPT_PER_RELAY: i64 = 20;
+const BACKOFF_BASE_SECS: i64 = 30;
+const BACKOFF_MAX_SECS: i64 = 6 * 60 * 60;
+const MAX_ATTEMPTS: i32 = 10;
+
+/// A listen as it is queued for forwarding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Listen {
+  pub artist: String,
+  pub track: String,
+  pub album: Option<String>,
+  pub duration: Option<i64>,
+  pub timestamp: i64,
+}
+
+/// The Last.fm API account scrob signs relayed scrobbles with. Session keys
+/// are only valid with the key pair they were issued for.
+#[derive(Debug, Clone)]
+pub struct LastfmApp {
+  pub api_key: String,
+  pub api_secret: String,
+}
+
+impl LastfmApp {
+  pub fn from_config(config: &Config) -> Option<Self> {
+    Some(Self {
+      api_key: config.lastfm_relay_api_key.clone()?,
+      api_secret: config.lastfm_relay_api_secret.clone()?,
+    })
+  }
+}
+
+/// Queue scrobbles for every enabled relay target of the user
+pub async fn enqueue_scrobbles(
+  db: impl sqlx::PgExecutor<'_>,
+  user_id: i64,
+  listens: &[Listen],
+) -> Result<(), sqlx::Error> {
+  if listens.is_empty() {
+    return Ok(());
+  }
+
+  let now = chrono::Utc::now().timestamp();
+  let payloads: Vec<Json<&Listen>> = listens.iter().map(Json).collect();
+
+  sqlx::query!(
+    r#"
+    INSERT INTO relay_queue (relay_id, event, payload, created_at)
+    SELECT r.id, 'scrobble', l.payload, $3
+    FROM relays r
+    CROSS JOIN UNNEST($2::JSONB[]) WITH ORDINALITY AS l(payload, n)
+    WHERE r.user_id = $1 AND r.enabled = true
+    ORDER BY l.n, r.id
+    "#,
+    user_id,
+    &payloads as _,
+    now
+  )
+  .execute(db)
+  .await?;
+
+  Ok(())
+}
+
+/// Queue a now-playing update for relay targets that asked for them
+pub async fn enqueue_now_playing(pool: &DbPool, user_id: i64, listen: &Listen) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  sqlx::query!(
+    r#"
+    INSERT INTO relay_queue (relay_id, event, payload, created_at)
+    SELECT id, 'now_playing', $2, $3
+    FROM relays
+    WHERE user_id = $1 AND enabled = true AND include_now_playing = true
+    "#,
+    user_id,
+    Json(listen) as _,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+/// Start the background task that forwards queued listens
+pub fn spawn_worker(pool: DbPool, secrets: Option<Arc<SecretBox>>, lastfm: Option<LastfmApp>, outbound: Outbound) {
+  tokio::spawn(async move {
+    let client = client(&outbound);
+
+    let mut interval = tokio::time::interval(DELIVERY_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("relay", DELIVERY_INTERVAL);
+      if let Err(e) = deliver_pending(&pool, &client, &outbound, secrets.as_deref(), lastfm.as_ref()).await {
+        tracing::error!("Relay delivery failed: {}", e);
+      }
+    }
+  });
+}
+
+/// Relay URLs are user-supplied, so the client only reaches permitted addresses
+fn client(outbound: &Outbound) -> reqwest::Client {
+  outbound
+    .client_builder()
+    .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION")))
+    .timeout(Duration::from_secs(10))
+    .build()
+    .expect("failed to build relay HTTP client")
+}
+
+/// Delay before the next attempt after `attempts` failed deliveries
+fn backoff_secs(attempts: i32) -> i64 {
+  let exponent = attempts.saturating_sub(1).clamp(0, 20) as u32;
+  BACKOFF_BASE_SECS.saturating_mul(1 << exponent).min(BACKOFF_MAX_SECS)
+}
+
+async fn deliver_pending(
+  pool: &DbPool,
+  client: &reqwest::Client,
+  outbound: &Outbound,
+  secrets: Option<&SecretBox>,
+  lastfm: Option<&LastfmApp>,
+) -> Result<(), sqlx::Error> {
+  let now = chrono::Utc::now().timestamp();
+
+  let items = sqlx::query!(
+    r#"
+    SELECT
+        q.id as "id!",
+        q.relay_id as "relay_id!",
+        q.payload as "payload!: Json<Listen>",
+        q.attempts as "attempts!",
+        q.event,
+        r.kind,
+        r.url,
+        r.secret
+    FROM relay_queue q
+    JOIN relays r ON r.id = q.relay_id
+    WHERE r.enabled = true AND q.status = 'pending' AND q.next_attempt_at <= $1
+    ORDER BY q.id
+    LIMIT $2
+    "#,
+    now,
+    DELIVERY_BATCH
+  )
+  .fetch_all(pool)
+  .await?;
+
+  // Stop at the first failure per relay so listens are forwarded in order
+  let mut failed: HashSet<i64> = HashSet::new();
+
+  for item in items {
+    if failed.contains(&item.relay_id) {
+      continue;
+    }
+
+    let now_playing = item.event == "now_playing";
+    // Relays saved before URLs were checked may point at a literal private address
+    let checked = match item.url.as_deref() {
+      Some(url) => outbound.check_literal(url),
+      None => Ok(()),
+    };
+    let result = match checked.and_then(|()| decrypt_secret(secrets, item.secret.as_deref())) {
+      Ok(secret) => match item.kind.as_str() {
+        "listenbrainz" => deliver_listenbrainz(client, item.url.as_deref(), secret.as_deref(), now_playing, &item.payload).await,
+        "lastfm" => deliver_lastfm(client, lastfm, item.url.as_deref(), secret.as_deref(), now_playing, &item.payload).await,
+        "webhook" => deliver_webhook(client, item.url.as_deref(), secret.as_deref(), now_playing, &item.payload).await,
+        other => Err(format!("Unknown relay kind: {}", other)),
+      },
+      Err(e) => Err(e),
+    };
+
+    let now = chrono::Utc::now().timestamp();
+
+    match result {
+      Ok(()) => {
+        sqlx::query!("DELETE FROM relay_queue WHERE id = $1", item.id)
+          .execute(pool)
+          .await?;
+
+        sqlx::query!(
+          "UPDATE relays SET last_success_at = $1 WHERE id = $2",
+          now,
+          item.relay_id
+        )
+        .execute(pool)
+        .await?;
+      }
+      Err(message) => {
+        tracing::warn!("Relay {} delivery failed: {}", item.relay_id, message);
+        failed.insert(item.relay_id);
+        record_error(pool, item.relay_id, &message, now).await?;
+
+        // Now-playing updates are stale by the time a retry would happen
+        if now_playing {
+          sqlx::query!("DELETE FROM relay_queue WHERE id = $1", item.id)
+            .execute(pool)
+            .await?;
+        } else {
+          record_failure(pool, item.id, item.relay_id, item.attempts + 1, &message, now).await?;
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Back off the whole relay after a failure, dead-lettering the item once it runs out of attempts
+async fn record_failure(
+  pool: &DbPool,
+  item_id: i64,
+  relay_id: i64,
+  attempts: i32,
+  message: &str,
+  now: i64,
+) -> Result<(), sqlx::Error> {
+  if attempts >= MAX_ATTEMPTS {
+    tracing::warn!("Relay {} item {} dead-lettered after {} attempts", relay_id, item_id, attempts);
+
+    sqlx::query!(
+      "UPDATE relay_queue SET status = 'dead', attempts = $1, last_error = $2 WHERE id = $3",
+      attempts,
+      message,
+      item_id
+    )
+    .execute(pool)
+    .await?;
+
+    return Ok(());
+  }
+
+  let next_attempt_at = now + backoff_secs(attempts);
+
+  sqlx::query!(
+    "UPDATE relay_queue SET attempts = $1, last_error = $2 WHERE id = $3",
+    attempts,
+    message,
+    item_id
+  )
+  .execute(pool)
+  .await?;
+
+  sqlx::query!(
+    r#"
+    UPDATE relay_queue
+    SET next_attempt_at = GREATEST(next_attempt_at, $1)
+    WHERE relay_id = $2 AND status = 'pending'
+    "#,
+    next_attempt_at,
+    relay_id
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+async fn record_error(pool: &DbPool, relay_id: i64, message: &str, now: i64) -> Result<(), sqlx::Error> {
+  sqlx::query!(
+    "INSERT INTO relay_errors (relay_id, message, created_at) VALUES ($1, $2, $3)",
+    relay_id,
+    message,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  // Only keep the most recent errors around
+  sqlx::query!(
+    r#"
+    DELETE FROM relay_errors
+    WHERE relay_id = $1 AND id NOT IN (
+      SELECT id FROM relay_errors
+      WHERE relay_id = $1
+      ORDER BY created_at DESC, id DESC
+      LIMIT $2
+    )
+    "#,
+    relay_id,
+    ERRORS_KEPT_PER_RELAY
+  )
+  .execute(pool)
+  .await?;
+
+  Ok(())
+}
+
+fn decrypt_secret(secrets: Option<&SecretBox>, secret: Option<&str>) -> Result<Option<String>, String> {
+  match (secret, secrets) {
+    (None, _) => Ok(None),
+    (Some(_), None) => Err("SECRET_KEY is not configured, can't decrypt relay secret".to_string()),
+    (Some(secret), Some(secrets)) => secrets.decrypt(secret).map(Some),
+  }
+}
+
+async fn deliver_listenbrainz(
+  client: &reqwest::Client,
+  url: Option<&str>,
+  token: Option<&str>,
+  now_playing: bool,
+  listen: &Listen,
+) -> Result<(), String> {
+  let token = token.ok_or("ListenBrainz relay has no token configured")?;
+  let root = url.unwrap_or(LISTENBRAINZ_API_ROOT).trim_end_matches('/');
+
+  let mut additional_info = json!({ "submission_client": "scrob" });
+  if let Some(duration) = listen.duration {
+    additional_info["duration"] = json!(duration);
+  }
+
+  let mut item = json!({
+    "track_metadata": {
+      "artist_name": listen.artist,
+      "track_name": listen.track,
+      "release_name": listen.album,
+      "additional_info": additional_info,
+    },
+  });
+  if !now_playing {
+    item["listened_at"] = json!(listen.timestamp);
+  }
+
+  let body = json!({
+    "listen_type": if now_playing { "playing_now" } else { "single" },
+    "payload": [item],
+  });
+
+  let response = client
+    .post(format!("{}/1/submit-listens", root))
+    .header("Authorization", format!("Token {}", token))
+    .json(&body)
+    .send()
+    .await
+    .map_err(|e| format!("Request failed: {}", e))?;
+
+  check_status(response).await
+}
+
+async fn deliver_lastfm(
+  client: &reqwest::Client,
+  app: Option<&LastfmApp>,
+  url: Option<&str>,
+  session_key: Option<&str>,
+  now_playing: bool,
+  listen: &Listen,
+) -> Result<(), String> {
+  let app = app.ok_or("LASTFM_RELAY_API_KEY and LASTFM_RELAY_API_SECRET are not configured")?;
+  let session_key = session_key.ok_or("Last.fm relay has no session key configured")?;
+
+  let mut params = Params::new();
+  params.insert("method".into(), if now_playing { "track.updateNowPlaying" } else { "track.scrobble" }.into());
+  params.insert("artist".into(), listen.artist.clone());
+  params.insert("track".into(), listen.track.clone());
+  if let Some(album) = listen.album.as_ref().filter(|a| !a.is_empty()) {
+    params.insert("album".into(), album.clone());
+  }
+  if let Some(duration) = listen.duration {
+    params.insert("duration".into(), duration.to_string());
+  }
+  if !now_playing {
+    params.insert("timestamp".into(), listen.timestamp.to_string());
+  }
+
+  params.insert("sk".into(), session_key.to_string());
+
+  call_lastfm(client, app, url, params).await.map(|_| ())
+}
+
+/// Exchange a Last.fm username and password for a session key, so the
+/// password itself never has to be stored
+pub async fn lastfm_session(
+  app: &LastfmApp,
+  outbound: &Outbound,
+  url: Option<&str>,
+  username: &str,
+  password: &str,
+) -> Result<String, String> {
+  let mut params = Params::new();
+  params.insert("method".into(), "auth.getMobileSession".into());
+  params.insert("username".into(), username.to_string());
+  params.insert("password".into(), password.to_string());
+
+  let body = call_lastfm(&client(outbound), app, url, params).await?;
+
+  body["session"]["key"]
+    .as_str()
+    .map(str::to_string)
+    .ok_or_else(|| "Last.fm returned no session key".to_string())
+}
+
+/// Sign and send a Last.fm API call, turning its error documents into errors
+async fn call_lastfm(
+  client: &reqwest::Client,
+  app: &LastfmApp,
+  url: Option<&str>,
+  mut params: Params,
+) -> Result<serde_json::Value, String> {
+  params.insert("api_key".into(), app.api_key.clone());
+  let signature = sign(&params, &app.api_secret);
+  params.insert("api_sig".into(), signature);
+  params.insert("format".into(), "json".into());
+
+  let response = client
+    .post(url.unwrap_or(LASTFM_API_ROOT))
+    .form(&params)
+    .send()
+    .await
+    .map_err(|e| format!("Request failed: {}", e))?;
+
+  let status = response.status();
+  let text = response.text().await.unwrap_or_default();
+  let body: serde_json::Value = serde_json::from_str(&text).unwrap_or_default();
+
+  if let Some(code) = body.get("error") {
+    let message = body["message"].as_str().unwrap_or_default();
+    return Err(format!("Last.fm error {}: {}", code, message));
+  }
+  if !status.is_success() {
+    return Err(format!("HTTP {}: {}", status.as_u16(), text.chars().take(200).collect::<String>()));
+  }
+
+  Ok(body)
+}
+
+async fn deliver_webhook(
+  client: &reqwest::Client,
+  url: Option<&str>,
+  token: Option<&str>,
+  now_playing: bool,
+  listen: &Listen,
+) -> Result<(), String> {
+  let url = url.ok_or("Webhook relay has no URL configured")?;
+
+  let body = if now_playing {
+    json!({ "event": "now_playing", "now_playing": listen })
+  } else {
+    json!({ "event": "scrobble", "scrobble": listen })
+  };
+
+  let mut request = client.post(url).json(&body);
+
+  if let Some(token) = token {
+    request = request.bearer_auth(token);
+  }
+
+  let response = request
+    .send()
+    .await
+    .map_err(|e| format!("Request failed: {}", e))?;
+
+  check_status(response).await
+}
+
+async fn check_status(response: reqwest::Response) -> Result<(), String> {
+  let status = response.status();
+  if status.is_success() {
+    return Ok(());
+  }
+
+  let body = response.text().await.unwrap_or_default();
+  Err(format!("HTTP {}: {}", status.as_u16(), body.chars().take(200).collect::<String>()))
+}
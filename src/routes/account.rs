@@ -0,0 +1,583 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::{
+    archive,
+    auth::{extract_token_from_header, invalidate_user, verify_password, AuthUser},
+    config::Config,
+    import::upload_path,
+    net::Outbound,
+    personal_data::{self, PersonalData},
+};
+
+/// Large histories can take a while to download from the source instance
+const MOVE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+/// How fresh the SSO session confirming a password-less account's deletion
+/// or anonymization must be
+const RECENT_SIGN_IN_SECS: i64 = 10 * 60;
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnonymizeRequest {
+    pub password: Option<String>,
+    /// Confirms accounts without a password (see `confirm_owner`)
+    pub confirm_username: Option<String>,
+    #[serde(default = "default_keep_scrobbles")]
+    pub keep_scrobbles: bool,
+}
+
+fn default_keep_scrobbles() -> bool {
+    true
+}
+
+#[derive(Debug, Serialize)]
+pub struct AnonymizeResponse {
+    pub scrobbles_kept: i64,
+    pub scrobbles_deleted: i64,
+}
+
+pub async fn anonymize_account(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(req): Json<AnonymizeRequest>,
+) -> Result<Json<AnonymizeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    confirm_owner(&pool, &headers, &user, req.password.as_deref(), req.confirm_username.as_deref()).await?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    // Uploads of unfinished imports live on disk, outside the database
+    let uploads = sqlx::query_scalar!(
+        "SELECT file_name FROM import_jobs WHERE user_id = $1 AND status IN ('pending', 'running')",
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let scrobbles_deleted = if req.keep_scrobbles {
+        0
+    } else {
+        erase_listening_history(&mut tx, user.id).await.map_err(db_error)?
+    };
+
+    let scrobbles_kept = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM scrobs WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    erase_personal_data(&mut tx, user.id, &user.username).await.map_err(db_error)?;
+
+    // Replace the username and make the password unusable, keeping the row
+    // so remaining scrobbles still count towards instance stats
+    let anonymous_name = format!("anon_{}", hex::encode(rand::random::<[u8; 8]>()));
+    let now = chrono::Utc::now().timestamp();
+
+    sqlx::query!(
+        r#"
+        UPDATE users
+        SET username = $1, password_hash = '', is_admin = false, is_private = true, anonymized_at = $2,
+            email = NULL, email_verified_at = NULL
+        WHERE id = $3
+        "#,
+        anonymous_name,
+        now,
+        user.id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+    invalidate_user(user.id);
+    remove_uploads(&config, uploads).await;
+
+    tracing::info!("User {} anonymized their account", user.id);
+
+    Ok(Json(AnonymizeResponse {
+        scrobbles_kept,
+        scrobbles_deleted,
+    }))
+}
+
+/// Make sure the account's owner is behind a request to delete or anonymize
+/// it, so a leaked token alone can't. Accounts with a password confirm with
+/// it. Accounts created through SSO have none; they send their username as
+/// `confirm_username`, from an SSO session signed in within the last ten
+/// minutes.
+async fn confirm_owner(
+    pool: &PgPool,
+    headers: &axum::http::HeaderMap,
+    user: &AuthUser,
+    password: Option<&str>,
+    confirm_username: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let password_hash = sqlx::query_scalar!("SELECT password_hash FROM users WHERE id = $1", user.id)
+        .fetch_one(pool)
+        .await
+        .map_err(db_error)?;
+
+    if !password_hash.is_empty() {
+        if !password.is_some_and(|password| verify_password(password, &password_hash).unwrap_or(false)) {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse {
+                    error: "Invalid password".to_string(),
+                }),
+            ));
+        }
+        return Ok(());
+    }
+
+    if confirm_username != Some(user.username.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "This account has no password; send your username as confirm_username".to_string(),
+            }),
+        ));
+    }
+
+    let token = headers
+        .get(axum::http::header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(extract_token_from_header)
+        .unwrap_or_default();
+    let recent = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM api_tokens
+            WHERE token = $1 AND user_id = $2 AND label = 'oidc' AND revoked = false AND created_at >= $3
+        ) as "recent!"
+        "#,
+        token,
+        user.id,
+        chrono::Utc::now().timestamp() - RECENT_SIGN_IN_SECS
+    )
+    .fetch_one(pool)
+    .await
+    .map_err(db_error)?;
+
+    if !recent {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Sign in through your identity provider again, then retry within ten minutes".to_string(),
+            }),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Delete the user's scrobbles and everything derived from them, returning
+/// how many scrobbles were deleted
+async fn erase_listening_history(tx: &mut Transaction<'_, Postgres>, user_id: i64) -> Result<i64, sqlx::Error> {
+    let deleted = sqlx::query!("DELETE FROM scrobs WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?
+        .rows_affected() as i64;
+
+    // Edit history keeps old values, and archived charts summarize the listens
+    sqlx::query!("DELETE FROM scrob_edits WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM chart_snapshots WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(deleted)
+}
+
+/// Delete everything that identifies the person behind an account when it is
+/// anonymized: credentials, linked services, settings and personal lists.
+/// This is the one list of what anonymization wipes; a new table holding
+/// per-user data must be added here and to `personal_data::build` (see
+/// CLAUDE.md, "Adding a Database Table").
+async fn erase_personal_data(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i64,
+    username: &str,
+) -> Result<(), sqlx::Error> {
+    // Credentials: API tokens (including Last.fm session keys), pending
+    // Last.fm desktop auth tokens, Audioscrobbler sessions, mailed tokens
+    sqlx::query!("DELETE FROM api_tokens WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM lastfm_auth_tokens WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM audioscrobbler_sessions WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    // SSO identities would otherwise sign the person straight back in
+    sqlx::query!("DELETE FROM oidc_identities WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    // Failed logins are keyed by the old username
+    sqlx::query!("DELETE FROM login_failures WHERE key = $1", format!("user:{}", username))
+        .execute(&mut **tx)
+        .await?;
+
+    // Outbound integrations carry third-party credentials
+    sqlx::query!("DELETE FROM relays WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM discord_webhooks WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    // Inbound sync would keep scrobbling the person's plays into the account
+    sqlx::query!("DELETE FROM spotify_links WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM spotify_link_states WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM mpd_watchers WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    // Display name and timezone are personal; retention and global chart
+    // choices still apply to kept scrobbles
+    sqlx::query!(
+        r#"
+        UPDATE user_settings
+        SET display_name = NULL, timezone = 'UTC', default_chart_period = 'overall'
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .execute(&mut **tx)
+    .await?;
+
+    // Personal lists, state and social graph
+    sqlx::query!("DELETE FROM now_playing WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM loved_tracks WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM goals WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM notifications WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM announcement_reads WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM import_jobs WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM scrobble_rules WHERE user_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    sqlx::query!("DELETE FROM follows WHERE follower_id = $1 OR followee_id = $1", user_id)
+        .execute(&mut **tx)
+        .await?;
+
+    Ok(())
+}
+
+/// Remove the on-disk uploads of imports that hadn't finished
+async fn remove_uploads(config: &Config, uploads: Vec<String>) {
+    for file_name in uploads {
+        let path = upload_path(std::path::Path::new(&config.import_dir), &file_name);
+        if let Err(e) = tokio::fs::remove_file(&path).await {
+            tracing::warn!("Failed to remove import upload {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: Option<String>,
+    /// Confirms accounts without a password (see `confirm_owner`)
+    pub confirm_username: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteAccountResponse {
+    pub scrobbles_deleted: i64,
+}
+
+/// Delete the account and everything belonging to it. Unlike anonymizing,
+/// nothing is kept for instance stats.
+pub async fn delete_account(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(req): Json<DeleteAccountRequest>,
+) -> Result<Json<DeleteAccountResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    confirm_owner(&pool, &headers, &user, req.password.as_deref(), req.confirm_username.as_deref()).await?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    // Lock the admin rows so two admins can't both leave at once
+    if user.is_admin {
+        let other_admins = sqlx::query_scalar!(
+            "SELECT id FROM users WHERE is_admin = true AND id <> $1 FOR UPDATE",
+            user.id
+        )
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+        if other_admins.is_empty() {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "The last admin can't delete their account; promote another admin first".to_string(),
+                }),
+            ));
+        }
+    }
+
+    // Uploads of unfinished imports live on disk, outside the cascade
+    let uploads = sqlx::query_scalar!(
+        "SELECT file_name FROM import_jobs WHERE user_id = $1 AND status IN ('pending', 'running')",
+        user.id
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let scrobbles_deleted = sqlx::query!("DELETE FROM scrobs WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected() as i64;
+
+    sqlx::query!("DELETE FROM api_tokens WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    // Everything else keyed on the user goes with it via ON DELETE CASCADE
+    sqlx::query!("DELETE FROM users WHERE id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+    invalidate_user(user.id);
+    remove_uploads(&config, uploads).await;
+
+    tracing::info!("User {} deleted their account", user.id);
+
+    Ok(Json(DeleteAccountResponse { scrobbles_deleted }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MoveRequest {
+    /// Base URL of the instance the account is moving from
+    pub source_url: String,
+    /// API token for the account on the source instance
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MoveResponse {
+    pub source_username: String,
+    #[serde(flatten)]
+    pub summary: archive::RestoreSummary,
+}
+
+pub async fn export_account(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<archive::AccountArchive>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let archive = archive::build(&pool, user.id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Exported account archive for user {}", user.id);
+
+    Ok(Json(archive))
+}
+
+/// GET /account/data - everything stored about the user, credentials aside.
+/// See `personal_data.rs`; `/account/export` is the portable archive for moves.
+pub async fn export_personal_data(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<PersonalData>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let data = personal_data::build(&pool, user.id).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Exported personal data for user {}", user.id);
+
+    Ok(Json(data))
+}
+
+/// Pull an account archive from another scrob instance and merge it into this account
+pub async fn move_account(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(req): Json<MoveRequest>,
+) -> Result<Json<MoveResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    // The server fetches from this URL, so it may only reach public addresses
+    let source_url = req.source_url.trim().trim_end_matches('/');
+    let outbound = Outbound::new(&config.allowed_private_networks);
+    outbound.check_url(source_url).await.map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("source_url not allowed: {}", e),
+            }),
+        )
+    })?;
+
+    let bad_gateway = |error: String| (StatusCode::BAD_GATEWAY, Json(ErrorResponse { error }));
+
+    let client = outbound
+        .client_builder()
+        .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION")))
+        .timeout(MOVE_TIMEOUT)
+        .build()
+        .map_err(|e| bad_gateway(format!("Failed to build HTTP client: {}", e)))?;
+
+    let unreachable = |e: reqwest::Error| bad_gateway(format!("Could not reach source instance: {}", e));
+    let export = |path: &str| {
+        client
+            .get(format!("{}{}", source_url, path))
+            .bearer_auth(&req.token)
+            .send()
+    };
+
+    // Instances from before /api/v1 only serve the unprefixed path
+    let mut response = export("/api/v1/account/export").await.map_err(unreachable)?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        response = export("/account/export").await.map_err(unreachable)?;
+    }
+
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Source instance rejected the token".to_string(),
+            }),
+        ));
+    }
+
+    let archive: archive::AccountArchive = response
+        .error_for_status()
+        .map_err(|e| bad_gateway(format!("Source instance returned an error: {}", e)))?
+        .json()
+        .await
+        .map_err(|e| bad_gateway(format!("Invalid account archive: {}", e)))?;
+
+    if archive.format != archive::FORMAT || archive.version > archive::VERSION {
+        return Err(bad_gateway(format!(
+            "Unsupported account archive: {} v{}",
+            archive.format, archive.version
+        )));
+    }
+
+    let summary = archive::restore(&pool, user.id, &archive).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!(
+        "User {} moved account {} from {}: {} scrobbles imported, {} skipped",
+        user.id,
+        archive.username,
+        source_url,
+        summary.scrobbles_imported,
+        summary.scrobbles_skipped
+    );
+
+    Ok(Json(MoveResponse {
+        source_username: archive.username,
+        summary,
+    }))
+}
@@ -0,0 +1,488 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{AuthUser, Scope},
+    preferences,
+};
+
+/// Scrobble counts worth celebrating
+pub const MILESTONES: [i64; 13] = [
+    1, 100, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 50_000, 100_000, 250_000, 500_000, 1_000_000,
+];
+
+#[derive(Debug, Serialize)]
+pub struct Streak {
+    pub days: i64,
+    /// First and last day, `YYYY-MM-DD` in the user's timezone
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StreaksResponse {
+    pub timezone: String,
+    /// Still going if the last listen was today or yesterday
+    pub current: Option<Streak>,
+    pub longest: Option<Streak>,
+    pub listened_today: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Milestone {
+    pub count: i64,
+    pub id: i64,
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NextMilestone {
+    pub count: i64,
+    pub remaining: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MilestonesResponse {
+    pub total: i64,
+    pub milestones: Vec<Milestone>,
+    pub next: Option<NextMilestone>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ClockQuery {
+    /// Unix time range, `to` exclusive; defaults to all history
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ClockResponse {
+    pub timezone: String,
+    /// Scrobbles per local hour, 0-23
+    pub hours: [i64; 24],
+    /// Scrobbles per weekday, Monday first
+    pub weekdays: [i64; 7],
+    /// `grid[weekday][hour]`, Monday first
+    pub grid: [[i64; 24]; 7],
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CalendarQuery {
+    /// Defaults to the current year in the user's timezone
+    pub year: Option<i32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalendarDay {
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CalendarResponse {
+    pub year: i32,
+    pub timezone: String,
+    pub total: i64,
+    /// Busiest day's count, for scaling colours
+    pub max: i64,
+    /// Every day of the year in order, including ones without scrobbles
+    pub days: Vec<CalendarDay>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OnThisDayQuery {
+    /// Top artists and tracks per year (default 5, max 50)
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayArtist {
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DayTrack {
+    pub artist: String,
+    pub track: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OnThisDayYear {
+    pub year: i32,
+    /// `YYYY-MM-DD`
+    pub date: String,
+    pub scrobbles: i64,
+    pub artists: Vec<DayArtist>,
+    pub tracks: Vec<DayTrack>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OnThisDayResponse {
+    pub timezone: String,
+    /// Today, `YYYY-MM-DD` in the user's timezone
+    pub date: String,
+    /// Earlier years with scrobbles on this date, newest first
+    pub years: Vec<OnThisDayYear>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("Database error: {}", e),
+        }),
+    )
+}
+
+/// GET /stats/streaks - current and longest runs of consecutive days with
+/// at least one scrobble
+pub async fn listening_streaks(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<StreaksResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+
+    // Consecutive days minus their row number are constant within a run, so
+    // each run becomes one group. Only the longest and the latest runs come
+    // back.
+    let streaks = sqlx::query!(
+        r#"
+        WITH days AS (
+            SELECT DISTINCT (to_timestamp(timestamp) AT TIME ZONE $2)::date AS day
+            FROM scrobs
+            WHERE user_id = $1
+        ),
+        runs AS (
+            SELECT MIN(day) AS start_day, MAX(day) AS end_day, COUNT(*) AS days
+            FROM (SELECT day, day - ROW_NUMBER() OVER (ORDER BY day)::int AS run FROM days) d
+            GROUP BY run
+        )
+        SELECT
+            start_day::text as "start!",
+            end_day::text as "end!",
+            days as "days!",
+            end_day >= (now() AT TIME ZONE $2)::date - 1 as "current!",
+            end_day = (now() AT TIME ZONE $2)::date as "today!"
+        FROM runs
+        WHERE days = (SELECT MAX(days) FROM runs)
+           OR end_day = (SELECT MAX(end_day) FROM runs)
+        ORDER BY end_day DESC
+        "#,
+        user.id,
+        timezone
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let listened_today = streaks.first().is_some_and(|s| s.today);
+    let current = streaks.first().filter(|s| s.current).map(|s| Streak {
+        days: s.days,
+        start: s.start.clone(),
+        end: s.end.clone(),
+    });
+    // Rows are newest first, so ties go to the most recent run
+    let longest = streaks
+        .into_iter()
+        .reduce(|best, s| if s.days > best.days { s } else { best })
+        .map(|s| Streak {
+            days: s.days,
+            start: s.start,
+            end: s.end,
+        });
+
+    Ok(Json(StreaksResponse {
+        timezone,
+        current,
+        longest,
+        listened_today,
+    }))
+}
+
+/// GET /stats/milestones - the scrobbles that reached each milestone count
+pub async fn listening_milestones(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<MilestonesResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let total = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM scrobs WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let milestones = sqlx::query_as!(
+        Milestone,
+        r#"
+        SELECT n as "count!", id as "id!", artist as "artist!", track as "track!", album, timestamp as "timestamp!"
+        FROM (
+            SELECT id, artist, track, album, timestamp, ROW_NUMBER() OVER (ORDER BY timestamp, id) AS n
+            FROM scrobs
+            WHERE user_id = $1
+        ) s
+        WHERE n = ANY($2)
+        ORDER BY n
+        "#,
+        user.id,
+        &MILESTONES[..]
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let next = MILESTONES.iter().find(|&&count| count > total).map(|&count| NextMilestone {
+        count,
+        remaining: count - total,
+    });
+
+    Ok(Json(MilestonesResponse { total, milestones, next }))
+}
+
+/// GET /stats/clock - scrobbles by hour of day and day of week in the user's
+/// timezone
+pub async fn listening_clock(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ClockQuery>,
+) -> Result<Json<ClockResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let from = query.from.unwrap_or(0);
+    let to = query.to.unwrap_or(i64::MAX);
+    if from >= to {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "from must be before to".to_string(),
+            }),
+        ));
+    }
+
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+
+    let buckets = sqlx::query!(
+        r#"
+        SELECT
+            EXTRACT(ISODOW FROM local)::int as "weekday!",
+            EXTRACT(HOUR FROM local)::int as "hour!",
+            COUNT(*) as "count!"
+        FROM (
+            SELECT to_timestamp(timestamp) AT TIME ZONE $2 AS local
+            FROM scrobs
+            WHERE user_id = $1 AND timestamp >= $3 AND timestamp < $4
+        ) s
+        GROUP BY 1, 2
+        "#,
+        user.id,
+        timezone,
+        from,
+        to
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut hours = [0; 24];
+    let mut weekdays = [0; 7];
+    let mut grid = [[0; 24]; 7];
+    for bucket in buckets {
+        // ISODOW is 1 (Monday) to 7 (Sunday)
+        let (weekday, hour) = ((bucket.weekday - 1) as usize, bucket.hour as usize);
+        hours[hour] += bucket.count;
+        weekdays[weekday] += bucket.count;
+        grid[weekday][hour] = bucket.count;
+    }
+
+    Ok(Json(ClockResponse {
+        timezone,
+        hours,
+        weekdays,
+        grid,
+    }))
+}
+
+/// GET /stats/calendar?year=2024 - scrobbles per day of a year, for a
+/// contribution-style heatmap
+pub async fn listening_calendar(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<CalendarQuery>,
+) -> Result<Json<CalendarResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if query.year.is_some_and(|year| !(1970..=9999).contains(&year)) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "year must be between 1970 and 9999".to_string(),
+            }),
+        ));
+    }
+
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+
+    // The year's bounds are turned into unix times first so the scan can use
+    // the timestamp index
+    let days = sqlx::query!(
+        r#"
+        WITH bounds AS (
+            SELECT
+                make_date(y, 1, 1) AS first_day,
+                make_date(y, 12, 31) AS last_day,
+                EXTRACT(EPOCH FROM make_date(y, 1, 1)::timestamp AT TIME ZONE $2)::bigint AS start_ts,
+                EXTRACT(EPOCH FROM make_date(y + 1, 1, 1)::timestamp AT TIME ZONE $2)::bigint AS end_ts
+            FROM (SELECT COALESCE($3, EXTRACT(YEAR FROM now() AT TIME ZONE $2)::int) AS y) year
+        ),
+        counts AS (
+            SELECT (to_timestamp(s.timestamp) AT TIME ZONE $2)::date AS day, COUNT(*) AS count
+            FROM scrobs s, bounds b
+            WHERE s.user_id = $1 AND s.timestamp >= b.start_ts AND s.timestamp < b.end_ts
+            GROUP BY 1
+        )
+        SELECT EXTRACT(YEAR FROM b.first_day)::int as "year!", d::date::text as "date!", COALESCE(c.count, 0) as "count!"
+        FROM bounds b
+        CROSS JOIN generate_series(b.first_day, b.last_day, interval '1 day') d
+        LEFT JOIN counts c ON c.day = d::date
+        ORDER BY d
+        "#,
+        user.id,
+        timezone,
+        query.year
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let year = days.first().map(|day| day.year).unwrap_or_default();
+    let days: Vec<CalendarDay> = days
+        .into_iter()
+        .map(|row| CalendarDay {
+            date: row.date,
+            count: row.count,
+        })
+        .collect();
+
+    Ok(Json(CalendarResponse {
+        year,
+        timezone,
+        total: days.iter().map(|day| day.count).sum(),
+        max: days.iter().map(|day| day.count).max().unwrap_or(0),
+        days,
+    }))
+}
+
+/// GET /stats/on-this-day - what the user listened to on today's date in
+/// earlier years
+pub async fn on_this_day(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<OnThisDayQuery>,
+) -> Result<Json<OnThisDayResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(5).min(50);
+
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+    let today = preferences::local_today(&pool, &timezone).await.map_err(db_error)?;
+
+    // One local day per earlier year, skipping 29 February in years without
+    // it, each turned into unix bounds so the scan can use the timestamp index
+    let rows = sqlx::query!(
+        r#"
+        WITH days AS (
+            SELECT
+                x.day,
+                EXTRACT(EPOCH FROM x.day::timestamp AT TIME ZONE $3)::bigint AS start_ts,
+                EXTRACT(EPOCH FROM (x.day + 1)::timestamp AT TIME ZONE $3)::bigint AS end_ts
+            FROM generate_series(1, EXTRACT(YEAR FROM $2::date)::int - 1970) n,
+                LATERAL (SELECT ($2::date - make_interval(years => n))::date AS day) x
+            WHERE EXTRACT(DAY FROM x.day) = EXTRACT(DAY FROM $2::date)
+        )
+        SELECT d.day as "day!", a.name as artist, t.name as track, COUNT(*) as "count!"
+        FROM days d
+        JOIN scrobs s ON s.user_id = $1 AND s.timestamp >= d.start_ts AND s.timestamp < d.end_ts
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        GROUP BY d.day, t.id, a.id
+        ORDER BY d.day DESC, COUNT(*) DESC, a.name, t.name
+        "#,
+        user.id,
+        today,
+        timezone
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut years: Vec<OnThisDayYear> = Vec::new();
+    let mut artist_counts: Vec<Vec<DayArtist>> = Vec::new();
+    for row in rows {
+        if years.last().is_none_or(|year| year.year != row.day.year()) {
+            years.push(OnThisDayYear {
+                year: row.day.year(),
+                date: row.day.to_string(),
+                scrobbles: 0,
+                artists: Vec::new(),
+                tracks: Vec::new(),
+            });
+            artist_counts.push(Vec::new());
+        }
+        let (year, artists) = (years.last_mut().unwrap(), artist_counts.last_mut().unwrap());
+
+        year.scrobbles += row.count;
+        match artists.iter_mut().find(|artist| artist.name == row.artist) {
+            Some(artist) => artist.count += row.count,
+            None => artists.push(DayArtist {
+                name: row.artist.clone(),
+                count: row.count,
+            }),
+        }
+        // Rows come busiest first, so the first `limit` are the top tracks
+        if year.tracks.len() < limit {
+            year.tracks.push(DayTrack {
+                artist: row.artist,
+                track: row.track,
+                count: row.count,
+            });
+        }
+    }
+
+    for (year, mut artists) in years.iter_mut().zip(artist_counts) {
+        artists.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        artists.truncate(limit);
+        year.artists = artists;
+    }
+
+    Ok(Json(OnThisDayResponse {
+        timezone,
+        date: today.to_string(),
+        years,
+    }))
+}
@@ -1,8 +1,13 @@
-use axum::{extract::State, http::StatusCode, Json, extract::Path};
+use axum::{extract::{Query, State}, http::StatusCode, Json, extract::Path};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-use crate::auth::AuthUser;
+use crate::{
+    auth::{invalidate_user, AuthUser},
+    policy,
+    runtime::{RetentionScope, RuntimeSettings},
+    versioning::{self, versioned, Precondition, Versioned},
+};
 
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
@@ -18,6 +23,8 @@ pub struct UserListItem {
     pub is_admin: bool,
     pub created_at: i64,
     pub scrobble_count: i64,
+    /// A ban is currently in force
+    pub banned: bool,
 }
 
 #[derive(Debug, Serialize)]
@@ -28,12 +35,35 @@ pub struct UserDetail {
     pub created_at: i64,
     pub scrobble_count: i64,
     pub last_scrobble: Option<i64>,
+    pub banned: bool,
+    pub ban_reason: Option<String>,
+    pub banned_until: Option<i64>,
 }
 
+/// Users per page when `per_page` isn't given, and the most allowed
+const DEFAULT_USERS_PER_PAGE: i64 = 50;
+const MAX_USERS_PER_PAGE: i64 = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct UserListQuery {
+    /// 1-based (default 1)
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// Case-insensitive username substring
+    pub q: Option<String>,
+    /// `created` (the default), `username` or `scrobbles`
+    pub sort: Option<String>,
+    /// `asc` or `desc`; defaults to `asc` for `username`, `desc` otherwise
+    pub order: Option<String>,
+}
+
+/// GET /admin/users?page=&per_page=&q=&sort=&order= - a page of users with
+/// their scrobble counts. The number of matching users is in `X-Total-Count`.
 pub async fn list_users(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
-) -> Result<Json<Vec<UserListItem>>, (StatusCode, Json<ErrorResponse>)> {
+    Query(query): Query<UserListQuery>,
+) -> Result<([(&'static str, String); 1], Json<Vec<UserListItem>>), (StatusCode, Json<ErrorResponse>)> {
     let auth = AuthUser::from_headers(&pool, &headers).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
 
@@ -41,38 +71,85 @@ pub async fn list_users(
         return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
     }
 
-    let users = sqlx::query!(
-        r#"
-        SELECT
-            u.id as "id!",
-            u.username,
-            u.is_admin as "is_admin: bool",
-            u.created_at as "created_at!",
-            COUNT(s.id) as "scrobble_count!"
-        FROM users u
-        LEFT JOIN scrobs s ON u.id = s.user_id
-        GROUP BY u.id, u.username, u.is_admin, u.created_at
-        ORDER BY u.created_at DESC
-        "#
-    )
-    .fetch_all(&pool)
-    .await
-    .map_err(|e| {
+    let bad_request = |msg: &str| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: msg.to_string() }));
+    let db_error = |e: sqlx::Error| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
                 error: format!("Database error: {}", e),
             }),
         )
-    })?;
+    };
+
+    let sort = query.sort.as_deref().unwrap_or("created");
+    if !["created", "username", "scrobbles"].contains(&sort) {
+        return Err(bad_request("sort must be created, username or scrobbles"));
+    }
+    let descending = match query.order.as_deref() {
+        None => sort != "username",
+        Some("asc") => false,
+        Some("desc") => true,
+        Some(_) => return Err(bad_request("order must be asc or desc")),
+    };
+    let q = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let per_page = query.per_page.unwrap_or(DEFAULT_USERS_PER_PAGE).clamp(1, MAX_USERS_PER_PAGE);
+    let offset = (query.page.unwrap_or(1).max(1) - 1).saturating_mul(per_page);
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM users
+        WHERE $1::text IS NULL OR strpos(lower(username), lower($1)) > 0
+        "#,
+        q
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    // Only the page's users are counted, each from the user_id index; the
+    // scrobbles sort has to count every match to order them
+    let users = sqlx::query_as!(
+        UserListItem,
+        r#"
+        SELECT p.id, p.username, p.is_admin, p.created_at, c.count as "scrobble_count!",
+               (p.banned_at IS NOT NULL AND (p.banned_until IS NULL OR p.banned_until > $6)) as "banned!"
+        FROM (
+            SELECT u.id, u.username, u.is_admin, u.created_at, u.banned_at, u.banned_until
+            FROM users u
+            WHERE $1::text IS NULL OR strpos(lower(u.username), lower($1)) > 0
+            ORDER BY
+                CASE WHEN $3 AND $2 = 'created' THEN u.created_at END DESC,
+                CASE WHEN NOT $3 AND $2 = 'created' THEN u.created_at END,
+                CASE WHEN $3 AND $2 = 'username' THEN u.username END DESC,
+                CASE WHEN NOT $3 AND $2 = 'username' THEN u.username END,
+                CASE WHEN $3 AND $2 = 'scrobbles' THEN (SELECT COUNT(*) FROM scrobs s WHERE s.user_id = u.id) END DESC,
+                CASE WHEN NOT $3 AND $2 = 'scrobbles' THEN (SELECT COUNT(*) FROM scrobs s WHERE s.user_id = u.id) END,
+                u.id
+            LIMIT $4 OFFSET $5
+        ) p
+        CROSS JOIN LATERAL (SELECT COUNT(*) as count FROM scrobs s WHERE s.user_id = p.id) c
+        ORDER BY
+            CASE WHEN $3 AND $2 = 'created' THEN p.created_at END DESC,
+            CASE WHEN NOT $3 AND $2 = 'created' THEN p.created_at END,
+            CASE WHEN $3 AND $2 = 'username' THEN p.username END DESC,
+            CASE WHEN NOT $3 AND $2 = 'username' THEN p.username END,
+            CASE WHEN $3 AND $2 = 'scrobbles' THEN c.count END DESC,
+            CASE WHEN NOT $3 AND $2 = 'scrobbles' THEN c.count END,
+            p.id
+        "#,
+        q,
+        sort,
+        descending,
+        per_page,
+        offset,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
 
-    Ok(Json(users.into_iter().map(|u| UserListItem {
-        id: u.id,
-        username: u.username,
-        is_admin: u.is_admin,
-        created_at: u.created_at,
-        scrobble_count: u.scrobble_count,
-    }).collect()))
+    Ok(([("x-total-count", total.to_string())], Json(users)))
 }
 
 pub async fn get_user(
@@ -93,11 +170,15 @@ pub async fn get_user(
             id as "id!",
             username,
             is_admin as "is_admin: bool",
-            created_at as "created_at!"
+            created_at as "created_at!",
+            (banned_at IS NOT NULL AND (banned_until IS NULL OR banned_until > $2)) as "banned!",
+            ban_reason,
+            banned_until
         FROM users
         WHERE id = $1
         "#,
-        user_id
+        user_id,
+        chrono::Utc::now().timestamp()
     )
     .fetch_optional(&pool)
     .await
@@ -156,6 +237,9 @@ pub async fn get_user(
         created_at: user.created_at,
         scrobble_count: scrobble_count.count,
         last_scrobble: last_scrobble.last_scrobble,
+        banned: user.banned,
+        ban_reason: user.banned.then_some(user.ban_reason).flatten(),
+        banned_until: user.banned.then_some(user.banned_until).flatten(),
     }))
 }
 
@@ -213,6 +297,7 @@ pub async fn delete_user(
                 }),
             )
         })?;
+    invalidate_user(user_id);
 
     if result.rows_affected() == 0 {
         return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "User not found".to_string() })));
@@ -221,6 +306,285 @@ pub async fn delete_user(
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Debug, Deserialize)]
+pub struct BanRequest {
+    pub reason: Option<String>,
+    /// Unix time the ban ends; none bans until lifted
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BanResponse {
+    pub user_id: i64,
+    pub banned_at: i64,
+    pub banned_until: Option<i64>,
+    pub ban_reason: Option<String>,
+}
+
+/// POST /admin/users/{id}/ban - block the user from logging in and from
+/// using their tokens (so nothing is scrobbled) until `expires_at`. Their
+/// data and tokens are kept. Banning again replaces the ban.
+pub async fn ban_user(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(user_id): Path<i64>,
+    Json(req): Json<BanRequest>,
+) -> Result<Json<BanResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    if auth.id == user_id {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "Cannot ban yourself".to_string() })));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    if req.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "expires_at must be in the future".to_string() })));
+    }
+
+    let reason = req.reason.as_deref().map(str::trim).filter(|r| !r.is_empty());
+
+    let ban = sqlx::query_as!(
+        BanResponse,
+        r#"
+        UPDATE users
+        SET banned_at = $2, banned_until = $3, ban_reason = $4
+        WHERE id = $1
+        RETURNING id as user_id, banned_at as "banned_at!", banned_until, ban_reason
+        "#,
+        user_id,
+        now,
+        req.expires_at,
+        reason
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: "User not found".to_string() })))?;
+    invalidate_user(user_id);
+
+    tracing::info!("Admin {} banned user {} until {:?}", auth.id, user_id, ban.banned_until);
+
+    Ok(Json(ban))
+}
+
+/// DELETE /admin/users/{id}/ban - lift the user's ban
+pub async fn unban_user(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(user_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE users
+        SET banned_at = NULL, banned_until = NULL, ban_reason = NULL
+        WHERE id = $1 AND banned_at IS NOT NULL
+        "#,
+        user_id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "User not found or not banned".to_string() })));
+    }
+
+    tracing::info!("Admin {} lifted the ban on user {}", auth.id, user_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MergeQuery {
+    /// The account that keeps everything
+    pub into: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergeResponse {
+    pub from_user_id: i64,
+    pub into_user_id: i64,
+    pub scrobbles_moved: i64,
+    /// Listens the target already had, dropped from the source
+    pub duplicates_skipped: i64,
+    pub tokens_moved: i64,
+    pub loved_tracks_moved: i64,
+}
+
+/// POST /admin/users/{id}/merge?into= - move a user's scrobbles (with their
+/// edit history), tokens and loved tracks into another account, e.g. after
+/// history was imported into a second account by mistake. Moved tokens are
+/// revoked. All or nothing; the emptied account is left for the admin to
+/// delete.
+pub async fn merge_user(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(user_id): Path<i64>,
+    Query(query): Query<MergeQuery>,
+) -> Result<Json<MergeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let error = |status: StatusCode, msg: &str| (status, Json(ErrorResponse { error: msg.to_string() }));
+    let db_error = |e: sqlx::Error| error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e));
+
+    let into = query.into;
+    if into == user_id {
+        return Err(error(StatusCode::BAD_REQUEST, "Cannot merge a user into itself"));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    // Lock both rows so neither account is deleted mid-merge
+    let found = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 OR id = $2 FOR UPDATE",
+        user_id,
+        into
+    )
+    .fetch_all(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    if !found.contains(&user_id) {
+        return Err(error(StatusCode::NOT_FOUND, "User not found"));
+    }
+    if !found.contains(&into) {
+        return Err(error(StatusCode::NOT_FOUND, "Target user not found"));
+    }
+
+    // The same listen in both accounts would break the unique index
+    let duplicates_skipped = sqlx::query!(
+        r#"
+        DELETE FROM scrobs s
+        WHERE s.user_id = $1
+          AND EXISTS (
+              SELECT 1 FROM scrobs t
+              WHERE t.user_id = $2 AND t.artist = s.artist AND t.track = s.track AND t.timestamp = s.timestamp
+          )
+        "#,
+        user_id,
+        into
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .rows_affected() as i64;
+
+    let scrobbles_moved = sqlx::query!("UPDATE scrobs SET user_id = $2 WHERE user_id = $1", user_id, into)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected() as i64;
+
+    sqlx::query!("UPDATE scrob_edits SET user_id = $2 WHERE user_id = $1", user_id, into)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    let tokens_moved = sqlx::query!(
+        "UPDATE api_tokens SET user_id = $2, revoked = true WHERE user_id = $1",
+        user_id,
+        into
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .rows_affected() as i64;
+
+    // Tracks loved in both accounts keep the earlier date
+    sqlx::query!(
+        r#"
+        UPDATE loved_tracks t
+        SET loved_at = s.loved_at
+        FROM loved_tracks s
+        WHERE s.user_id = $1 AND t.user_id = $2
+          AND t.artist = s.artist AND t.track = s.track AND s.loved_at < t.loved_at
+        "#,
+        user_id,
+        into
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    let loved_tracks_moved = sqlx::query!(
+        r#"
+        UPDATE loved_tracks s
+        SET user_id = $2
+        WHERE s.user_id = $1
+          AND NOT EXISTS (
+              SELECT 1 FROM loved_tracks t
+              WHERE t.user_id = $2 AND t.artist = s.artist AND t.track = s.track
+          )
+        "#,
+        user_id,
+        into
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .rows_affected() as i64;
+
+    sqlx::query!("DELETE FROM loved_tracks WHERE user_id = $1", user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+    invalidate_user(user_id);
+
+    tracing::info!(
+        "Admin {} merged user {} into {}: {} scrobbles ({} duplicates), {} tokens, {} loved tracks",
+        auth.id,
+        user_id,
+        into,
+        scrobbles_moved,
+        duplicates_skipped,
+        tokens_moved,
+        loved_tracks_moved
+    );
+
+    Ok(Json(MergeResponse {
+        from_user_id: user_id,
+        into_user_id: into,
+        scrobbles_moved,
+        duplicates_skipped,
+        tokens_moved,
+        loved_tracks_moved,
+    }))
+}
+
 #[derive(Debug, Deserialize)]
 pub struct ToggleAdminRequest {
     pub is_admin: bool,
@@ -262,6 +626,7 @@ pub async fn toggle_admin(
     if result.rows_affected() == 0 {
         return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "User not found".to_string() })));
     }
+    invalidate_user(user_id);
 
     Ok(StatusCode::OK)
 }
@@ -282,10 +647,40 @@ pub struct TopUser {
     pub scrobble_count: i64,
 }
 
+#[derive(Debug, Serialize)]
+pub struct CohortActivity {
+    pub month: String,
+    pub active_users: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cohort {
+    pub month: String,
+    pub size: i64,
+    pub activity: Vec<CohortActivity>,
+}
+
+/// Days covered by the daily series, today included
+const DAILY_STATS_DAYS: i32 = 90;
+
+#[derive(Debug, Serialize)]
+pub struct DailyActivity {
+    /// UTC day, `YYYY-MM-DD`
+    pub date: String,
+    pub new_users: i64,
+    /// Scrobbles received that day, whenever they were played
+    pub scrobbles: i64,
+    pub active_users: i64,
+}
+
 #[derive(Debug, Serialize)]
 pub struct StatsResponse {
     pub stats: SystemStats,
     pub top_users: Vec<TopUser>,
+    pub cohorts: Vec<Cohort>,
+    pub cohorts_computed_at: Option<i64>,
+    /// Oldest first, one entry per day including days with no activity
+    pub daily: Vec<DailyActivity>,
 }
 
 pub async fn get_stats(
@@ -323,7 +718,7 @@ pub async fn get_stats(
             )
         })?;
 
-    let total_artists = sqlx::query!("SELECT COUNT(DISTINCT artist) as \"count!\" FROM scrobs")
+    let total_artists = sqlx::query!("SELECT COUNT(DISTINCT artist_id) as \"count!\" FROM scrobs")
         .fetch_one(&pool)
         .await
         .map_err(|e| {
@@ -335,7 +730,7 @@ pub async fn get_stats(
             )
         })?;
 
-    let total_tracks = sqlx::query!("SELECT COUNT(DISTINCT artist || ' - ' || track) as \"count!\" FROM scrobs")
+    let total_tracks = sqlx::query!("SELECT COUNT(DISTINCT track_id) as \"count!\" FROM scrobs")
         .fetch_one(&pool)
         .await
         .map_err(|e| {
@@ -371,6 +766,97 @@ pub async fn get_stats(
         )
     })?;
 
+    let cohort_rows = sqlx::query!(
+        r#"
+        SELECT cohort_month, activity_month, cohort_size, active_users, computed_at
+        FROM user_cohorts
+        ORDER BY cohort_month, activity_month
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let daily = sqlx::query!(
+        r#"
+        WITH days AS (
+            SELECT generate_series(
+                date_trunc('day', now() AT TIME ZONE 'UTC') - make_interval(days => $1 - 1),
+                date_trunc('day', now() AT TIME ZONE 'UTC'),
+                interval '1 day'
+            )::date as day
+        ),
+        signups AS (
+            SELECT date_trunc('day', to_timestamp(created_at) AT TIME ZONE 'UTC')::date as day, COUNT(*) as count
+            FROM users
+            WHERE created_at >= EXTRACT(EPOCH FROM (SELECT MIN(day) FROM days))::bigint
+            GROUP BY 1
+        ),
+        activity AS (
+            SELECT date_trunc('day', to_timestamp(created_at) AT TIME ZONE 'UTC')::date as day,
+                   COUNT(*) as scrobbles,
+                   COUNT(DISTINCT user_id) as active_users
+            FROM scrobs
+            WHERE created_at >= EXTRACT(EPOCH FROM (SELECT MIN(day) FROM days))::bigint
+            GROUP BY 1
+        )
+        SELECT d.day as "day!",
+               COALESCE(su.count, 0) as "new_users!",
+               COALESCE(a.scrobbles, 0) as "scrobbles!",
+               COALESCE(a.active_users, 0) as "active_users!"
+        FROM days d
+        LEFT JOIN signups su ON su.day = d.day
+        LEFT JOIN activity a ON a.day = d.day
+        ORDER BY d.day
+        "#,
+        DAILY_STATS_DAYS
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .into_iter()
+    .map(|r| DailyActivity {
+        date: r.day.format("%Y-%m-%d").to_string(),
+        new_users: r.new_users,
+        scrobbles: r.scrobbles,
+        active_users: r.active_users,
+    })
+    .collect();
+
+    let cohorts_computed_at = cohort_rows.iter().map(|r| r.computed_at).max();
+    let mut cohorts: Vec<Cohort> = Vec::new();
+    for row in cohort_rows {
+        let month = row.cohort_month.format("%Y-%m").to_string();
+        if cohorts.last().is_none_or(|c| c.month != month) {
+            cohorts.push(Cohort {
+                month,
+                size: row.cohort_size,
+                activity: Vec::new(),
+            });
+        }
+
+        if let Some(cohort) = cohorts.last_mut() {
+            cohort.activity.push(CohortActivity {
+                month: row.activity_month.format("%Y-%m").to_string(),
+                active_users: row.active_users,
+            });
+        }
+    }
+
     Ok(Json(StatsResponse {
         stats: SystemStats {
             total_users: total_users.count,
@@ -382,6 +868,9 @@ pub async fn get_stats(
             username: u.username,
             scrobble_count: u.scrobble_count,
         }).collect(),
+        cohorts,
+        cohorts_computed_at,
+        daily,
     }))
 }
 
@@ -417,3 +906,640 @@ pub async fn delete_scrobble(
 
     Ok(StatusCode::NO_CONTENT)
 }
+
+/// Scrobbles removed per transaction by a bulk delete
+const BULK_DELETE_BATCH: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct BulkDeleteRequest {
+    pub user_id: i64,
+    /// Exact artist name, ignoring case
+    pub artist: Option<String>,
+    /// Unix time range, `to` exclusive
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Defaults to true; only counts the matches
+    pub dry_run: Option<bool>,
+    /// Required to delete: the `matched` count from a dry run
+    pub expected_count: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkDeleteResponse {
+    pub dry_run: bool,
+    pub matched: i64,
+    pub deleted: i64,
+}
+
+/// POST /admin/scrobbles/bulk-delete - delete a user's scrobbles matching a
+/// filter, e.g. after importing a bad file. Without `dry_run: false` and the
+/// `expected_count` a dry run reported, nothing is deleted.
+pub async fn bulk_delete_scrobbles(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<BulkDeleteRequest>,
+) -> Result<Json<BulkDeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let error = |status: StatusCode, msg: &str| (status, Json(ErrorResponse { error: msg.to_string() }));
+    let db_error = |e: sqlx::Error| error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e));
+
+    let artist = req.artist.as_deref().map(str::trim).filter(|a| !a.is_empty());
+    let from = req.from.unwrap_or(i64::MIN);
+    let to = req.to.unwrap_or(i64::MAX);
+    if from >= to {
+        return Err(error(StatusCode::BAD_REQUEST, "from must be before to"));
+    }
+
+    let user_exists = sqlx::query_scalar!("SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) as \"exists!\"", req.user_id)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+    if !user_exists {
+        return Err(error(StatusCode::NOT_FOUND, "User not found"));
+    }
+
+    let matched = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+          AND ($4::text IS NULL OR lower(artist) = lower($4))
+        "#,
+        req.user_id,
+        from,
+        to,
+        artist
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if req.dry_run.unwrap_or(true) {
+        return Ok(Json(BulkDeleteResponse { dry_run: true, matched, deleted: 0 }));
+    }
+
+    match req.expected_count {
+        None => return Err(error(StatusCode::BAD_REQUEST, "expected_count from a dry run is required to delete")),
+        Some(expected) if expected != matched => {
+            return Err(error(
+                StatusCode::CONFLICT,
+                &format!("The filter now matches {} scrobbles, not {}; run the dry run again", matched, expected),
+            ));
+        }
+        Some(_) => {}
+    }
+
+    // Small transactions keep locks short on big imports; a failure part
+    // way through leaves the rest matching, so the request can be retried
+    let mut deleted = 0;
+    loop {
+        let mut tx = pool.begin().await.map_err(db_error)?;
+        let batch = sqlx::query!(
+            r#"
+            DELETE FROM scrobs
+            WHERE id IN (
+                SELECT id FROM scrobs
+                WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+                  AND ($4::text IS NULL OR lower(artist) = lower($4))
+                LIMIT $5
+            )
+            "#,
+            req.user_id,
+            from,
+            to,
+            artist,
+            BULK_DELETE_BATCH
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected() as i64;
+        tx.commit().await.map_err(db_error)?;
+
+        deleted += batch;
+        if batch < BULK_DELETE_BATCH {
+            break;
+        }
+    }
+
+    tracing::info!(
+        "Admin {} bulk deleted {} scrobbles of user {} (artist {:?}, from {:?}, to {:?})",
+        auth.id,
+        deleted,
+        req.user_id,
+        artist,
+        req.from,
+        req.to
+    );
+
+    Ok(Json(BulkDeleteResponse { dry_run: false, matched, deleted }))
+}
+
+// Invites
+
+/// Most codes minted by one request
+const MAX_INVITES_PER_REQUEST: i64 = 50;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInvitesRequest {
+    /// How many codes to mint (default 1)
+    pub count: Option<i64>,
+    pub note: Option<String>,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Invite {
+    pub code: String,
+    pub note: Option<String>,
+    pub created_by: Option<i64>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub used_by: Option<i64>,
+    pub used_at: Option<i64>,
+}
+
+/// 20 characters from an unambiguous alphabet, easy to read out or type
+fn invite_code() -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZ23456789";
+    (0..20)
+        .map(|_| ALPHABET[rand::random::<usize>() % ALPHABET.len()] as char)
+        .collect()
+}
+
+/// POST /admin/invites - mint single-use signup codes
+pub async fn create_invites(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<CreateInvitesRequest>,
+) -> Result<(StatusCode, Json<Vec<Invite>>), (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let count = req.count.unwrap_or(1);
+    if !(1..=MAX_INVITES_PER_REQUEST).contains(&count) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("count must be between 1 and {}", MAX_INVITES_PER_REQUEST) })));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    if req.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "expires_at must be in the future".to_string() })));
+    }
+
+    let note = req.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    let codes: Vec<String> = (0..count).map(|_| invite_code()).collect();
+
+    let invites = sqlx::query_as!(
+        Invite,
+        r#"
+        INSERT INTO invites (code, note, created_by, created_at, expires_at)
+        SELECT code, $2, $3, $4, $5 FROM UNNEST($1::TEXT[]) AS code
+        RETURNING code as "code!", note, created_by, created_at, expires_at, used_by, used_at
+        "#,
+        &codes,
+        note,
+        auth.id,
+        now,
+        req.expires_at
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Admin {} created {} invite codes", auth.id, invites.len());
+
+    Ok((StatusCode::CREATED, Json(invites)))
+}
+
+/// GET /admin/invites - every invite, newest first, used or not
+pub async fn list_invites(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Invite>>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let invites = sqlx::query_as!(
+        Invite,
+        r#"
+        SELECT code, note, created_by, created_at, expires_at, used_by, used_at
+        FROM invites
+        ORDER BY created_at DESC, code
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(invites))
+}
+
+/// DELETE /admin/invites/{code} - revoke an unused invite
+pub async fn delete_invite(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(code): Path<String>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let result = sqlx::query!("DELETE FROM invites WHERE code = $1 AND used_at IS NULL", code)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Unused invite not found".to_string() })));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// Announcements
+
+#[derive(Debug, Deserialize)]
+pub struct CreateAnnouncementRequest {
+    pub title: String,
+    pub body: String,
+    pub expires_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AdminAnnouncement {
+    pub id: i64,
+    pub title: String,
+    pub body: String,
+    pub created_by: Option<i64>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub read_count: i64,
+}
+
+pub async fn create_announcement(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<CreateAnnouncementRequest>,
+) -> Result<(StatusCode, Json<AdminAnnouncement>), (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    if req.title.trim().is_empty() || req.body.trim().is_empty() {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "Title and body are required".to_string() })));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    if req.expires_at.is_some_and(|expires_at| expires_at <= now) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "expires_at must be in the future".to_string() })));
+    }
+
+    let announcement = sqlx::query_as!(
+        AdminAnnouncement,
+        r#"
+        INSERT INTO announcements (title, body, created_by, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING
+            id as "id!",
+            title,
+            body,
+            created_by,
+            created_at as "created_at!",
+            expires_at,
+            0::BIGINT as "read_count!"
+        "#,
+        req.title.trim(),
+        req.body.trim(),
+        auth.id,
+        now,
+        req.expires_at
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Admin {} created announcement {}", auth.id, announcement.id);
+
+    Ok((StatusCode::CREATED, Json(announcement)))
+}
+
+pub async fn list_announcements(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<AdminAnnouncement>>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let announcements = sqlx::query_as!(
+        AdminAnnouncement,
+        r#"
+        SELECT
+            a.id as "id!",
+            a.title,
+            a.body,
+            a.created_by,
+            a.created_at as "created_at!",
+            a.expires_at,
+            COUNT(r.user_id) as "read_count!"
+        FROM announcements a
+        LEFT JOIN announcement_reads r ON r.announcement_id = a.id
+        GROUP BY a.id
+        ORDER BY a.created_at DESC
+        "#
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(announcements))
+}
+
+pub async fn delete_announcement(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(announcement_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let result = sqlx::query!("DELETE FROM announcements WHERE id = $1", announcement_id)
+        .execute(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Announcement not found".to_string() })));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct ServerSettings {
+    pub reserved_usernames: Vec<String>,
+    pub banned_words: Vec<String>,
+    pub min_public_account_age_days: i32,
+    pub registration_open: bool,
+    /// Serve `/charts/global/*`
+    pub global_charts_enabled: bool,
+    /// Overrides of the environment's limits; null means the environment value
+    pub scrobble_max_batch: Option<i32>,
+    pub scrobble_rate_limit: Option<i32>,
+    pub scrobble_rate_limit_window: Option<i32>,
+    /// Scrobbles older than this many days are deleted; null means
+    /// `SCROB_RETENTION_DAYS` and 0 keeps them
+    pub retention_days: Option<i32>,
+    /// `all` or `banned` (only users whose ban is in force)
+    pub retention_scope: String,
+    pub updated_at: Option<i64>,
+    #[serde(skip)]
+    pub version: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateServerSettings {
+    pub reserved_usernames: Option<Vec<String>>,
+    pub banned_words: Option<Vec<String>>,
+    pub min_public_account_age_days: Option<i32>,
+    pub registration_open: Option<bool>,
+    pub global_charts_enabled: Option<bool>,
+    // For these, `null` clears the value and a missing field leaves it
+    #[serde(default, deserialize_with = "nullable")]
+    pub scrobble_max_batch: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub scrobble_rate_limit: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub scrobble_rate_limit_window: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub retention_days: Option<Option<i32>>,
+    pub retention_scope: Option<String>,
+}
+
+/// Tell an explicit `null` (Some(None)) apart from a missing field (None)
+pub(crate) fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+pub async fn get_server_settings(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Versioned<ServerSettings>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let settings = sqlx::query_as!(
+        ServerSettings,
+        r#"
+        SELECT reserved_usernames, banned_words, min_public_account_age_days, registration_open,
+               global_charts_enabled, scrobble_max_batch, scrobble_rate_limit, scrobble_rate_limit_window,
+               retention_days, retention_scope, updated_at, version
+        FROM server_settings
+        WHERE id = 1
+        "#
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(versioned(settings.version, settings))
+}
+
+/// PATCH requires `If-Match` with the version from a previous GET (or `*`)
+/// so two admins can't silently overwrite each other's changes
+pub async fn update_server_settings(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(runtime): State<RuntimeSettings>,
+    Json(req): Json<UpdateServerSettings>,
+) -> Result<Versioned<ServerSettings>, (StatusCode, Json<ErrorResponse>)> {
+    let auth = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !auth.is_admin {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: "Admin access required".to_string() })));
+    }
+
+    let expected = match versioning::if_match(&headers) {
+        Ok(Precondition::Missing) => {
+            return Err((StatusCode::PRECONDITION_REQUIRED, Json(ErrorResponse { error: "If-Match with the current settings version is required".to_string() })));
+        }
+        Ok(precondition) => precondition.expected(),
+        Err(error) => return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error }))),
+    };
+
+    if req.min_public_account_age_days.is_some_and(|days| days < 0) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "min_public_account_age_days must not be negative".to_string() })));
+    }
+
+    for (name, value, min) in [
+        ("scrobble_max_batch", req.scrobble_max_batch, 1),
+        ("scrobble_rate_limit", req.scrobble_rate_limit, 0),
+        ("scrobble_rate_limit_window", req.scrobble_rate_limit_window, 1),
+        ("retention_days", req.retention_days, 0),
+    ] {
+        if value.flatten().is_some_and(|v| v < min) {
+            return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("{} must be at least {}", name, min) })));
+        }
+    }
+
+    if req.retention_scope.as_deref().is_some_and(|scope| RetentionScope::parse(scope).is_none()) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: format!("retention_scope must be one of {}", RetentionScope::NAMES.join(", ")) })));
+    }
+
+    let reserved_usernames = req.reserved_usernames.map(policy::normalize_list);
+    let banned_words = req.banned_words.map(policy::normalize_list);
+    let now = chrono::Utc::now().timestamp();
+
+    let settings = sqlx::query_as!(
+        ServerSettings,
+        r#"
+        UPDATE server_settings
+        SET reserved_usernames = COALESCE($1, reserved_usernames),
+            banned_words = COALESCE($2, banned_words),
+            min_public_account_age_days = COALESCE($3, min_public_account_age_days),
+            registration_open = COALESCE($6, registration_open),
+            scrobble_max_batch = CASE WHEN $7 THEN $8 ELSE scrobble_max_batch END,
+            scrobble_rate_limit = CASE WHEN $9 THEN $10 ELSE scrobble_rate_limit END,
+            scrobble_rate_limit_window = CASE WHEN $11 THEN $12 ELSE scrobble_rate_limit_window END,
+            retention_days = CASE WHEN $13 THEN $14 ELSE retention_days END,
+            retention_scope = COALESCE($15, retention_scope),
+            global_charts_enabled = COALESCE($16, global_charts_enabled),
+            updated_at = $4,
+            version = version + 1
+        WHERE id = 1 AND ($5::BIGINT IS NULL OR version = $5)
+        RETURNING reserved_usernames, banned_words, min_public_account_age_days, registration_open,
+                  global_charts_enabled, scrobble_max_batch, scrobble_rate_limit, scrobble_rate_limit_window,
+                  retention_days, retention_scope, updated_at, version
+        "#,
+        reserved_usernames.as_deref(),
+        banned_words.as_deref(),
+        req.min_public_account_age_days,
+        now,
+        expected,
+        req.registration_open,
+        req.scrobble_max_batch.is_some(),
+        req.scrobble_max_batch.flatten(),
+        req.scrobble_rate_limit.is_some(),
+        req.scrobble_rate_limit.flatten(),
+        req.scrobble_rate_limit_window.is_some(),
+        req.scrobble_rate_limit_window.flatten(),
+        req.retention_days.is_some(),
+        req.retention_days.flatten(),
+        req.retention_scope,
+        req.global_charts_enabled
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .ok_or_else(|| {
+        (
+            StatusCode::PRECONDITION_FAILED,
+            Json(ErrorResponse {
+                error: "Server settings were changed by another admin; fetch them again and retry".to_string(),
+            }),
+        )
+    })?;
+
+    tracing::info!("Admin {} updated server settings (version {})", auth.id, settings.version);
+
+    // Other instances catch up on their next refresh
+    if let Err(e) = runtime.reload(&pool).await {
+        tracing::warn!("Failed to apply server settings: {}", e);
+    }
+
+    Ok(versioned(settings.version, settings))
+}
@@ -0,0 +1,114 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::auth::{AuthUser, Scope};
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Announcement {
+    pub id: i64,
+    pub title: String,
+    pub body: String,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub read: bool,
+}
+
+pub async fn announcements(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Announcement>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let announcements = sqlx::query_as!(
+        Announcement,
+        r#"
+        SELECT
+            a.id as "id!",
+            a.title,
+            a.body,
+            a.created_at as "created_at!",
+            a.expires_at,
+            EXISTS(
+                SELECT 1 FROM announcement_reads r
+                WHERE r.announcement_id = a.id AND r.user_id = $1
+            ) as "read!"
+        FROM announcements a
+        WHERE a.expires_at IS NULL OR a.expires_at > $2
+        ORDER BY a.created_at DESC
+        "#,
+        user.id,
+        now
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(announcements))
+}
+
+pub async fn mark_announcement_read(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(announcement_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query!(
+        r#"
+        INSERT INTO announcement_reads (announcement_id, user_id, read_at)
+        SELECT id, $2, $3 FROM announcements WHERE id = $1
+        ON CONFLICT (announcement_id, user_id) DO NOTHING
+        "#,
+        announcement_id,
+        user.id,
+        now
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        // Either already read or the announcement doesn't exist
+        let exists = sqlx::query!("SELECT id FROM announcements WHERE id = $1", announcement_id)
+            .fetch_optional(&pool)
+            .await
+            .map_err(|e| {
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Database error: {}", e),
+                    }),
+                )
+            })?;
+
+        if exists.is_none() {
+            return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Announcement not found".to_string() })));
+        }
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
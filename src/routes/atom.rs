@@ -0,0 +1,118 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::routes::{audioscrobbler::base_url, lastfm::escape_xml};
+
+const CACHE_CONTROL: &str = "public, max-age=300";
+
+#[derive(Debug, Deserialize)]
+pub struct AtomFeedQuery {
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+/// GET /users/{username}/feed.atom - recent scrobbles of a public profile
+/// as an Atom feed, one entry per scrobble
+pub async fn user_atom_feed(
+    headers: HeaderMap,
+    Path(username): Path<String>,
+    State(pool): State<PgPool>,
+    Query(query): Query<AtomFeedQuery>,
+) -> Result<Response, ApiError> {
+    let user = sqlx::query!("SELECT id, username, is_private FROM users WHERE username = $1", username)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))?;
+
+    if user.is_private {
+        return Err(error(StatusCode::FORBIDDEN, "This user's profile is private"));
+    }
+
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+
+    let scrobs = sqlx::query!(
+        r#"
+        SELECT id, artist, track, album, timestamp as "timestamp!"
+        FROM scrobs
+        WHERE user_id = $1
+        ORDER BY timestamp DESC, id DESC
+        LIMIT $2
+        "#,
+        user.id,
+        limit
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let base = base_url(&headers);
+    let feed_url = format!("{}/users/{}/feed.atom", base, user.username);
+    // A feed without entries still needs an updated time
+    let updated = scrobs.first().map(|s| s.timestamp).unwrap_or(0);
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    xml.push_str(&format!("  <title>{}'s listens</title>\n", escape_xml(&user.username)));
+    xml.push_str(&format!("  <id>{}</id>\n", escape_xml(&feed_url)));
+    xml.push_str(&format!("  <link rel=\"self\" href=\"{}\"/>\n", escape_xml(&feed_url)));
+    xml.push_str(&format!("  <updated>{}</updated>\n", rfc3339(updated)));
+    xml.push_str(&format!("  <author><name>{}</name></author>\n", escape_xml(&user.username)));
+    xml.push_str("  <generator>scrob</generator>\n");
+
+    for scrob in &scrobs {
+        let title = format!("{} – {}", scrob.artist, scrob.track);
+        let summary = match scrob.album.as_deref().filter(|a| !a.trim().is_empty()) {
+            Some(album) => format!("{} from {}", title, album),
+            None => title.clone(),
+        };
+
+        // Scrobble ids never change, even when the scrobble is edited
+        xml.push_str("  <entry>\n");
+        xml.push_str(&format!("    <id>{}/users/{}/scrobbles/{}</id>\n", escape_xml(&base), escape_xml(&user.username), scrob.id));
+        xml.push_str(&format!("    <title>{}</title>\n", escape_xml(&title)));
+        xml.push_str(&format!("    <published>{}</published>\n", rfc3339(scrob.timestamp)));
+        xml.push_str(&format!("    <updated>{}</updated>\n", rfc3339(scrob.timestamp)));
+        xml.push_str(&format!("    <summary>{}</summary>\n", escape_xml(&summary)));
+        xml.push_str("  </entry>\n");
+    }
+
+    xml.push_str("</feed>\n");
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "application/atom+xml; charset=utf-8"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        xml,
+    )
+        .into_response())
+}
+
+fn rfc3339(timestamp: i64) -> String {
+    DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
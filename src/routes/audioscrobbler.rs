@@ -0,0 +1,300 @@
+//! Legacy Audioscrobbler 1.2 submission protocol, for older clients
+//! (mpdscribble, Audacious, ...) that only speak the handshake protocol.
+//!
+//! scrob only stores password hashes, so clients authenticate with one of
+//! the user's API tokens in place of the password:
+//! `a = md5(md5(token) + t)`.
+
+use std::collections::BTreeMap;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Form,
+};
+use md5::{Digest, Md5};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    auth::generate_token,
+    now_playing as now_playing_store, relay,
+    routes::scrobble::{is_mbid, normalize_mbid, submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+/// The protocol allows at most 50 tracks per submission
+const MAX_SUBMISSIONS_PER_REQUEST: usize = 50;
+/// How far the handshake timestamp may be from the server clock
+const MAX_HANDSHAKE_SKEW_SECS: i64 = 5 * 60;
+/// Sessions that haven't been used for this long are dropped on the next handshake
+const SESSION_IDLE_SECS: i64 = 30 * 24 * 60 * 60;
+
+const NOW_PLAYING_PATH: &str = "/audioscrobbler/nowplaying";
+const SUBMISSION_PATH: &str = "/audioscrobbler/submission";
+
+type Params = BTreeMap<String, String>;
+
+#[derive(Debug, Deserialize)]
+pub struct HandshakeQuery {
+    pub hs: Option<String>,
+    pub p: Option<String>,
+    pub c: Option<String>,
+    pub u: Option<String>,
+    pub t: Option<String>,
+    pub a: Option<String>,
+}
+
+/// Protocol responses are newline-terminated plain text lines
+fn reply(lines: &[&str]) -> Response {
+    let mut body = lines.join("\n");
+    body.push('\n');
+    (StatusCode::OK, [(header::CONTENT_TYPE, "text/plain; charset=utf-8")], body).into_response()
+}
+
+fn failed(reason: &str) -> Response {
+    reply(&[&format!("FAILED {}", reason)])
+}
+
+fn md5_hex(input: &str) -> String {
+    hex::encode(Md5::digest(input.as_bytes()))
+}
+
+/// GET /?hs=true&p=1.2&c=<client>&v=<version>&u=<user>&t=<unix time>&a=<auth>
+pub async fn audioscrobbler_handshake(
+    State(pool): State<PgPool>,
+    headers: HeaderMap,
+    Query(query): Query<HandshakeQuery>,
+) -> Response {
+    if query.hs.as_deref() != Some("true") {
+        return (StatusCode::NOT_FOUND, "Not found").into_response();
+    }
+
+    if !matches!(query.p.as_deref(), Some("1.2") | Some("1.2.1")) {
+        return failed("Unsupported protocol version");
+    }
+
+    let (Some(username), Some(timestamp), Some(auth)) = (&query.u, &query.t, &query.a) else {
+        return reply(&["BADAUTH"]);
+    };
+
+    let now = chrono::Utc::now().timestamp();
+    match timestamp.parse::<i64>() {
+        Ok(t) if (t - now).abs() <= MAX_HANDSHAKE_SKEW_SECS => {}
+        _ => return reply(&["BADTIME"]),
+    }
+
+    let tokens = match sqlx::query!(
+        r#"
+        SELECT t.id, t.user_id, t.token
+        FROM api_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE u.username = $1 AND t.revoked = false AND 'scrobble' = ANY(t.scopes)
+          AND (t.expires_at IS NULL OR t.expires_at > $2)
+          AND (u.banned_at IS NULL OR u.banned_until <= $2)
+        "#,
+        username,
+        now
+    )
+    .fetch_all(&pool)
+    .await
+    {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            tracing::error!("Audioscrobbler handshake database error: {}", e);
+            return failed("Database error");
+        }
+    };
+
+    let auth = auth.to_ascii_lowercase();
+    let Some(token) = tokens
+        .iter()
+        .find(|t| md5_hex(&format!("{}{}", md5_hex(&t.token), timestamp)) == auth)
+    else {
+        return reply(&["BADAUTH"]);
+    };
+
+    let session_id = generate_token();
+    let created = async {
+        sqlx::query!(
+            "DELETE FROM audioscrobbler_sessions WHERE user_id = $1 AND last_used_at < $2",
+            token.user_id,
+            now - SESSION_IDLE_SECS
+        )
+        .execute(&pool)
+        .await?;
+
+        sqlx::query!(
+            r#"
+            INSERT INTO audioscrobbler_sessions (session_id, user_id, token_id, client, created_at, last_used_at)
+            VALUES ($1, $2, $3, $4, $5, $5)
+            "#,
+            session_id,
+            token.user_id,
+            token.id,
+            query.c,
+            now
+        )
+        .execute(&pool)
+        .await
+    };
+
+    if let Err(e) = created.await {
+        tracing::error!("Failed to create Audioscrobbler session: {}", e);
+        return failed("Database error");
+    }
+
+    let base = base_url(&headers);
+    reply(&[
+        "OK",
+        &session_id,
+        &format!("{}{}", base, NOW_PLAYING_PATH),
+        &format!("{}{}", base, SUBMISSION_PATH),
+    ])
+}
+
+/// Our own root URL as the client reached it, for responses that hand out
+/// absolute URLs
+pub(crate) fn base_url(headers: &HeaderMap) -> String {
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("http");
+    let host = headers
+        .get(header::HOST)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("localhost");
+    format!("{}://{}", scheme, host)
+}
+
+/// Resolve the `s` parameter to a user, refreshing the session's last use
+async fn session_user(pool: &PgPool, params: &Params) -> Result<Option<i64>, sqlx::Error> {
+    let Some(session_id) = params.get("s") else {
+        return Ok(None);
+    };
+
+    sqlx::query_scalar!(
+        r#"
+        UPDATE audioscrobbler_sessions s
+        SET last_used_at = $2
+        FROM api_tokens t, users u
+        WHERE s.session_id = $1 AND t.id = s.token_id AND t.revoked = false AND 'scrobble' = ANY(t.scopes)
+          AND (t.expires_at IS NULL OR t.expires_at > $2)
+          AND u.id = t.user_id AND (u.banned_at IS NULL OR u.banned_until <= $2)
+        RETURNING s.user_id
+        "#,
+        session_id,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+fn field(params: &Params, name: &str) -> Option<String> {
+    params.get(name).filter(|v| !v.trim().is_empty()).cloned()
+}
+
+/// POST /audioscrobbler/nowplaying (form: s, a, t, b, l, n, m)
+pub async fn audioscrobbler_now_playing(
+    State(pool): State<PgPool>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Response {
+    let params: Params = pairs.into_iter().collect();
+
+    let user_id = match session_user(&pool, &params).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return reply(&["BADSESSION"]),
+        Err(e) => {
+            tracing::error!("Audioscrobbler session lookup failed: {}", e);
+            return failed("Database error");
+        }
+    };
+
+    let (Some(artist), Some(track)) = (field(&params, "a"), field(&params, "t")) else {
+        return failed("Artist and track are required");
+    };
+
+    let listen = relay::Listen {
+        artist,
+        track,
+        album: field(&params, "b"),
+        duration: params.get("l").and_then(|l| l.parse().ok()).filter(|l| *l > 0),
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+
+    if let Err(e) = now_playing_store::record(&pool, user_id, &listen).await {
+        tracing::error!("Failed to store now-playing for user {}: {}", user_id, e);
+        return failed("Database error");
+    }
+
+    if let Err(e) = relay::enqueue_now_playing(&pool, user_id, &listen).await {
+        tracing::error!("Failed to queue now-playing for relays: {}", e);
+    }
+
+    reply(&["OK"])
+}
+
+/// POST /audioscrobbler/submission (form: s, a[i], t[i], i[i], o[i], r[i], l[i], b[i], n[i], m[i])
+pub async fn audioscrobbler_submission(
+    State(pool): State<PgPool>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Response {
+    let params: Params = pairs.into_iter().collect();
+
+    let user_id = match session_user(&pool, &params).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return reply(&["BADSESSION"]),
+        Err(e) => {
+            tracing::error!("Audioscrobbler session lookup failed: {}", e);
+            return failed("Database error");
+        }
+    };
+
+    let mut indexes: Vec<usize> = params
+        .keys()
+        .filter_map(|key| key.strip_prefix("a[")?.strip_suffix(']')?.parse().ok())
+        .collect();
+    indexes.sort_unstable();
+
+    if indexes.len() > MAX_SUBMISSIONS_PER_REQUEST {
+        return failed(&format!("At most {} tracks per submission", MAX_SUBMISSIONS_PER_REQUEST));
+    }
+
+    for index in indexes {
+        let get = |name: &str| field(&params, &format!("{}[{}]", name, index));
+
+        // Skipped tracks are reported but never count as listens
+        if get("r").as_deref() == Some("S") {
+            continue;
+        }
+
+        let scrob = ScrobbleRequest {
+            artist: get("a").unwrap_or_default(),
+            track: get("t").unwrap_or_default(),
+            timestamp: get("i").and_then(|i| i.parse().ok()).unwrap_or(0),
+            album: get("b"),
+            album_artist: None,
+            duration: get("l").and_then(|l| l.parse().ok()).filter(|l| *l > 0),
+            track_number: get("n").and_then(|n| n.parse().ok()),
+            artist_mbid: None,
+            release_mbid: None,
+            recording_mbid: normalize_mbid(get("m").as_deref()).filter(|mbid| is_mbid(mbid)),
+            source: Some("audioscrobbler".to_string()),
+        };
+
+        // Like the original service, invalid tracks are dropped rather than
+        // failing the batch, since the client would otherwise resubmit forever
+        match submit_scrobble(&pool, user_id, &scrob).await {
+            Ok(ScrobbleOutcome::Rejected(reason)) => {
+                tracing::debug!("Dropped Audioscrobbler submission for user {}: {}", user_id, reason);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Audioscrobbler submission failed for user {}: {}", user_id, e);
+                return failed("Database error");
+            }
+        }
+    }
+
+    reply(&["OK"])
+}
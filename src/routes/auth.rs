@@ -1,8 +1,19 @@
-use axum::{extract::State, http::StatusCode, Json};
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-use crate::auth::{generate_token, hash_password, verify_password};
+use crate::{
+    auth::{authenticate, check_password, check_username, create_token, hash_password, ClientInfo, LoginResult, Scope},
+    config::Config,
+    policy::ContentPolicy,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct LoginRequest {
@@ -14,6 +25,8 @@ pub struct LoginRequest {
 pub struct SignupRequest {
     pub username: String,
     pub password: String,
+    /// Required while registration is closed
+    pub invite_code: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -29,74 +42,58 @@ pub struct ErrorResponse {
 }
 
 pub async fn login(
+    headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
     Json(req): Json<LoginRequest>,
-) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let user = sqlx::query!(
-        r#"
-        SELECT id as "id!", username, password_hash, is_admin as "is_admin: bool"
-        FROM users
-        WHERE username = $1
-        "#,
-        req.username
-    )
-    .fetch_optional(&pool)
-    .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Database error: {}", e),
-            }),
-        )
-    })?;
-
-    let user = user.ok_or_else(|| {
-        (
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse {
-                error: "Invalid username or password".to_string(),
-            }),
-        )
-    })?;
+) -> Result<Json<LoginResponse>, Response> {
+    let client = ClientInfo::from_headers(&headers);
+    let result = authenticate(&pool, &config, &req.username, &req.password, client.ip.as_deref())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+                .into_response()
+        })?;
 
-    if !verify_password(&req.password, &user.password_hash).map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Password verification error: {}", e),
-            }),
-        )
-    })? {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            Json(ErrorResponse {
-                error: "Invalid username or password".to_string(),
-            }),
-        ));
-    }
+    let user = match result {
+        LoginResult::Success(user) => user,
+        LoginResult::Invalid => {
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse {
+                    error: "Invalid username or password".to_string(),
+                }),
+            )
+                .into_response());
+        }
+        LoginResult::Banned(ban) => {
+            return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: ban.message() })).into_response());
+        }
+        LoginResult::Locked(retry_after) => {
+            return Err((
+                StatusCode::TOO_MANY_REQUESTS,
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                Json(ErrorResponse {
+                    error: "Too many failed logins, please try again later".to_string(),
+                }),
+            )
+                .into_response());
+        }
+    };
 
-    let token = generate_token();
-    let now = chrono::Utc::now().timestamp();
-
-    sqlx::query!(
-        r#"
-        INSERT INTO api_tokens (user_id, token, label, created_at, revoked)
-        VALUES ($1, $2, 'session', $3, false)
-        "#,
-        user.id,
-        token,
-        now
-    )
-    .execute(&pool)
-    .await
-    .map_err(|e| {
+    let token = create_token(&pool, user.id, "session", &Scope::ALL, None).await.map(|t| t.token).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
                 error: format!("Failed to create session: {}", e),
             }),
         )
+            .into_response()
     })?;
 
     Ok(Json(LoginResponse {
@@ -108,59 +105,40 @@ pub async fn login(
 
 pub async fn signup(
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
     Json(req): Json<SignupRequest>,
 ) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
-    // Validate username (alphanumeric and underscores only, 3-20 chars)
-    if req.username.len() < 3 || req.username.len() > 20 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "Username must be between 3 and 20 characters".to_string(),
-            }),
-        ));
-    }
+    check_username(&req.username)
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: error.to_string() })))?;
 
-    if !req.username.chars().all(|c| c.is_alphanumeric() || c == '_') {
-        return Err((
-            StatusCode::BAD_REQUEST,
+    let policy = ContentPolicy::load(&pool).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "Username can only contain letters, numbers, and underscores".to_string(),
+                error: format!("Database error: {}", e),
             }),
-        ));
-    }
+        )
+    })?;
 
-    // Validate password length
-    if req.password.len() < 8 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "Password must be at least 8 characters".to_string(),
-            }),
-        ));
-    }
+    policy
+        .check_username(&req.username)
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })))?;
 
-    if req.password.len() > 72 {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "Password must be at most 72 characters".to_string(),
-            }),
-        ));
-    }
-
-    // Validate password complexity
-    let has_lowercase = req.password.chars().any(|c| c.is_lowercase());
-    let has_uppercase = req.password.chars().any(|c| c.is_uppercase());
-    let has_digit = req.password.chars().any(|c| c.is_numeric());
+    let invite_code = match req.invite_code.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+        _ if policy.registration_open => None,
+        Some(code) => Some(code),
+        None => {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Registration is closed; an invite code is required".to_string(),
+                }),
+            ));
+        }
+    };
 
-    if !has_lowercase || !has_uppercase || !has_digit {
-        return Err((
-            StatusCode::BAD_REQUEST,
-            Json(ErrorResponse {
-                error: "Password must contain at least one lowercase letter, one uppercase letter, and one number".to_string(),
-            }),
-        ));
-    }
+    check_password(&req.password)
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error: error.to_string() })))?;
 
     // Check if username already exists
     let existing = sqlx::query!(
@@ -192,7 +170,7 @@ pub async fn signup(
     }
 
     // Hash password
-    let password_hash = hash_password(&req.password).map_err(|e| {
+    let password_hash = hash_password(&req.password, &config).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
@@ -203,43 +181,66 @@ pub async fn signup(
 
     let now = chrono::Utc::now().timestamp();
 
-    // Create user (first user is admin)
-    let user = sqlx::query!(
-        r#"
-        INSERT INTO users (username, password_hash, is_admin, created_at)
-        VALUES ($1, $2, NOT EXISTS(SELECT 1 FROM users), $3)
-        RETURNING id as "id!", username, is_admin as "is_admin: bool"
-        "#,
-        req.username,
-        password_hash,
-        now
-    )
-    .fetch_one(&pool)
-    .await
-    .map_err(|e| {
+    // New accounts start private when public profiles need a minimum account age
+    let is_private = !policy.allows_public(now, now);
+
+    let db_error = |e: sqlx::Error| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
                 error: format!("Failed to create user: {}", e),
             }),
         )
-    })?;
+    };
 
-    // Generate token
-    let token = generate_token();
+    let mut tx = pool.begin().await.map_err(db_error)?;
 
-    sqlx::query!(
+    // Create user (first user is admin)
+    let user = sqlx::query!(
         r#"
-        INSERT INTO api_tokens (user_id, token, label, created_at, revoked)
-        VALUES ($1, $2, 'session', $3, false)
+        INSERT INTO users (username, password_hash, is_admin, is_private, created_at)
+        VALUES ($1, $2, NOT EXISTS(SELECT 1 FROM users), $3, $4)
+        RETURNING id as "id!", username, is_admin as "is_admin: bool"
         "#,
-        user.id,
-        token,
+        req.username,
+        password_hash,
+        is_private,
         now
     )
-    .execute(&pool)
+    .fetch_one(&mut *tx)
     .await
-    .map_err(|e| {
+    .map_err(db_error)?;
+
+    // Redeeming the code with the insert means two signups can't share it
+    if let Some(code) = invite_code {
+        let redeemed = sqlx::query!(
+            r#"
+            UPDATE invites
+            SET used_by = $1, used_at = $2
+            WHERE code = $3 AND used_by IS NULL AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $2)
+            "#,
+            user.id,
+            now,
+            code
+        )
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?
+        .rows_affected();
+
+        if redeemed == 0 {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: "Invalid, used or expired invite code".to_string(),
+                }),
+            ));
+        }
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    let token = create_token(&pool, user.id, "session", &Scope::ALL, None).await.map(|t| t.token).map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
@@ -0,0 +1,116 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{now_playing, routes::lastfm::escape_xml};
+
+/// Short enough that a badge follows along with the music
+const CACHE_CONTROL: &str = "public, max-age=60";
+/// Longer titles are cut off with an ellipsis
+const MAX_TEXT_CHARS: usize = 60;
+/// Rough width of a character at the badge's font size
+const CHAR_WIDTH: usize = 7;
+const PADDING: usize = 10;
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+/// GET /badge/{username}.svg - the user's current or last track as an SVG
+/// badge for profile pages. Public profiles only.
+pub async fn badge(
+    Path(file): Path<String>,
+    State(pool): State<PgPool>,
+) -> Result<Response, ApiError> {
+    let username = file
+        .strip_suffix(".svg")
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Badges are served as {username}.svg"))?;
+
+    let user = sqlx::query!("SELECT id, is_private FROM users WHERE username = $1", username)
+        .fetch_optional(&pool)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))?;
+
+    if user.is_private {
+        return Err(error(StatusCode::FORBIDDEN, "This user's profile is private"));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let (label, text) = match now_playing::current(&pool, user.id, now).await.map_err(db_error)? {
+        Some(current) => ("now playing", format!("{} – {}", current.artist, current.track)),
+        None => {
+            let last = sqlx::query!(
+                "SELECT artist, track FROM scrobs WHERE user_id = $1 ORDER BY timestamp DESC LIMIT 1",
+                user.id
+            )
+            .fetch_optional(&pool)
+            .await
+            .map_err(db_error)?;
+
+            match last {
+                Some(last) => ("last played", format!("{} – {}", last.artist, last.track)),
+                None => ("last played", "nothing yet".to_string()),
+            }
+        }
+    };
+
+    Ok((
+        StatusCode::OK,
+        [
+            (header::CONTENT_TYPE, "image/svg+xml; charset=utf-8"),
+            (header::CACHE_CONTROL, CACHE_CONTROL),
+        ],
+        render(label, &text),
+    )
+        .into_response())
+}
+
+/// A two-part flat badge: grey label, green value
+fn render(label: &str, text: &str) -> String {
+    let text = if text.chars().count() > MAX_TEXT_CHARS {
+        let cut: String = text.chars().take(MAX_TEXT_CHARS - 1).collect();
+        format!("{}…", cut.trim_end())
+    } else {
+        text.to_string()
+    };
+
+    let label_width = label.chars().count() * CHAR_WIDTH + 2 * PADDING;
+    let text_width = text.chars().count() * CHAR_WIDTH + 2 * PADDING;
+    let width = label_width + text_width;
+    let label = escape_xml(label);
+    let text = escape_xml(&text);
+
+    format!(
+        r##"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="20" role="img" aria-label="{label}: {text}">
+<title>{label}: {text}</title>
+<clipPath id="r"><rect width="{width}" height="20" rx="3" fill="#fff"/></clipPath>
+<g clip-path="url(#r)">
+<rect width="{label_width}" height="20" fill="#555"/>
+<rect x="{label_width}" width="{text_width}" height="20" fill="#1db954"/>
+</g>
+<g fill="#fff" font-family="Verdana,DejaVu Sans,sans-serif" font-size="11">
+<text x="{PADDING}" y="14">{label}</text>
+<text x="{text_x}" y="14">{text}</text>
+</g>
+</svg>
+"##,
+        text_x = label_width + PADDING,
+    )
+}
@@ -0,0 +1,335 @@
+use axum::{extract::{Query, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{auth::{AuthUser, Scope}, jobs::charts, policy::ContentPolicy, routes::stats::period_days};
+
+/// A backfill still marked running after this long is assumed lost (e.g. the
+/// server restarted mid-run) and may be started again
+const STALE_BACKFILL_SECS: i64 = 60 * 60;
+
+#[derive(Debug, Deserialize)]
+pub struct ChartsQuery {
+    pub period: Option<String>,
+    pub kind: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartSnapshot {
+    pub period: String,
+    pub kind: String,
+    pub period_start: i64,
+    pub period_end: i64,
+    pub entries: serde_json::Value,
+    pub computed_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BackfillStatus {
+    pub status: String,
+    pub snapshots: i64,
+    pub error: Option<String>,
+    pub started_at: i64,
+    pub finished_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GlobalChartQuery {
+    /// `7day` (the default), `1month`, `3month`, `12month` or `overall`;
+    /// days are UTC
+    pub period: Option<String>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GlobalArtist {
+    pub name: String,
+    pub count: i64,
+    /// Opted-in users who played the artist in the period
+    pub listeners: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GlobalTrack {
+    pub artist: String,
+    pub track: String,
+    pub count: i64,
+    pub listeners: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub async fn charts(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ChartsQuery>,
+) -> Result<Json<Vec<ChartSnapshot>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let period = query.period.unwrap_or_else(|| "week".to_string());
+    let kind = query.kind.unwrap_or_else(|| "artists".to_string());
+    let limit = query.limit.unwrap_or(10).min(100);
+
+    if !charts::PERIODS.contains(&period.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "period must be 'week' or 'month'".to_string(),
+            }),
+        ));
+    }
+
+    if kind != "artists" && kind != "tracks" {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "kind must be 'artists' or 'tracks'".to_string(),
+            }),
+        ));
+    }
+
+    let snapshots = sqlx::query_as!(
+        ChartSnapshot,
+        r#"
+        SELECT period, kind, period_start, period_end, entries, computed_at
+        FROM chart_snapshots
+        WHERE user_id = $1 AND period = $2 AND kind = $3
+        ORDER BY period_start DESC
+        LIMIT $4
+        "#,
+        user.id,
+        period,
+        kind,
+        limit
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(snapshots))
+}
+
+pub async fn start_chart_backfill(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<(StatusCode, Json<BackfillStatus>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    // Claim the backfill slot unless one is already running for this user
+    let status = sqlx::query_as!(
+        BackfillStatus,
+        r#"
+        INSERT INTO chart_backfills (user_id, status, started_at)
+        VALUES ($1, 'running', $2)
+        ON CONFLICT (user_id) DO UPDATE
+        SET status = 'running', snapshots = 0, error = NULL, started_at = $2, finished_at = NULL
+        WHERE chart_backfills.status <> 'running' OR chart_backfills.started_at < $3
+        RETURNING status, snapshots, error, started_at, finished_at
+        "#,
+        user.id,
+        now,
+        now - STALE_BACKFILL_SECS
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .ok_or((
+        StatusCode::CONFLICT,
+        Json(ErrorResponse {
+            error: "A chart backfill is already running".to_string(),
+        }),
+    ))?;
+
+    charts::spawn_backfill(pool, user.id);
+
+    Ok((StatusCode::ACCEPTED, Json(status)))
+}
+
+pub async fn chart_backfill_status(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<BackfillStatus>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let status = sqlx::query_as!(
+        BackfillStatus,
+        r#"
+        SELECT status, snapshots, error, started_at, finished_at
+        FROM chart_backfills
+        WHERE user_id = $1
+        "#,
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .ok_or((
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "No chart backfill has been requested".to_string(),
+        }),
+    ))?;
+
+    Ok(Json(status))
+}
+
+// Global charts
+
+/// Check the caller and the admin switch, then resolve `period` into the
+/// start of the chart in unix time
+async fn global_chart_start(
+    pool: &PgPool,
+    headers: &axum::http::HeaderMap,
+    query: &GlobalChartQuery,
+) -> Result<i64, (StatusCode, Json<ErrorResponse>)> {
+    AuthUser::from_headers_with_scope(pool, headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let policy = ContentPolicy::load(pool).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if !policy.global_charts_enabled {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Global charts are disabled on this instance".to_string(),
+            }),
+        ));
+    }
+
+    let period = query.period.as_deref().unwrap_or("7day");
+    if period == "overall" {
+        return Ok(0);
+    }
+
+    let days = period_days(period).ok_or((
+        StatusCode::BAD_REQUEST,
+        Json(ErrorResponse {
+            error: "period must be one of 7day, 1month, 3month, 12month, overall".to_string(),
+        }),
+    ))?;
+
+    let first_day = chrono::Utc::now().date_naive() - chrono::Duration::days(days - 1);
+    Ok(first_day.and_time(chrono::NaiveTime::MIN).and_utc().timestamp())
+}
+
+/// GET /charts/global/artists - most played artists across every user who
+/// opted in with `include_in_global_charts`. Banned users are left out.
+pub async fn global_top_artists(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<GlobalChartQuery>,
+) -> Result<Json<Vec<GlobalArtist>>, (StatusCode, Json<ErrorResponse>)> {
+    let from = global_chart_start(&pool, &headers, &query).await?;
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+
+    let artists = sqlx::query_as!(
+        GlobalArtist,
+        r#"
+        SELECT a.name, COUNT(*) as "count!", COUNT(DISTINCT s.user_id) as "listeners!"
+        FROM scrobs s
+        JOIN user_settings us ON us.user_id = s.user_id AND us.include_in_global_charts
+        JOIN users u ON u.id = s.user_id
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.timestamp >= $1
+          AND u.anonymized_at IS NULL
+          AND (u.banned_at IS NULL OR u.banned_until <= $3)
+        GROUP BY a.id
+        ORDER BY COUNT(*) DESC, a.name
+        LIMIT $2
+        "#,
+        from,
+        limit,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(artists))
+}
+
+/// GET /charts/global/tracks - most played tracks across opted-in users
+pub async fn global_top_tracks(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<GlobalChartQuery>,
+) -> Result<Json<Vec<GlobalTrack>>, (StatusCode, Json<ErrorResponse>)> {
+    let from = global_chart_start(&pool, &headers, &query).await?;
+    let limit = query.limit.unwrap_or(10).clamp(1, 100);
+
+    let tracks = sqlx::query_as!(
+        GlobalTrack,
+        r#"
+        SELECT a.name as "artist!", t.name as "track!", COUNT(*) as "count!",
+               COUNT(DISTINCT s.user_id) as "listeners!"
+        FROM scrobs s
+        JOIN user_settings us ON us.user_id = s.user_id AND us.include_in_global_charts
+        JOIN users u ON u.id = s.user_id
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE s.timestamp >= $1
+          AND u.anonymized_at IS NULL
+          AND (u.banned_at IS NULL OR u.banned_until <= $3)
+        GROUP BY t.id, a.id
+        ORDER BY COUNT(*) DESC, a.name, t.name
+        LIMIT $2
+        "#,
+        from,
+        limit,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(tracks))
+}
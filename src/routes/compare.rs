@@ -0,0 +1,265 @@
+use axum::{extract::{Query, State}, http::StatusCode, Json};
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{auth::{AuthUser, Scope}, preferences};
+
+#[derive(Debug, Deserialize)]
+pub struct CompareQuery {
+    pub a: String,
+    pub b: String,
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CompareResponse {
+    /// The user's timezone, which the periods' boundaries follow
+    pub timezone: String,
+    pub a: PeriodSummary,
+    pub b: PeriodSummary,
+    pub artists: Changes<ArtistChange>,
+    pub tracks: Changes<TrackChange>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PeriodSummary {
+    pub period: String,
+    /// Unix time range, `to` exclusive
+    pub from: i64,
+    pub to: i64,
+    pub scrobbles: i64,
+}
+
+/// Entries present in both periods rose or fell; the rest appeared or disappeared
+#[derive(Debug, Serialize)]
+pub struct Changes<T> {
+    pub rose: Vec<T>,
+    pub fell: Vec<T>,
+    pub appeared: Vec<T>,
+    pub disappeared: Vec<T>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistChange {
+    pub artist: String,
+    pub count_a: i64,
+    pub count_b: i64,
+    pub change: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackChange {
+    pub artist: String,
+    pub track: String,
+    pub count_a: i64,
+    pub count_b: i64,
+    pub change: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Parse `YYYY`, `YYYY-MM` or `YYYY-MM-DD` into its first day and the day after its last
+fn parse_period(period: &str) -> Option<(NaiveDate, NaiveDate)> {
+    let parts: Vec<&str> = period.split('-').collect();
+    let (start, end) = match parts.as_slice() {
+        [year] => {
+            let year: i32 = year.parse().ok()?;
+            (NaiveDate::from_ymd_opt(year, 1, 1)?, NaiveDate::from_ymd_opt(year + 1, 1, 1)?)
+        }
+        [year, month] => {
+            let start = NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)?;
+            let end = if start.month() == 12 {
+                NaiveDate::from_ymd_opt(start.year() + 1, 1, 1)?
+            } else {
+                NaiveDate::from_ymd_opt(start.year(), start.month() + 1, 1)?
+            };
+            (start, end)
+        }
+        [_, _, _] => {
+            let day = NaiveDate::parse_from_str(period, "%Y-%m-%d").ok()?;
+            (day, day.succ_opt()?)
+        }
+        _ => return None,
+    };
+
+    Some((start, end))
+}
+
+/// Sort each bucket by size of the change and keep the top `limit`
+fn classify<T>(items: Vec<(T, i64, i64)>, limit: usize) -> Changes<T> {
+    let mut rose = Vec::new();
+    let mut fell = Vec::new();
+    let mut appeared = Vec::new();
+    let mut disappeared = Vec::new();
+
+    for (item, count_a, count_b) in items {
+        let bucket = match (count_a, count_b) {
+            (0, _) => &mut appeared,
+            (_, 0) => &mut disappeared,
+            (a, b) if b > a => &mut rose,
+            (a, b) if b < a => &mut fell,
+            _ => continue,
+        };
+        bucket.push(((count_b - count_a).abs(), item));
+    }
+
+    let top = |mut bucket: Vec<(i64, T)>| {
+        bucket.sort_by_key(|(magnitude, _)| std::cmp::Reverse(*magnitude));
+        bucket.into_iter().take(limit).map(|(_, item)| item).collect()
+    };
+
+    Changes {
+        rose: top(rose),
+        fell: top(fell),
+        appeared: top(appeared),
+        disappeared: top(disappeared),
+    }
+}
+
+/// GET /stats/compare?a=2023&b=2024 - what changed between two periods
+pub async fn compare_periods(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<CompareQuery>,
+) -> Result<Json<CompareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(10).min(100);
+
+    let invalid_period = |period: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid period '{}': use YYYY, YYYY-MM or YYYY-MM-DD", period),
+            }),
+        )
+    };
+    let (a_start, a_end) = parse_period(&query.a).ok_or_else(|| invalid_period(&query.a))?;
+    let (b_start, b_end) = parse_period(&query.b).ok_or_else(|| invalid_period(&query.b))?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    // Periods start and end at midnight in the user's timezone
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+    let bounds = preferences::local_midnights(&pool, &timezone, &[a_start, a_end, b_start, b_end])
+        .await
+        .map_err(db_error)?;
+    let (a_from, a_to, b_from, b_to) = (bounds[0], bounds[1], bounds[2], bounds[3]);
+
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) FILTER (WHERE timestamp >= $2 AND timestamp < $3) as "a!",
+            COUNT(*) FILTER (WHERE timestamp >= $4 AND timestamp < $5) as "b!"
+        FROM scrobs
+        WHERE user_id = $1
+          AND ((timestamp >= $2 AND timestamp < $3) OR (timestamp >= $4 AND timestamp < $5))
+        "#,
+        user.id,
+        a_from,
+        a_to,
+        b_from,
+        b_to
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let artists = sqlx::query!(
+        r#"
+        SELECT
+            a.name as artist,
+            COUNT(*) FILTER (WHERE s.timestamp >= $2 AND s.timestamp < $3) as "a!",
+            COUNT(*) FILTER (WHERE s.timestamp >= $4 AND s.timestamp < $5) as "b!"
+        FROM scrobs s
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.user_id = $1
+          AND ((s.timestamp >= $2 AND s.timestamp < $3) OR (s.timestamp >= $4 AND s.timestamp < $5))
+        GROUP BY a.id
+        "#,
+        user.id,
+        a_from,
+        a_to,
+        b_from,
+        b_to
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .map(|row| {
+        let change = ArtistChange {
+            artist: row.artist,
+            count_a: row.a,
+            count_b: row.b,
+            change: row.b - row.a,
+        };
+        (change, row.a, row.b)
+    })
+    .collect();
+
+    let tracks = sqlx::query!(
+        r#"
+        SELECT
+            a.name as artist,
+            t.name as track,
+            COUNT(*) FILTER (WHERE s.timestamp >= $2 AND s.timestamp < $3) as "a!",
+            COUNT(*) FILTER (WHERE s.timestamp >= $4 AND s.timestamp < $5) as "b!"
+        FROM scrobs s
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE s.user_id = $1
+          AND ((s.timestamp >= $2 AND s.timestamp < $3) OR (s.timestamp >= $4 AND s.timestamp < $5))
+        GROUP BY t.id, a.id
+        "#,
+        user.id,
+        a_from,
+        a_to,
+        b_from,
+        b_to
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .map(|row| {
+        let change = TrackChange {
+            artist: row.artist,
+            track: row.track,
+            count_a: row.a,
+            count_b: row.b,
+            change: row.b - row.a,
+        };
+        (change, row.a, row.b)
+    })
+    .collect();
+
+    Ok(Json(CompareResponse {
+        timezone,
+        a: PeriodSummary {
+            period: query.a,
+            from: a_from,
+            to: a_to,
+            scrobbles: totals.a,
+        },
+        b: PeriodSummary {
+            period: query.b,
+            from: b_from,
+            to: b_to,
+            scrobbles: totals.b,
+        },
+        artists: classify(artists, limit),
+        tracks: classify(tracks, limit),
+    }))
+}
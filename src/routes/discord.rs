@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{auth::AuthUser, crypto::SecretBox, discord};
+
+#[derive(Debug, Serialize)]
+pub struct DiscordWebhook {
+    pub daily_summary: bool,
+    pub milestones: bool,
+    pub weekly_top_artist: bool,
+    pub enabled: bool,
+    pub updated_at: i64,
+    pub last_success_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DiscordWebhookRequest {
+    pub url: String,
+    pub daily_summary: Option<bool>,
+    pub milestones: Option<bool>,
+    pub weekly_top_artist: Option<bool>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+/// GET /settings/discord - which events go to the user's Discord webhook and
+/// how the last post went. The URL itself isn't returned.
+pub async fn get_discord_webhook(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<DiscordWebhook>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let webhook = sqlx::query_as!(
+        DiscordWebhook,
+        r#"
+        SELECT daily_summary, milestones, weekly_top_artist, enabled, updated_at,
+               last_success_at, last_error
+        FROM discord_webhooks
+        WHERE user_id = $1
+        "#,
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "No Discord webhook configured"))?;
+
+    Ok(Json(webhook))
+}
+
+/// PUT /settings/discord - set the webhook and the events posted to it, all
+/// on by default. Events already posted aren't posted again after a change.
+pub async fn put_discord_webhook(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    Json(req): Json<DiscordWebhookRequest>,
+) -> Result<Json<DiscordWebhook>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let url = req.url.trim();
+    if !discord::is_webhook_url(url) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            "URL must be a Discord webhook (https://discord.com/api/webhooks/...)",
+        ));
+    }
+
+    let secrets = secrets.ok_or_else(|| {
+        error(StatusCode::SERVICE_UNAVAILABLE, "SECRET_KEY must be configured to store Discord webhooks")
+    })?;
+    let url = secrets.encrypt(url).map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e))?;
+
+    let webhook = sqlx::query_as!(
+        DiscordWebhook,
+        r#"
+        INSERT INTO discord_webhooks (user_id, url, daily_summary, milestones, weekly_top_artist, enabled, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        ON CONFLICT (user_id) DO UPDATE
+        SET url = $2, daily_summary = $3, milestones = $4, weekly_top_artist = $5, enabled = $6,
+            updated_at = $7, last_error = NULL
+        RETURNING daily_summary, milestones, weekly_top_artist, enabled, updated_at,
+                  last_success_at, last_error
+        "#,
+        user.id,
+        url,
+        req.daily_summary.unwrap_or(true),
+        req.milestones.unwrap_or(true),
+        req.weekly_top_artist.unwrap_or(true),
+        req.enabled.unwrap_or(true),
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    tracing::info!("Set Discord webhook for user {}", user.id);
+
+    Ok(Json(webhook))
+}
+
+/// DELETE /settings/discord - stop posting to the user's Discord webhook
+pub async fn delete_discord_webhook(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let result = sqlx::query!("DELETE FROM discord_webhooks WHERE user_id = $1", user.id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "No Discord webhook configured"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
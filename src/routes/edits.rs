@@ -0,0 +1,298 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json as JsonValue, PgPool, Postgres, Transaction};
+
+use crate::{
+    auth::{AuthUser, Scope},
+    routes::scrobble::{validate_scrobble, ScrobbleRequest},
+};
+
+/// Fields to change; anything omitted keeps its current value
+#[derive(Debug, Deserialize)]
+pub struct EditScrobbleRequest {
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    /// An empty string clears the album
+    pub album: Option<String>,
+    /// An empty string clears the album artist
+    pub album_artist: Option<String>,
+    pub track_number: Option<u32>,
+    pub duration: Option<u64>,
+    pub timestamp: Option<u64>,
+}
+
+/// The editable fields of a scrobble, as recorded in `scrob_edits`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScrobbleValues {
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub duration: Option<i64>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EditedScrobble {
+    pub id: i64,
+    #[serde(flatten)]
+    pub values: ScrobbleValues,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ScrobbleEdit {
+    pub id: i64,
+    /// `edit` or `delete`
+    pub action: String,
+    pub old_values: ScrobbleValues,
+    pub new_values: Option<ScrobbleValues>,
+    pub edited_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(ErrorResponse { error: message.into() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+fn not_found() -> ApiError {
+    error(StatusCode::NOT_FOUND, "Scrobble not found")
+}
+
+/// Lock one of the user's scrobbles for an edit. Other users' scrobbles
+/// look the same as missing ones.
+async fn lock_scrobble(
+    tx: &mut Transaction<'_, Postgres>,
+    scrobble_id: i64,
+    user_id: i64,
+) -> Result<ScrobbleValues, ApiError> {
+    sqlx::query_as!(
+        ScrobbleValues,
+        r#"
+        SELECT artist, track, album, album_artist, track_number, duration, timestamp
+        FROM scrobs
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#,
+        scrobble_id,
+        user_id
+    )
+    .fetch_optional(&mut **tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(not_found)
+}
+
+async fn record_edit(
+    tx: &mut Transaction<'_, Postgres>,
+    scrobble_id: i64,
+    user_id: i64,
+    old: &ScrobbleValues,
+    new: Option<&ScrobbleValues>,
+) -> Result<(), ApiError> {
+    let action = if new.is_some() { "edit" } else { "delete" };
+
+    sqlx::query!(
+        r#"
+        INSERT INTO scrob_edits (scrob_id, user_id, action, old_values, new_values, edited_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        scrobble_id,
+        user_id,
+        action,
+        JsonValue(old) as _,
+        new.map(JsonValue) as _,
+        chrono::Utc::now().timestamp()
+    )
+    .execute(&mut **tx)
+    .await
+    .map_err(db_error)?;
+
+    Ok(())
+}
+
+/// PATCH /scrobbles/{id} - correct one of your own scrobbles
+pub async fn edit_scrobble(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(scrobble_id): Path<i64>,
+    Json(req): Json<EditScrobbleRequest>,
+) -> Result<Json<EditedScrobble>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+    let old = lock_scrobble(&mut tx, scrobble_id, user.id).await?;
+
+    let clearable = |value: Option<String>, current: &Option<String>| match value {
+        Some(value) if value.is_empty() => None,
+        Some(value) => Some(value),
+        None => current.clone(),
+    };
+
+    // Run the result through the same checks as a new submission
+    let scrob = ScrobbleRequest {
+        artist: req.artist.unwrap_or_else(|| old.artist.clone()),
+        track: req.track.unwrap_or_else(|| old.track.clone()),
+        timestamp: req.timestamp.unwrap_or(old.timestamp as u64),
+        album: clearable(req.album, &old.album),
+        album_artist: clearable(req.album_artist, &old.album_artist),
+        duration: req.duration.or(old.duration.map(|d| d as u64)),
+        track_number: req.track_number.or(old.track_number.map(|n| n as u32)),
+        artist_mbid: None,
+        release_mbid: None,
+        recording_mbid: None,
+        source: None,
+    };
+    validate_scrobble(&scrob).map_err(|reason| error(StatusCode::BAD_REQUEST, reason))?;
+
+    let new = ScrobbleValues {
+        artist: scrob.artist,
+        track: scrob.track,
+        album: scrob.album,
+        album_artist: scrob.album_artist,
+        track_number: scrob.track_number.and_then(|n| i32::try_from(n).ok()),
+        duration: scrob.duration.map(|d| d as i64),
+        timestamp: scrob.timestamp as i64,
+    };
+
+    if new == old {
+        return Ok(Json(EditedScrobble { id: scrobble_id, values: old }));
+    }
+
+    // A looked-up duration and the MusicBrainz ids belong to the old
+    // artist, album or track, so they go when those change
+    sqlx::query!(
+        r#"
+        UPDATE scrobs
+        SET artist = $3,
+            track = $4,
+            album = $5,
+            album_artist = $6,
+            track_number = $7,
+            duration = $8,
+            timestamp = $9,
+            duration_estimated = CASE WHEN artist = $3 AND track = $4 THEN duration_estimated END,
+            artist_mbid = CASE WHEN artist = $3 THEN artist_mbid END,
+            release_mbid = CASE
+                WHEN album IS NOT DISTINCT FROM $5 AND COALESCE(album_artist, artist) = COALESCE($6, $3) THEN release_mbid
+            END,
+            recording_mbid = CASE WHEN artist = $3 AND track = $4 THEN recording_mbid END
+        WHERE id = $1 AND user_id = $2
+        "#,
+        scrobble_id,
+        user.id,
+        new.artist,
+        new.track,
+        new.album,
+        new.album_artist,
+        new.track_number,
+        new.duration,
+        new.timestamp
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| {
+        if e.as_database_error().is_some_and(|e| e.is_unique_violation()) {
+            error(StatusCode::CONFLICT, "You already have a scrobble of this track at that time")
+        } else {
+            db_error(e)
+        }
+    })?;
+
+    record_edit(&mut tx, scrobble_id, user.id, &old, Some(&new)).await?;
+    tx.commit().await.map_err(db_error)?;
+
+    tracing::info!("User {} edited scrobble {}", user.id, scrobble_id);
+
+    Ok(Json(EditedScrobble { id: scrobble_id, values: new }))
+}
+
+/// DELETE /scrobbles/{id} - remove one of your own scrobbles
+pub async fn remove_scrobble(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(scrobble_id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let old = sqlx::query_as!(
+        ScrobbleValues,
+        r#"
+        DELETE FROM scrobs
+        WHERE id = $1 AND user_id = $2
+        RETURNING artist, track, album, album_artist, track_number, duration, timestamp
+        "#,
+        scrobble_id,
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(not_found)?;
+
+    record_edit(&mut tx, scrobble_id, user.id, &old, None).await?;
+    tx.commit().await.map_err(db_error)?;
+
+    tracing::info!("User {} deleted scrobble {}", user.id, scrobble_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /scrobbles/{id}/edits - the change history of one of your scrobbles,
+/// oldest first
+pub async fn list_scrobble_edits(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(scrobble_id): Path<i64>,
+) -> Result<Json<Vec<ScrobbleEdit>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let edits = sqlx::query!(
+        r#"
+        SELECT
+            id,
+            action,
+            old_values as "old_values: JsonValue<ScrobbleValues>",
+            new_values as "new_values: JsonValue<ScrobbleValues>",
+            edited_at
+        FROM scrob_edits
+        WHERE scrob_id = $1 AND user_id = $2
+        ORDER BY edited_at, id
+        "#,
+        scrobble_id,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .map(|row| ScrobbleEdit {
+        id: row.id,
+        action: row.action,
+        old_values: row.old_values.0,
+        new_values: row.new_values.map(|values| values.0),
+        edited_at: row.edited_at,
+    })
+    .collect();
+
+    Ok(Json(edits))
+}
@@ -0,0 +1,426 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{check_password, hash_password, invalidate_user, AuthUser},
+    config::Config,
+    lockout,
+    mail::{self, Mailer},
+};
+
+const VERIFY_TOKEN_TTL_SECS: i64 = 24 * 60 * 60;
+const RESET_TOKEN_TTL_SECS: i64 = 60 * 60;
+/// No new reset mail while a token younger than this is outstanding
+const RESET_COOLDOWN_SECS: i64 = 5 * 60;
+
+#[derive(Debug, Serialize)]
+pub struct EmailStatus {
+    pub email: Option<String>,
+    pub verified: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailUpdate {
+    /// `null` or empty removes the address
+    pub email: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetRequest {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordResetConfirm {
+    pub token: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+fn require_mailer(mailer: Option<Arc<Mailer>>) -> Result<Arc<Mailer>, ApiError> {
+    mailer.ok_or_else(|| error(StatusCode::SERVICE_UNAVAILABLE, "Email is not configured on this instance"))
+}
+
+fn new_token() -> String {
+    hex::encode(rand::random::<[u8; 32]>())
+}
+
+/// Tokens are stored hashed, so a database leak can't reset passwords
+fn token_hash(token: &str) -> String {
+    hex::encode(Sha256::digest(token.trim().as_bytes()))
+}
+
+fn invalid_token() -> ApiError {
+    error(StatusCode::BAD_REQUEST, "Invalid or expired token")
+}
+
+/// GET /account/email - the user's address and whether it is verified
+pub async fn get_email(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<EmailStatus>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let row = sqlx::query!("SELECT email, email_verified_at FROM users WHERE id = $1", user.id)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+
+    Ok(Json(EmailStatus {
+        verified: row.email.is_some() && row.email_verified_at.is_some(),
+        email: row.email,
+    }))
+}
+
+/// POST /account/email - set the user's address and mail it a verification
+/// token, or remove it. A changed address is unverified until confirmed.
+pub async fn set_email(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(mailer): State<Option<Arc<Mailer>>>,
+    Json(req): Json<EmailUpdate>,
+) -> Result<(StatusCode, Json<EmailStatus>), ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let Some(email) = req.email.as_deref().map(str::trim).filter(|e| !e.is_empty()) else {
+        let mut tx = pool.begin().await.map_err(db_error)?;
+
+        sqlx::query!("UPDATE users SET email = NULL, email_verified_at = NULL WHERE id = $1", user.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1", user.id)
+            .execute(&mut *tx)
+            .await
+            .map_err(db_error)?;
+
+        tx.commit().await.map_err(db_error)?;
+
+        return Ok((StatusCode::OK, Json(EmailStatus { email: None, verified: false })));
+    };
+
+    let mailer = require_mailer(mailer)?;
+    let email = mail::normalize_address(email)
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Invalid email address"))?;
+
+    let current = sqlx::query!("SELECT email, email_verified_at FROM users WHERE id = $1", user.id)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+
+    if current.email.as_deref() == Some(email.as_str()) && current.email_verified_at.is_some() {
+        return Ok((StatusCode::OK, Json(EmailStatus { email: Some(email), verified: true })));
+    }
+
+    let taken = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM users
+            WHERE lower(email) = lower($1) AND email_verified_at IS NOT NULL AND id <> $2
+        ) as "taken!"
+        "#,
+        email,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if taken {
+        return Err(error(StatusCode::CONFLICT, "Email address is already in use"));
+    }
+
+    let token = new_token();
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    sqlx::query!("UPDATE users SET email = $1, email_verified_at = NULL WHERE id = $2", email, user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    // Tokens mailed to the previous address stop working
+    sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1", user.id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_tokens (token_hash, user_id, kind, email, created_at, expires_at)
+        VALUES ($1, $2, 'verify', $3, $4, $5)
+        "#,
+        token_hash(&token),
+        user.id,
+        email,
+        now,
+        now + VERIFY_TOKEN_TTL_SECS
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    let body = format!(
+        "Hi {},\n\n\
+         Use this token to verify your email address:\n\n\
+         {}\n\n\
+         Enter it in your client or send it to POST /account/email/verify. It expires in 24 hours.\n\n\
+         If you didn't add this address, you can ignore this email.\n",
+        user.username, token
+    );
+
+    mailer.send(&email, "Verify your email address", body).await.map_err(|e| {
+        tracing::error!("Failed to send verification email for user {}: {}", user.id, e);
+        error(StatusCode::BAD_GATEWAY, "Failed to send the verification email")
+    })?;
+
+    tracing::info!("Sent email verification for user {}", user.id);
+
+    Ok((StatusCode::ACCEPTED, Json(EmailStatus { email: Some(email), verified: false })))
+}
+
+/// POST /account/email/verify - confirm the address with the mailed token.
+/// The token identifies the user, so no auth is needed.
+pub async fn verify_email(
+    State(pool): State<PgPool>,
+    State(mailer): State<Option<Arc<Mailer>>>,
+    Json(req): Json<VerifyEmailRequest>,
+) -> Result<Json<EmailStatus>, ApiError> {
+    require_mailer(mailer)?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let token = sqlx::query!(
+        r#"
+        DELETE FROM email_tokens
+        WHERE token_hash = $1 AND kind = 'verify'
+        RETURNING user_id, email, expires_at
+        "#,
+        token_hash(&req.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .filter(|t| t.expires_at > now)
+    .ok_or_else(invalid_token)?;
+
+    // Another account may have verified the same address in the meantime
+    let verified = sqlx::query!(
+        "UPDATE users SET email_verified_at = $1 WHERE id = $2 AND email = $3",
+        now,
+        token.user_id,
+        token.email
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| match &e {
+        sqlx::Error::Database(db) if db.is_unique_violation() => {
+            error(StatusCode::CONFLICT, "Email address is already in use")
+        }
+        _ => db_error(e),
+    })?
+    .rows_affected();
+
+    if verified == 0 {
+        return Err(invalid_token());
+    }
+
+    tx.commit().await.map_err(db_error)?;
+
+    tracing::info!("User {} verified their email address", token.user_id);
+
+    Ok(Json(EmailStatus {
+        email: Some(token.email),
+        verified: true,
+    }))
+}
+
+/// POST /password-reset/request - mail a reset token to a verified address.
+/// Always 202, so the response doesn't reveal whether the address is known.
+pub async fn request_password_reset(
+    State(pool): State<PgPool>,
+    State(mailer): State<Option<Arc<Mailer>>>,
+    Json(req): Json<PasswordResetRequest>,
+) -> Result<StatusCode, ApiError> {
+    let mailer = require_mailer(mailer)?;
+    let email = mail::normalize_address(&req.email)
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "Invalid email address"))?;
+    let now = chrono::Utc::now().timestamp();
+
+    // Accounts without a password (SSO, anonymized) have nothing to reset
+    let user = sqlx::query!(
+        r#"
+        SELECT id, username, email as "email!"
+        FROM users
+        WHERE lower(email) = lower($1) AND email_verified_at IS NOT NULL
+          AND anonymized_at IS NULL AND password_hash <> ''
+        "#,
+        email
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let Some(user) = user else {
+        return Ok(StatusCode::ACCEPTED);
+    };
+
+    let recent = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM email_tokens
+            WHERE user_id = $1 AND kind = 'reset' AND created_at > $2
+        ) as "recent!"
+        "#,
+        user.id,
+        now - RESET_COOLDOWN_SECS
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if recent {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1 AND expires_at <= $2", user.id, now)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+
+    let token = new_token();
+
+    sqlx::query!(
+        r#"
+        INSERT INTO email_tokens (token_hash, user_id, kind, email, created_at, expires_at)
+        VALUES ($1, $2, 'reset', $3, $4, $5)
+        "#,
+        token_hash(&token),
+        user.id,
+        user.email,
+        now,
+        now + RESET_TOKEN_TTL_SECS
+    )
+    .execute(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let body = format!(
+        "Hi {},\n\n\
+         Someone asked to reset the password of your account. Use this token to choose a new one:\n\n\
+         {}\n\n\
+         Enter it in your client or send it with the new password to POST /password-reset/confirm. \
+         It expires in 1 hour.\n\n\
+         If this wasn't you, you can ignore this email; your password stays the same.\n",
+        user.username, token
+    );
+
+    // Sent in the background so known and unknown addresses answer alike
+    tokio::spawn(async move {
+        match mailer.send(&user.email, "Reset your password", body).await {
+            Ok(()) => tracing::info!("Sent password reset for user {}", user.id),
+            Err(e) => tracing::error!("Failed to send password reset for user {}: {}", user.id, e),
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// POST /password-reset/confirm - set a new password with a mailed reset
+/// token. Login sessions are signed out; API tokens keep working.
+pub async fn confirm_password_reset(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(mailer): State<Option<Arc<Mailer>>>,
+    Json(req): Json<PasswordResetConfirm>,
+) -> Result<StatusCode, ApiError> {
+    require_mailer(mailer)?;
+    check_password(&req.password).map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+
+    let password_hash = hash_password(&req.password, &config)
+        .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Password hashing error: {}", e)))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let token = sqlx::query!(
+        r#"
+        DELETE FROM email_tokens
+        WHERE token_hash = $1 AND kind = 'reset'
+        RETURNING user_id, expires_at
+        "#,
+        token_hash(&req.token)
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .filter(|t| t.expires_at > now)
+    .ok_or_else(invalid_token)?;
+
+    let username = sqlx::query_scalar!(
+        r#"
+        UPDATE users SET password_hash = $1
+        WHERE id = $2 AND anonymized_at IS NULL AND password_hash <> ''
+        RETURNING username
+        "#,
+        password_hash,
+        token.user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(invalid_token)?;
+
+    sqlx::query!("DELETE FROM email_tokens WHERE user_id = $1 AND kind = 'reset'", token.user_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(db_error)?;
+
+    sqlx::query!(
+        "UPDATE api_tokens SET revoked = true WHERE user_id = $1 AND label = 'session' AND revoked = false",
+        token.user_id
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+    invalidate_user(token.user_id);
+
+    // A forgotten password usually comes with failed logins
+    lockout::record_success(&pool, &username).await.map_err(db_error)?;
+
+    tracing::info!("User {} reset their password", token.user_id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,174 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+
+use crate::auth::{AuthUser, Scope};
+
+/// Rows are written out in chunks of about this many bytes
+const CHUNK_BYTES: usize = 64 * 1024;
+const CSV_HEADER: &str = "timestamp,utc_time,artist,track,album,album_artist,track_number,duration,created_at\n";
+
+#[derive(Debug, Deserialize)]
+pub struct HistoryExportQuery {
+    pub format: Option<String>,
+    /// Only scrobbles recorded at or after this Unix time
+    pub since: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ExportedScrobble {
+    id: i64,
+    artist: String,
+    track: String,
+    album: Option<String>,
+    album_artist: Option<String>,
+    track_number: Option<i32>,
+    duration: Option<i64>,
+    timestamp: i64,
+    created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum HistoryFormat {
+    Csv,
+    JsonLines,
+}
+
+fn csv_field(out: &mut String, value: &str) {
+    if value.contains([',', '"', '\n', '\r']) {
+        out.push('"');
+        out.push_str(&value.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(value);
+    }
+}
+
+fn write_row(out: &mut String, format: HistoryFormat, scrob: &ExportedScrobble) {
+    match format {
+        HistoryFormat::Csv => {
+            let utc_time = chrono::DateTime::from_timestamp(scrob.timestamp, 0)
+                .map(|t| t.to_rfc3339_opts(chrono::SecondsFormat::Secs, true))
+                .unwrap_or_default();
+            let optional = |value: Option<i64>| value.map(|v| v.to_string()).unwrap_or_default();
+
+            out.push_str(&format!("{},{},", scrob.timestamp, utc_time));
+            csv_field(out, &scrob.artist);
+            out.push(',');
+            csv_field(out, &scrob.track);
+            out.push(',');
+            csv_field(out, scrob.album.as_deref().unwrap_or_default());
+            out.push(',');
+            csv_field(out, scrob.album_artist.as_deref().unwrap_or_default());
+            out.push_str(&format!(
+                ",{},{},{}\n",
+                optional(scrob.track_number.map(i64::from)),
+                optional(scrob.duration),
+                scrob.created_at
+            ));
+        }
+        HistoryFormat::JsonLines => {
+            // Serializing a struct of strings and numbers can't fail
+            out.push_str(&serde_json::to_string(scrob).unwrap_or_default());
+            out.push('\n');
+        }
+    }
+}
+
+/// GET /export?format=csv|jsonl&since= - the user's whole scrobble history,
+/// oldest first, streamed so large histories never sit in memory
+pub async fn export_history(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<HistoryExportQuery>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let (format, content_type, extension) = match query.format.as_deref().unwrap_or("csv") {
+        "csv" => (HistoryFormat::Csv, "text/csv; charset=utf-8", "csv"),
+        "jsonl" => (HistoryFormat::JsonLines, "application/x-ndjson", "jsonl"),
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "Unsupported format (supported: csv, jsonl)".to_string(),
+                }),
+            ));
+        }
+    };
+    let since = query.since.unwrap_or(0);
+    let user_id = user.id;
+
+    // The query runs in its own task so the response can start streaming
+    // while rows are still being read
+    let (sender, receiver) = mpsc::channel::<Result<Bytes, std::io::Error>>(4);
+    tokio::spawn(async move {
+        let mut rows = sqlx::query_as!(
+            ExportedScrobble,
+            r#"
+            SELECT id, artist, track, album, album_artist, track_number, duration, timestamp, created_at
+            FROM scrobs
+            WHERE user_id = $1 AND created_at >= $2
+            ORDER BY timestamp, id
+            "#,
+            user_id,
+            since
+        )
+        .fetch(&pool);
+
+        let mut chunk = String::with_capacity(CHUNK_BYTES);
+        if let HistoryFormat::Csv = format {
+            chunk.push_str(CSV_HEADER);
+        }
+
+        while let Some(row) = rows.next().await {
+            let scrob = match row {
+                Ok(scrob) => scrob,
+                Err(e) => {
+                    tracing::error!("History export for user {} failed: {}", user_id, e);
+                    let _ = sender.send(Err(std::io::Error::other(e))).await;
+                    return;
+                }
+            };
+
+            write_row(&mut chunk, format, &scrob);
+            if chunk.len() >= CHUNK_BYTES {
+                let full = std::mem::replace(&mut chunk, String::with_capacity(CHUNK_BYTES));
+                if sender.send(Ok(Bytes::from(full))).await.is_err() {
+                    // Client went away
+                    return;
+                }
+            }
+        }
+
+        if !chunk.is_empty() {
+            let _ = sender.send(Ok(Bytes::from(chunk))).await;
+        }
+    });
+
+    tracing::info!("Exporting scrobble history for user {} as {}", user_id, extension);
+
+    let disposition = format!("attachment; filename=\"scrobbles.{}\"", extension);
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        Body::from_stream(ReceiverStream::new(receiver)),
+    )
+        .into_response())
+}
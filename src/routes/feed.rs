@@ -0,0 +1,125 @@
+use std::{convert::Infallible, time::Duration};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio_stream::{wrappers::{errors::BroadcastStreamRecvError, BroadcastStream}, Stream, StreamExt};
+
+use crate::{
+    auth::{get_user_by_token, AuthUser, Scope},
+    db,
+    feed::{Feed, FeedEvent, FeedEventKind},
+    now_playing as now_playing_store,
+    relay::Listen,
+};
+
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Deserialize)]
+pub struct FeedQuery {
+    /// `EventSource` can't send headers, so browsers pass the token here
+    pub token: Option<String>,
+}
+
+/// Data of a `scrobble` or `now_playing` event
+#[derive(Debug, Serialize)]
+pub struct FeedItem {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub duration: Option<i64>,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+fn to_event(kind: FeedEventKind, id: Option<i64>, listen: Listen) -> Event {
+    let item = FeedItem {
+        id,
+        artist: listen.artist,
+        track: listen.track,
+        album: listen.album,
+        duration: listen.duration,
+        timestamp: listen.timestamp,
+    };
+    Event::default()
+        .event(kind.as_str())
+        .json_data(item)
+        .unwrap_or_else(|_| Event::default().comment("unencodable event"))
+}
+
+/// GET /feed/live - server-sent events for the user's new scrobbles and
+/// now-playing updates. The current track, if any, is sent on connect.
+pub async fn live_feed(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(feed): State<Feed>,
+    Query(query): Query<FeedQuery>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, Json<ErrorResponse>)> {
+    let unauthorized = |status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() }));
+
+    let user_id = match (headers.contains_key("authorization"), query.token) {
+        (false, Some(token)) => get_user_by_token(&pool, &token, Scope::Read)
+            .await
+            .map_err(|e| {
+                if db::is_unavailable(&e) {
+                    unauthorized(StatusCode::SERVICE_UNAVAILABLE)
+                } else {
+                    unauthorized(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            })?
+            .ok_or_else(|| unauthorized(StatusCode::UNAUTHORIZED))?
+            .id,
+        _ => AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await.map_err(unauthorized)?.id,
+    };
+
+    // Subscribe before reading the current track so nothing falls in between
+    let receiver = feed.subscribe();
+
+    let current = now_playing_store::current(&pool, user_id, chrono::Utc::now().timestamp())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    let initial = current.map(|track| {
+        let listen = Listen {
+            artist: track.artist,
+            track: track.track,
+            album: track.album,
+            duration: track.duration,
+            timestamp: track.started_at,
+        };
+        Ok(to_event(FeedEventKind::NowPlaying, None, listen))
+    });
+
+    let live = BroadcastStream::new(receiver).filter_map(move |message| match message {
+        Ok(FeedEvent { user_id: owner, kind, id, listen }) if owner == user_id => {
+            Some(Ok(to_event(kind, id, listen)))
+        }
+        Ok(_) => None,
+        // The client was too slow; tell it to refetch instead of silently dropping
+        Err(BroadcastStreamRecvError::Lagged(missed)) => {
+            Some(Ok(Event::default().event("lagged").data(missed.to_string())))
+        }
+    });
+
+    let stream = tokio_stream::iter(initial).chain(live);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::new().interval(KEEP_ALIVE_INTERVAL)))
+}
@@ -0,0 +1,179 @@
+//! The follower/following graph between users
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::auth::{AuthUser, Scope};
+
+#[derive(Debug, Serialize)]
+pub struct Follow {
+    pub username: String,
+    /// Whether their profile is private now; they were public when followed
+    pub is_private: bool,
+    pub followed_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+/// POST /follow/{username} - follow a user with a public profile. Following
+/// someone again is a no-op and returns the original follow with 200.
+pub async fn follow_user(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(username): Path<String>,
+) -> Result<(StatusCode, Json<Follow>), ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let target = sqlx::query!(
+        "SELECT id, username, is_private FROM users WHERE username = $1 AND anonymized_at IS NULL",
+        username
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "User not found"))?;
+
+    if target.id == user.id {
+        return Err(error(StatusCode::BAD_REQUEST, "Cannot follow yourself"));
+    }
+
+    let existing = sqlx::query_scalar!(
+        "SELECT created_at FROM follows WHERE follower_id = $1 AND followee_id = $2",
+        user.id,
+        target.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if let Some(followed_at) = existing {
+        return Ok((
+            StatusCode::OK,
+            Json(Follow { username: target.username, is_private: target.is_private, followed_at }),
+        ));
+    }
+
+    if target.is_private {
+        return Err(error(StatusCode::FORBIDDEN, "This user's profile is private"));
+    }
+
+    // A concurrent follow of the same user keeps whichever landed first
+    let followed_at = sqlx::query_scalar!(
+        r#"
+        INSERT INTO follows (follower_id, followee_id, created_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (follower_id, followee_id) DO UPDATE SET created_at = follows.created_at
+        RETURNING created_at
+        "#,
+        user.id,
+        target.id,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Follow { username: target.username, is_private: false, followed_at }),
+    ))
+}
+
+/// DELETE /follow/{username} - stop following a user
+pub async fn unfollow_user(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(username): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let result = sqlx::query!(
+        r#"
+        DELETE FROM follows
+        WHERE follower_id = $1 AND followee_id = (SELECT id FROM users WHERE username = $2)
+        "#,
+        user.id,
+        username
+    )
+    .execute(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "You don't follow this user"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /followers - who follows you, newest first
+pub async fn list_followers(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Follow>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let followers = sqlx::query_as!(
+        Follow,
+        r#"
+        SELECT u.username, u.is_private, f.created_at as followed_at
+        FROM follows f
+        JOIN users u ON u.id = f.follower_id
+        WHERE f.followee_id = $1
+        ORDER BY f.created_at DESC, u.username
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(followers))
+}
+
+/// GET /following - who you follow, newest first
+pub async fn list_following(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Follow>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let following = sqlx::query_as!(
+        Follow,
+        r#"
+        SELECT u.username, u.is_private, f.created_at as followed_at
+        FROM follows f
+        JOIN users u ON u.id = f.followee_id
+        WHERE f.follower_id = $1
+        ORDER BY f.created_at DESC, u.username
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(following))
+}
@@ -0,0 +1,230 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{auth::{AuthUser, Scope}, goals, preferences};
+
+const MAX_GOALS_PER_USER: i64 = 20;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateGoalRequest {
+    pub metric: String,
+    pub period: String,
+    pub target: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Goal {
+    pub id: i64,
+    pub metric: String,
+    pub period: String,
+    pub target: i64,
+    pub progress: i64,
+    pub reached: bool,
+    pub period_start: i64,
+    pub period_end: i64,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub async fn list_goals(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Goal>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT id as "id!", metric, period, target, created_at
+        FROM goals
+        WHERE user_id = $1
+        ORDER BY created_at
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+    let mut result = Vec::with_capacity(rows.len());
+
+    for row in rows {
+        let (period_start, period_end) = goals::local_period_bounds(&pool, &timezone, &row.period)
+            .await
+            .map_err(db_error)?;
+        let progress = goals::progress(&pool, user.id, &row.metric, period_start, period_end)
+            .await
+            .map_err(db_error)?;
+
+        result.push(Goal {
+            id: row.id,
+            metric: row.metric,
+            period: row.period,
+            target: row.target,
+            progress,
+            reached: progress >= row.target,
+            period_start,
+            period_end,
+            created_at: row.created_at,
+        });
+    }
+
+    Ok(Json(result))
+}
+
+pub async fn create_goal(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<CreateGoalRequest>,
+) -> Result<(StatusCode, Json<Goal>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !goals::METRICS.contains(&req.metric.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("metric must be one of: {}", goals::METRICS.join(", ")),
+            }),
+        ));
+    }
+
+    if !goals::PERIODS.contains(&req.period.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("period must be one of: {}", goals::PERIODS.join(", ")),
+            }),
+        ));
+    }
+
+    if req.target <= 0 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "target must be positive".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM goals WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if count >= MAX_GOALS_PER_USER {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("At most {} goals are allowed", MAX_GOALS_PER_USER),
+            }),
+        ));
+    }
+
+    let now = chrono::Utc::now();
+    let timezone = preferences::load(&pool, user.id).await.map_err(db_error)?.timezone;
+    let (period_start, period_end) = goals::local_period_bounds(&pool, &timezone, &req.period)
+        .await
+        .map_err(db_error)?;
+    let progress = goals::progress(&pool, user.id, &req.metric, period_start, period_end)
+        .await
+        .map_err(db_error)?;
+
+    // A goal that is already met when created isn't announced for this period
+    let reached_period_start = (progress >= req.target).then_some(period_start);
+
+    let id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO goals (user_id, metric, period, target, created_at, reached_period_start)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id
+        "#,
+        user.id,
+        req.metric,
+        req.period,
+        req.target,
+        now.timestamp(),
+        reached_period_start
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(Goal {
+            id,
+            metric: req.metric,
+            period: req.period,
+            target: req.target,
+            progress,
+            reached: progress >= req.target,
+            period_start,
+            period_end,
+            created_at: now.timestamp(),
+        }),
+    ))
+}
+
+pub async fn delete_goal(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(goal_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let result = sqlx::query!(
+        "DELETE FROM goals WHERE id = $1 AND user_id = $2",
+        goal_id,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Goal not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,144 @@
+use std::time::{Duration, Instant};
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{db::MIGRATOR, health};
+
+/// A readiness probe shouldn't wait out the pool's acquire timeout
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Serialize)]
+pub struct LiveResponse {
+    pub status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DatabaseCheck {
+    pub status: &'static str,
+    pub latency_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MigrationsCheck {
+    pub status: &'static str,
+    /// Newest migration built into this binary
+    pub latest: Option<i64>,
+    /// Built-in migrations not applied to the database
+    pub pending: Vec<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerCheck {
+    pub name: &'static str,
+    pub status: &'static str,
+    pub interval_secs: u64,
+    pub last_beat: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReadyResponse {
+    /// `ok`, `degraded` (a worker stalled) or `unavailable`
+    pub status: &'static str,
+    pub database: DatabaseCheck,
+    pub migrations: MigrationsCheck,
+    pub workers: Vec<WorkerCheck>,
+}
+
+/// GET /health/live (and /health) - the process is up and serving requests.
+/// Nothing else is checked, so a database outage doesn't get it restarted.
+pub async fn health_live() -> Json<LiveResponse> {
+    Json(LiveResponse { status: "ok" })
+}
+
+/// GET /health/ready - 200 while the database answers and its schema is
+/// current, otherwise 503. Stalled background workers are reported as
+/// `degraded` without failing the probe, since requests are still served.
+pub async fn health_ready(State(pool): State<PgPool>) -> (StatusCode, Json<ReadyResponse>) {
+    let started = Instant::now();
+    let database = match tokio::time::timeout(DATABASE_TIMEOUT, sqlx::query!("SELECT 1 as one").fetch_one(&pool)).await {
+        Ok(Ok(_)) => DatabaseCheck {
+            status: "ok",
+            latency_ms: Some(started.elapsed().as_millis() as u64),
+            error: None,
+        },
+        Ok(Err(e)) => DatabaseCheck {
+            status: "error",
+            latency_ms: None,
+            error: Some(e.to_string()),
+        },
+        Err(_) => DatabaseCheck {
+            status: "error",
+            latency_ms: None,
+            error: Some(format!("No answer within {}s", DATABASE_TIMEOUT.as_secs())),
+        },
+    };
+
+    let latest = MIGRATOR.iter().map(|m| m.version).max();
+    let migrations = if database.status != "ok" {
+        MigrationsCheck {
+            status: "unknown",
+            latest,
+            pending: Vec::new(),
+            error: None,
+        }
+    } else {
+        // `_sqlx_migrations` is created by sqlx at startup rather than by a
+        // migration, so the checked macros can't see it
+        match sqlx::query_scalar::<_, i64>("SELECT version FROM _sqlx_migrations WHERE success")
+            .fetch_all(&pool)
+            .await
+        {
+            Ok(applied) => {
+                let pending: Vec<i64> = MIGRATOR
+                    .iter()
+                    .filter(|m| !m.migration_type.is_down_migration() && !applied.contains(&m.version))
+                    .map(|m| m.version)
+                    .collect();
+                MigrationsCheck {
+                    status: if pending.is_empty() { "ok" } else { "pending" },
+                    latest,
+                    pending,
+                    error: None,
+                }
+            }
+            Err(e) => MigrationsCheck {
+                status: "error",
+                latest,
+                pending: Vec::new(),
+                error: Some(e.to_string()),
+            },
+        }
+    };
+
+    let workers: Vec<WorkerCheck> = health::workers()
+        .into_iter()
+        .map(|w| WorkerCheck {
+            name: w.name,
+            status: if w.stalled { "stalled" } else { "ok" },
+            interval_secs: w.interval_secs,
+            last_beat: w.last_beat,
+        })
+        .collect();
+
+    let (code, status) = if database.status != "ok" || migrations.status != "ok" {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    } else if workers.iter().any(|w| w.status != "ok") {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::OK, "ok")
+    };
+
+    (
+        code,
+        Json(ReadyResponse {
+            status,
+            database,
+            migrations,
+            workers,
+        }),
+    )
+}
@@ -0,0 +1,193 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio::io::AsyncWriteExt;
+use tokio_stream::StreamExt;
+
+use crate::{
+    auth::AuthUser,
+    config::Config,
+    import::{upload_path, ImportFormat},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    pub format: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportJob {
+    pub id: i64,
+    pub format: String,
+    pub status: String,
+    pub bytes: i64,
+    pub bytes_processed: i64,
+    pub processed: i64,
+    pub imported: i64,
+    pub duplicates: i64,
+    pub skipped: i64,
+    pub rejected: i64,
+    pub errors: Vec<String>,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(ErrorResponse { error: message.into() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+/// Stream the request body to `path`, giving up past `max_bytes`
+async fn save_upload(body: Body, path: &std::path::Path, max_bytes: u64) -> Result<u64, ApiError> {
+    let write_error = |e: std::io::Error| {
+        tracing::error!("Failed to save import upload: {}", e);
+        error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload")
+    };
+
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(write_error)?;
+    }
+    let mut file = tokio::fs::File::create(path).await.map_err(write_error)?;
+
+    let mut written = 0u64;
+    let mut stream = body.into_data_stream();
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| error(StatusCode::BAD_REQUEST, format!("Failed to read upload: {}", e)))?;
+        written += chunk.len() as u64;
+        if written > max_bytes {
+            return Err(error(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Import files are limited to {} bytes", max_bytes),
+            ));
+        }
+        file.write_all(&chunk).await.map_err(write_error)?;
+    }
+    file.flush().await.map_err(write_error)?;
+
+    Ok(written)
+}
+
+/// POST /import?format=lastfm|listenbrainz|spotify - upload an export as the
+/// raw request body. It is processed in the background; poll
+/// `GET /import/{id}` for progress.
+pub async fn create_import(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<ImportQuery>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportJob>), ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let format = ImportFormat::parse(&query.format).ok_or_else(|| {
+        error(
+            StatusCode::BAD_REQUEST,
+            "Unsupported format (supported: lastfm, listenbrainz, spotify)",
+        )
+    })?;
+
+    // One import at a time per user keeps a single account from hogging the worker
+    let active = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS(
+            SELECT 1 FROM import_jobs
+            WHERE user_id = $1 AND status IN ('pending', 'running')
+        ) as "exists!"
+        "#,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if active {
+        return Err(error(StatusCode::CONFLICT, "An import is already in progress"));
+    }
+
+    let file_name = format!("{}-{}.upload", user.id, crate::auth::generate_token());
+    let path = upload_path(std::path::Path::new(&config.import_dir), &file_name);
+
+    let bytes = match save_upload(body, &path, config.import_max_bytes).await {
+        Ok(bytes) if bytes > 0 => bytes,
+        result => {
+            let _ = tokio::fs::remove_file(&path).await;
+            return Err(result.err().unwrap_or_else(|| error(StatusCode::BAD_REQUEST, "Upload is empty")));
+        }
+    };
+
+    let job = sqlx::query_as!(
+        ImportJob,
+        r#"
+        INSERT INTO import_jobs (user_id, format, file_name, bytes, created_at)
+        VALUES ($1, $2, $3, $4, $5)
+        RETURNING id, format, status, bytes, bytes_processed, processed, imported,
+                  duplicates, skipped, rejected, errors, error, created_at, started_at, finished_at
+        "#,
+        user.id,
+        format.as_str(),
+        file_name,
+        bytes as i64,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await;
+
+    match job {
+        Ok(job) => {
+            tracing::info!("Queued {} import {} for user {} ({} bytes)", job.format, job.id, user.id, bytes);
+            Ok((StatusCode::ACCEPTED, Json(job)))
+        }
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&path).await;
+            Err(db_error(e))
+        }
+    }
+}
+
+/// GET /import/{id} - progress and rejection reasons of an import
+pub async fn get_import(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(job_id): Path<i64>,
+) -> Result<Json<ImportJob>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let job = sqlx::query_as!(
+        ImportJob,
+        r#"
+        SELECT id, format, status, bytes, bytes_processed, processed, imported,
+               duplicates, skipped, rejected, errors, error, created_at, started_at, finished_at
+        FROM import_jobs
+        WHERE id = $1 AND user_id = $2
+        "#,
+        job_id,
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "Import not found"))?;
+
+    Ok(Json(job))
+}
@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use axum::{extract::State, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{api_version, config::Config, crypto::SecretBox, mail::Mailer, policy::ContentPolicy, runtime::RuntimeSettings};
+
+/// Generated by build.rs from Cargo and git metadata
+mod built_info {
+    include!(concat!(env!("OUT_DIR"), "/built.rs"));
+}
+
+/// Largest `limit` accepted by list endpoints such as /recent and /top/*
+const MAX_PAGE_SIZE: i64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct ServerInfo {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub features: Features,
+    pub compat_apis: Vec<&'static str>,
+    pub api: ApiVersions,
+    pub limits: Limits,
+    pub import_formats: Vec<&'static str>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Features {
+    pub graphql: bool,
+    pub federation: bool,
+    /// Open signup; false when registration needs an invite code
+    pub registration: bool,
+    pub relays: bool,
+    /// `/charts/global/*` is enabled
+    pub global_charts: bool,
+    /// SMTP is configured, so addresses can be verified and passwords reset
+    pub password_reset: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiVersions {
+    /// Newest version, served at `/api/<current>`
+    pub current: &'static str,
+    /// Every version this server answers, oldest first
+    pub supported: &'static [&'static str],
+    /// The REST API is also served at its old unprefixed paths
+    pub legacy_routes: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Limits {
+    /// Maximum scrobbles per POST /scrob (None when unbounded)
+    pub max_batch_size: Option<usize>,
+    pub max_page_size: i64,
+    /// Requests allowed per rate-limit window (None when not rate limited)
+    pub rate_limit: Option<u32>,
+}
+
+/// Unauthenticated capability discovery so clients can auto-configure
+pub async fn server_info(
+    State(pool): State<PgPool>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    State(mailer): State<Option<Arc<Mailer>>>,
+    State(config): State<Arc<Config>>,
+    State(runtime): State<RuntimeSettings>,
+) -> Json<ServerInfo> {
+    let mut compat_apis = vec!["listenbrainz", "audioscrobbler"];
+    if config.lastfm_api_key.is_some() && config.lastfm_api_secret.is_some() {
+        compat_apis.push("lastfm");
+    }
+
+    // Discovery shouldn't fail over this, so assume the default
+    let (registration, global_charts) = match ContentPolicy::load(&pool).await {
+        Ok(policy) => (policy.registration_open, policy.global_charts_enabled),
+        Err(e) => {
+            tracing::warn!("Failed to load registration setting: {}", e);
+            (true, true)
+        }
+    };
+
+    Json(ServerInfo {
+        name: "scrob",
+        version: env!("CARGO_PKG_VERSION"),
+        features: Features {
+            graphql: false,
+            federation: false,
+            registration,
+            relays: secrets.is_some(),
+            global_charts,
+            password_reset: mailer.is_some(),
+        },
+        compat_apis,
+        api: ApiVersions {
+            current: api_version::CURRENT,
+            supported: api_version::SUPPORTED,
+            legacy_routes: config.legacy_routes,
+        },
+        limits: Limits {
+            max_batch_size: Some(runtime.limits().scrobble_max_batch),
+            max_page_size: MAX_PAGE_SIZE,
+            rate_limit: Some(config.rate_limit).filter(|limit| *limit > 0),
+        },
+        import_formats: vec![],
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    /// Commit the binary was built from, None outside a git checkout
+    pub git_commit: Option<&'static str>,
+    /// The checkout had uncommitted changes
+    pub git_dirty: Option<bool>,
+    /// Unix timestamp of the build
+    pub built_at: Option<i64>,
+    /// Cargo features enabled at compile time
+    pub features: Vec<&'static str>,
+    pub rustc: &'static str,
+    pub target: &'static str,
+    pub profile: &'static str,
+}
+
+/// GET /version - build information for admin UIs and monitoring (no auth)
+pub async fn version_info() -> Json<VersionInfo> {
+    let built_at = chrono::DateTime::parse_from_rfc2822(built_info::BUILT_TIME_UTC)
+        .map(|t| t.timestamp())
+        .ok();
+
+    Json(VersionInfo {
+        version: built_info::PKG_VERSION,
+        git_commit: built_info::GIT_COMMIT_HASH,
+        git_dirty: built_info::GIT_DIRTY,
+        built_at,
+        features: built_info::FEATURES_LOWERCASE.iter().copied().filter(|f| !f.is_empty()).collect(),
+        rustc: built_info::RUSTC_VERSION,
+        target: built_info::TARGET,
+        profile: built_info::PROFILE,
+    })
+}
@@ -0,0 +1,203 @@
+//! Jellyfin Webhook plugin ingestion, so a Jellyfin server can report what
+//! its users play without a scrobbling client
+
+use axum::{
+    body::Bytes,
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+
+use crate::{
+    auth::{get_user_by_token, AuthUser, Scope},
+    db,
+    now_playing as now_playing_store, relay,
+    routes::scrobble::{is_mbid, normalize_mbid, submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+/// Jellyfin times are in 100ns ticks
+const TICKS_PER_SEC: i64 = 10_000_000;
+
+#[derive(Debug, Deserialize)]
+pub struct JellyfinQuery {
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JellyfinResponse {
+    /// `now_playing`, `accepted`, `ignored_duplicate`, `rejected` or `ignored`
+    pub status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+fn ignored(reason: &str) -> Json<JellyfinResponse> {
+    Json(JellyfinResponse {
+        status: "ignored",
+        reason: Some(reason.to_string()),
+    })
+}
+
+/// POST /ingest/jellyfin?token=... - PlaybackStart and PlaybackProgress
+/// notifications set now playing, PlaybackStop scrobbles the track if enough
+/// of it was played. Everything else is acknowledged and ignored, so the
+/// plugin doesn't retry it.
+pub async fn jellyfin_webhook(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<JellyfinQuery>,
+    body: Bytes,
+) -> Result<Json<JellyfinResponse>, ApiError> {
+    let unauthorized = |status| error(status, "Unauthorized");
+
+    // The plugin can't always send headers, so the token may be in the URL
+    let user_id = match (headers.contains_key("authorization"), query.token) {
+        (false, Some(token)) => get_user_by_token(&pool, &token, Scope::Scrobble)
+            .await
+            .map_err(|e| {
+                if db::is_unavailable(&e) {
+                    unauthorized(StatusCode::SERVICE_UNAVAILABLE)
+                } else {
+                    unauthorized(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            })?
+            .ok_or_else(|| unauthorized(StatusCode::UNAUTHORIZED))?
+            .id,
+        _ => AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await.map_err(unauthorized)?.id,
+    };
+
+    // Payloads come from a user-editable template, so don't insist on a
+    // content type or on how values are quoted
+    let payload: Value = serde_json::from_slice(&body)
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &format!("Invalid JSON: {}", e)))?;
+
+    let event = text(&payload, "NotificationType").unwrap_or_default();
+    if !matches!(event.as_str(), "PlaybackStart" | "PlaybackProgress" | "PlaybackStop") {
+        return Ok(ignored("Only playback notifications are handled"));
+    }
+
+    if text(&payload, "ItemType").is_some_and(|t| t != "Audio") {
+        return Ok(ignored("Only audio items are scrobbled"));
+    }
+
+    let (Some(artist), Some(track)) = (
+        text(&payload, "Artist").or_else(|| text(&payload, "AlbumArtist")),
+        text(&payload, "Name"),
+    ) else {
+        return Ok(ignored("Artist and Name are required"));
+    };
+
+    let album = text(&payload, "Album");
+    let duration = number(&payload, "RunTimeTicks").map(|t| t / TICKS_PER_SEC).filter(|d| *d > 0);
+    let position = number(&payload, "PlaybackPositionTicks").map(|t| t / TICKS_PER_SEC).unwrap_or(0);
+    let now = chrono::Utc::now().timestamp();
+
+    if event != "PlaybackStop" {
+        if flag(&payload, "IsPaused") {
+            return Ok(ignored("Playback is paused"));
+        }
+
+        // Progress arrives every few seconds; only a new track is news
+        let current = now_playing_store::current(&pool, user_id, now).await.map_err(db_error)?;
+        if current.is_some_and(|c| c.artist == artist && c.track == track) {
+            return Ok(Json(JellyfinResponse { status: "now_playing", reason: None }));
+        }
+
+        let listen = relay::Listen {
+            artist,
+            track,
+            album,
+            duration,
+            timestamp: now - position,
+        };
+
+        now_playing_store::record(&pool, user_id, &listen).await.map_err(db_error)?;
+
+        if let Err(e) = relay::enqueue_now_playing(&pool, user_id, &listen).await {
+            tracing::error!("Failed to queue now-playing for relays: {}", e);
+        }
+
+        return Ok(Json(JellyfinResponse { status: "now_playing", reason: None }));
+    }
+
+    now_playing_store::clear(&pool, user_id, &artist, &track).await.map_err(db_error)?;
+
+    let played = match (flag(&payload, "PlayedToCompletion"), duration) {
+        (true, Some(duration)) => duration.max(position),
+        _ => position,
+    };
+
+    if let Err(reason) = now_playing_store::check_threshold(played, duration) {
+        return Ok(ignored(&reason));
+    }
+
+    let scrob = ScrobbleRequest {
+        artist,
+        track,
+        timestamp: (now - played).max(0) as u64,
+        album,
+        album_artist: text(&payload, "AlbumArtist"),
+        duration: duration.map(|d| d as u64),
+        track_number: number(&payload, "IndexNumber").and_then(|n| u32::try_from(n).ok()),
+        artist_mbid: mbid(&payload, "Provider_musicbrainzartist"),
+        release_mbid: mbid(&payload, "Provider_musicbrainzalbum"),
+        // Jellyfin stores Picard's "MusicBrainz Track Id", the recording
+        recording_mbid: mbid(&payload, "Provider_musicbrainztrack"),
+        source: Some("jellyfin".to_string()),
+    };
+
+    let response = match submit_scrobble(&pool, user_id, &scrob).await.map_err(db_error)? {
+        ScrobbleOutcome::Accepted(_) => JellyfinResponse { status: "accepted", reason: None },
+        ScrobbleOutcome::Duplicate => JellyfinResponse { status: "ignored_duplicate", reason: None },
+        ScrobbleOutcome::Rejected(reason) => JellyfinResponse { status: "rejected", reason: Some(reason) },
+    };
+
+    Ok(Json(response))
+}
+
+fn text(payload: &Value, key: &str) -> Option<String> {
+    match payload.get(key)? {
+        Value::String(s) => Some(s.trim().to_string()).filter(|s| !s.is_empty()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+fn number(payload: &Value, key: &str) -> Option<i64> {
+    match payload.get(key)? {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn flag(payload: &Value, key: &str) -> bool {
+    match payload.get(key) {
+        Some(Value::Bool(b)) => *b,
+        Some(Value::String(s)) => s.trim().eq_ignore_ascii_case("true"),
+        _ => false,
+    }
+}
+
+/// Malformed ids are dropped rather than rejecting the play
+fn mbid(payload: &Value, key: &str) -> Option<String> {
+    normalize_mbid(text(payload, key).as_deref()).filter(|id| is_mbid(id))
+}
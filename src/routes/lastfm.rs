@@ -0,0 +1,503 @@
+//! Last.fm Scrobble 2.0 API compatibility, so Last.fm-capable players can use
+//! scrob by overriding their API root to `http://<host>/2.0/`
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Form, Json,
+};
+use md5::{Digest, Md5};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{authenticate, create_token, generate_token, get_user_by_token, AuthUser, ClientInfo, LoginResult, Scope},
+    config::Config,
+    now_playing as now_playing_store, relay,
+    routes::scrobble::{is_mbid, normalize_mbid, submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+/// Last.fm accepts at most 50 scrobbles per track.scrobble call
+const MAX_SCROBBLES_PER_REQUEST: usize = 50;
+/// Unused auth.getToken tokens expire after an hour, as on Last.fm
+const AUTH_TOKEN_TTL_SECS: i64 = 60 * 60;
+
+// Last.fm error codes
+const INVALID_METHOD: u16 = 3;
+const AUTH_FAILED: u16 = 4;
+const INVALID_PARAMETERS: u16 = 6;
+const OPERATION_FAILED: u16 = 8;
+const INVALID_SESSION_KEY: u16 = 9;
+const INVALID_API_KEY: u16 = 10;
+const INVALID_SIGNATURE: u16 = 13;
+const UNAUTHORIZED_TOKEN: u16 = 14;
+const TOKEN_EXPIRED: u16 = 15;
+const RATE_LIMIT_EXCEEDED: u16 = 29;
+
+pub(crate) type Params = BTreeMap<String, String>;
+
+/// A Last.fm API error, rendered in the requested format
+struct LfmError {
+    code: u16,
+    message: String,
+}
+
+impl LfmError {
+    fn new(code: u16, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.code {
+            INVALID_METHOD | INVALID_PARAMETERS => StatusCode::BAD_REQUEST,
+            OPERATION_FAILED => StatusCode::INTERNAL_SERVER_ERROR,
+            RATE_LIMIT_EXCEEDED => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl From<sqlx::Error> for LfmError {
+    fn from(e: sqlx::Error) -> Self {
+        tracing::error!("Last.fm API database error: {}", e);
+        Self::new(OPERATION_FAILED, "Operation failed - Most likely the backend service failed")
+    }
+}
+
+/// Single entry point for all Last.fm methods (GET or form POST to /2.0/)
+pub async fn lastfm_api(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Form(pairs): Form<Vec<(String, String)>>,
+) -> Response {
+    let params: Params = pairs.into_iter().collect();
+    let json = params.get("format").is_some_and(|f| f == "json");
+
+    let client = ClientInfo::from_headers(&headers);
+
+    match dispatch(&pool, &config, &client, &params).await {
+        Ok(body) => render(StatusCode::OK, &body, json),
+        Err(e) => {
+            let body = if json {
+                json!({ "error": e.code, "message": e.message })
+            } else {
+                json!({ "error": { "code": e.code, "#text": e.message } })
+            };
+            render(e.status(), &body, json)
+        }
+    }
+}
+
+async fn dispatch(pool: &PgPool, config: &Config, client: &ClientInfo, params: &Params) -> Result<Value, LfmError> {
+    let (Some(api_key), Some(api_secret)) = (&config.lastfm_api_key, &config.lastfm_api_secret) else {
+        return Err(LfmError::new(INVALID_API_KEY, "The Last.fm API is not enabled on this instance"));
+    };
+
+    if params.get("api_key") != Some(api_key) {
+        return Err(LfmError::new(INVALID_API_KEY, "Invalid API key - You must be granted a valid key by last.fm"));
+    }
+
+    let method = params.get("method").map(|m| m.to_ascii_lowercase()).unwrap_or_default();
+    let signature = params.get("api_sig").map(|s| s.to_ascii_lowercase());
+
+    // Every supported method is a write or auth method, so all must be signed
+    if signature.as_deref() != Some(sign(params, api_secret).as_str()) {
+        return Err(LfmError::new(INVALID_SIGNATURE, "Invalid method signature supplied"));
+    }
+
+    match method.as_str() {
+        "auth.gettoken" => get_token(pool).await,
+        "auth.getsession" => get_session(pool, params).await,
+        "auth.getmobilesession" => get_mobile_session(pool, config, client, params).await,
+        "track.scrobble" => track_scrobble(pool, params).await,
+        "track.updatenowplaying" => update_now_playing(pool, params).await,
+        _ => Err(LfmError::new(INVALID_METHOD, "Invalid Method - No method with that name in this package")),
+    }
+}
+
+/// md5 of the sorted `name` + `value` pairs followed by the shared secret
+pub(crate) fn sign(params: &Params, secret: &str) -> String {
+    let mut hasher = Md5::new();
+    for (name, value) in params {
+        if name == "format" || name == "callback" || name == "api_sig" {
+            continue;
+        }
+        hasher.update(name.as_bytes());
+        hasher.update(value.as_bytes());
+    }
+    hasher.update(secret.as_bytes());
+    hex::encode(hasher.finalize())
+}
+
+fn required<'a>(params: &'a Params, name: &str) -> Result<&'a str, LfmError> {
+    params
+        .get(name)
+        .map(String::as_str)
+        .filter(|v| !v.is_empty())
+        .ok_or_else(|| LfmError::new(INVALID_PARAMETERS, format!("Invalid parameters - Missing {}", name)))
+}
+
+async fn session_user(pool: &PgPool, params: &Params) -> Result<i64, LfmError> {
+    let sk = required(params, "sk")?;
+    get_user_by_token(pool, sk, Scope::Scrobble)
+        .await?
+        .map(|user| user.id)
+        .ok_or_else(|| LfmError::new(INVALID_SESSION_KEY, "Invalid session key - Please re-authenticate"))
+}
+
+async fn create_session(pool: &PgPool, user_id: i64, username: &str) -> Result<Value, LfmError> {
+    // Last.fm clients only scrobble, so their sessions can't manage the account
+    let key = create_token(pool, user_id, "lastfm", &[Scope::Scrobble, Scope::Read], None).await?.token;
+
+    Ok(json!({ "session": { "name": username, "key": key, "subscriber": 0 } }))
+}
+
+async fn get_token(pool: &PgPool) -> Result<Value, LfmError> {
+    let token = generate_token();
+
+    sqlx::query!(
+        "INSERT INTO lastfm_auth_tokens (token, created_at) VALUES ($1, $2)",
+        token,
+        chrono::Utc::now().timestamp()
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(json!({ "token": token }))
+}
+
+async fn get_session(pool: &PgPool, params: &Params) -> Result<Value, LfmError> {
+    let token = required(params, "token")?;
+    let now = chrono::Utc::now().timestamp();
+
+    let row = sqlx::query!(
+        r#"
+        SELECT t.user_id, t.created_at, u.username as "username?"
+        FROM lastfm_auth_tokens t
+        LEFT JOIN users u ON u.id = t.user_id
+        WHERE t.token = $1
+        "#,
+        token
+    )
+    .fetch_optional(pool)
+    .await?
+    .filter(|row| row.created_at > now - AUTH_TOKEN_TTL_SECS)
+    .ok_or_else(|| LfmError::new(TOKEN_EXPIRED, "This token has expired"))?;
+
+    let (Some(user_id), Some(username)) = (row.user_id, row.username) else {
+        return Err(LfmError::new(UNAUTHORIZED_TOKEN, "This token has not been authorized"));
+    };
+
+    sqlx::query!("DELETE FROM lastfm_auth_tokens WHERE token = $1", token)
+        .execute(pool)
+        .await?;
+
+    create_session(pool, user_id, &username).await
+}
+
+async fn get_mobile_session(pool: &PgPool, config: &Config, client: &ClientInfo, params: &Params) -> Result<Value, LfmError> {
+    let username = required(params, "username")?;
+    let password = required(params, "password")?;
+    let auth_failed = || LfmError::new(AUTH_FAILED, "Authentication Failed - Invalid username or password");
+
+    let user = match authenticate(pool, config, username, password, client.ip.as_deref()).await? {
+        LoginResult::Success(user) => user,
+        LoginResult::Invalid => return Err(auth_failed()),
+        LoginResult::Banned(ban) => return Err(LfmError::new(AUTH_FAILED, ban.message())),
+        LoginResult::Locked(_) => {
+            return Err(LfmError::new(
+                RATE_LIMIT_EXCEEDED,
+                "Rate limit exceeded - Too many failed logins, please try again later",
+            ))
+        }
+    };
+
+    create_session(pool, user.id, &user.username).await
+}
+
+/// Parameters of one scrobble, from `name[i]` (or unindexed `name`) pairs
+#[derive(Debug, Default)]
+struct IndexedScrobble {
+    artist: Option<String>,
+    track: Option<String>,
+    timestamp: Option<String>,
+    album: Option<String>,
+    album_artist: Option<String>,
+    duration: Option<String>,
+    track_number: Option<String>,
+    mbid: Option<String>,
+}
+
+fn indexed_scrobbles(params: &Params) -> BTreeMap<usize, IndexedScrobble> {
+    let mut scrobbles: BTreeMap<usize, IndexedScrobble> = BTreeMap::new();
+
+    for (key, value) in params {
+        let (name, index) = match key.strip_suffix(']').and_then(|k| k.split_once('[')) {
+            Some((name, index)) => match index.parse() {
+                Ok(index) => (name, index),
+                Err(_) => continue,
+            },
+            None => (key.as_str(), 0),
+        };
+
+        let entry = scrobbles.entry(index);
+        let field = match name {
+            "artist" => &mut entry.or_default().artist,
+            "track" => &mut entry.or_default().track,
+            "timestamp" => &mut entry.or_default().timestamp,
+            "album" => &mut entry.or_default().album,
+            "albumArtist" => &mut entry.or_default().album_artist,
+            "duration" => &mut entry.or_default().duration,
+            "trackNumber" => &mut entry.or_default().track_number,
+            "mbid" => &mut entry.or_default().mbid,
+            _ => continue,
+        };
+        *field = Some(value.clone()).filter(|v| !v.is_empty());
+    }
+
+    scrobbles
+}
+
+/// Scrobble fields are echoed back with Last.fm's (never applied) correction flag
+fn uncorrected(text: Option<&str>) -> Value {
+    json!({ "corrected": "0", "#text": text.unwrap_or_default() })
+}
+
+async fn track_scrobble(pool: &PgPool, params: &Params) -> Result<Value, LfmError> {
+    let user_id = session_user(pool, params).await?;
+    let scrobbles = indexed_scrobbles(params);
+
+    if scrobbles.is_empty() || scrobbles.len() > MAX_SCROBBLES_PER_REQUEST {
+        return Err(LfmError::new(
+            INVALID_PARAMETERS,
+            format!("Invalid parameters - Between 1 and {} scrobbles are allowed", MAX_SCROBBLES_PER_REQUEST),
+        ));
+    }
+
+    let mut accepted = 0;
+    let mut ignored = 0;
+    let mut results = Vec::with_capacity(scrobbles.len());
+
+    for item in scrobbles.into_values() {
+        let (Some(artist), Some(track), Some(timestamp)) = (&item.artist, &item.track, &item.timestamp) else {
+            return Err(LfmError::new(INVALID_PARAMETERS, "Invalid parameters - artist, track and timestamp are required"));
+        };
+        let timestamp: u64 = timestamp
+            .parse()
+            .map_err(|_| LfmError::new(INVALID_PARAMETERS, "Invalid parameters - timestamp must be a unix time"))?;
+
+        let scrob = ScrobbleRequest {
+            artist: artist.clone(),
+            track: track.clone(),
+            timestamp,
+            album: item.album.clone(),
+            album_artist: item.album_artist.clone(),
+            duration: item.duration.as_deref().and_then(|d| d.parse().ok()),
+            track_number: item.track_number.as_deref().and_then(|n| n.parse().ok()),
+            artist_mbid: None,
+            release_mbid: None,
+            // Clients send junk here often enough that it isn't worth a rejection
+            recording_mbid: normalize_mbid(item.mbid.as_deref()).filter(|mbid| is_mbid(mbid)),
+            source: Some("lastfm".to_string()),
+        };
+
+        // Duplicates are accepted silently, as Last.fm does
+        let ignored_message = match submit_scrobble(pool, user_id, &scrob).await? {
+            ScrobbleOutcome::Accepted(_) | ScrobbleOutcome::Duplicate => {
+                accepted += 1;
+                json!({ "code": "0", "#text": "" })
+            }
+            ScrobbleOutcome::Rejected(reason) => {
+                ignored += 1;
+                json!({ "code": "1", "#text": reason })
+            }
+        };
+
+        results.push(json!({
+            "artist": uncorrected(Some(artist)),
+            "track": uncorrected(Some(track)),
+            "album": uncorrected(item.album.as_deref()),
+            "albumArtist": uncorrected(item.album_artist.as_deref()),
+            "timestamp": timestamp.to_string(),
+            "ignoredMessage": ignored_message,
+        }));
+    }
+
+    let scrobble = if results.len() == 1 {
+        results.pop().unwrap_or_default()
+    } else {
+        Value::Array(results)
+    };
+
+    Ok(json!({
+        "scrobbles": {
+            "scrobble": scrobble,
+            "@attr": { "accepted": accepted, "ignored": ignored },
+        }
+    }))
+}
+
+async fn update_now_playing(pool: &PgPool, params: &Params) -> Result<Value, LfmError> {
+    let user_id = session_user(pool, params).await?;
+    let artist = required(params, "artist")?;
+    let track = required(params, "track")?;
+    let album = params.get("album").filter(|a| !a.is_empty());
+
+    let listen = relay::Listen {
+        artist: artist.to_string(),
+        track: track.to_string(),
+        album: album.cloned(),
+        duration: params.get("duration").and_then(|d| d.parse().ok()),
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+
+    now_playing_store::record(pool, user_id, &listen).await?;
+
+    if let Err(e) = relay::enqueue_now_playing(pool, user_id, &listen).await {
+        tracing::error!("Failed to queue now-playing for relays: {}", e);
+    }
+
+    Ok(json!({
+        "nowplaying": {
+            "artist": uncorrected(Some(artist)),
+            "track": uncorrected(Some(track)),
+            "album": uncorrected(album.map(String::as_str)),
+            "albumArtist": uncorrected(params.get("albumArtist").map(String::as_str)),
+            "ignoredMessage": { "code": "0", "#text": "" },
+        }
+    }))
+}
+
+fn render(status: StatusCode, body: &Value, json: bool) -> Response {
+    if json {
+        return (status, Json(body)).into_response();
+    }
+
+    let lfm_status = if status.is_success() { "ok" } else { "failed" };
+    let mut xml = format!("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<lfm status=\"{}\">", lfm_status);
+    if let Value::Object(fields) = body {
+        for (name, value) in fields {
+            write_xml(&mut xml, name, value);
+        }
+    }
+    xml.push_str("</lfm>\n");
+
+    (status, [(header::CONTENT_TYPE, "text/xml; charset=utf-8")], xml).into_response()
+}
+
+/// Convert Last.fm-style JSON to XML: `@attr` holds attributes, and an object
+/// with `#text` becomes an element whose other scalar fields are attributes
+fn write_xml(out: &mut String, name: &str, value: &Value) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                write_xml(out, name, item);
+            }
+        }
+        Value::Object(fields) => {
+            let text = fields.get("#text");
+            out.push('<');
+            out.push_str(name);
+            for (key, attr) in fields {
+                let attrs = match (key.as_str(), attr) {
+                    ("@attr", Value::Object(attrs)) => attrs.iter().map(|(k, v)| (k.as_str(), v)).collect(),
+                    (key, attr) if text.is_some() && key != "#text" => vec![(key, attr)],
+                    _ => vec![],
+                };
+                for (key, attr) in attrs {
+                    out.push_str(&format!(" {}=\"{}\"", key, escape_xml(&scalar(attr))));
+                }
+            }
+            out.push('>');
+            match text {
+                Some(text) => out.push_str(&escape_xml(&scalar(text))),
+                None => {
+                    for (key, child) in fields.iter().filter(|(key, _)| *key != "@attr") {
+                        write_xml(out, key, child);
+                    }
+                }
+            }
+            out.push_str(&format!("</{}>", name));
+        }
+        scalar_value => {
+            out.push_str(&format!("<{}>{}</{}>", name, escape_xml(&scalar(scalar_value)), name));
+        }
+    }
+}
+
+fn scalar(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+pub(crate) fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AuthorizeLastfmRequest {
+    pub token: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Approve an auth.getToken token for the signed-in user (the step a browser
+/// performs on last.fm/api/auth)
+pub async fn authorize_lastfm_token(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<AuthorizeLastfmRequest>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE lastfm_auth_tokens
+        SET user_id = $2
+        WHERE token = $1 AND user_id IS NULL AND created_at > $3
+        "#,
+        req.token,
+        user.id,
+        now - AUTH_TOKEN_TTL_SECS
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Token not found, expired or already authorized".to_string(),
+            }),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
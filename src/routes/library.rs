@@ -0,0 +1,676 @@
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{AuthUser, Scope},
+    routes::{scrobble::MAX_FIELD_LEN, stats::Scrob},
+};
+
+const DEFAULT_HISTORY_LIMIT: i64 = 50;
+const MAX_HISTORY_LIMIT: i64 = 100;
+
+#[derive(Debug, Deserialize)]
+pub struct RenameRequest {
+    /// `artist`, `album` or `track`
+    pub kind: String,
+    pub from: String,
+    pub to: String,
+    /// Only rename albums or tracks by this artist
+    pub artist: Option<String>,
+    /// Count what would change without changing it
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RenameResponse {
+    pub dry_run: bool,
+    /// Scrobbles matching `from`
+    pub matched: i64,
+    pub renamed: i64,
+    /// Scrobbles dropped because the renamed listen was already stored
+    pub merged: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrackQuery {
+    pub artist: String,
+    pub track: String,
+    /// Listens per page (default 50, max 100)
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AlbumQuery {
+    /// Album artist, or the track artist for scrobbles without one
+    pub artist: String,
+    pub album: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackDetail {
+    pub artist: String,
+    pub track: String,
+    pub plays: i64,
+    pub first_played: i64,
+    pub last_played: i64,
+    /// A page of listens, newest first
+    pub listens: Vec<Scrob>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AlbumDetail {
+    pub artist: String,
+    pub album: String,
+    pub plays: i64,
+    pub first_played: i64,
+    pub last_played: i64,
+    /// A page of listens, newest first
+    pub listens: Vec<Scrob>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LibraryQuery {
+    /// Case-insensitive substring to search for
+    pub q: Option<String>,
+    /// `plays` (the default), `recent` or `name`
+    pub sort: Option<String>,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryPage<T> {
+    /// Entries matching `q`, across all pages
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub items: Vec<T>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryArtist {
+    pub name: String,
+    pub plays: i64,
+    pub last_played: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryAlbum {
+    pub album: String,
+    /// Album artist, falling back to the track artist when none was submitted
+    pub artist: String,
+    pub plays: i64,
+    pub last_played: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LibraryTrack {
+    /// Catalogue id, also the song id for Subsonic players
+    pub id: i64,
+    pub artist: String,
+    pub track: String,
+    pub plays: i64,
+    pub last_played: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(ErrorResponse { error: message.into() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    if e.as_database_error().is_some_and(|e| e.is_unique_violation()) {
+        return error(StatusCode::CONFLICT, "Scrobbles changed during the rename, please try again");
+    }
+    error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+/// POST /library/rename - rename an artist, album or track across all of
+/// the user's scrobbles in one transaction
+///
+/// The queries below share one matching rule, keyed on `$5` (the kind):
+/// artists match on `artist` or `album_artist`, albums on `album` (and the
+/// album artist when `artist` is given), tracks on `track` (and `artist`).
+/// A renamed scrobble that would duplicate a listen already stored under the
+/// new name is deleted instead. Every change is recorded in `scrob_edits`.
+pub async fn rename_library(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<RenameRequest>,
+) -> Result<Json<RenameResponse>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    if !["artist", "album", "track"].contains(&req.kind.as_str()) {
+        return Err(error(StatusCode::BAD_REQUEST, "kind must be artist, album or track"));
+    }
+    if req.kind == "artist" && req.artist.is_some() {
+        return Err(error(StatusCode::BAD_REQUEST, "artist can only narrow an album or track rename"));
+    }
+
+    let to = req.to.trim();
+    if req.from.is_empty() || to.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "from and to are required"));
+    }
+    if to.len() > MAX_FIELD_LEN {
+        return Err(error(StatusCode::BAD_REQUEST, format!("to must be at most {} bytes", MAX_FIELD_LEN)));
+    }
+    if req.from == to {
+        return Err(error(StatusCode::BAD_REQUEST, "from and to are the same"));
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let counts = sqlx::query!(
+        r#"
+        WITH matched AS (
+            SELECT
+                id,
+                timestamp,
+                CASE WHEN $5 = 'artist' AND artist = $2 THEN $3 ELSE artist END AS new_artist,
+                CASE WHEN $5 = 'track' THEN $3 ELSE track END AS new_track
+            FROM scrobs
+            WHERE user_id = $1 AND CASE $5
+                WHEN 'artist' THEN artist = $2 OR album_artist = $2
+                WHEN 'album' THEN album = $2 AND ($4::TEXT IS NULL OR COALESCE(album_artist, artist) = $4)
+                ELSE track = $2 AND ($4::TEXT IS NULL OR artist = $4)
+            END
+            FOR UPDATE
+        )
+        SELECT
+            COUNT(*) as "matched!",
+            COUNT(*) FILTER (WHERE EXISTS (
+                SELECT 1 FROM scrobs s
+                WHERE s.user_id = $1 AND s.id <> m.id
+                  AND s.artist = m.new_artist AND s.track = m.new_track AND s.timestamp = m.timestamp
+            )) as "merged!"
+        FROM matched m
+        "#,
+        user.id,
+        req.from,
+        to,
+        req.artist,
+        req.kind
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    if req.dry_run {
+        return Ok(Json(RenameResponse {
+            dry_run: true,
+            matched: counts.matched,
+            renamed: counts.matched - counts.merged,
+            merged: counts.merged,
+        }));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+
+    let merged = sqlx::query!(
+        r#"
+        WITH deleted AS (
+            DELETE FROM scrobs m
+            WHERE m.user_id = $1 AND CASE $5
+                WHEN 'artist' THEN m.artist = $2 OR m.album_artist = $2
+                WHEN 'album' THEN m.album = $2 AND ($4::TEXT IS NULL OR COALESCE(m.album_artist, m.artist) = $4)
+                ELSE m.track = $2 AND ($4::TEXT IS NULL OR m.artist = $4)
+            END
+            AND EXISTS (
+                SELECT 1 FROM scrobs s
+                WHERE s.user_id = $1 AND s.id <> m.id
+                  AND s.artist = CASE WHEN $5 = 'artist' AND m.artist = $2 THEN $3 ELSE m.artist END
+                  AND s.track = CASE WHEN $5 = 'track' THEN $3 ELSE m.track END
+                  AND s.timestamp = m.timestamp
+            )
+            RETURNING m.id, m.artist, m.track, m.album, m.album_artist, m.track_number, m.duration, m.timestamp
+        )
+        INSERT INTO scrob_edits (scrob_id, user_id, action, old_values, new_values, edited_at)
+        SELECT id, $1, 'delete', to_jsonb(deleted) - 'id', NULL, $6
+        FROM deleted
+        "#,
+        user.id,
+        req.from,
+        to,
+        req.artist,
+        req.kind,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .rows_affected() as i64;
+
+    // A looked-up duration belongs to the old artist or track, so it goes
+    // when either changes, as do the MusicBrainz ids of whatever was renamed
+    let renamed = sqlx::query!(
+        r#"
+        WITH old AS (
+            SELECT id, artist, track, album, album_artist, track_number, duration, timestamp
+            FROM scrobs
+            WHERE user_id = $1 AND CASE $5
+                WHEN 'artist' THEN artist = $2 OR album_artist = $2
+                WHEN 'album' THEN album = $2 AND ($4::TEXT IS NULL OR COALESCE(album_artist, artist) = $4)
+                ELSE track = $2 AND ($4::TEXT IS NULL OR artist = $4)
+            END
+        ),
+        updated AS (
+            UPDATE scrobs s
+            SET artist = CASE WHEN $5 = 'artist' AND s.artist = $2 THEN $3 ELSE s.artist END,
+                album_artist = CASE WHEN $5 = 'artist' AND s.album_artist = $2 THEN $3 ELSE s.album_artist END,
+                album = CASE WHEN $5 = 'album' THEN $3 ELSE s.album END,
+                track = CASE WHEN $5 = 'track' THEN $3 ELSE s.track END,
+                duration_estimated = CASE
+                    WHEN ($5 = 'artist' AND s.artist = $2) OR $5 = 'track' THEN NULL
+                    ELSE s.duration_estimated
+                END,
+                artist_mbid = CASE WHEN $5 = 'artist' AND s.artist = $2 THEN NULL ELSE s.artist_mbid END,
+                release_mbid = CASE WHEN $5 IN ('artist', 'album') THEN NULL ELSE s.release_mbid END,
+                recording_mbid = CASE
+                    WHEN ($5 = 'artist' AND s.artist = $2) OR $5 = 'track' THEN NULL
+                    ELSE s.recording_mbid
+                END
+            FROM old
+            WHERE s.id = old.id
+            RETURNING
+                s.id,
+                to_jsonb(old) - 'id' AS old_values,
+                jsonb_build_object(
+                    'artist', s.artist,
+                    'track', s.track,
+                    'album', s.album,
+                    'album_artist', s.album_artist,
+                    'track_number', s.track_number,
+                    'duration', s.duration,
+                    'timestamp', s.timestamp
+                ) AS new_values
+        )
+        INSERT INTO scrob_edits (scrob_id, user_id, action, old_values, new_values, edited_at)
+        SELECT id, $1, 'edit', old_values, new_values, $6
+        FROM updated
+        "#,
+        user.id,
+        req.from,
+        to,
+        req.artist,
+        req.kind,
+        now
+    )
+    .execute(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .rows_affected() as i64;
+
+    tx.commit().await.map_err(db_error)?;
+
+    tracing::info!(
+        "User {} renamed {} '{}' to '{}' ({} renamed, {} merged)",
+        user.id,
+        req.kind,
+        req.from,
+        to,
+        renamed,
+        merged
+    );
+
+    Ok(Json(RenameResponse {
+        dry_run: false,
+        matched: renamed + merged,
+        renamed,
+        merged,
+    }))
+}
+
+/// Clamp a history page to sensible bounds
+fn page(limit: Option<i64>, offset: Option<i64>) -> (i64, i64) {
+    (
+        limit.unwrap_or(DEFAULT_HISTORY_LIMIT).clamp(1, MAX_HISTORY_LIMIT),
+        offset.unwrap_or(0).max(0),
+    )
+}
+
+/// GET /track?artist=&track= - play count, first and last play and a page
+/// of listens for one track
+pub async fn track_detail(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<TrackQuery>,
+) -> Result<Json<TrackDetail>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let (limit, offset) = page(query.limit, query.offset);
+
+    let summary = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "plays!", MIN(timestamp) as first_played, MAX(timestamp) as last_played
+        FROM scrobs
+        WHERE user_id = $1 AND artist = $2 AND track = $3
+        "#,
+        user.id,
+        query.artist,
+        query.track
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let (Some(first_played), Some(last_played)) = (summary.first_played, summary.last_played) else {
+        return Err(error(StatusCode::NOT_FOUND, "Track not found"));
+    };
+
+    let listens = sqlx::query_as!(
+        Scrob,
+        r#"
+        SELECT
+            id as "id!",
+            artist,
+            track,
+            album,
+            album_artist,
+            track_number,
+            COALESCE(duration, duration_estimated) as duration,
+            (duration IS NULL AND duration_estimated IS NOT NULL) as "duration_estimated!",
+            timestamp as "timestamp!",
+            artist_mbid,
+            release_mbid,
+            recording_mbid
+        FROM scrobs
+        WHERE user_id = $1 AND artist = $2 AND track = $3
+        ORDER BY timestamp DESC
+        LIMIT $4 OFFSET $5
+        "#,
+        user.id,
+        query.artist,
+        query.track,
+        limit,
+        offset
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(TrackDetail {
+        artist: query.artist,
+        track: query.track,
+        plays: summary.plays,
+        first_played,
+        last_played,
+        listens,
+        limit,
+        offset,
+    }))
+}
+
+/// GET /album?artist=&album= - play count, first and last play and a page
+/// of listens for one album
+///
+/// Albums are matched the way `/top/albums` groups them: by trimmed name and
+/// album artist (else the track artist), with the spellings of "Various
+/// Artists" treated as one.
+pub async fn album_detail(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<AlbumQuery>,
+) -> Result<Json<AlbumDetail>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let (limit, offset) = page(query.limit, query.offset);
+
+    let summary = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "plays!", MIN(s.timestamp) as first_played, MAX(s.timestamp) as last_played
+        FROM scrobs s
+        JOIN albums al ON al.id = s.album_id
+        JOIN artists a ON a.id = al.artist_id
+        WHERE s.user_id = $1 AND a.name = $2 AND al.name = $3
+        "#,
+        user.id,
+        query.artist,
+        query.album
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let (Some(first_played), Some(last_played)) = (summary.first_played, summary.last_played) else {
+        return Err(error(StatusCode::NOT_FOUND, "Album not found"));
+    };
+
+    let listens = sqlx::query_as!(
+        Scrob,
+        r#"
+        SELECT
+            s.id as "id!",
+            s.artist,
+            s.track,
+            s.album,
+            s.album_artist,
+            s.track_number,
+            COALESCE(s.duration, s.duration_estimated) as duration,
+            (s.duration IS NULL AND s.duration_estimated IS NOT NULL) as "duration_estimated!",
+            s.timestamp as "timestamp!",
+            s.artist_mbid,
+            s.release_mbid,
+            s.recording_mbid
+        FROM scrobs s
+        JOIN albums al ON al.id = s.album_id
+        JOIN artists a ON a.id = al.artist_id
+        WHERE s.user_id = $1 AND a.name = $2 AND al.name = $3
+        ORDER BY s.timestamp DESC
+        LIMIT $4 OFFSET $5
+        "#,
+        user.id,
+        query.artist,
+        query.album,
+        limit,
+        offset
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(AlbumDetail {
+        artist: query.artist,
+        album: query.album,
+        plays: summary.plays,
+        first_played,
+        last_played,
+        listens,
+        limit,
+        offset,
+    }))
+}
+
+/// Check a library listing's sort and page, and turn an empty `q` into none
+fn library_params(query: &LibraryQuery) -> Result<(Option<&str>, &str, i64, i64), ApiError> {
+    let sort = query.sort.as_deref().unwrap_or("plays");
+    if !["plays", "recent", "name"].contains(&sort) {
+        return Err(error(StatusCode::BAD_REQUEST, "sort must be plays, recent or name"));
+    }
+    let q = query.q.as_deref().map(str::trim).filter(|q| !q.is_empty());
+    let (limit, offset) = page(query.limit, query.offset);
+    Ok((q, sort, limit, offset))
+}
+
+/// GET /library/artists?q=&sort=plays - every artist the user has
+/// scrobbled, with play counts
+pub async fn library_artists(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<LibraryQuery>,
+) -> Result<Json<LibraryPage<LibraryArtist>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let (q, sort, limit, offset) = library_params(&query)?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT s.artist_id) as "count!"
+        FROM scrobs s
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.user_id = $1 AND ($2::text IS NULL OR strpos(lower(a.name), lower($2)) > 0)
+        "#,
+        user.id,
+        q
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let items = sqlx::query_as!(
+        LibraryArtist,
+        r#"
+        SELECT a.name, COUNT(*) as "plays!", MAX(s.timestamp) as "last_played!"
+        FROM scrobs s
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.user_id = $1 AND ($2::text IS NULL OR strpos(lower(a.name), lower($2)) > 0)
+        GROUP BY a.id
+        ORDER BY
+            CASE WHEN $3 = 'plays' THEN COUNT(*) END DESC,
+            CASE WHEN $3 = 'recent' THEN MAX(s.timestamp) END DESC,
+            a.name
+        LIMIT $4 OFFSET $5
+        "#,
+        user.id,
+        q,
+        sort,
+        limit,
+        offset
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(LibraryPage { total, limit, offset, items }))
+}
+
+/// GET /library/albums?q=&sort=plays - every album the user has scrobbled,
+/// grouped like `/top/albums`. `q` matches the album or its artist.
+pub async fn library_albums(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<LibraryQuery>,
+) -> Result<Json<LibraryPage<LibraryAlbum>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let (q, sort, limit, offset) = library_params(&query)?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT s.album_id) as "count!"
+        FROM scrobs s
+        JOIN albums al ON al.id = s.album_id
+        JOIN artists a ON a.id = al.artist_id
+        WHERE s.user_id = $1
+          AND ($2::text IS NULL OR strpos(lower(al.name), lower($2)) > 0 OR strpos(lower(a.name), lower($2)) > 0)
+        "#,
+        user.id,
+        q
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let items = sqlx::query_as!(
+        LibraryAlbum,
+        r#"
+        SELECT al.name as album, a.name as artist, COUNT(*) as "plays!", MAX(s.timestamp) as "last_played!"
+        FROM scrobs s
+        JOIN albums al ON al.id = s.album_id
+        JOIN artists a ON a.id = al.artist_id
+        WHERE s.user_id = $1
+          AND ($2::text IS NULL OR strpos(lower(al.name), lower($2)) > 0 OR strpos(lower(a.name), lower($2)) > 0)
+        GROUP BY al.id, a.id
+        ORDER BY
+            CASE WHEN $3 = 'plays' THEN COUNT(*) END DESC,
+            CASE WHEN $3 = 'recent' THEN MAX(s.timestamp) END DESC,
+            al.name, a.name
+        LIMIT $4 OFFSET $5
+        "#,
+        user.id,
+        q,
+        sort,
+        limit,
+        offset
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(LibraryPage { total, limit, offset, items }))
+}
+
+/// GET /library/tracks?q=&sort=plays - every track the user has scrobbled.
+/// `q` matches the track or its artist.
+pub async fn library_tracks(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<LibraryQuery>,
+) -> Result<Json<LibraryPage<LibraryTrack>>, ApiError> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let (q, sort, limit, offset) = library_params(&query)?;
+
+    let total = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(DISTINCT s.track_id) as "count!"
+        FROM scrobs s
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE s.user_id = $1
+          AND ($2::text IS NULL OR strpos(lower(t.name), lower($2)) > 0 OR strpos(lower(a.name), lower($2)) > 0)
+        "#,
+        user.id,
+        q
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let items = sqlx::query_as!(
+        LibraryTrack,
+        r#"
+        SELECT t.id, a.name as artist, t.name as track, COUNT(*) as "plays!", MAX(s.timestamp) as "last_played!"
+        FROM scrobs s
+        JOIN tracks t ON t.id = s.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE s.user_id = $1
+          AND ($2::text IS NULL OR strpos(lower(t.name), lower($2)) > 0 OR strpos(lower(a.name), lower($2)) > 0)
+        GROUP BY t.id, a.id
+        ORDER BY
+            CASE WHEN $3 = 'plays' THEN COUNT(*) END DESC,
+            CASE WHEN $3 = 'recent' THEN MAX(s.timestamp) END DESC,
+            t.name, a.name
+        LIMIT $4 OFFSET $5
+        "#,
+        user.id,
+        q,
+        sort,
+        limit,
+        offset
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(LibraryPage { total, limit, offset, items }))
+}
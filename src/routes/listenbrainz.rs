@@ -0,0 +1,214 @@
+//! ListenBrainz-compatible submission API, so ListenBrainz clients can use
+//! scrob as a custom server root
+
+use axum::{extract::{Query, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sqlx::PgPool;
+
+use crate::{
+    auth::{extract_token_from_header, get_user_by_token, AuthUser, Scope},
+    now_playing as now_playing_store, relay,
+    routes::scrobble::{submit_scrobble, validate_scrobble, ScrobbleRequest},
+};
+
+/// Same cap ListenBrainz applies to a single import request
+const MAX_LISTENS_PER_REQUEST: usize = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct SubmitListens {
+    pub listen_type: String,
+    pub payload: Vec<LbListen>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LbListen {
+    pub listened_at: Option<i64>,
+    pub track_metadata: TrackMetadata,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrackMetadata {
+    pub artist_name: String,
+    pub track_name: String,
+    pub release_name: Option<String>,
+    #[serde(default)]
+    pub additional_info: AdditionalInfo,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AdditionalInfo {
+    pub duration_ms: Option<u64>,
+    pub duration: Option<u64>,
+    pub release_artist_name: Option<String>,
+    pub tracknumber: Option<serde_json::Value>,
+    #[serde(default)]
+    pub artist_mbids: Vec<String>,
+    pub release_mbid: Option<String>,
+    pub recording_mbid: Option<String>,
+    /// Name of the submitting client, recorded as the scrobble's source
+    pub submission_client: Option<String>,
+}
+
+impl AdditionalInfo {
+    fn duration_secs(&self) -> Option<u64> {
+        self.duration.or(self.duration_ms.map(|ms| (ms + 500) / 1000))
+    }
+
+    /// Clients send the track number as either a number or a string
+    fn track_number(&self) -> Option<u32> {
+        match self.tracknumber.as_ref()? {
+            serde_json::Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+            serde_json::Value::String(s) => s.split('/').next()?.trim().parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ValidateTokenQuery {
+    pub token: Option<String>,
+}
+
+/// ListenBrainz error body
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub code: u16,
+    pub error: String,
+}
+
+fn lb_error(status: StatusCode, error: impl Into<String>) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        status,
+        Json(ErrorResponse {
+            code: status.as_u16(),
+            error: error.into(),
+        }),
+    )
+}
+
+pub async fn submit_listens(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<SubmitListens>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| lb_error(status, "Invalid authorization token."))?;
+
+    match req.listen_type.as_str() {
+        "single" | "playing_now" if req.payload.len() != 1 => {
+            return Err(lb_error(
+                StatusCode::BAD_REQUEST,
+                format!("JSON document must contain exactly one listen for listen_type {}.", req.listen_type),
+            ));
+        }
+        "import" if req.payload.is_empty() || req.payload.len() > MAX_LISTENS_PER_REQUEST => {
+            return Err(lb_error(
+                StatusCode::BAD_REQUEST,
+                format!("JSON document must contain between 1 and {} listens.", MAX_LISTENS_PER_REQUEST),
+            ));
+        }
+        "single" | "playing_now" | "import" => {}
+        _ => {
+            return Err(lb_error(
+                StatusCode::BAD_REQUEST,
+                "listen_type must be one of single, playing_now or import.",
+            ));
+        }
+    }
+
+    let db_error = |e: sqlx::Error| lb_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e));
+
+    if req.listen_type == "playing_now" {
+        let listen = &req.payload[0];
+        let metadata = &listen.track_metadata;
+        let playing = relay::Listen {
+            artist: metadata.artist_name.clone(),
+            track: metadata.track_name.clone(),
+            album: metadata.release_name.clone(),
+            duration: metadata.additional_info.duration_secs().map(|d| d as i64),
+            timestamp: chrono::Utc::now().timestamp(),
+        };
+
+        now_playing_store::record(&pool, user.id, &playing).await.map_err(db_error)?;
+
+        if let Err(e) = relay::enqueue_now_playing(&pool, user.id, &playing).await {
+            tracing::error!("Failed to queue now-playing for relays: {}", e);
+        }
+
+        return Ok(Json(json!({ "status": "ok" })));
+    }
+
+    // ListenBrainz rejects the whole document if any listen is invalid
+    let mut scrobs = Vec::with_capacity(req.payload.len());
+    for (index, listen) in req.payload.into_iter().enumerate() {
+        let listened_at = listen
+            .listened_at
+            .and_then(|ts| u64::try_from(ts).ok())
+            .ok_or_else(|| lb_error(StatusCode::BAD_REQUEST, format!("Listen {} is missing listened_at.", index)))?;
+        let metadata = listen.track_metadata;
+        let info = metadata.additional_info;
+
+        let scrob = ScrobbleRequest {
+            artist: metadata.artist_name,
+            track: metadata.track_name,
+            timestamp: listened_at,
+            album: metadata.release_name,
+            duration: info.duration_secs(),
+            track_number: info.track_number(),
+            album_artist: info.release_artist_name,
+            // Only the first of several credited artists is kept
+            artist_mbid: info.artist_mbids.into_iter().next(),
+            release_mbid: info.release_mbid,
+            recording_mbid: info.recording_mbid,
+            source: Some(info.submission_client.unwrap_or_else(|| "listenbrainz".to_string())),
+        };
+
+        validate_scrobble(&scrob)
+            .map_err(|reason| lb_error(StatusCode::BAD_REQUEST, format!("Listen {}: {}", index, reason)))?;
+        scrobs.push(scrob);
+    }
+
+    for scrob in &scrobs {
+        submit_scrobble(&pool, user.id, scrob).await.map_err(db_error)?;
+    }
+
+    tracing::info!("Accepted {} ListenBrainz listen(s) from user {}", scrobs.len(), user.id);
+
+    Ok(Json(json!({ "status": "ok" })))
+}
+
+pub async fn validate_token(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ValidateTokenQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let token = query.token.or_else(|| {
+        headers
+            .get("authorization")
+            .and_then(|h| h.to_str().ok())
+            .and_then(extract_token_from_header)
+    });
+
+    let Some(token) = token else {
+        return Err(lb_error(StatusCode::BAD_REQUEST, "You need to provide an Authorization token."));
+    };
+
+    let user = get_user_by_token(&pool, &token, Scope::Scrobble)
+        .await
+        .map_err(|e| lb_error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e)))?;
+
+    Ok(Json(match user {
+        Some(user) => json!({
+            "code": 200,
+            "message": "Token valid.",
+            "valid": true,
+            "user_name": user.username,
+        }),
+        None => json!({
+            "code": 200,
+            "message": "Token invalid.",
+            "valid": false,
+        }),
+    }))
+}
@@ -0,0 +1,249 @@
+use axum::{extract::{Path, Query, State}, http::{header, StatusCode}, Json};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{AuthUser, Scope},
+    routes::scrobble::{is_mbid, normalize_mbid},
+};
+
+const MAX_FIELD_LEN: usize = 1024;
+/// JSPF extension namespace used by ListenBrainz for playlist metadata
+const JSPF_PLAYLIST_EXTENSION: &str = "https://musicbrainz.org/doc/jspf#playlist";
+
+#[derive(Debug, Deserialize)]
+pub struct LoveRequest {
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub recording_mbid: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LovedTrack {
+    pub id: i64,
+    pub artist: String,
+    pub track: String,
+    pub album: Option<String>,
+    pub recording_mbid: Option<String>,
+    pub loved_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub async fn list_loved(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<LovedTrack>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let loved = sqlx::query_as!(
+        LovedTrack,
+        r#"
+        SELECT id, artist, track, album, recording_mbid, loved_at
+        FROM loved_tracks
+        WHERE user_id = $1
+        ORDER BY loved_at DESC
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(loved))
+}
+
+/// Love a track. Loving it again keeps the original date but fills in an
+/// album or MBID that wasn't known before.
+pub async fn love_track(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<LoveRequest>,
+) -> Result<(StatusCode, Json<LovedTrack>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    if req.artist.trim().is_empty() || req.track.trim().is_empty() {
+        return Err(bad_request("artist and track are required".to_string()));
+    }
+
+    let too_long = [Some(&req.artist), Some(&req.track), req.album.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|field| field.len() > MAX_FIELD_LEN);
+    if too_long {
+        return Err(bad_request(format!("Artist, track and album must be at most {} bytes", MAX_FIELD_LEN)));
+    }
+
+    let recording_mbid = normalize_mbid(req.recording_mbid.as_deref());
+    if recording_mbid.as_deref().is_some_and(|mbid| !is_mbid(mbid)) {
+        return Err(bad_request("recording_mbid must be a MusicBrainz recording id".to_string()));
+    }
+
+    let loved = sqlx::query_as!(
+        LovedTrack,
+        r#"
+        INSERT INTO loved_tracks (user_id, artist, track, album, recording_mbid, loved_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (user_id, artist, track) DO UPDATE
+        SET album = COALESCE(EXCLUDED.album, loved_tracks.album),
+            recording_mbid = COALESCE(EXCLUDED.recording_mbid, loved_tracks.recording_mbid)
+        RETURNING id, artist, track, album, recording_mbid, loved_at
+        "#,
+        user.id,
+        req.artist,
+        req.track,
+        req.album,
+        recording_mbid,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok((StatusCode::CREATED, Json(loved)))
+}
+
+pub async fn unlove_track(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(loved_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let result = sqlx::query!(
+        "DELETE FROM loved_tracks WHERE id = $1 AND user_id = $2",
+        loved_id,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Loved track not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// GET /export/loved?format=jspf - loved tracks as a JSPF playlist, the
+/// format ListenBrainz imports and exports
+pub async fn export_loved(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ExportQuery>,
+) -> Result<([(header::HeaderName, &'static str); 2], Json<Value>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if query.format.as_deref().is_some_and(|format| format != "jspf") {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Unsupported format (supported: jspf)".to_string(),
+            }),
+        ));
+    }
+
+    let loved = sqlx::query!(
+        r#"
+        SELECT artist, track, album, recording_mbid, loved_at
+        FROM loved_tracks
+        WHERE user_id = $1
+        ORDER BY loved_at DESC
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let tracks: Vec<Value> = loved
+        .into_iter()
+        .map(|row| {
+            let mut track = json!({
+                "title": row.track,
+                "creator": row.artist,
+            });
+            if let Some(album) = row.album {
+                track["album"] = json!(album);
+            }
+            if let Some(mbid) = row.recording_mbid {
+                track["identifier"] = json!([format!("https://musicbrainz.org/recording/{}", mbid)]);
+            }
+            track
+        })
+        .collect();
+
+    let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let playlist = json!({
+        "playlist": {
+            "title": format!("{}'s loved tracks", user.username),
+            "creator": user.username,
+            "date": now,
+            "extension": {
+                JSPF_PLAYLIST_EXTENSION: {
+                    "creator": user.username,
+                    "public": false,
+                }
+            },
+            "track": tracks,
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/jspf+json"),
+            (header::CONTENT_DISPOSITION, "attachment; filename=\"loved.jspf\""),
+        ],
+        Json(playlist),
+    ))
+}
@@ -1,11 +1,77 @@
+pub mod account;
+pub mod activity;
 pub mod admin;
+pub mod announcements;
+pub mod atom;
+pub mod audioscrobbler;
 pub mod auth;
+pub mod badge;
+pub mod charts;
+pub mod compare;
+pub mod discord;
+pub mod edits;
+pub mod email;
+pub mod export;
+pub mod feed;
+pub mod follows;
+pub mod goals;
+pub mod health;
+pub mod import;
+pub mod info;
+pub mod jellyfin;
+pub mod lastfm;
+pub mod library;
+pub mod listenbrainz;
+pub mod loved;
+pub mod mpd;
+pub mod notifications;
+pub mod oidc;
+pub mod overview;
+pub mod relays;
+pub mod rules;
 pub mod scrobble;
+pub mod sessions;
 pub mod settings;
+pub mod spotify;
 pub mod stats;
+pub mod subsonic;
+pub mod tokens;
 
+pub use account::*;
+pub use activity::*;
 pub use admin::*;
+pub use announcements::*;
+pub use atom::*;
+pub use audioscrobbler::*;
 pub use auth::*;
+pub use badge::*;
+pub use charts::*;
+pub use compare::*;
+pub use discord::*;
+pub use edits::*;
+pub use email::*;
+pub use export::*;
+pub use feed::*;
+pub use follows::*;
+pub use goals::*;
+pub use health::*;
+pub use import::*;
+pub use info::*;
+pub use jellyfin::*;
+pub use lastfm::*;
+pub use library::*;
+pub use listenbrainz::*;
+pub use loved::*;
+pub use mpd::*;
+pub use notifications::*;
+pub use oidc::*;
+pub use overview::*;
+pub use relays::*;
+pub use rules::*;
 pub use scrobble::*;
+pub use sessions::*;
 pub use settings::*;
+pub use spotify::*;
 pub use stats::*;
+pub use subsonic::*;
+pub use tokens::*;
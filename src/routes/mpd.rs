@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{auth::AuthUser, config::Config, crypto::SecretBox, net::Outbound};
+
+const DEFAULT_PORT: u16 = 6600;
+const MAX_HOST_LEN: usize = 255;
+
+#[derive(Debug, Serialize)]
+pub struct MpdWatcher {
+    pub host: String,
+    pub port: i32,
+    pub has_password: bool,
+    pub enabled: bool,
+    pub updated_at: i64,
+    pub last_connected_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MpdWatcherRequest {
+    pub host: String,
+    pub port: Option<u16>,
+    pub password: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+async fn authorize(pool: &PgPool, headers: &axum::http::HeaderMap, config: &Config) -> Result<AuthUser, ApiError> {
+    let user = AuthUser::from_headers(pool, headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    if !config.mpd_watcher {
+        return Err(error(StatusCode::SERVICE_UNAVAILABLE, "The MPD watcher is not enabled on this instance"));
+    }
+
+    Ok(user)
+}
+
+/// GET /mpd - the user's watched MPD server and whether scrob can reach it
+pub async fn get_mpd_watcher(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<MpdWatcher>, ApiError> {
+    let user = authorize(&pool, &headers, &config).await?;
+
+    let watcher = sqlx::query_as!(
+        MpdWatcher,
+        r#"
+        SELECT host, port, password IS NOT NULL as "has_password!", enabled, updated_at,
+               last_connected_at, last_error
+        FROM mpd_watchers
+        WHERE user_id = $1
+        "#,
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| error(StatusCode::NOT_FOUND, "No MPD server configured"))?;
+
+    Ok(Json(watcher))
+}
+
+/// PUT /mpd - set the MPD server scrob watches for the user. The watcher
+/// picks up changes within a minute.
+pub async fn put_mpd_watcher(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    Json(req): Json<MpdWatcherRequest>,
+) -> Result<Json<MpdWatcher>, ApiError> {
+    let user = authorize(&pool, &headers, &config).await?;
+
+    let host = req.host.trim();
+    if host.is_empty() || host.len() > MAX_HOST_LEN {
+        return Err(error(StatusCode::BAD_REQUEST, "Host must be 1-255 characters"));
+    }
+
+    let port = req.port.unwrap_or(DEFAULT_PORT);
+    if port == 0 {
+        return Err(error(StatusCode::BAD_REQUEST, "Port must be between 1 and 65535"));
+    }
+
+    // Checked again on each connect, in case the host resolves elsewhere later
+    Outbound::new(&config.allowed_private_networks)
+        .resolve(host, port)
+        .await
+        .map_err(|e| error(StatusCode::BAD_REQUEST, &format!("MPD host not allowed: {}", e)))?;
+
+    let password = match req.password.as_deref().filter(|p| !p.is_empty()) {
+        None => None,
+        Some(password) => {
+            let secrets = secrets.ok_or_else(|| {
+                error(StatusCode::SERVICE_UNAVAILABLE, "SECRET_KEY must be configured to store MPD passwords")
+            })?;
+            Some(secrets.encrypt(password).map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, &e))?)
+        }
+    };
+
+    let watcher = sqlx::query_as!(
+        MpdWatcher,
+        r#"
+        INSERT INTO mpd_watchers (user_id, host, port, password, enabled, updated_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        ON CONFLICT (user_id) DO UPDATE
+        SET host = $2, port = $3, password = $4, enabled = $5, updated_at = $6,
+            last_connected_at = NULL, last_error = NULL
+        RETURNING host, port, password IS NOT NULL as "has_password!", enabled, updated_at,
+                  last_connected_at, last_error
+        "#,
+        user.id,
+        host,
+        port as i32,
+        password,
+        req.enabled.unwrap_or(true),
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    tracing::info!("Set MPD watcher {}:{} for user {}", watcher.host, watcher.port, user.id);
+
+    Ok(Json(watcher))
+}
+
+/// DELETE /mpd - stop watching the user's MPD server
+pub async fn delete_mpd_watcher(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<StatusCode, ApiError> {
+    let user = authorize(&pool, &headers, &config).await?;
+
+    let result = sqlx::query!("DELETE FROM mpd_watchers WHERE user_id = $1", user.id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "No MPD server configured"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
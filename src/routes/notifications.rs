@@ -0,0 +1,121 @@
+use axum::{extract::{Path, Query, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::auth::{AuthUser, Scope};
+
+#[derive(Debug, Deserialize)]
+pub struct NotificationsQuery {
+    #[serde(default)]
+    pub unread: bool,
+    pub limit: Option<i64>,
+}
+
+/// A notification, or an announcement (`kind: "announcement"`, `id` is the
+/// announcement's, marked read through `/announcements/{id}/read`)
+#[derive(Debug, Serialize)]
+pub struct Notification {
+    pub id: i64,
+    pub kind: String,
+    /// Only set for announcements
+    pub title: Option<String>,
+    pub message: String,
+    pub created_at: i64,
+    pub read_at: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+pub async fn notifications(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<NotificationsQuery>,
+) -> Result<Json<Vec<Notification>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(50).min(100);
+    let now = chrono::Utc::now().timestamp();
+
+    // Goal notifications plus the current announcements, read through
+    // `announcement_reads`
+    let notifications = sqlx::query_as!(
+        Notification,
+        r#"
+        SELECT id as "id!", kind as "kind!", title, message as "message!", created_at as "created_at!", read_at
+        FROM (
+            SELECT n.id, n.kind, NULL::TEXT as title, n.message, n.created_at, n.read_at
+            FROM notifications n
+            WHERE n.user_id = $1
+            UNION ALL
+            SELECT a.id, 'announcement', a.title, a.body, a.created_at, r.read_at
+            FROM announcements a
+            LEFT JOIN announcement_reads r ON r.announcement_id = a.id AND r.user_id = $1
+            WHERE a.expires_at IS NULL OR a.expires_at > $4
+        ) feed
+        WHERE NOT $2 OR read_at IS NULL
+        ORDER BY created_at DESC, id DESC
+        LIMIT $3
+        "#,
+        user.id,
+        query.unread,
+        limit,
+        now
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(notifications))
+}
+
+pub async fn mark_notification_read(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(notification_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let result = sqlx::query!(
+        r#"
+        UPDATE notifications
+        SET read_at = COALESCE(read_at, $3)
+        WHERE id = $1 AND user_id = $2
+        "#,
+        notification_id,
+        user.id,
+        now
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Notification not found".to_string(),
+            }),
+        ));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::Redirect,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{active_ban, create_token, Scope},
+    config::Config,
+    oidc::{self, OidcError, OidcSettings},
+    routes::auth::LoginResponse,
+};
+
+#[derive(Debug, Deserialize)]
+pub struct OidcLoginQuery {
+    /// Needed when registration is closed and this login creates the account
+    pub invite_code: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by the provider when the user declined or the login failed
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+fn settings(config: &Config) -> Result<OidcSettings, (StatusCode, Json<ErrorResponse>)> {
+    OidcSettings::from_config(config).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "OIDC login is not configured".to_string(),
+            }),
+        )
+    })
+}
+
+fn oidc_error(e: OidcError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        OidcError::Provider(_) => StatusCode::BAD_GATEWAY,
+        OidcError::Rejected(_) => StatusCode::BAD_REQUEST,
+        OidcError::Forbidden(_) => StatusCode::FORBIDDEN,
+        OidcError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, Json(ErrorResponse { error: e.to_string() }))
+}
+
+/// GET /auth/oidc/login - redirect to the identity provider
+pub async fn oidc_login(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<OidcLoginQuery>,
+) -> Result<Redirect, (StatusCode, Json<ErrorResponse>)> {
+    let settings = settings(&config)?;
+    let url = oidc::start_login(&pool, &settings, query.invite_code.as_deref()).await.map_err(oidc_error)?;
+
+    Ok(Redirect::to(&url))
+}
+
+/// GET /auth/oidc/callback - finish the login and issue a session token,
+/// creating the account on first sign-in
+pub async fn oidc_callback(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Json<LoginResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let settings = settings(&config)?;
+
+    if let Some(error) = query.error {
+        let detail = query.error_description.map(|d| format!(": {}", d)).unwrap_or_default();
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: format!("Login failed ({}){}", error, detail),
+            }),
+        ));
+    }
+
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Missing code or state".to_string(),
+            }),
+        ));
+    };
+
+    let (claims, invite_code) = oidc::finish_login(&pool, &settings, &code, &state).await.map_err(oidc_error)?;
+    let user_id = oidc::find_or_create_user(&pool, &settings, &claims, invite_code.as_deref())
+        .await
+        .map_err(oidc_error)?;
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let user = sqlx::query!(
+        r#"SELECT username, is_admin as "is_admin: bool", anonymized_at FROM users WHERE id = $1"#,
+        user_id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    // Anonymizing unlinks identities; this catches one linked meanwhile
+    if user.anonymized_at.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "This account has been anonymized".to_string(),
+            }),
+        ));
+    }
+
+    if let Some(ban) = active_ban(&pool, user_id).await.map_err(db_error)? {
+        return Err((StatusCode::FORBIDDEN, Json(ErrorResponse { error: ban.message() })));
+    }
+
+    let token = create_token(&pool, user_id, "oidc", &Scope::ALL, None)
+        .await
+        .map_err(db_error)?
+        .token;
+
+    tracing::info!("User {} signed in via OIDC", user_id);
+
+    Ok(Json(LoginResponse {
+        token,
+        username: user.username,
+        is_admin: user.is_admin,
+    }))
+}
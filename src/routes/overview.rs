@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::{config::Config, policy::ContentPolicy};
+
+/// Number of recent public scrobbles shown on the landing page
+const RECENT_ACTIVITY_LIMIT: i64 = 10;
+
+#[derive(Debug, Serialize)]
+pub struct Overview {
+    pub name: String,
+    pub description: Option<String>,
+    pub total_listens: i64,
+    pub total_users: i64,
+    pub registration_open: bool,
+    pub recent_activity: Vec<PublicActivity>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PublicActivity {
+    pub username: String,
+    pub artist: String,
+    pub track: String,
+    pub timestamp: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// Unauthenticated landing page data, only served when PUBLIC_OVERVIEW is set
+pub async fn overview(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+) -> Result<Json<Overview>, (StatusCode, Json<ErrorResponse>)> {
+    if !config.public_overview {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Overview is not enabled on this instance".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let total_listens = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM scrobs"#)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+
+    let total_users = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM users WHERE anonymized_at IS NULL"#
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let registration_open = ContentPolicy::load(&pool).await.map_err(db_error)?.registration_open;
+
+    // Only users with public profiles appear in recent activity
+    let recent_activity = sqlx::query_as!(
+        PublicActivity,
+        r#"
+        SELECT u.username, s.artist, s.track, s.timestamp as "timestamp!"
+        FROM scrobs s
+        JOIN users u ON u.id = s.user_id
+        WHERE u.is_private = false AND u.anonymized_at IS NULL
+        ORDER BY s.timestamp DESC
+        LIMIT $1
+        "#,
+        RECENT_ACTIVITY_LIMIT
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(Overview {
+        name: config.instance_name.clone(),
+        description: config.instance_description.clone(),
+        total_listens,
+        total_users,
+        registration_open,
+        recent_activity,
+    }))
+}
@@ -0,0 +1,553 @@
+use std::sync::Arc;
+
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use super::admin::nullable;
+use crate::{auth::AuthUser, config::Config, crypto::SecretBox, net::Outbound, relay::{self, LastfmApp}};
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+const RELAY_KINDS: [&str; 3] = ["lastfm", "listenbrainz", "webhook"];
+
+#[derive(Debug, Serialize)]
+pub struct Relay {
+    pub id: i64,
+    pub kind: String,
+    pub label: Option<String>,
+    pub url: Option<String>,
+    pub has_secret: bool,
+    pub include_now_playing: bool,
+    pub enabled: bool,
+    pub created_at: i64,
+    pub last_success_at: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRelayRequest {
+    pub kind: String,
+    pub label: Option<String>,
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub include_now_playing: bool,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRelayRequest {
+    // For these, `null` clears the value and a missing field leaves it
+    #[serde(default, deserialize_with = "nullable")]
+    pub label: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub url: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    pub secret: Option<Option<String>>,
+    pub include_now_playing: Option<bool>,
+    pub enabled: Option<bool>,
+}
+
+/// Relay URLs must be http(s) and resolve to public addresses (or
+/// `ALLOWED_PRIVATE_NETWORKS`), since the server posts to them
+async fn validate_url(config: &Config, url: &str) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    Outbound::new(&config.allowed_private_networks)
+        .check_url(url)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("Relay URL not allowed: {}", e),
+                }),
+            )
+        })
+}
+
+fn encrypt_secret(
+    secrets: Option<&SecretBox>,
+    secret: Option<&str>,
+) -> Result<Option<String>, (StatusCode, Json<ErrorResponse>)> {
+    let Some(secret) = secret else {
+        return Ok(None);
+    };
+
+    let secrets = secrets.ok_or_else(|| {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "SECRET_KEY must be configured to store relay secrets".to_string(),
+            }),
+        )
+    })?;
+
+    secrets.encrypt(secret).map(Some).map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: e }),
+        )
+    })
+}
+
+pub async fn list_relays(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<Relay>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let relays = sqlx::query_as!(
+        Relay,
+        r#"
+        SELECT
+            id as "id!",
+            kind,
+            label,
+            url,
+            secret IS NOT NULL as "has_secret!",
+            include_now_playing as "include_now_playing: bool",
+            enabled as "enabled: bool",
+            created_at as "created_at!",
+            last_success_at
+        FROM relays
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(relays))
+}
+
+pub async fn create_relay(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    State(config): State<Arc<Config>>,
+    Json(req): Json<CreateRelayRequest>,
+) -> Result<(StatusCode, Json<Relay>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    if !RELAY_KINDS.contains(&req.kind.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Relay kind must be one of: {}", RELAY_KINDS.join(", ")),
+            }),
+        ));
+    }
+
+    if req.kind == "webhook" && req.url.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Webhook relays require a url".to_string(),
+            }),
+        ));
+    }
+
+    if req.kind == "listenbrainz" && req.secret.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "ListenBrainz relays require a user token as secret".to_string(),
+            }),
+        ));
+    }
+
+    if req.kind == "lastfm" {
+        if LastfmApp::from_config(&config).is_none() {
+            return Err(lastfm_disabled());
+        }
+
+        if req.secret.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "Last.fm relays require a session key as secret".to_string(),
+                }),
+            ));
+        }
+    }
+
+    if let Some(url) = &req.url {
+        validate_url(&config, url).await?;
+    }
+
+    let secret = encrypt_secret(secrets.as_deref(), req.secret.as_deref())?;
+    let now = chrono::Utc::now().timestamp();
+
+    let relay = sqlx::query_as!(
+        Relay,
+        r#"
+        INSERT INTO relays (user_id, kind, label, url, secret, include_now_playing, enabled, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING
+            id as "id!",
+            kind,
+            label,
+            url,
+            secret IS NOT NULL as "has_secret!",
+            include_now_playing as "include_now_playing: bool",
+            enabled as "enabled: bool",
+            created_at as "created_at!",
+            last_success_at
+        "#,
+        user.id,
+        req.kind,
+        req.label,
+        req.url,
+        secret,
+        req.include_now_playing,
+        req.enabled.unwrap_or(true),
+        now
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Created {} relay {} for user {}", relay.kind, relay.id, user.id);
+
+    Ok((StatusCode::CREATED, Json(relay)))
+}
+
+fn lastfm_disabled() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(ErrorResponse {
+            error: "Last.fm relays are not enabled on this instance".to_string(),
+        }),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LastfmSessionRequest {
+    pub username: String,
+    pub password: String,
+    /// API root of a Last.fm-compatible service, as on the relay
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LastfmSessionResponse {
+    pub session_key: String,
+}
+
+/// POST /relays/lastfm/session - trade Last.fm credentials for the session
+/// key a Last.fm relay is created with. Nothing is stored.
+pub async fn lastfm_relay_session(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Json(req): Json<LastfmSessionRequest>,
+) -> Result<Json<LastfmSessionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let app = LastfmApp::from_config(&config).ok_or_else(lastfm_disabled)?;
+
+    if let Some(url) = &req.url {
+        validate_url(&config, url).await?;
+    }
+
+    let outbound = Outbound::new(&config.allowed_private_networks);
+    let session_key = relay::lastfm_session(&app, &outbound, req.url.as_deref(), &req.username, &req.password)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, Json(ErrorResponse { error: e })))?;
+
+    Ok(Json(LastfmSessionResponse { session_key }))
+}
+
+pub async fn update_relay(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    State(config): State<Arc<Config>>,
+    Path(relay_id): Path<i64>,
+    Json(req): Json<UpdateRelayRequest>,
+) -> Result<Json<Relay>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let kind = sqlx::query_scalar!("SELECT kind FROM relays WHERE id = $1 AND user_id = $2", relay_id, user.id)
+        .fetch_optional(&pool)
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Relay not found".to_string() })))?;
+
+    // Clearing a field the relay can't deliver without is refused, as on create
+    if kind == "webhook" && req.url == Some(None) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: "Webhook relays require a url".to_string() })));
+    }
+    let secret_required = match kind.as_str() {
+        "listenbrainz" => Some("ListenBrainz relays require a user token as secret"),
+        "lastfm" => Some("Last.fm relays require a session key as secret"),
+        _ => None,
+    };
+    if let (Some(error), Some(None)) = (secret_required, &req.secret) {
+        return Err((StatusCode::BAD_REQUEST, Json(ErrorResponse { error: error.to_string() })));
+    }
+
+    if let Some(Some(url)) = &req.url {
+        validate_url(&config, url).await?;
+    }
+
+    let secret = encrypt_secret(secrets.as_deref(), req.secret.clone().flatten().as_deref())?;
+
+    let relay = sqlx::query_as!(
+        Relay,
+        r#"
+        UPDATE relays
+        SET
+            label = CASE WHEN $1 THEN $2 ELSE label END,
+            url = CASE WHEN $3 THEN $4 ELSE url END,
+            secret = CASE WHEN $5 THEN $6 ELSE secret END,
+            include_now_playing = COALESCE($7, include_now_playing),
+            enabled = COALESCE($8, enabled)
+        WHERE id = $9 AND user_id = $10
+        RETURNING
+            id as "id!",
+            kind,
+            label,
+            url,
+            secret IS NOT NULL as "has_secret!",
+            include_now_playing as "include_now_playing: bool",
+            enabled as "enabled: bool",
+            created_at as "created_at!",
+            last_success_at
+        "#,
+        req.label.is_some(),
+        req.label.flatten(),
+        req.url.is_some(),
+        req.url.flatten(),
+        req.secret.is_some(),
+        secret,
+        req.include_now_playing,
+        req.enabled,
+        relay_id,
+        user.id
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?
+    .ok_or_else(|| (StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Relay not found".to_string() })))?;
+
+    Ok(Json(relay))
+}
+
+pub async fn delete_relay(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(relay_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let result = sqlx::query!(
+        "DELETE FROM relays WHERE id = $1 AND user_id = $2",
+        relay_id,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if result.rows_affected() == 0 {
+        return Err((StatusCode::NOT_FOUND, Json(ErrorResponse { error: "Relay not found".to_string() })));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelayError {
+    pub message: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RelayStatus {
+    pub id: i64,
+    pub kind: String,
+    pub label: Option<String>,
+    pub enabled: bool,
+    pub last_success_at: Option<i64>,
+    pub queued: i64,
+    pub dead_lettered: i64,
+    pub recent_errors: Vec<RelayError>,
+}
+
+pub async fn relay_status(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<RelayStatus>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let relays = sqlx::query!(
+        r#"
+        SELECT
+            r.id as "id!",
+            r.kind,
+            r.label,
+            r.enabled as "enabled: bool",
+            r.last_success_at,
+            (SELECT COUNT(*) FROM relay_queue q WHERE q.relay_id = r.id AND q.status = 'pending') as "queued!",
+            (SELECT COUNT(*) FROM relay_queue q WHERE q.relay_id = r.id AND q.status = 'dead') as "dead_lettered!"
+        FROM relays r
+        WHERE r.user_id = $1
+        ORDER BY r.id
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let errors = sqlx::query!(
+        r#"
+        SELECT relay_id as "relay_id!", message as "message!", created_at as "created_at!"
+        FROM (
+            SELECT
+                e.relay_id,
+                e.message,
+                e.created_at,
+                ROW_NUMBER() OVER (PARTITION BY e.relay_id ORDER BY e.created_at DESC, e.id DESC) as rn
+            FROM relay_errors e
+            JOIN relays r ON r.id = e.relay_id
+            WHERE r.user_id = $1
+        ) recent
+        WHERE rn <= 5
+        ORDER BY relay_id, created_at DESC
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(relays.into_iter().map(|r| RelayStatus {
+        id: r.id,
+        kind: r.kind,
+        label: r.label,
+        enabled: r.enabled,
+        last_success_at: r.last_success_at,
+        queued: r.queued,
+        dead_lettered: r.dead_lettered,
+        recent_errors: errors
+            .iter()
+            .filter(|e| e.relay_id == r.id)
+            .map(|e| RelayError {
+                message: e.message.clone(),
+                created_at: e.created_at,
+            })
+            .collect(),
+    }).collect()))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct RetryRequest {
+    pub relay_id: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RetryResponse {
+    pub requeued: u64,
+}
+
+pub async fn retry_relays(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    body: Option<Json<RetryRequest>>,
+) -> Result<Json<RetryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let req = body.map(|Json(req)| req).unwrap_or_default();
+
+    // Re-drive dead-lettered listens, optionally for a single relay
+    let result = sqlx::query!(
+        r#"
+        UPDATE relay_queue q
+        SET status = 'pending', attempts = 0, next_attempt_at = 0
+        FROM relays r
+        WHERE r.id = q.relay_id
+          AND r.user_id = $1
+          AND ($2::BIGINT IS NULL OR r.id = $2)
+          AND q.status = 'dead'
+        "#,
+        user.id,
+        req.relay_id
+    )
+    .execute(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    tracing::info!("Requeued {} dead-lettered relay item(s) for user {}", result.rows_affected(), user.id);
+
+    Ok(Json(RetryResponse {
+        requeued: result.rows_affected(),
+    }))
+}
@@ -0,0 +1,271 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::AuthUser,
+    routes::scrobble::MAX_FIELD_LEN,
+    rules::{Matcher, RULE_ACTIONS, RULE_FIELDS},
+};
+
+/// Most rules a user can have, since all of them run on every scrobble
+const MAX_RULES: i64 = 100;
+
+#[derive(Debug, Serialize)]
+pub struct ScrobbleRule {
+    pub id: i64,
+    pub field: String,
+    pub match_type: String,
+    pub pattern: String,
+    pub action: String,
+    pub replacement: Option<String>,
+    pub enabled: bool,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateRuleRequest {
+    /// `artist`, `track` or `album`
+    pub field: String,
+    /// `exact` or `regex`
+    pub match_type: String,
+    pub pattern: String,
+    /// `rewrite` or `block`
+    pub action: String,
+    /// What a rewrite puts in place of the match; `$1` etc. refer to regex groups
+    pub replacement: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateRuleRequest {
+    pub field: Option<String>,
+    pub match_type: Option<String>,
+    pub pattern: Option<String>,
+    pub action: Option<String>,
+    pub replacement: Option<String>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, message: impl Into<String>) -> ApiError {
+    (status, Json(ErrorResponse { error: message.into() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, format!("Database error: {}", e))
+}
+
+fn not_found() -> ApiError {
+    error(StatusCode::NOT_FOUND, "Rule not found")
+}
+
+/// Check a rule as a whole; returns the replacement to store
+fn validate_rule(
+    field: &str,
+    match_type: &str,
+    pattern: &str,
+    action: &str,
+    replacement: Option<String>,
+) -> Result<Option<String>, ApiError> {
+    if !RULE_FIELDS.contains(&field) {
+        return Err(error(
+            StatusCode::BAD_REQUEST,
+            format!("field must be one of: {}", RULE_FIELDS.join(", ")),
+        ));
+    }
+
+    Matcher::compile(match_type, pattern).map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+
+    match action {
+        "rewrite" => {
+            let replacement = replacement
+                .ok_or_else(|| error(StatusCode::BAD_REQUEST, "A rewrite rule needs a replacement"))?;
+            if replacement.len() > MAX_FIELD_LEN {
+                return Err(error(
+                    StatusCode::BAD_REQUEST,
+                    format!("replacement must be at most {} bytes", MAX_FIELD_LEN),
+                ));
+            }
+            Ok(Some(replacement))
+        }
+        "block" => Ok(None),
+        _ => Err(error(
+            StatusCode::BAD_REQUEST,
+            format!("action must be one of: {}", RULE_ACTIONS.join(", ")),
+        )),
+    }
+}
+
+/// GET /rules - the user's scrobble rules in the order they apply
+pub async fn list_rules(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<ScrobbleRule>>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let rules = sqlx::query_as!(
+        ScrobbleRule,
+        r#"
+        SELECT id, field, match_type, pattern, action, replacement, enabled, created_at
+        FROM scrobble_rules
+        WHERE user_id = $1
+        ORDER BY id
+        "#,
+        user.id
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(Json(rules))
+}
+
+/// POST /rules - add a rule; it applies after the existing ones
+pub async fn create_rule(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<CreateRuleRequest>,
+) -> Result<(StatusCode, Json<ScrobbleRule>), ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let replacement = validate_rule(&req.field, &req.match_type, &req.pattern, &req.action, req.replacement)?;
+
+    let count = sqlx::query_scalar!(
+        r#"SELECT COUNT(*) as "count!" FROM scrobble_rules WHERE user_id = $1"#,
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if count >= MAX_RULES {
+        return Err(error(
+            StatusCode::CONFLICT,
+            format!("You can have at most {} scrobble rules", MAX_RULES),
+        ));
+    }
+
+    let rule = sqlx::query_as!(
+        ScrobbleRule,
+        r#"
+        INSERT INTO scrobble_rules (user_id, field, match_type, pattern, action, replacement, enabled, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+        RETURNING id, field, match_type, pattern, action, replacement, enabled, created_at
+        "#,
+        user.id,
+        req.field,
+        req.match_type,
+        req.pattern,
+        req.action,
+        replacement,
+        req.enabled.unwrap_or(true),
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    tracing::info!("Created scrobble rule {} for user {}", rule.id, user.id);
+
+    Ok((StatusCode::CREATED, Json(rule)))
+}
+
+/// PATCH /rules/{id} - change any part of a rule
+pub async fn update_rule(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(rule_id): Path<i64>,
+    Json(req): Json<UpdateRuleRequest>,
+) -> Result<Json<ScrobbleRule>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let current = sqlx::query_as!(
+        ScrobbleRule,
+        r#"
+        SELECT id, field, match_type, pattern, action, replacement, enabled, created_at
+        FROM scrobble_rules
+        WHERE id = $1 AND user_id = $2
+        FOR UPDATE
+        "#,
+        rule_id,
+        user.id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(not_found)?;
+
+    // The merged rule is checked as a whole, e.g. switching to a rewrite
+    // needs a replacement
+    let field = req.field.unwrap_or(current.field);
+    let match_type = req.match_type.unwrap_or(current.match_type);
+    let pattern = req.pattern.unwrap_or(current.pattern);
+    let action = req.action.unwrap_or(current.action);
+    let replacement = validate_rule(&field, &match_type, &pattern, &action, req.replacement.or(current.replacement))?;
+
+    let rule = sqlx::query_as!(
+        ScrobbleRule,
+        r#"
+        UPDATE scrobble_rules
+        SET field = $3, match_type = $4, pattern = $5, action = $6, replacement = $7, enabled = $8
+        WHERE id = $1 AND user_id = $2
+        RETURNING id, field, match_type, pattern, action, replacement, enabled, created_at
+        "#,
+        rule_id,
+        user.id,
+        field,
+        match_type,
+        pattern,
+        action,
+        replacement,
+        req.enabled.unwrap_or(current.enabled)
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(Json(rule))
+}
+
+/// DELETE /rules/{id}
+pub async fn delete_rule(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(rule_id): Path<i64>,
+) -> Result<StatusCode, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let result = sqlx::query!(
+        "DELETE FROM scrobble_rules WHERE id = $1 AND user_id = $2",
+        rule_id,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(not_found());
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
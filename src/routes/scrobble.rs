@@ -1,20 +1,41 @@
-use axum::{extract::State, http::StatusCode, Json};
-use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{Form, FromRequest, Query, Request, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use sqlx::PgPool;
 
-use crate::auth::AuthUser;
+use crate::{
+    auth::{extract_token_from_header, AuthUser, Scope},
+    config::Config,
+    db,
+    feed::{self, FeedEventKind},
+    now_playing as now_playing_store, relay, rules,
+    runtime::RuntimeSettings,
+    spool::Spool,
+};
 
 #[derive(Debug, Deserialize)]
 pub struct NowPlayingRequest {
     pub artist: String,
     pub track: String,
     pub album: Option<String>,
+    #[allow(dead_code)]
     pub album_artist: Option<String>,
     pub duration: Option<u64>,
+    #[allow(dead_code)]
     pub track_number: Option<u32>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ScrobbleRequest {
     pub artist: String,
     pub track: String,
@@ -23,14 +44,75 @@ pub struct ScrobbleRequest {
     pub album_artist: Option<String>,
     pub duration: Option<u64>,
     pub track_number: Option<u32>,
+    /// MusicBrainz ids, matched before the names when grouping stats
+    #[serde(default)]
+    pub artist_mbid: Option<String>,
+    #[serde(default)]
+    pub release_mbid: Option<String>,
+    #[serde(default)]
+    pub recording_mbid: Option<String>,
+    /// The client or integration that submitted it; sent as `client` on
+    /// `/scrob`, or in the `X-Scrob-Client` header for the whole batch
+    #[serde(default, alias = "client")]
+    pub source: Option<String>,
+}
+
+/// Allowed clock skew for scrobbles timestamped in the future
+const MAX_FUTURE_SKEW_SECS: i64 = 5 * 60;
+const MAX_DURATION_SECS: u64 = 24 * 60 * 60;
+pub const MAX_FIELD_LEN: usize = 1024;
+/// Longer source names are cut off rather than rejected
+const MAX_SOURCE_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScrobbleStatus {
+    Accepted,
+    IgnoredDuplicate,
+    Rejected,
+    /// Accepted while the database was unavailable; stored once it's back
+    Queued,
 }
 
 #[derive(Debug, Serialize)]
 pub struct ScrobbleResponse {
-    pub id: i64,
-    pub artist: String,
-    pub track: String,
-    pub timestamp: i64,
+    pub index: usize,
+    pub status: ScrobbleStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub artist: Option<String>,
+    pub track: Option<String>,
+    pub timestamp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ScrobbleQuery {
+    /// Store the first `SCROBBLE_MAX_BATCH` items of a larger batch and reject
+    /// the rest, instead of refusing the whole request
+    #[serde(default)]
+    pub partial: bool,
+}
+
+/// Error body for a submission over `SCROBBLE_MAX_BATCH` or
+/// `SCROBBLE_MAX_BODY_BYTES`
+#[derive(Debug, Serialize)]
+pub struct BatchLimitError {
+    pub error: String,
+    /// `batch_too_large` or `payload_too_large`
+    pub code: &'static str,
+    pub limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub received: Option<usize>,
+}
+
+/// Result of submitting a single scrobble
+#[derive(Debug)]
+pub enum ScrobbleOutcome {
+    Accepted(i64),
+    Duplicate,
+    Rejected(String),
 }
 
 #[derive(Debug, Serialize)]
@@ -38,14 +120,136 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+/// A `/scrob` or `/now` body: JSON, or `application/x-www-form-urlencoded`
+/// as legacy scrobblers send it, with `artist[0]`, `track[0]`, ... fields
+pub struct ScrobbleBody<T>(pub T);
+
+/// How form items map onto the JSON shape a handler takes
+pub trait FormShape {
+    fn from_form(items: Vec<Value>) -> Value;
+}
+
+impl FormShape for Vec<Value> {
+    fn from_form(items: Vec<Value>) -> Value {
+        Value::Array(items)
+    }
+}
+
+impl FormShape for NowPlayingRequest {
+    fn from_form(items: Vec<Value>) -> Value {
+        items.into_iter().next().unwrap_or_else(|| Value::Object(Default::default()))
+    }
+}
+
+/// Why a `ScrobbleBody` couldn't be read, with the status axum gave it
+pub struct BodyRejection {
+    pub status: StatusCode,
+    pub error: String,
+}
+
+impl IntoResponse for BodyRejection {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorResponse { error: self.error })).into_response()
+    }
+}
+
+impl<S, T> FromRequest<S> for ScrobbleBody<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned + FormShape,
+{
+    type Rejection = BodyRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_form = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.to_ascii_lowercase().starts_with("application/x-www-form-urlencoded"));
+
+        if !is_form {
+            return match Json::<T>::from_request(req, state).await {
+                Ok(Json(body)) => Ok(ScrobbleBody(body)),
+                Err(rejection) if rejection.status() == StatusCode::UNSUPPORTED_MEDIA_TYPE => Err(BodyRejection {
+                    status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    error: "Content-Type must be application/json or application/x-www-form-urlencoded".to_string(),
+                }),
+                Err(rejection) => Err(BodyRejection {
+                    status: rejection.status(),
+                    error: rejection.body_text(),
+                }),
+            };
+        }
+
+        let Form(pairs) = Form::<Vec<(String, String)>>::from_request(req, state)
+            .await
+            .map_err(|rejection| BodyRejection {
+                status: rejection.status(),
+                error: rejection.body_text(),
+            })?;
+
+        serde_json::from_value(T::from_form(form_items(pairs)))
+            .map(ScrobbleBody)
+            .map_err(|e| BodyRejection {
+                status: StatusCode::UNPROCESSABLE_ENTITY,
+                error: format!("Invalid form body: {}", e),
+            })
+    }
+}
+
+/// Scrobble fields read from a form body, by their JSON names
+const FORM_FIELDS: &[&str] = &[
+    "artist", "track", "timestamp", "album", "album_artist", "duration", "track_number",
+    "artist_mbid", "release_mbid", "recording_mbid", "source", "client",
+];
+
+/// Group `name[i]` (or unindexed `name`) form fields into one JSON object
+/// per index, in index order. Names are the JSON ones, with the Last.fm
+/// spellings `albumArtist`, `trackNumber` and `mbid` accepted too. Numeric
+/// fields become numbers when they parse, so a bad one is rejected with its
+/// item rather than failing the whole form.
+fn form_items(pairs: Vec<(String, String)>) -> Vec<Value> {
+    let mut items: BTreeMap<usize, serde_json::Map<String, Value>> = BTreeMap::new();
+
+    for (key, value) in pairs {
+        let (name, index) = match key.strip_suffix(']').and_then(|k| k.split_once('[')) {
+            Some((name, index)) => match index.parse() {
+                Ok(index) => (name, index),
+                Err(_) => continue,
+            },
+            None => (key.as_str(), 0),
+        };
+        if value.is_empty() {
+            continue;
+        }
+
+        let name = match name {
+            "albumArtist" => "album_artist",
+            "trackNumber" => "track_number",
+            "mbid" => "recording_mbid",
+            name => name,
+        };
+        // Client credentials and signatures (`api_key`, `sk`, ...) aren't items
+        if !FORM_FIELDS.contains(&name) {
+            continue;
+        }
+        let value = match name {
+            "timestamp" | "duration" | "track_number" => value.parse::<u64>().map(Value::from).unwrap_or(Value::String(value)),
+            _ => Value::String(value),
+        };
+        items.entry(index).or_default().insert(name.to_string(), value);
+    }
+
+    items.into_values().map(Value::Object).collect()
+}
+
 pub async fn now_playing(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
-    Json(req): Json<NowPlayingRequest>,
+    ScrobbleBody(req): ScrobbleBody<NowPlayingRequest>,
 ) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
-    let user = AuthUser::from_headers(&pool, &headers).await
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
-    // For now-playing, we just log it - we don't store it
     tracing::info!(
         "Now playing for user {}: {} - {}",
         user.id,
@@ -53,41 +257,38 @@ pub async fn now_playing(
         req.track
     );
 
+    let listen = relay::Listen {
+        artist: req.artist,
+        track: req.track,
+        album: req.album,
+        duration: req.duration.map(|d| d as i64),
+        timestamp: chrono::Utc::now().timestamp(),
+    };
+
+    now_playing_store::record(&pool, user.id, &listen).await.map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    if let Err(e) = relay::enqueue_now_playing(&pool, user.id, &listen).await {
+        tracing::error!("Failed to queue now-playing for relays: {}", e);
+    }
+
     Ok(StatusCode::OK)
 }
 
-pub async fn scrobble(
+pub async fn get_now_playing(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
-    Json(scrobbles): Json<Vec<ScrobbleRequest>>,
-) -> Result<Json<Vec<ScrobbleResponse>>, (StatusCode, Json<ErrorResponse>)> {
-    let user = AuthUser::from_headers(&pool, &headers).await
+) -> Result<Json<Option<now_playing_store::CurrentTrack>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
 
-    tracing::info!("Received {} scrobble(s) from user {}", scrobbles.len(), user.id);
-
-    let mut results = Vec::new();
-
-    for scrob in scrobbles {
-        let now = chrono::Utc::now().timestamp();
-        let timestamp = scrob.timestamp as i64;
-        let duration = scrob.duration.map(|d| d as i64);
-
-        let result = sqlx::query!(
-            r#"
-            INSERT INTO scrobs (user_id, artist, track, album, duration, timestamp, created_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING id
-            "#,
-            user.id,
-            scrob.artist,
-            scrob.track,
-            scrob.album,
-            duration,
-            timestamp,
-            now
-        )
-        .fetch_one(&pool)
+    let current = now_playing_store::current(&pool, user.id, chrono::Utc::now().timestamp())
         .await
         .map_err(|e| {
             (
@@ -98,23 +299,416 @@ pub async fn scrobble(
             )
         })?;
 
-        let scrob_id = result.id;
+    Ok(Json(current))
+}
+
+/// Submit a batch of scrobbles. If the database is unreachable, validated
+/// scrobbles are spooled to disk and the response is 202 with `queued` items.
+pub async fn scrobble(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(spool): State<Arc<Spool>>,
+    State(config): State<Arc<Config>>,
+    State(runtime): State<RuntimeSettings>,
+    Query(query): Query<ScrobbleQuery>,
+    payload: Result<ScrobbleBody<Vec<Value>>, BodyRejection>,
+) -> Result<(StatusCode, Json<Vec<ScrobbleResponse>>), Response> {
+    let ScrobbleBody(mut items) = payload.map_err(|rejection| {
+        if rejection.status == StatusCode::PAYLOAD_TOO_LARGE {
+            let limit = config.scrobble_max_body_bytes;
+            return (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                Json(BatchLimitError {
+                    error: format!("Request body must be at most {} bytes", limit),
+                    code: "payload_too_large",
+                    limit,
+                    received: None,
+                }),
+            )
+                .into_response();
+        }
+        rejection.into_response()
+    })?;
+
+    let token = headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(extract_token_from_header);
+
+    let client = normalize_source(headers.get("x-scrob-client").and_then(|h| h.to_str().ok()));
+
+    // With the database down the token can't be checked yet; it is
+    // resolved when the spool is replayed
+    let user_id = match AuthUser::from_headers_with_scope(&pool, &headers, Scope::Scrobble).await {
+        Ok(user) => Some(user.id),
+        Err(StatusCode::SERVICE_UNAVAILABLE) if spool.enabled() && token.is_some() => None,
+        Err(status) => {
+            return Err((status, Json(ErrorResponse { error: "Unauthorized".to_string() })).into_response());
+        }
+    };
+
+    match user_id {
+        Some(user_id) => tracing::info!("Received {} scrobble(s) from user {}", items.len(), user_id),
+        None => tracing::warn!("Database unavailable; spooling {} scrobble(s)", items.len()),
+    }
+
+    let limit = runtime.limits().scrobble_max_batch;
+    let received = items.len();
+    if received > limit && !query.partial {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(BatchLimitError {
+                error: format!("At most {} scrobbles can be submitted at once", limit),
+                code: "batch_too_large",
+                limit,
+                received: Some(received),
+            }),
+        )
+            .into_response());
+    }
+    items.truncate(limit);
+
+    // In partial mode everything past the limit is turned away
+    let mut results: Vec<ScrobbleResponse> = (items.len()..received)
+        .map(|index| ScrobbleResponse {
+            index,
+            status: ScrobbleStatus::Rejected,
+            id: None,
+            artist: None,
+            track: None,
+            timestamp: None,
+            reason: Some(format!("Over the batch limit of {}; submit it again separately", limit)),
+        })
+        .collect();
+    let mut indices = Vec::with_capacity(items.len());
+    let mut valid = Vec::with_capacity(items.len());
+
+    for (index, item) in items.into_iter().enumerate() {
+        // Parse each item separately so one malformed entry doesn't fail the batch
+        let mut scrob = match serde_json::from_value::<ScrobbleRequest>(item) {
+            Ok(scrob) => scrob,
+            Err(e) => {
+                results.push(ScrobbleResponse {
+                    index,
+                    status: ScrobbleStatus::Rejected,
+                    id: None,
+                    artist: None,
+                    track: None,
+                    timestamp: None,
+                    reason: Some(format!("Invalid scrobble: {}", e)),
+                });
+                continue;
+            }
+        };
+
+        if scrob.source.is_none() {
+            scrob.source = client.clone();
+        }
+
+        match validate_scrobble(&scrob) {
+            Ok(()) => {
+                indices.push(index);
+                valid.push(scrob);
+            }
+            Err(reason) => results.push(item_response(index, &scrob, ScrobbleStatus::Rejected, None, Some(reason))),
+        }
+    }
+
+    // The valid items are stored together, so a failure leaves none of
+    // them behind and the whole batch can go to the spool. Responses echo
+    // the items as the user's rules rewrote them; the spool keeps them as
+    // submitted, since replay applies the rules again.
+    let mut rewritten = valid.clone();
+    let queued = match user_id {
+        Some(user_id) => match insert_scrobbles(&pool, user_id, &mut rewritten).await {
+            Ok(outcomes) => {
+                for ((index, scrob), outcome) in indices.iter().zip(&rewritten).zip(outcomes) {
+                    let (status, id, reason) = match outcome {
+                        ScrobbleOutcome::Accepted(id) => (ScrobbleStatus::Accepted, Some(id), None),
+                        ScrobbleOutcome::Duplicate => (ScrobbleStatus::IgnoredDuplicate, None, None),
+                        ScrobbleOutcome::Rejected(reason) => (ScrobbleStatus::Rejected, None, Some(reason)),
+                    };
+                    results.push(item_response(*index, scrob, status, id, reason));
+                }
+                false
+            }
+            Err(e) if spool.enabled() && token.is_some() && db::is_unavailable(&e) => {
+                tracing::warn!("Database unavailable; spooling the batch: {}", e);
+                true
+            }
+            Err(e) => {
+                return Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Database error: {}", e),
+                    }),
+                )
+                    .into_response());
+            }
+        },
+        None => true,
+    };
+
+    let (Some(token), true, false) = (token, queued, valid.is_empty()) else {
+        results.sort_by_key(|result| result.index);
+        return Ok((StatusCode::OK, Json(results)));
+    };
+
+    for (index, scrob) in indices.iter().zip(&valid) {
+        results.push(item_response(*index, scrob, ScrobbleStatus::Queued, None, None));
+    }
+    results.sort_by_key(|result| result.index);
+
+    spool.push(&token, valid).await.map_err(|e| {
+        tracing::error!("Failed to spool scrobbles: {}", e);
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(ErrorResponse {
+                error: "Database is unavailable, please retry later".to_string(),
+            }),
+        )
+            .into_response()
+    })?;
+
+    Ok((StatusCode::ACCEPTED, Json(results)))
+}
+
+fn item_response(
+    index: usize,
+    scrob: &ScrobbleRequest,
+    status: ScrobbleStatus,
+    id: Option<i64>,
+    reason: Option<String>,
+) -> ScrobbleResponse {
+    ScrobbleResponse {
+        index,
+        status,
+        id,
+        artist: Some(scrob.artist.clone()),
+        track: Some(scrob.track.clone()),
+        timestamp: i64::try_from(scrob.timestamp).ok(),
+        reason,
+    }
+}
+
+/// MusicBrainz ids are lowercase hyphenated UUIDs
+pub fn is_mbid(value: &str) -> bool {
+    value.len() == 36
+        && value.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_hexdigit(),
+        })
+}
+
+/// Trim and lowercase a submitted MusicBrainz id, treating blank as absent
+pub fn normalize_mbid(value: Option<&str>) -> Option<String> {
+    value.map(|mbid| mbid.trim().to_ascii_lowercase()).filter(|mbid| !mbid.is_empty())
+}
+
+/// Trim a client name and cap its length, treating blank as absent
+pub fn normalize_source(value: Option<&str>) -> Option<String> {
+    value
+        .map(|source| source.trim().chars().filter(|c| !c.is_control()).take(MAX_SOURCE_LEN).collect::<String>())
+        .filter(|source| !source.is_empty())
+}
+
+/// Check a scrobble before it is stored, returning the rejection reason
+pub fn validate_scrobble(scrob: &ScrobbleRequest) -> Result<(), String> {
+    if scrob.artist.trim().is_empty() {
+        return Err("Artist is required".to_string());
+    }
+
+    if scrob.track.trim().is_empty() {
+        return Err("Track is required".to_string());
+    }
+
+    let too_long = [Some(&scrob.artist), Some(&scrob.track), scrob.album.as_ref(), scrob.album_artist.as_ref()]
+        .into_iter()
+        .flatten()
+        .any(|field| field.len() > MAX_FIELD_LEN);
+    if too_long {
+        return Err(format!("Artist, track and album fields must be at most {} bytes", MAX_FIELD_LEN));
+    }
+
+    let timestamp = i64::try_from(scrob.timestamp).map_err(|_| "Timestamp is out of range".to_string())?;
+    if timestamp == 0 {
+        return Err("Timestamp is required".to_string());
+    }
+
+    if timestamp > chrono::Utc::now().timestamp() + MAX_FUTURE_SKEW_SECS {
+        return Err("Timestamp is in the future".to_string());
+    }
+
+    if scrob.duration.is_some_and(|d| d == 0 || d > MAX_DURATION_SECS) {
+        return Err(format!("Duration must be between 1 and {} seconds", MAX_DURATION_SECS));
+    }
+
+    let mbids = [
+        ("artist_mbid", &scrob.artist_mbid),
+        ("release_mbid", &scrob.release_mbid),
+        ("recording_mbid", &scrob.recording_mbid),
+    ];
+    for (name, mbid) in mbids {
+        if normalize_mbid(mbid.as_deref()).is_some_and(|mbid| !is_mbid(&mbid)) {
+            return Err(format!("{} must be a MusicBrainz id", name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Validate, de-duplicate and store a single scrobble, queueing it for relays
+pub async fn submit_scrobble(
+    pool: &PgPool,
+    user_id: i64,
+    scrob: &ScrobbleRequest,
+) -> Result<ScrobbleOutcome, sqlx::Error> {
+    if let Err(reason) = validate_scrobble(scrob) {
+        return Ok(ScrobbleOutcome::Rejected(reason));
+    }
+
+    let mut outcomes = insert_scrobbles(pool, user_id, &mut [scrob.clone()]).await?;
+    Ok(outcomes.pop().unwrap_or(ScrobbleOutcome::Duplicate))
+}
+
+/// Apply the user's scrobble rules, then store what's left with one
+/// multi-row insert and queue the new ones for relays, all in a single
+/// transaction. Expects validated scrobbles, which are left as the rules
+/// rewrote them; returns `Accepted`, `Duplicate` or `Rejected` (blocked by a
+/// rule) for each item, in order.
+pub async fn insert_scrobbles(
+    pool: &PgPool,
+    user_id: i64,
+    submitted: &mut [ScrobbleRequest],
+) -> Result<Vec<ScrobbleOutcome>, sqlx::Error> {
+    if submitted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let rules = rules::load(pool, user_id).await?;
+
+    let mut outcomes = Vec::with_capacity(submitted.len());
+    let mut scrobs = Vec::with_capacity(submitted.len());
+    for scrob in submitted.iter_mut() {
+        let outcome = match rules::apply(&rules, scrob) {
+            Some(rule_id) => Some(ScrobbleOutcome::Rejected(format!("Blocked by scrobble rule {}", rule_id))),
+            // A rewrite can leave the scrobble invalid
+            None => validate_scrobble(scrob).err().map(ScrobbleOutcome::Rejected),
+        };
+        if outcome.is_none() {
+            scrobs.push(scrob.clone());
+        }
+        outcomes.push(outcome);
+    }
+
+    if scrobs.is_empty() {
+        return Ok(outcomes.into_iter().flatten().collect());
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let artists: Vec<&str> = scrobs.iter().map(|s| s.artist.as_str()).collect();
+    let tracks: Vec<&str> = scrobs.iter().map(|s| s.track.as_str()).collect();
+    let albums: Vec<Option<&str>> = scrobs.iter().map(|s| s.album.as_deref()).collect();
+    let album_artists: Vec<Option<&str>> = scrobs.iter().map(|s| s.album_artist.as_deref()).collect();
+    let track_numbers: Vec<Option<i32>> = scrobs
+        .iter()
+        .map(|s| s.track_number.and_then(|n| i32::try_from(n).ok()))
+        .collect();
+    let durations: Vec<Option<i64>> = scrobs.iter().map(|s| s.duration.map(|d| d as i64)).collect();
+    let timestamps: Vec<i64> = scrobs.iter().map(|s| s.timestamp as i64).collect();
+    let artist_mbids: Vec<Option<String>> = scrobs.iter().map(|s| normalize_mbid(s.artist_mbid.as_deref())).collect();
+    let release_mbids: Vec<Option<String>> = scrobs.iter().map(|s| normalize_mbid(s.release_mbid.as_deref())).collect();
+    let recording_mbids: Vec<Option<String>> =
+        scrobs.iter().map(|s| normalize_mbid(s.recording_mbid.as_deref())).collect();
+    let sources: Vec<Option<String>> = scrobs.iter().map(|s| normalize_source(s.source.as_deref())).collect();
+
+    let mut tx = pool.begin().await?;
+
+    // The unique index makes a retried submission a no-op, even when the
+    // retry races the original. Within the batch the first copy wins.
+    let rows = sqlx::query!(
+        r#"
+        INSERT INTO scrobs (
+            user_id, artist, track, album, album_artist, track_number, duration, timestamp, created_at,
+            artist_mbid, release_mbid, recording_mbid, source
+        )
+        SELECT $1, i.artist, i.track, i.album, i.album_artist, i.track_number, i.duration, i.timestamp, $9,
+               i.artist_mbid, i.release_mbid, i.recording_mbid, i.source
+        FROM UNNEST(
+            $2::TEXT[], $3::TEXT[], $4::TEXT[], $5::TEXT[], $6::INTEGER[], $7::BIGINT[], $8::BIGINT[],
+            $10::TEXT[], $11::TEXT[], $12::TEXT[], $13::TEXT[]
+        ) WITH ORDINALITY AS i(
+            artist, track, album, album_artist, track_number, duration, timestamp,
+            artist_mbid, release_mbid, recording_mbid, source, n
+        )
+        ORDER BY i.n
+        ON CONFLICT (user_id, artist, track, timestamp) DO NOTHING
+        RETURNING id, artist, track, timestamp
+        "#,
+        user_id,
+        &artists as &[&str],
+        &tracks as &[&str],
+        &albums as &[Option<&str>],
+        &album_artists as &[Option<&str>],
+        &track_numbers as &[Option<i32>],
+        &durations as &[Option<i64>],
+        &timestamps,
+        now,
+        &artist_mbids as &[Option<String>],
+        &release_mbids as &[Option<String>],
+        &recording_mbids as &[Option<String>],
+        &sources as &[Option<String>]
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut inserted: HashMap<(String, String, i64), i64> = rows
+        .into_iter()
+        .map(|row| ((row.artist, row.track, row.timestamp), row.id))
+        .collect();
+
+    let mut stored = Vec::with_capacity(scrobs.len());
+    let mut accepted = Vec::new();
+    for (scrob, (duration, timestamp)) in scrobs.iter().zip(durations.into_iter().zip(timestamps)) {
+        let key = (scrob.artist.clone(), scrob.track.clone(), timestamp);
+        match inserted.remove(&key) {
+            Some(scrob_id) => {
+                let listen = relay::Listen {
+                    artist: scrob.artist.clone(),
+                    track: scrob.track.clone(),
+                    album: scrob.album.clone(),
+                    duration,
+                    timestamp,
+                };
+                stored.push(ScrobbleOutcome::Accepted(scrob_id));
+                accepted.push((scrob_id, listen));
+            }
+            None => stored.push(ScrobbleOutcome::Duplicate),
+        }
+    }
+
+    let listens: Vec<relay::Listen> = accepted.iter().map(|(_, listen)| listen.clone()).collect();
+    relay::enqueue_scrobbles(&mut *tx, user_id, &listens).await?;
+
+    tx.commit().await?;
 
+    for (scrob_id, listen) in &accepted {
         tracing::info!(
             "Scrobbled for user {}: {} - {} (id: {})",
-            user.id,
-            scrob.artist,
-            scrob.track,
+            user_id,
+            listen.artist,
+            listen.track,
             scrob_id
         );
 
-        results.push(ScrobbleResponse {
-            id: scrob_id,
-            artist: scrob.artist,
-            track: scrob.track,
-            timestamp,
-        });
+        if let Err(e) = feed::publish(pool, user_id, FeedEventKind::Scrobble, Some(*scrob_id), listen).await {
+            tracing::error!("Failed to publish scrobble {} to the live feed: {}", scrob_id, e);
+        }
     }
 
-    Ok(Json(results))
+    let mut stored = stored.into_iter();
+    Ok(outcomes
+        .into_iter()
+        .filter_map(|outcome| outcome.or_else(|| stored.next()))
+        .collect())
 }
@@ -0,0 +1,109 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Serialize;
+use sqlx::PgPool;
+
+use crate::auth::{extract_token_from_header, invalidate_user, AuthUser};
+
+/// A token that can still be used, with where it was last seen
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id: i64,
+    pub label: Option<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    pub last_ip: Option<String>,
+    pub last_user_agent: Option<String>,
+    /// The token this request was made with
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RevokeAllResponse {
+    pub revoked: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("Database error: {}", e),
+        }),
+    )
+}
+
+fn current_token(headers: &axum::http::HeaderMap) -> Option<String> {
+    headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(extract_token_from_header)
+}
+
+/// GET /sessions - every active token, most recently used first
+pub async fn list_sessions(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<SessionInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let current = current_token(&headers);
+
+    let sessions = sqlx::query!(
+        r#"
+        SELECT id, token, label, created_at, last_used_at, expires_at, last_ip, last_user_agent
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked = false AND (expires_at IS NULL OR expires_at > $2)
+        ORDER BY last_used_at DESC NULLS LAST, id DESC
+        "#,
+        user.id,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .map(|row| SessionInfo {
+        id: row.id,
+        label: row.label,
+        created_at: row.created_at,
+        last_used_at: row.last_used_at,
+        expires_at: row.expires_at,
+        last_ip: row.last_ip,
+        last_user_agent: row.last_user_agent,
+        current: current.as_deref() == Some(row.token.as_str()),
+    })
+    .collect();
+
+    Ok(Json(sessions))
+}
+
+/// POST /sessions/revoke-all - log out everywhere except this request's token
+pub async fn revoke_other_sessions(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<RevokeAllResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    // from_headers succeeded, so the header holds a token
+    let current = current_token(&headers).unwrap_or_default();
+
+    let revoked = sqlx::query!(
+        "UPDATE api_tokens SET revoked = true WHERE user_id = $1 AND revoked = false AND token <> $2",
+        user.id,
+        current
+    )
+    .execute(&pool)
+    .await
+    .map_err(db_error)?
+    .rows_affected();
+    invalidate_user(user.id);
+
+    tracing::info!("Revoked {} other token(s) for user {}", revoked, user.id);
+
+    Ok(Json(RevokeAllResponse { revoked }))
+}
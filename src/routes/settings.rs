@@ -2,7 +2,12 @@ use axum::{extract::State, http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-use crate::auth::AuthUser;
+use crate::{
+    auth::{invalidate_user, AuthUser},
+    policy::ContentPolicy,
+    preferences::{self, Preferences, CHART_PERIODS, MAX_DISPLAY_NAME_LEN},
+    versioning::{self, versioned, Versioned},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct PrivacyUpdate {
@@ -19,31 +24,81 @@ pub struct ErrorResponse {
     pub error: String,
 }
 
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("Database error: {}", e),
+        }),
+    )
+}
+
+/// The `If-Match` version an update must match. Settings updates accept a
+/// missing header so older clients keep working.
+fn expected_version(headers: &axum::http::HeaderMap) -> Result<Option<i64>, (StatusCode, Json<ErrorResponse>)> {
+    versioning::if_match(headers)
+        .map(|precondition| precondition.expected())
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })))
+}
+
+fn version_conflict() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::PRECONDITION_FAILED,
+        Json(ErrorResponse {
+            error: "Settings were changed by another client; fetch them again and retry".to_string(),
+        }),
+    )
+}
+
 pub async fn update_privacy(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
     Json(payload): Json<PrivacyUpdate>,
-) -> Result<Json<PrivacyResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Versioned<PrivacyResponse>, (StatusCode, Json<ErrorResponse>)> {
     let user = AuthUser::from_headers(&pool, &headers).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let expected = expected_version(&headers)?;
+
+    if !payload.is_private {
+        let policy = ContentPolicy::load(&pool).await.map_err(db_error)?;
 
-    sqlx::query!(
-        "UPDATE users SET is_private = $1 WHERE id = $2",
+        let created_at = sqlx::query_scalar!("SELECT created_at FROM users WHERE id = $1", user.id)
+            .fetch_one(&pool)
+            .await
+            .map_err(db_error)?;
+
+        if !policy.allows_public(created_at, chrono::Utc::now().timestamp()) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: format!(
+                        "Accounts must be at least {} days old to make their profile public",
+                        policy.min_public_account_age_days
+                    ),
+                }),
+            ));
+        }
+    }
+
+    let version = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET is_private = $1, settings_version = settings_version + 1, settings_updated_at = $3
+        WHERE id = $2 AND ($4::BIGINT IS NULL OR settings_version = $4)
+        RETURNING settings_version
+        "#,
         payload.is_private,
-        user.id
+        user.id,
+        chrono::Utc::now().timestamp(),
+        expected
     )
-    .execute(&pool)
+    .fetch_optional(&pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Database error: {}", e),
-            }),
-        )
-    })?;
+    .map_err(db_error)?
+    .ok_or_else(version_conflict)?;
+    invalidate_user(user.id);
 
-    Ok(Json(PrivacyResponse {
+    Ok(versioned(version, PrivacyResponse {
         is_private: payload.is_private,
     }))
 }
@@ -51,11 +106,194 @@ pub async fn update_privacy(
 pub async fn get_privacy(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
-) -> Result<Json<PrivacyResponse>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Versioned<PrivacyResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let row = sqlx::query!(
+        "SELECT is_private, settings_version FROM users WHERE id = $1",
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(versioned(row.settings_version, PrivacyResponse {
+        is_private: row.is_private,
+    }))
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct NowPlayingSettings {
+    pub auto_promote_now_playing: bool,
+}
+
+pub async fn get_now_playing_settings(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Versioned<NowPlayingSettings>, (StatusCode, Json<ErrorResponse>)> {
     let user = AuthUser::from_headers(&pool, &headers).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
 
-    Ok(Json(PrivacyResponse {
-        is_private: user.is_private,
+    let row = sqlx::query!(
+        "SELECT auto_promote_now_playing, settings_version FROM users WHERE id = $1",
+        user.id
+    )
+    .fetch_one(&pool)
+    .await
+    .map_err(db_error)?;
+
+    Ok(versioned(row.settings_version, NowPlayingSettings {
+        auto_promote_now_playing: row.auto_promote_now_playing,
     }))
 }
+
+pub async fn update_now_playing_settings(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(payload): Json<NowPlayingSettings>,
+) -> Result<Versioned<NowPlayingSettings>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let expected = expected_version(&headers)?;
+
+    let version = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET auto_promote_now_playing = $1, settings_version = settings_version + 1, settings_updated_at = $3
+        WHERE id = $2 AND ($4::BIGINT IS NULL OR settings_version = $4)
+        RETURNING settings_version
+        "#,
+        payload.auto_promote_now_playing,
+        user.id,
+        chrono::Utc::now().timestamp(),
+        expected
+    )
+    .fetch_optional(&pool)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(version_conflict)?;
+    invalidate_user(user.id);
+
+    Ok(versioned(version, payload))
+}
+
+/// Fields left out of an update keep their value; an empty `display_name`
+/// clears it
+#[derive(Debug, Deserialize)]
+pub struct PreferencesUpdate {
+    pub display_name: Option<String>,
+    pub timezone: Option<String>,
+    pub default_chart_period: Option<String>,
+    pub retention_opt_out: Option<bool>,
+    pub include_in_global_charts: Option<bool>,
+}
+
+pub async fn get_preferences(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Versioned<Preferences>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let version = sqlx::query_scalar!("SELECT settings_version FROM users WHERE id = $1", user.id)
+        .fetch_one(&pool)
+        .await
+        .map_err(db_error)?;
+    let preferences = preferences::load(&pool, user.id).await.map_err(db_error)?;
+
+    Ok(versioned(version, preferences))
+}
+
+pub async fn update_preferences(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(payload): Json<PreferencesUpdate>,
+) -> Result<Versioned<Preferences>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let expected = expected_version(&headers)?;
+
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    let display_name = payload.display_name.map(|name| name.trim().to_string());
+    if let Some(name) = display_name.as_deref().filter(|name| !name.is_empty()) {
+        if name.chars().count() > MAX_DISPLAY_NAME_LEN {
+            return Err(bad_request(format!(
+                "display_name must be at most {} characters",
+                MAX_DISPLAY_NAME_LEN
+            )));
+        }
+        if name.chars().any(char::is_control) {
+            return Err(bad_request("display_name can't contain control characters".to_string()));
+        }
+        let policy = ContentPolicy::load(&pool).await.map_err(db_error)?;
+        policy.check_text(name).map_err(bad_request)?;
+    }
+
+    if let Some(timezone) = payload.timezone.as_deref() {
+        if !preferences::is_valid_timezone(&pool, timezone).await.map_err(db_error)? {
+            return Err(bad_request(format!(
+                "Unknown timezone '{}': use an IANA name like Europe/Berlin",
+                timezone
+            )));
+        }
+    }
+
+    if let Some(period) = payload.default_chart_period.as_deref() {
+        if !CHART_PERIODS.contains(&period) {
+            return Err(bad_request(format!(
+                "default_chart_period must be one of {}",
+                CHART_PERIODS.join(", ")
+            )));
+        }
+    }
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let version = sqlx::query_scalar!(
+        r#"
+        UPDATE users
+        SET settings_version = settings_version + 1, settings_updated_at = $2
+        WHERE id = $1 AND ($3::BIGINT IS NULL OR settings_version = $3)
+        RETURNING settings_version
+        "#,
+        user.id,
+        chrono::Utc::now().timestamp(),
+        expected
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(version_conflict)?;
+
+    let preferences = sqlx::query_as!(
+        Preferences,
+        r#"
+        INSERT INTO user_settings (
+            user_id, display_name, timezone, default_chart_period, retention_opt_out, include_in_global_charts
+        )
+        VALUES ($1, NULLIF($2, ''), COALESCE($3, 'UTC'), COALESCE($4, 'overall'), COALESCE($5, false), COALESCE($6, false))
+        ON CONFLICT (user_id) DO UPDATE
+        SET display_name = CASE WHEN $2::TEXT IS NULL THEN user_settings.display_name ELSE NULLIF($2, '') END,
+            timezone = COALESCE($3, user_settings.timezone),
+            default_chart_period = COALESCE($4, user_settings.default_chart_period),
+            retention_opt_out = COALESCE($5, user_settings.retention_opt_out),
+            include_in_global_charts = COALESCE($6, user_settings.include_in_global_charts)
+        RETURNING display_name, timezone, default_chart_period, retention_opt_out, include_in_global_charts
+        "#,
+        user.id,
+        display_name,
+        payload.timezone,
+        payload.default_chart_period,
+        payload.retention_opt_out,
+        payload.include_in_global_charts
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(db_error)?;
+
+    tx.commit().await.map_err(db_error)?;
+
+    Ok(versioned(version, preferences))
+}
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::AuthUser,
+    config::Config,
+    crypto::SecretBox,
+    spotify::{self, SpotifyError, SpotifySettings},
+};
+
+#[derive(Debug, Serialize)]
+pub struct SpotifyConnectResponse {
+    /// Send the user here to approve access
+    pub url: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SpotifyCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by Spotify when the user declined
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpotifyLink {
+    pub spotify_user_id: String,
+    pub display_name: Option<String>,
+    pub linked_at: i64,
+    /// When the newest imported play ended
+    pub last_played_at: Option<i64>,
+    pub last_polled_at: Option<i64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+type ApiError = (StatusCode, Json<ErrorResponse>);
+
+fn error(status: StatusCode, msg: &str) -> ApiError {
+    (status, Json(ErrorResponse { error: msg.to_string() }))
+}
+
+fn db_error(e: sqlx::Error) -> ApiError {
+    error(StatusCode::INTERNAL_SERVER_ERROR, &format!("Database error: {}", e))
+}
+
+fn settings(config: &Config) -> Result<SpotifySettings, ApiError> {
+    SpotifySettings::from_config(config)
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "Spotify sync is not configured"))
+}
+
+fn spotify_error(e: SpotifyError) -> ApiError {
+    let status = match e {
+        SpotifyError::Provider(_) => StatusCode::BAD_GATEWAY,
+        SpotifyError::Rejected(_) => StatusCode::BAD_REQUEST,
+        SpotifyError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    error(status, &e.to_string())
+}
+
+fn secrets_required(secrets: Option<Arc<SecretBox>>) -> Result<Arc<SecretBox>, ApiError> {
+    secrets.ok_or_else(|| error(StatusCode::SERVICE_UNAVAILABLE, "SECRET_KEY must be configured to link Spotify"))
+}
+
+async fn fetch_link(pool: &PgPool, user_id: i64) -> Result<Option<SpotifyLink>, sqlx::Error> {
+    sqlx::query_as!(
+        SpotifyLink,
+        r#"
+        SELECT spotify_user_id, display_name, linked_at, cursor_ms / 1000 as last_played_at,
+               last_polled_at, last_error
+        FROM spotify_links
+        WHERE user_id = $1
+        "#,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+/// GET /connect/spotify - start linking a Spotify account. Returns the URL to
+/// send the user to rather than redirecting, since the request is authenticated.
+pub async fn connect_spotify(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+) -> Result<Json<SpotifyConnectResponse>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+    let settings = settings(&config)?;
+    secrets_required(secrets)?;
+
+    let url = spotify::start_link(&pool, &settings, user.id).await.map_err(spotify_error)?;
+
+    Ok(Json(SpotifyConnectResponse { url }))
+}
+
+/// GET /connect/spotify/callback - Spotify redirects here after the user
+/// approves. The state ties it back to whoever started the link.
+pub async fn spotify_callback(
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    State(secrets): State<Option<Arc<SecretBox>>>,
+    Query(query): Query<SpotifyCallbackQuery>,
+) -> Result<Json<SpotifyLink>, ApiError> {
+    let settings = settings(&config)?;
+    let secrets = secrets_required(secrets)?;
+
+    if let Some(e) = query.error {
+        return Err(error(StatusCode::BAD_REQUEST, &format!("Spotify link failed ({})", e)));
+    }
+
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(error(StatusCode::BAD_REQUEST, "Missing code or state"));
+    };
+
+    let user_id = spotify::finish_link(&pool, &settings, &secrets, &code, &state)
+        .await
+        .map_err(spotify_error)?;
+
+    let link = fetch_link(&pool, user_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::INTERNAL_SERVER_ERROR, "Spotify link was not saved"))?;
+
+    Ok(Json(link))
+}
+
+/// GET /connect/spotify/status - the linked account and how polling is going
+pub async fn spotify_status(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<SpotifyLink>, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let link = fetch_link(&pool, user.id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| error(StatusCode::NOT_FOUND, "No Spotify account linked"))?;
+
+    Ok(Json(link))
+}
+
+/// DELETE /connect/spotify - unlink and stop polling. Imported scrobbles stay.
+pub async fn disconnect_spotify(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<StatusCode, ApiError> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| error(status, "Unauthorized"))?;
+
+    let result = sqlx::query!("DELETE FROM spotify_links WHERE user_id = $1", user.id)
+        .execute(&pool)
+        .await
+        .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err(error(StatusCode::NOT_FOUND, "No Spotify account linked"));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
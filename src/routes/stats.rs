@@ -1,8 +1,17 @@
+use std::sync::Arc;
+
 use axum::{extract::{Path, Query, State}, http::StatusCode, Json};
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 
-use crate::{auth::AuthUser, db::models::User};
+use crate::{
+    auth::{AuthUser, Scope},
+    chart_cache::{self, Cached, ChartKind},
+    config::Config,
+    db::models::User,
+    now_playing, preferences,
+    versioning::{self, Conditional},
+};
 
 #[derive(Debug, Deserialize)]
 pub struct RecentScrobsQuery {
@@ -12,6 +21,96 @@ pub struct RecentScrobsQuery {
 #[derive(Debug, Deserialize)]
 pub struct TopQuery {
     pub limit: Option<i64>,
+    /// `7day`, `1month`, `3month`, `12month` or `overall` (the default)
+    pub period: Option<String>,
+    /// Explicit unix time range, `to` exclusive; can't be combined with `period`
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+/// Resolve a chart query's `period` or `from`/`to` into a `[from, to)` range.
+/// Without either, the user's `default_chart_period` applies. Periods cover
+/// whole days in the user's timezone, today included.
+async fn time_range(
+    pool: &PgPool,
+    user_id: i64,
+    query: &TopQuery,
+) -> Result<(i64, i64), (StatusCode, Json<ErrorResponse>)> {
+    let (_, from, to) = chart_range(pool, user_id, query).await?;
+    Ok((from, to))
+}
+
+/// Like `time_range`, also returning the rolling period the range came from;
+/// `None` for an explicit `from`/`to`
+async fn chart_range(
+    pool: &PgPool,
+    user_id: i64,
+    query: &TopQuery,
+) -> Result<(Option<String>, i64, i64), (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+            }),
+        )
+    };
+
+    if query.period.is_some() && (query.from.is_some() || query.to.is_some()) {
+        return Err(bad_request("Use either period or from/to, not both"));
+    }
+
+    if query.from.is_some() || query.to.is_some() {
+        let from = query.from.unwrap_or(0);
+        let to = query.to.unwrap_or(i64::MAX);
+        if from >= to {
+            return Err(bad_request("from must be before to"));
+        }
+        return Ok((None, from, to));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let preferences = preferences::load(pool, user_id).await.map_err(db_error)?;
+    let period = query.period.as_deref().unwrap_or(&preferences.default_chart_period);
+
+    if period == "overall" {
+        return Ok((Some(period.to_string()), 0, i64::MAX));
+    }
+    let days = period_days(period)
+        .ok_or_else(|| bad_request("period must be one of 7day, 1month, 3month, 12month, overall"))?;
+
+    let (_, from) = period_starts(pool, &preferences.timezone, days).await.map_err(db_error)?;
+
+    Ok((Some(period.to_string()), from, i64::MAX))
+}
+
+/// Days covered by a rolling chart period; `None` for `overall` or unknown ones
+pub(crate) fn period_days(period: &str) -> Option<i64> {
+    match period {
+        "7day" => Some(7),
+        "1month" => Some(30),
+        "3month" => Some(90),
+        "12month" => Some(365),
+        _ => None,
+    }
+}
+
+/// Starts of the previous and current rolling period of `days` days. The
+/// current one is the last `days` days including today, from midnight in
+/// `timezone`; the previous one is the `days` days before it.
+pub(crate) async fn period_starts(pool: &PgPool, timezone: &str, days: i64) -> Result<(i64, i64), sqlx::Error> {
+    let today = preferences::local_today(pool, timezone).await?;
+    let first_day = today - chrono::Duration::days(days - 1);
+    let starts = preferences::local_midnights(pool, timezone, &[first_day - chrono::Duration::days(days), first_day]).await?;
+    Ok((starts[0], starts[1]))
 }
 
 #[derive(Debug, Serialize)]
@@ -20,40 +119,194 @@ pub struct Scrob {
     pub artist: String,
     pub track: String,
     pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub track_number: Option<i32>,
+    pub duration: Option<i64>,
+    /// True when `duration` was looked up rather than submitted
+    pub duration_estimated: bool,
     pub timestamp: i64,
+    pub artist_mbid: Option<String>,
+    pub release_mbid: Option<String>,
+    pub recording_mbid: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TopArtist {
     pub name: String,
     pub count: i64,
+    /// Seconds listened, counting `DEFAULT_TRACK_DURATION` for scrobbles
+    /// without a known duration
+    pub listening_time: i64,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct TopTrack {
     pub artist: String,
     pub track: String,
     pub count: i64,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TopAlbum {
+    pub album: String,
+    /// Album artist, falling back to the track artist when none was submitted
+    pub artist: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChartDiffQuery {
+    pub limit: Option<i64>,
+    /// `7day`, `1month`, `3month` or `12month`; defaults to the user's
+    /// `default_chart_period` when that isn't `overall`
+    pub period: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ArtistMovement {
+    pub name: String,
+    pub rank: i64,
+    /// Rank in the previous period, beyond `limit` if needed; absent when the
+    /// artist wasn't played then
+    pub previous_rank: Option<i64>,
+    pub count: i64,
+    pub previous_count: i64,
+    /// `new`, `up`, `down` or `same`
+    pub movement: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DroppedArtist {
+    pub name: String,
+    pub previous_rank: i64,
+    pub previous_count: i64,
+    /// Rank this period, absent when not played at all
+    pub rank: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChartDiff {
+    pub period: String,
+    /// Unix start of the current period, which runs until now
+    pub from: i64,
+    /// Unix start of the previous period, which ends at `from`
+    pub previous_from: i64,
+    /// The current top `limit`
+    pub artists: Vec<ArtistMovement>,
+    /// Artists in the previous top `limit` that fell out of it
+    pub dropped: Vec<DroppedArtist>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsOverview {
+    pub scrobbles: i64,
+    pub artists: i64,
+    pub tracks: i64,
+    /// Seconds listened, counting `fallback_duration` for each scrobble in
+    /// `unknown_durations`
+    pub listening_time: i64,
+    pub unknown_durations: i64,
+    pub fallback_duration: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SourceCount {
+    /// Client or integration; null for scrobbles sent without one
+    pub source: Option<String>,
+    pub count: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ObsessionsQuery {
+    /// `24h` or `7d` (the default)
+    pub window: Option<String>,
+    /// Plays within one window that make a track an obsession (default 5)
+    pub min_plays: Option<i64>,
+    pub limit: Option<i64>,
+    /// Range to look in, as for the charts; defaults to the last 30 days
+    pub period: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Obsession {
+    pub artist: String,
+    pub track: String,
+    /// Most plays within any one window
+    pub plays: i64,
+    /// First and last play of that busiest window
+    pub window_start: i64,
+    pub window_end: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ObsessionsResponse {
+    pub window: String,
+    pub min_plays: i64,
+    pub tracks: Vec<Obsession>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Discovery {
+    pub name: String,
+    /// The user's first ever scrobble of the artist
+    pub first_played: i64,
+    /// Scrobbles of the artist in the range
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DiscoveriesResponse {
+    /// Artists first played in the range, not just those listed
+    pub total: i64,
+    pub artists: Vec<Discovery>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("Database error: {}", e),
+        }),
+    )
+}
+
 pub async fn recent_scrobbles(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
     Query(query): Query<RecentScrobsQuery>,
-) -> Result<Json<Vec<Scrob>>, (StatusCode, Json<ErrorResponse>)> {
-    let user = AuthUser::from_headers(&pool, &headers).await
+) -> Result<Conditional<Json<Vec<Scrob>>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
     let limit = query.limit.unwrap_or(20).min(100);
 
+    let etag = versioning::scrobbles_etag(&pool, user.id, &[limit]).await.map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
     let scrobs = sqlx::query_as!(
         Scrob,
         r#"
-        SELECT id as "id!", artist, track, album, timestamp as "timestamp!"
+        SELECT
+            id as "id!",
+            artist,
+            track,
+            album,
+            album_artist,
+            track_number,
+            COALESCE(duration, duration_estimated) as duration,
+            (duration IS NULL AND duration_estimated IS NOT NULL) as "duration_estimated!",
+            timestamp as "timestamp!",
+            artist_mbid,
+            release_mbid,
+            recording_mbid
         FROM scrobs
         WHERE user_id = $1
         ORDER BY timestamp DESC
@@ -64,6 +317,278 @@ pub async fn recent_scrobbles(
     )
     .fetch_all(&pool)
     .await
+    .map_err(db_error)?;
+
+    Ok(Conditional::Modified(etag, Json(scrobs)))
+}
+
+pub async fn top_artists(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Conditional<Cached<TopArtist>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(10).min(100);
+    let (period, from, to) = chart_range(&pool, user.id, &query).await?;
+
+    let chart = chart_cache::top(
+        &pool,
+        ChartKind::Artists,
+        user.id,
+        period.as_deref(),
+        from,
+        to,
+        limit,
+        config.default_track_duration,
+    )
+    .await
+    .map_err(db_error)?;
+
+    let etag = versioning::scrobbles_etag(
+        &pool,
+        user.id,
+        &[from, to, limit, config.default_track_duration, chart.revision()],
+    )
+    .await
+    .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    Ok(Conditional::Modified(etag, chart.with_headers()))
+}
+
+pub async fn top_tracks(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Conditional<Cached<TopTrack>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(10).min(100);
+    let (period, from, to) = chart_range(&pool, user.id, &query).await?;
+
+    let chart = chart_cache::top(
+        &pool,
+        ChartKind::Tracks,
+        user.id,
+        period.as_deref(),
+        from,
+        to,
+        limit,
+        config.default_track_duration,
+    )
+    .await
+    .map_err(db_error)?;
+
+    let etag = versioning::scrobbles_etag(
+        &pool,
+        user.id,
+        &[from, to, limit, config.default_track_duration, chart.revision()],
+    )
+    .await
+    .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    Ok(Conditional::Modified(etag, chart.with_headers()))
+}
+
+/// Albums are keyed by name + album artist. Scrobbles without an album are
+/// left out, and the usual spellings of "Various Artists" are merged so a
+/// compilation is one entry rather than one per track artist.
+pub async fn top_albums(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Conditional<Cached<TopAlbum>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(10).min(100);
+    let (period, from, to) = chart_range(&pool, user.id, &query).await?;
+
+    let chart = chart_cache::top(
+        &pool,
+        ChartKind::Albums,
+        user.id,
+        period.as_deref(),
+        from,
+        to,
+        limit,
+        config.default_track_duration,
+    )
+    .await
+    .map_err(db_error)?;
+
+    let etag = versioning::scrobbles_etag(
+        &pool,
+        user.id,
+        &[from, to, limit, config.default_track_duration, chart.revision()],
+    )
+    .await
+    .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    Ok(Conditional::Modified(etag, chart.with_headers()))
+}
+
+/// GET /top/artists/diff?period=1month - the top artists with their rank
+/// movement since the previous period of the same length
+pub async fn top_artists_diff(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ChartDiffQuery>,
+) -> Result<Conditional<Json<ChartDiff>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let limit = query.limit.unwrap_or(10).min(100);
+
+    let db_error = |e: sqlx::Error| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let preferences = preferences::load(&pool, user.id).await.map_err(db_error)?;
+    let period = query.period.unwrap_or(preferences.default_chart_period);
+    let days = period_days(&period).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "period must be one of 7day, 1month, 3month, 12month".to_string(),
+            }),
+        )
+    })?;
+
+    let (previous_from, from) = period_starts(&pool, &preferences.timezone, days).await.map_err(db_error)?;
+
+    let etag = versioning::scrobbles_etag(&pool, user.id, &[previous_from, from, limit])
+        .await
+        .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    // Both periods are ranked in full so entries outside the top `limit`
+    // still get their real rank; only rows in either top `limit` come back
+    let rows = sqlx::query!(
+        r#"
+        WITH counts AS (
+            SELECT
+                a.name as artist,
+                COUNT(*) FILTER (WHERE s.timestamp >= $3) as count,
+                COUNT(*) FILTER (WHERE s.timestamp < $3) as previous_count
+            FROM scrobs s
+            JOIN artists a ON a.id = s.artist_id
+            WHERE s.user_id = $1 AND s.timestamp >= $2
+            GROUP BY a.id
+        ),
+        ranked AS (
+            SELECT
+                artist,
+                count,
+                previous_count,
+                CASE WHEN count > 0 THEN ROW_NUMBER() OVER (ORDER BY count DESC, artist) END as rank,
+                CASE WHEN previous_count > 0 THEN ROW_NUMBER() OVER (ORDER BY previous_count DESC, artist) END as previous_rank
+            FROM counts
+        )
+        SELECT artist as name, count as "count!", previous_count as "previous_count!", rank, previous_rank
+        FROM ranked
+        WHERE rank <= $4 OR previous_rank <= $4
+        ORDER BY rank NULLS LAST, previous_rank
+        "#,
+        user.id,
+        previous_from,
+        from,
+        limit
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let mut artists = Vec::new();
+    let mut dropped = Vec::new();
+    for row in rows {
+        match row.rank.filter(|&rank| rank <= limit) {
+            Some(rank) => artists.push(ArtistMovement {
+                name: row.name,
+                rank,
+                previous_rank: row.previous_rank,
+                count: row.count,
+                previous_count: row.previous_count,
+                movement: match row.previous_rank {
+                    None => "new",
+                    Some(previous) if previous > rank => "up",
+                    Some(previous) if previous < rank => "down",
+                    Some(_) => "same",
+                },
+            }),
+            None => {
+                if let Some(previous_rank) = row.previous_rank {
+                    dropped.push(DroppedArtist {
+                        name: row.name,
+                        previous_rank,
+                        previous_count: row.previous_count,
+                        rank: row.rank,
+                    });
+                }
+            }
+        }
+    }
+    dropped.sort_by_key(|artist| artist.previous_rank);
+
+    Ok(Conditional::Modified(
+        etag,
+        Json(ChartDiff {
+            period,
+            from,
+            previous_from,
+            artists,
+            dropped,
+        }),
+    ))
+}
+
+/// GET /stats/overview?period=1month - totals for a chart period, including
+/// time spent listening
+pub async fn stats_overview(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
+    Query(query): Query<TopQuery>,
+) -> Result<Json<StatsOverview>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let (from, to) = time_range(&pool, user.id, &query).await?;
+
+    let totals = sqlx::query!(
+        r#"
+        SELECT
+            COUNT(*) as "scrobbles!",
+            COUNT(DISTINCT artist_id) as "artists!",
+            COUNT(DISTINCT track_id) as "tracks!",
+            COALESCE(SUM(COALESCE(duration, duration_estimated, $4)), 0)::BIGINT as "listening_time!",
+            COUNT(*) FILTER (WHERE duration IS NULL AND duration_estimated IS NULL) as "unknown_durations!"
+        FROM scrobs
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        "#,
+        user.id,
+        from,
+        to,
+        config.default_track_duration
+    )
+    .fetch_one(&pool)
+    .await
     .map_err(|e| {
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -73,29 +598,125 @@ pub async fn recent_scrobbles(
         )
     })?;
 
-    Ok(Json(scrobs))
+    Ok(Json(StatsOverview {
+        scrobbles: totals.scrobbles,
+        artists: totals.artists,
+        tracks: totals.tracks,
+        listening_time: totals.listening_time,
+        unknown_durations: totals.unknown_durations,
+        fallback_duration: config.default_track_duration,
+    }))
 }
 
-pub async fn top_artists(
+/// GET /stats/sources?period=1month - scrobbles per client or integration
+/// (MPD, Spotify sync, imports, ...), most first
+pub async fn scrobble_sources(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
     Query(query): Query<TopQuery>,
-) -> Result<Json<Vec<TopArtist>>, (StatusCode, Json<ErrorResponse>)> {
-    let user = AuthUser::from_headers(&pool, &headers).await
+) -> Result<Json<Vec<SourceCount>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
-    let limit = query.limit.unwrap_or(10).min(100);
+    let (from, to) = time_range(&pool, user.id, &query).await?;
 
-    let artists = sqlx::query_as!(
-        TopArtist,
+    let sources = sqlx::query_as!(
+        SourceCount,
         r#"
-        SELECT artist as name, COUNT(*) as "count!: i64"
+        SELECT source, COUNT(*) as "count!"
         FROM scrobs
-        WHERE user_id = $1
-        GROUP BY artist
-        ORDER BY COUNT(*) DESC
-        LIMIT $2
+        WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+        GROUP BY source
+        ORDER BY COUNT(*) DESC, source NULLS LAST
         "#,
         user.id,
+        from,
+        to
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(|e| {
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(sources))
+}
+
+/// GET /stats/obsessions?window=7d&min_plays=5 - tracks played on repeat,
+/// by their most plays within a rolling window
+pub async fn obsessions(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Query(query): Query<ObsessionsQuery>,
+) -> Result<Json<ObsessionsResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let bad_request = |error: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+            }),
+        )
+    };
+
+    let window = query.window.unwrap_or_else(|| "7d".to_string());
+    let window_secs: i64 = match window.as_str() {
+        "24h" => 24 * 60 * 60,
+        "7d" => 7 * 24 * 60 * 60,
+        _ => return Err(bad_request("window must be 24h or 7d")),
+    };
+    let min_plays = query.min_plays.unwrap_or(5);
+    if min_plays < 2 {
+        return Err(bad_request("min_plays must be at least 2"));
+    }
+    let limit = query.limit.unwrap_or(10).min(100);
+
+    let range_query = TopQuery {
+        limit: None,
+        period: query.period.or_else(|| (query.from.is_none() && query.to.is_none()).then(|| "1month".to_string())),
+        from: query.from,
+        to: query.to,
+    };
+    let (from, to) = time_range(&pool, user.id, &range_query).await?;
+
+    // Each play counts the plays of the same track in the window ending at
+    // it; a track's busiest window is its highest count
+    let tracks = sqlx::query_as!(
+        Obsession,
+        r#"
+        WITH windows AS (
+            SELECT
+                track_id,
+                timestamp,
+                COUNT(*) OVER w as plays,
+                MIN(timestamp) OVER w as window_start
+            FROM scrobs
+            WHERE user_id = $1 AND timestamp >= $2 AND timestamp < $3
+            WINDOW w AS (PARTITION BY track_id ORDER BY timestamp RANGE BETWEEN $4::bigint PRECEDING AND CURRENT ROW)
+        ),
+        peaks AS (
+            SELECT DISTINCT ON (track_id) track_id, plays, window_start, timestamp as window_end
+            FROM windows
+            ORDER BY track_id, plays DESC, timestamp DESC
+        )
+        SELECT a.name as artist, t.name as track, p.plays as "plays!", p.window_start as "window_start!", p.window_end
+        FROM peaks p
+        JOIN tracks t ON t.id = p.track_id
+        JOIN artists a ON a.id = t.artist_id
+        WHERE p.plays >= $5
+        ORDER BY p.plays DESC, p.window_end DESC
+        LIMIT $6
+        "#,
+        user.id,
+        from,
+        to,
+        window_secs - 1,
+        min_plays,
         limit
     )
     .fetch_all(&pool)
@@ -109,29 +730,45 @@ pub async fn top_artists(
         )
     })?;
 
-    Ok(Json(artists))
+    Ok(Json(ObsessionsResponse {
+        window,
+        min_plays,
+        tracks,
+    }))
 }
 
-pub async fn top_tracks(
+/// GET /stats/discoveries?period=1month - artists first scrobbled in the
+/// range, most played first
+pub async fn discoveries(
     headers: axum::http::HeaderMap,
     State(pool): State<PgPool>,
     Query(query): Query<TopQuery>,
-) -> Result<Json<Vec<TopTrack>>, (StatusCode, Json<ErrorResponse>)> {
-    let user = AuthUser::from_headers(&pool, &headers).await
+) -> Result<Json<DiscoveriesResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers_with_scope(&pool, &headers, Scope::Read).await
         .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
     let limit = query.limit.unwrap_or(10).min(100);
+    let (from, to) = time_range(&pool, user.id, &query).await?;
 
-    let tracks = sqlx::query_as!(
-        TopTrack,
+    // An artist's first scrobble is its earliest, so everything from there
+    // to the end of the range is in the range
+    let rows = sqlx::query!(
         r#"
-        SELECT artist as "artist!", track as "track!", COUNT(*) as "count!: i64"
-        FROM scrobs
-        WHERE user_id = $1
-        GROUP BY artist, track
-        ORDER BY COUNT(*) DESC
-        LIMIT $2
+        SELECT
+            a.name,
+            MIN(s.timestamp) as "first_played!",
+            COUNT(*) FILTER (WHERE s.timestamp < $3) as "count!",
+            COUNT(*) OVER () as "total!"
+        FROM scrobs s
+        JOIN artists a ON a.id = s.artist_id
+        WHERE s.user_id = $1
+        GROUP BY a.id
+        HAVING MIN(s.timestamp) >= $2 AND MIN(s.timestamp) < $3
+        ORDER BY 3 DESC, 2, a.name
+        LIMIT $4
         "#,
         user.id,
+        from,
+        to,
         limit
     )
     .fetch_all(&pool)
@@ -145,16 +782,27 @@ pub async fn top_tracks(
         )
     })?;
 
-    Ok(Json(tracks))
+    Ok(Json(DiscoveriesResponse {
+        total: rows.first().map(|row| row.total).unwrap_or(0),
+        artists: rows
+            .into_iter()
+            .map(|row| Discovery {
+                name: row.name,
+                first_played: row.first_played,
+                count: row.count,
+            })
+            .collect(),
+    }))
 }
 
 // Public user profile endpoints
 
 pub async fn user_recent_scrobbles(
+    headers: axum::http::HeaderMap,
     Path(username): Path<String>,
     State(pool): State<PgPool>,
     Query(query): Query<RecentScrobsQuery>,
-) -> Result<Json<Vec<Scrob>>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Conditional<Json<Vec<Scrob>>>, (StatusCode, Json<ErrorResponse>)> {
     // Look up user by username
     let user = sqlx::query_as!(
         User,
@@ -192,10 +840,27 @@ pub async fn user_recent_scrobbles(
 
     let limit = query.limit.unwrap_or(20).min(100);
 
+    let etag = versioning::scrobbles_etag(&pool, user.id, &[limit]).await.map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
     let scrobs = sqlx::query_as!(
         Scrob,
         r#"
-        SELECT id as "id!", artist, track, album, timestamp as "timestamp!"
+        SELECT
+            id as "id!",
+            artist,
+            track,
+            album,
+            album_artist,
+            track_number,
+            COALESCE(duration, duration_estimated) as duration,
+            (duration IS NULL AND duration_estimated IS NOT NULL) as "duration_estimated!",
+            timestamp as "timestamp!",
+            artist_mbid,
+            release_mbid,
+            recording_mbid
         FROM scrobs
         WHERE user_id = $1
         ORDER BY timestamp DESC
@@ -206,23 +871,18 @@ pub async fn user_recent_scrobbles(
     )
     .fetch_all(&pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Database error: {}", e),
-            }),
-        )
-    })?;
+    .map_err(db_error)?;
 
-    Ok(Json(scrobs))
+    Ok(Conditional::Modified(etag, Json(scrobs)))
 }
 
 pub async fn user_top_artists(
+    headers: axum::http::HeaderMap,
     Path(username): Path<String>,
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
     Query(query): Query<TopQuery>,
-) -> Result<Json<Vec<TopArtist>>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Conditional<Cached<TopArtist>>, (StatusCode, Json<ErrorResponse>)> {
     // Look up user by username
     let user = sqlx::query_as!(
         User,
@@ -259,39 +919,42 @@ pub async fn user_top_artists(
     }
 
     let limit = query.limit.unwrap_or(10).min(100);
+    let (period, from, to) = chart_range(&pool, user.id, &query).await?;
 
-    let artists = sqlx::query_as!(
-        TopArtist,
-        r#"
-        SELECT artist as name, COUNT(*) as "count!: i64"
-        FROM scrobs
-        WHERE user_id = $1
-        GROUP BY artist
-        ORDER BY COUNT(*) DESC
-        LIMIT $2
-        "#,
+    let chart = chart_cache::top(
+        &pool,
+        ChartKind::Artists,
         user.id,
-        limit
+        period.as_deref(),
+        from,
+        to,
+        limit,
+        config.default_track_duration,
     )
-    .fetch_all(&pool)
     .await
-    .map_err(|e| {
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Database error: {}", e),
-            }),
-        )
-    })?;
+    .map_err(db_error)?;
 
-    Ok(Json(artists))
+    let etag = versioning::scrobbles_etag(
+        &pool,
+        user.id,
+        &[from, to, limit, config.default_track_duration, chart.revision()],
+    )
+    .await
+    .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    Ok(Conditional::Modified(etag, chart.with_headers()))
 }
 
 pub async fn user_top_tracks(
+    headers: axum::http::HeaderMap,
     Path(username): Path<String>,
     State(pool): State<PgPool>,
+    State(config): State<Arc<Config>>,
     Query(query): Query<TopQuery>,
-) -> Result<Json<Vec<TopTrack>>, (StatusCode, Json<ErrorResponse>)> {
+) -> Result<Conditional<Cached<TopTrack>>, (StatusCode, Json<ErrorResponse>)> {
     // Look up user by username
     let user = sqlx::query_as!(
         User,
@@ -328,21 +991,46 @@ pub async fn user_top_tracks(
     }
 
     let limit = query.limit.unwrap_or(10).min(100);
+    let (period, from, to) = chart_range(&pool, user.id, &query).await?;
 
-    let tracks = sqlx::query_as!(
-        TopTrack,
-        r#"
-        SELECT artist as "artist!", track as "track!", COUNT(*) as "count!: i64"
-        FROM scrobs
-        WHERE user_id = $1
-        GROUP BY artist, track
-        ORDER BY COUNT(*) DESC
-        LIMIT $2
-        "#,
+    let chart = chart_cache::top(
+        &pool,
+        ChartKind::Tracks,
         user.id,
-        limit
+        period.as_deref(),
+        from,
+        to,
+        limit,
+        config.default_track_duration,
     )
-    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?;
+
+    let etag = versioning::scrobbles_etag(
+        &pool,
+        user.id,
+        &[from, to, limit, config.default_track_duration, chart.revision()],
+    )
+    .await
+    .map_err(db_error)?;
+    if versioning::not_modified(&headers, &etag) {
+        return Ok(Conditional::NotModified(etag));
+    }
+
+    Ok(Conditional::Modified(etag, chart.with_headers()))
+}
+
+pub async fn user_now_playing(
+    Path(username): Path<String>,
+    State(pool): State<PgPool>,
+) -> Result<Json<Option<now_playing::CurrentTrack>>, (StatusCode, Json<ErrorResponse>)> {
+    // Look up user by username
+    let user = sqlx::query_as!(
+        User,
+        "SELECT * FROM users WHERE username = $1",
+        username
+    )
+    .fetch_optional(&pool)
     .await
     .map_err(|e| {
         (
@@ -351,7 +1039,36 @@ pub async fn user_top_tracks(
                 error: format!("Database error: {}", e),
             }),
         )
+    })?
+    .ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "User not found".to_string(),
+            }),
+        )
     })?;
 
-    Ok(Json(tracks))
+    // Check if profile is private
+    if user.is_private {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "This user's profile is private".to_string(),
+            }),
+        ));
+    }
+
+    let current = now_playing::current(&pool, user.id, chrono::Utc::now().timestamp())
+        .await
+        .map_err(|e| {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(current))
 }
@@ -0,0 +1,434 @@
+//! Subsonic API scrobbling (`ping`, `scrobble`, `getNowPlaying`), so players
+//! that speak Subsonic can scrobble to scrob directly.
+//!
+//! scrob only stores password hashes, so clients authenticate with one of
+//! the user's API tokens in place of the password, either sent as `p` or
+//! salted as `t = md5(token + s)`. OpenSubsonic's `apiKey` takes a token too.
+//! scrob has no media library: song ids are scrob catalogue track ids (as
+//! listed by `/library/tracks`) or recording MBIDs.
+
+use axum::{
+    extract::{rejection::FormRejection, Query, State},
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+    Form,
+};
+use md5::{Digest, Md5};
+use serde_json::{json, Map, Value};
+use sqlx::PgPool;
+
+use crate::{
+    auth::Scope,
+    now_playing as now_playing_store, relay,
+    routes::{
+        lastfm::escape_xml,
+        scrobble::{is_mbid, normalize_mbid, submit_scrobble, ScrobbleOutcome, ScrobbleRequest},
+    },
+    runtime::RuntimeSettings,
+};
+
+/// The Subsonic API version these endpoints implement
+const API_VERSION: &str = "1.16.1";
+const XML_NAMESPACE: &str = "http://subsonic.org/restapi";
+
+// Subsonic error codes
+const GENERIC_ERROR: u16 = 0;
+const MISSING_PARAMETER: u16 = 10;
+const WRONG_CREDENTIALS: u16 = 40;
+const NOT_AUTHORIZED: u16 = 50;
+const NOT_FOUND: u16 = 70;
+
+type Params = Vec<(String, String)>;
+
+struct SubsonicError {
+    code: u16,
+    message: String,
+}
+
+impl SubsonicError {
+    fn new(code: u16, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl From<sqlx::Error> for SubsonicError {
+    fn from(e: sqlx::Error) -> Self {
+        tracing::error!("Subsonic API database error: {}", e);
+        Self::new(GENERIC_ERROR, "Database error")
+    }
+}
+
+/// A track from the catalogue, with what the latest scrobble of it knew
+struct Song {
+    artist: String,
+    track: String,
+    album: Option<String>,
+    album_artist: Option<String>,
+    duration: Option<i64>,
+    artist_mbid: Option<String>,
+    release_mbid: Option<String>,
+    recording_mbid: Option<String>,
+}
+
+/// GET/POST /rest/ping.view - lets players check the server and credentials
+pub async fn subsonic_ping(
+    State(pool): State<PgPool>,
+    method: Method,
+    Query(query): Query<Params>,
+    form: Result<Form<Params>, FormRejection>,
+) -> Response {
+    let params = merge(method, query, form);
+    let result = async {
+        authenticate(&pool, &params, Scope::Read).await?;
+        Ok(Map::new())
+    };
+    render(&params, result.await)
+}
+
+/// GET/POST /rest/scrobble.view?id=...&time=...&submission=true - scrobbles
+/// one or more songs, or sets now playing with `submission=false`
+pub async fn subsonic_scrobble(
+    State(pool): State<PgPool>,
+    State(runtime): State<RuntimeSettings>,
+    method: Method,
+    Query(query): Query<Params>,
+    form: Result<Form<Params>, FormRejection>,
+) -> Response {
+    let params = merge(method, query, form);
+    let limit = runtime.limits().scrobble_max_batch;
+    render(&params, scrobble(&pool, &params, limit).await)
+}
+
+/// GET/POST /rest/getNowPlaying.view - what the user and public profiles are
+/// playing right now
+pub async fn subsonic_now_playing(
+    State(pool): State<PgPool>,
+    method: Method,
+    Query(query): Query<Params>,
+    form: Result<Form<Params>, FormRejection>,
+) -> Response {
+    let params = merge(method, query, form);
+    render(&params, now_playing(&pool, &params).await)
+}
+
+/// Parameters may be in the query string, a form body, or both
+fn merge(method: Method, mut query: Params, form: Result<Form<Params>, FormRejection>) -> Params {
+    if method == Method::POST {
+        if let Ok(Form(form)) = form {
+            query.extend(form);
+        }
+    }
+    query
+}
+
+fn param<'a>(params: &'a Params, name: &str) -> Option<&'a str> {
+    params.iter().find(|(k, _)| k == name).map(|(_, v)| v.as_str())
+}
+
+fn all_params<'a>(params: &'a Params, name: &str) -> Vec<&'a str> {
+    params.iter().filter(|(k, _)| k == name).map(|(_, v)| v.as_str()).collect()
+}
+
+fn md5_hex(input: &str) -> String {
+    hex::encode(Md5::digest(input.as_bytes()))
+}
+
+/// Resolve the user from `apiKey`, or `u` plus `t`/`s` or `p`
+async fn authenticate(pool: &PgPool, params: &Params, scope: Scope) -> Result<i64, SubsonicError> {
+    let wrong_credentials = || SubsonicError::new(WRONG_CREDENTIALS, "Wrong username or password");
+    let now = chrono::Utc::now().timestamp();
+
+    let api_key = param(params, "apiKey");
+    let username = param(params, "u");
+    if api_key.is_none() && username.is_none() {
+        return Err(SubsonicError::new(MISSING_PARAMETER, "Required parameter is missing: u"));
+    }
+
+    let tokens = sqlx::query!(
+        r#"
+        SELECT t.user_id, t.token, t.scopes
+        FROM api_tokens t
+        JOIN users u ON u.id = t.user_id
+        WHERE ($1::TEXT IS NULL OR u.username = $1) AND ($2::TEXT IS NULL OR t.token = $2)
+          AND t.revoked = false AND (t.expires_at IS NULL OR t.expires_at > $3)
+          AND (u.banned_at IS NULL OR u.banned_until <= $3)
+        "#,
+        username,
+        api_key,
+        now
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let token = if api_key.is_some() {
+        tokens.first()
+    } else if let (Some(t), Some(salt)) = (param(params, "t"), param(params, "s")) {
+        let t = t.to_ascii_lowercase();
+        tokens.iter().find(|token| md5_hex(&format!("{}{}", token.token, salt)) == t)
+    } else if let Some(password) = param(params, "p") {
+        let password = match password.strip_prefix("enc:") {
+            Some(encoded) => hex::decode(encoded)
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .ok_or_else(wrong_credentials)?,
+            None => password.to_string(),
+        };
+        tokens.iter().find(|token| token.token == password)
+    } else {
+        return Err(SubsonicError::new(MISSING_PARAMETER, "Required parameter is missing: t and s, or p"));
+    };
+
+    let token = token.ok_or_else(wrong_credentials)?;
+    if !token.scopes.iter().any(|s| s == scope.as_str()) {
+        return Err(SubsonicError::new(
+            NOT_AUTHORIZED,
+            format!("This token lacks the {} scope", scope.as_str()),
+        ));
+    }
+
+    Ok(token.user_id)
+}
+
+/// Each id costs a catalogue lookup and an insert, so a request holds at
+/// most `max_batch` of them, as on `/scrob`
+async fn scrobble(pool: &PgPool, params: &Params, max_batch: usize) -> Result<Map<String, Value>, SubsonicError> {
+    let user_id = authenticate(pool, params, Scope::Scrobble).await?;
+
+    let ids = all_params(params, "id");
+    if ids.is_empty() {
+        return Err(SubsonicError::new(MISSING_PARAMETER, "Required parameter is missing: id"));
+    }
+    if ids.len() > max_batch {
+        return Err(SubsonicError::new(
+            GENERIC_ERROR,
+            format!("At most {} songs can be scrobbled at once", max_batch),
+        ));
+    }
+
+    let submission = param(params, "submission").is_none_or(|s| !s.eq_ignore_ascii_case("false"));
+    let times = all_params(params, "time");
+    let now = chrono::Utc::now().timestamp();
+
+    // Resolve every song first so a bad id doesn't leave half a batch stored
+    let mut songs = Vec::with_capacity(ids.len());
+    for id in &ids {
+        let song = find_song(pool, user_id, id)
+            .await?
+            .ok_or_else(|| SubsonicError::new(NOT_FOUND, format!("Song not found: {}", id)))?;
+        songs.push(song);
+    }
+
+    if !submission {
+        // Only the last song can be playing
+        let Some(song) = songs.pop() else {
+            return Ok(Map::new());
+        };
+
+        let listen = relay::Listen {
+            artist: song.artist,
+            track: song.track,
+            album: song.album,
+            duration: song.duration,
+            timestamp: now,
+        };
+
+        now_playing_store::record(pool, user_id, &listen).await?;
+
+        if let Err(e) = relay::enqueue_now_playing(pool, user_id, &listen).await {
+            tracing::error!("Failed to queue now-playing for relays: {}", e);
+        }
+
+        return Ok(Map::new());
+    }
+
+    for (i, song) in songs.into_iter().enumerate() {
+        // Times are in milliseconds
+        let timestamp = times
+            .get(i)
+            .and_then(|t| t.parse::<i64>().ok())
+            .map(|ms| ms / 1000)
+            .unwrap_or(now);
+
+        let scrob = ScrobbleRequest {
+            artist: song.artist,
+            track: song.track,
+            timestamp: timestamp.max(0) as u64,
+            album: song.album,
+            album_artist: song.album_artist,
+            duration: song.duration.map(|d| d as u64),
+            track_number: None,
+            artist_mbid: song.artist_mbid,
+            release_mbid: song.release_mbid,
+            recording_mbid: song.recording_mbid,
+            source: Some("subsonic".to_string()),
+        };
+
+        if let ScrobbleOutcome::Rejected(reason) = submit_scrobble(pool, user_id, &scrob).await? {
+            tracing::info!("Subsonic scrobble for user {} rejected: {}", user_id, reason);
+        }
+    }
+
+    Ok(Map::new())
+}
+
+/// Look a song id up in the catalogue, by track id or recording MBID
+async fn find_song(pool: &PgPool, user_id: i64, id: &str) -> Result<Option<Song>, sqlx::Error> {
+    let track_id = id.parse::<i64>().ok();
+    let mbid = normalize_mbid(Some(id)).filter(|id| is_mbid(id));
+    if track_id.is_none() && mbid.is_none() {
+        return Ok(None);
+    }
+
+    // Album, duration and release come from the latest scrobble of the track,
+    // the user's own if there is one
+    sqlx::query_as!(
+        Song,
+        r#"
+        SELECT
+            a.name as artist,
+            t.name as track,
+            latest.album,
+            latest.album_artist,
+            latest.duration,
+            a.mbid as artist_mbid,
+            latest.release_mbid,
+            t.mbid as recording_mbid
+        FROM tracks t
+        JOIN artists a ON a.id = t.artist_id
+        LEFT JOIN LATERAL (
+            SELECT s.album, s.album_artist, s.duration, s.release_mbid
+            FROM scrobs s
+            WHERE s.track_id = t.id
+            ORDER BY s.user_id = $3 DESC, s.timestamp DESC
+            LIMIT 1
+        ) latest ON true
+        WHERE t.id = $1 OR t.mbid = $2
+        LIMIT 1
+        "#,
+        track_id,
+        mbid,
+        user_id
+    )
+    .fetch_optional(pool)
+    .await
+}
+
+async fn now_playing(pool: &PgPool, params: &Params) -> Result<Map<String, Value>, SubsonicError> {
+    let user_id = authenticate(pool, params, Scope::Read).await?;
+    let now = chrono::Utc::now().timestamp();
+
+    let entries = sqlx::query!(
+        r#"
+        SELECT
+            u.username,
+            n.artist,
+            n.track,
+            n.album,
+            n.duration,
+            n.started_at,
+            (
+                SELECT t.id FROM tracks t
+                JOIN artists a ON a.id = t.artist_id
+                WHERE a.name = n.artist AND t.name = n.track
+                ORDER BY t.mbid IS NOT NULL, t.id
+                LIMIT 1
+            ) as track_id
+        FROM now_playing n
+        JOIN users u ON u.id = n.user_id
+        WHERE (n.user_id = $1 OR u.is_private = false)
+          AND n.started_at + COALESCE(n.duration, $3) > $2
+        ORDER BY n.started_at DESC
+        "#,
+        user_id,
+        now,
+        now_playing_store::DEFAULT_TTL_SECS
+    )
+    .fetch_all(pool)
+    .await?;
+
+    let entries: Vec<Value> = entries
+        .into_iter()
+        .map(|e| {
+            json!({
+                "id": e.track_id.map(|id| id.to_string()).unwrap_or_default(),
+                "isDir": false,
+                "title": e.track,
+                "artist": e.artist,
+                "album": e.album,
+                "duration": e.duration,
+                "type": "music",
+                "username": e.username,
+                "minutesAgo": (now - e.started_at).max(0) / 60,
+                "playerId": 0,
+            })
+        })
+        .collect();
+
+    let mut body = Map::new();
+    body.insert("nowPlaying".to_string(), json!({ "entry": entries }));
+    Ok(body)
+}
+
+/// Wrap a result in `subsonic-response`: XML by default, JSON with `f=json`.
+/// Errors are reported in the body with a 200, as Subsonic does.
+fn render(params: &Params, result: Result<Map<String, Value>, SubsonicError>) -> Response {
+    let mut body = Map::new();
+    body.insert("status".to_string(), json!(if result.is_ok() { "ok" } else { "failed" }));
+    body.insert("version".to_string(), json!(API_VERSION));
+    body.insert("type".to_string(), json!("scrob"));
+    body.insert("serverVersion".to_string(), json!(env!("CARGO_PKG_VERSION")));
+    body.insert("openSubsonic".to_string(), json!(true));
+    match result {
+        Ok(fields) => body.extend(fields),
+        Err(e) => {
+            body.insert("error".to_string(), json!({ "code": e.code, "message": e.message }));
+        }
+    }
+
+    if param(params, "f") == Some("json") {
+        return axum::Json(json!({ "subsonic-response": body })).into_response();
+    }
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    body.insert("xmlns".to_string(), json!(XML_NAMESPACE));
+    write_xml(&mut xml, "subsonic-response", &body);
+    xml.push('\n');
+
+    (StatusCode::OK, [(header::CONTENT_TYPE, "text/xml; charset=utf-8")], xml).into_response()
+}
+
+/// Subsonic XML: scalar fields are attributes, objects are child elements
+/// and arrays repeat the element
+fn write_xml(out: &mut String, name: &str, fields: &Map<String, Value>) {
+    out.push('<');
+    out.push_str(name);
+    for (key, value) in fields {
+        let text = match value {
+            Value::String(s) => s.clone(),
+            Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            _ => continue,
+        };
+        out.push_str(&format!(" {}=\"{}\"", key, escape_xml(&text)));
+    }
+
+    let children: Vec<(&String, &Map<String, Value>)> = fields
+        .iter()
+        .flat_map(|(key, value)| match value {
+            Value::Object(child) => vec![(key, child)],
+            Value::Array(items) => items.iter().filter_map(|item| item.as_object()).map(|child| (key, child)).collect(),
+            _ => vec![],
+        })
+        .collect();
+
+    if children.is_empty() {
+        out.push_str("/>");
+        return;
+    }
+
+    out.push('>');
+    for (key, child) in children {
+        write_xml(out, key, child);
+    }
+    out.push_str(&format!("</{}>", name));
+}
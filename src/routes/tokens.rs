@@ -0,0 +1,264 @@
+use axum::{extract::{Path, State}, http::StatusCode, Json};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    auth::{create_token, extract_token_from_header, invalidate_user, AuthUser, Scope},
+    db::models::ApiToken,
+};
+
+const MAX_LABEL_LEN: usize = 100;
+/// Characters of a token shown in listings so users can tell them apart
+const TOKEN_PREFIX_LEN: usize = 8;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenRequest {
+    pub label: Option<String>,
+    /// Defaults to every scope
+    pub scopes: Option<Vec<String>>,
+    /// Seconds until the token expires; omitted means it never does
+    pub ttl: Option<i64>,
+}
+
+/// A token as listed; the secret itself is only returned when it is created
+#[derive(Debug, Serialize)]
+pub struct TokenInfo {
+    pub id: i64,
+    pub label: Option<String>,
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
+    pub expires_at: Option<i64>,
+    /// The token this request was made with
+    pub current: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    pub id: i64,
+    pub token: String,
+    pub label: Option<String>,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+}
+
+impl From<ApiToken> for CreatedToken {
+    fn from(token: ApiToken) -> Self {
+        CreatedToken {
+            id: token.id,
+            token: token.token,
+            label: token.label,
+            scopes: token.scopes,
+            created_at: token.created_at,
+            expires_at: token.expires_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+fn db_error(e: sqlx::Error) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::INTERNAL_SERVER_ERROR,
+        Json(ErrorResponse {
+            error: format!("Database error: {}", e),
+        }),
+    )
+}
+
+pub async fn list_tokens(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+) -> Result<Json<Vec<TokenInfo>>, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let current = headers
+        .get("authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(extract_token_from_header);
+
+    let tokens = sqlx::query!(
+        r#"
+        SELECT id, token, label, scopes, created_at, last_used_at, expires_at
+        FROM api_tokens
+        WHERE user_id = $1 AND revoked = false AND (expires_at IS NULL OR expires_at > $2)
+        ORDER BY created_at DESC, id DESC
+        "#,
+        user.id,
+        chrono::Utc::now().timestamp()
+    )
+    .fetch_all(&pool)
+    .await
+    .map_err(db_error)?
+    .into_iter()
+    .map(|row| TokenInfo {
+        id: row.id,
+        label: row.label,
+        prefix: row.token.chars().take(TOKEN_PREFIX_LEN).collect(),
+        scopes: row.scopes,
+        created_at: row.created_at,
+        last_used_at: row.last_used_at,
+        expires_at: row.expires_at,
+        current: current.as_deref() == Some(row.token.as_str()),
+    })
+    .collect();
+
+    Ok(Json(tokens))
+}
+
+/// Create a token for a scrobbling client. The response is the only time
+/// the token is shown.
+pub async fn create_api_token(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Json(req): Json<CreateTokenRequest>,
+) -> Result<(StatusCode, Json<CreatedToken>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let label = req.label.as_deref().map(str::trim).filter(|l| !l.is_empty()).unwrap_or("api");
+    if label.chars().count() > MAX_LABEL_LEN {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("label must be at most {} characters", MAX_LABEL_LEN),
+            }),
+        ));
+    }
+
+    let scopes = match req.scopes {
+        None => Scope::ALL.to_vec(),
+        Some(names) => {
+            let mut scopes = Vec::with_capacity(names.len());
+            for name in &names {
+                let Some(scope) = Scope::parse(name) else {
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Unknown scope '{}' (supported: scrobble, read, admin)", name),
+                        }),
+                    ));
+                };
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                }
+            }
+            if scopes.is_empty() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: "A token needs at least one scope".to_string(),
+                    }),
+                ));
+            }
+            scopes
+        }
+    };
+
+    let expires_at = match req.ttl {
+        None => None,
+        Some(ttl) if ttl > 0 => Some(chrono::Utc::now().timestamp().saturating_add(ttl)),
+        Some(_) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "ttl must be a positive number of seconds".to_string(),
+                }),
+            ));
+        }
+    };
+
+    let token = create_token(&pool, user.id, label, &scopes, expires_at).await.map_err(db_error)?;
+    tracing::info!("Created API token {} for user {}", token.id, user.id);
+
+    Ok((StatusCode::CREATED, Json(token.into())))
+}
+
+/// Replace a token with a fresh one carrying the same label and scopes, and
+/// revoke the old one. A token that expires gets the same lifetime again.
+pub async fn rotate_token(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(token_id): Path<i64>,
+) -> Result<(StatusCode, Json<CreatedToken>), (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tx = pool.begin().await.map_err(db_error)?;
+
+    let old = sqlx::query!(
+        r#"
+        UPDATE api_tokens
+        SET revoked = true
+        WHERE id = $1 AND user_id = $2 AND revoked = false AND (expires_at IS NULL OR expires_at > $3)
+        RETURNING label, scopes, created_at, expires_at
+        "#,
+        token_id,
+        user.id,
+        now
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(db_error)?
+    .ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Token not found".to_string(),
+            }),
+        )
+    })?;
+
+    let scopes: Vec<Scope> = old.scopes.iter().filter_map(|s| Scope::parse(s)).collect();
+    let expires_at = old.expires_at.map(|expires_at| now.saturating_add(expires_at - old.created_at));
+    let label = old.label.as_deref().unwrap_or("api");
+
+    let token = create_token(&mut *tx, user.id, label, &scopes, expires_at)
+        .await
+        .map_err(db_error)?;
+    tx.commit().await.map_err(db_error)?;
+    invalidate_user(user.id);
+
+    tracing::info!("Rotated API token {} to {} for user {}", token_id, token.id, user.id);
+
+    Ok((StatusCode::CREATED, Json(token.into())))
+}
+
+/// Revoke a token. Revoking the one the request was made with logs out.
+pub async fn revoke_token(
+    headers: axum::http::HeaderMap,
+    State(pool): State<PgPool>,
+    Path(token_id): Path<i64>,
+) -> Result<StatusCode, (StatusCode, Json<ErrorResponse>)> {
+    let user = AuthUser::from_headers(&pool, &headers).await
+        .map_err(|status| (status, Json(ErrorResponse { error: "Unauthorized".to_string() })))?;
+
+    let result = sqlx::query!(
+        "UPDATE api_tokens SET revoked = true WHERE id = $1 AND user_id = $2 AND revoked = false",
+        token_id,
+        user.id
+    )
+    .execute(&pool)
+    .await
+    .map_err(db_error)?;
+
+    if result.rows_affected() == 0 {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Token not found".to_string(),
+            }),
+        ));
+    }
+    invalidate_user(user.id);
+
+    tracing::info!("Revoked API token {} for user {}", token_id, user.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,126 @@
+//! Per-user scrobble rules from `scrobble_rules`, applied before a scrobble
+//! is stored
+
+use regex::{Regex, RegexBuilder};
+
+use crate::{db::DbPool, routes::scrobble::ScrobbleRequest};
+
+pub const RULE_FIELDS: [&str; 3] = ["artist", "track", "album"];
+pub const MATCH_TYPES: [&str; 2] = ["exact", "regex"];
+pub const RULE_ACTIONS: [&str; 2] = ["rewrite", "block"];
+pub const MAX_PATTERN_LEN: usize = 512;
+/// Cap on a compiled pattern, since every rule runs on every scrobble
+const REGEX_SIZE_LIMIT: usize = 256 * 1024;
+
+pub enum Matcher {
+  Exact(String),
+  Regex(Regex),
+}
+
+impl Matcher {
+  /// Check a pattern the way it will be used, returning why it's unusable
+  pub fn compile(match_type: &str, pattern: &str) -> Result<Self, String> {
+    if pattern.is_empty() {
+      return Err("pattern is required".to_string());
+    }
+    if pattern.len() > MAX_PATTERN_LEN {
+      return Err(format!("pattern must be at most {} bytes", MAX_PATTERN_LEN));
+    }
+
+    match match_type {
+      "exact" => Ok(Matcher::Exact(pattern.to_string())),
+      "regex" => RegexBuilder::new(pattern)
+        .size_limit(REGEX_SIZE_LIMIT)
+        .build()
+        .map(Matcher::Regex)
+        .map_err(|e| format!("Invalid regex: {}", e)),
+      _ => Err(format!("match_type must be one of: {}", MATCH_TYPES.join(", "))),
+    }
+  }
+
+  fn matches(&self, value: &str) -> bool {
+    match self {
+      Matcher::Exact(pattern) => value == pattern,
+      Matcher::Regex(re) => re.is_match(value),
+    }
+  }
+
+  fn rewrite(&self, value: &str, replacement: &str) -> String {
+    match self {
+      Matcher::Exact(_) => replacement.to_string(),
+      Matcher::Regex(re) => re.replace_all(value, replacement).into_owned(),
+    }
+  }
+}
+
+pub struct Rule {
+  pub id: i64,
+  field: String,
+  matcher: Matcher,
+  /// None blocks the scrobble
+  replacement: Option<String>,
+}
+
+/// The user's enabled rules in the order they apply
+pub async fn load(pool: &DbPool, user_id: i64) -> Result<Vec<Rule>, sqlx::Error> {
+  let rows = sqlx::query!(
+    r#"
+    SELECT id, field, match_type, pattern, action, replacement
+    FROM scrobble_rules
+    WHERE user_id = $1 AND enabled = true
+    ORDER BY id
+    "#,
+    user_id
+  )
+  .fetch_all(pool)
+  .await?;
+
+  let rules = rows
+    .into_iter()
+    .filter_map(|row| match Matcher::compile(&row.match_type, &row.pattern) {
+      Ok(matcher) => Some(Rule {
+        id: row.id,
+        field: row.field,
+        matcher,
+        replacement: if row.action == "rewrite" { row.replacement } else { None },
+      }),
+      Err(e) => {
+        tracing::warn!("Skipping scrobble rule {}: {}", row.id, e);
+        None
+      }
+    })
+    .collect();
+
+  Ok(rules)
+}
+
+/// Run the rules over a scrobble in order, rewriting it in place. Returns the
+/// id of the rule that blocked it, if one did.
+pub fn apply(rules: &[Rule], scrob: &mut ScrobbleRequest) -> Option<i64> {
+  for rule in rules {
+    let value = match rule.field.as_str() {
+      "artist" => &mut scrob.artist,
+      "track" => &mut scrob.track,
+      _ => match scrob.album.as_mut() {
+        Some(album) => album,
+        None => continue,
+      },
+    };
+
+    if !rule.matcher.matches(value) {
+      continue;
+    }
+
+    match &rule.replacement {
+      Some(replacement) => *value = rule.matcher.rewrite(value, replacement),
+      None => return Some(rule.id),
+    }
+  }
+
+  // Stripping an album down to nothing means there's no album
+  if scrob.album.as_deref().is_some_and(str::is_empty) {
+    scrob.album = None;
+  }
+
+  None
+}
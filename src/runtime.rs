@@ -0,0 +1,126 @@
+//! Operational limits admins can change without a restart. Columns in
+//! `server_settings` override the environment; NULL falls back to it.
+
+use std::{
+  sync::{Arc, RwLock},
+  time::Duration,
+};
+
+use crate::{config::Config, db::DbPool, health, limits::RateLimiter};
+
+/// How often other instances' changes are picked up
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+  pub scrobble_max_batch: usize,
+  pub scrobble_rate_limit: u32,
+  pub scrobble_rate_limit_window: u64,
+  /// None keeps scrobbles forever
+  pub retention_days: Option<u32>,
+  pub retention_scope: RetentionScope,
+}
+
+/// Whose scrobbles the retention job prunes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionScope {
+  All,
+  /// Only users whose ban is in force
+  Banned,
+}
+
+impl RetentionScope {
+  pub const NAMES: [&'static str; 2] = ["all", "banned"];
+
+  pub fn parse(name: &str) -> Option<Self> {
+    match name {
+      "all" => Some(Self::All),
+      "banned" => Some(Self::Banned),
+      _ => None,
+    }
+  }
+}
+
+/// The limits in effect, shared by handlers and the scrobble rate limiter
+#[derive(Clone)]
+pub struct RuntimeSettings {
+  defaults: Limits,
+  current: Arc<RwLock<Limits>>,
+  scrobble_limiter: RateLimiter,
+}
+
+impl RuntimeSettings {
+  /// Start from the environment until `reload` reads the overrides
+  pub fn new(config: &Config) -> Self {
+    let defaults = Limits {
+      scrobble_max_batch: config.scrobble_max_batch,
+      scrobble_rate_limit: config.scrobble_rate_limit,
+      scrobble_rate_limit_window: config.scrobble_rate_limit_window,
+      retention_days: config.retention_days,
+      retention_scope: RetentionScope::All,
+    };
+
+    Self {
+      defaults,
+      current: Arc::new(RwLock::new(defaults)),
+      scrobble_limiter: RateLimiter::new(defaults.scrobble_rate_limit, defaults.scrobble_rate_limit_window),
+    }
+  }
+
+  pub fn limits(&self) -> Limits {
+    *self.current.read().unwrap_or_else(|e| e.into_inner())
+  }
+
+  /// The limiter in front of scrobble submission
+  pub fn scrobble_limiter(&self) -> RateLimiter {
+    self.scrobble_limiter.clone()
+  }
+
+  /// Apply the overrides currently stored in `server_settings`
+  pub async fn reload(&self, pool: &DbPool) -> Result<Limits, sqlx::Error> {
+    let row = sqlx::query!(
+      r#"
+      SELECT scrobble_max_batch, scrobble_rate_limit, scrobble_rate_limit_window,
+             retention_days, retention_scope
+      FROM server_settings
+      WHERE id = 1
+      "#
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let limits = Limits {
+      scrobble_max_batch: row.scrobble_max_batch.map_or(self.defaults.scrobble_max_batch, |v| v as usize),
+      scrobble_rate_limit: row.scrobble_rate_limit.map_or(self.defaults.scrobble_rate_limit, |v| v as u32),
+      scrobble_rate_limit_window: row
+        .scrobble_rate_limit_window
+        .map_or(self.defaults.scrobble_rate_limit_window, |v| v as u64),
+      // 0 switches off retention the environment asks for
+      retention_days: match row.retention_days {
+        Some(days) => Some(days as u32).filter(|d| *d > 0),
+        None => self.defaults.retention_days,
+      },
+      retention_scope: RetentionScope::parse(&row.retention_scope).unwrap_or(RetentionScope::All),
+    };
+
+    self.scrobble_limiter.set(limits.scrobble_rate_limit, limits.scrobble_rate_limit_window);
+    *self.current.write().unwrap_or_else(|e| e.into_inner()) = limits;
+
+    Ok(limits)
+  }
+
+  /// Reload periodically so every instance follows changes made on another
+  pub fn spawn_refresh(&self, pool: DbPool) {
+    let settings = self.clone();
+    tokio::spawn(async move {
+      let mut interval = tokio::time::interval(REFRESH_INTERVAL);
+      loop {
+        interval.tick().await;
+        health::beat("settings_refresh", REFRESH_INTERVAL);
+        if let Err(e) = settings.reload(&pool).await {
+          tracing::warn!("Failed to reload server settings: {}", e);
+        }
+      }
+    });
+  }
+}
@@ -0,0 +1,186 @@
+//! On-disk queue for scrobbles accepted while the database is unreachable.
+//! Each spooled request is one JSON file holding the caller's token and its
+//! validated scrobbles; a worker replays them once the database is back.
+
+use std::{
+  path::{Path, PathBuf},
+  sync::Arc,
+  time::Duration,
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  auth::{get_user_by_token, Scope},
+  db::{self, DbPool},
+  health,
+  routes::scrobble::{submit_scrobble, ScrobbleRequest},
+};
+
+const REPLAY_INTERVAL: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SpoolEntry {
+  /// The user is resolved on replay, since auth needed the database too
+  token: String,
+  received_at: i64,
+  scrobbles: Vec<ScrobbleRequest>,
+}
+
+/// Bounded directory of spooled scrobble batches
+pub struct Spool {
+  dir: PathBuf,
+  max_entries: usize,
+}
+
+impl Spool {
+  pub fn new(dir: impl AsRef<Path>, max_entries: usize) -> Self {
+    Self {
+      dir: dir.as_ref().to_path_buf(),
+      max_entries,
+    }
+  }
+
+  /// `SPOOL_MAX_ENTRIES=0` turns spooling off
+  pub fn enabled(&self) -> bool {
+    self.max_entries > 0
+  }
+
+  /// Spooled batches, oldest first
+  async fn entries(&self) -> std::io::Result<Vec<PathBuf>> {
+    let mut entries = Vec::new();
+    let mut dir = match tokio::fs::read_dir(&self.dir).await {
+      Ok(dir) => dir,
+      Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(entries),
+      Err(e) => return Err(e),
+    };
+
+    while let Some(entry) = dir.next_entry().await? {
+      let path = entry.path();
+      if path.extension().is_some_and(|ext| ext == "json") {
+        entries.push(path);
+      }
+    }
+
+    // File names start with a zero-padded timestamp, so this is arrival order
+    entries.sort();
+    Ok(entries)
+  }
+
+  /// Queue a batch for replay; fails when the spool is full or unwritable
+  pub async fn push(&self, token: &str, scrobbles: Vec<ScrobbleRequest>) -> Result<(), String> {
+    let queued = self
+      .entries()
+      .await
+      .map_err(|e| format!("Failed to read spool directory: {}", e))?
+      .len();
+    if queued >= self.max_entries {
+      return Err(format!("Scrobble spool is full ({} batches)", queued));
+    }
+
+    tokio::fs::create_dir_all(&self.dir)
+      .await
+      .map_err(|e| format!("Failed to create spool directory: {}", e))?;
+
+    let now = chrono::Utc::now();
+    let entry = SpoolEntry {
+      token: token.to_string(),
+      received_at: now.timestamp(),
+      scrobbles,
+    };
+    let body = serde_json::to_vec(&entry).map_err(|e| format!("Failed to encode spool entry: {}", e))?;
+
+    let name = format!(
+      "{:020}-{:08x}",
+      now.timestamp_nanos_opt().unwrap_or_default(),
+      rand::random::<u32>()
+    );
+    let tmp = self.dir.join(format!("{}.tmp", name));
+    let path = self.dir.join(format!("{}.json", name));
+
+    tokio::fs::write(&tmp, body)
+      .await
+      .map_err(|e| format!("Failed to write spool entry: {}", e))?;
+    tokio::fs::rename(&tmp, &path)
+      .await
+      .map_err(|e| format!("Failed to write spool entry: {}", e))
+  }
+}
+
+/// Start the background task that drains the spool into the database
+pub fn spawn_replay(pool: DbPool, spool: Arc<Spool>) {
+  if !spool.enabled() {
+    return;
+  }
+
+  tokio::spawn(async move {
+    let mut interval = tokio::time::interval(REPLAY_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("spool_replay", REPLAY_INTERVAL);
+      if let Err(e) = replay(&pool, &spool).await {
+        tracing::warn!("Scrobble spool replay paused: {}", e);
+      }
+    }
+  });
+}
+
+/// Replay spooled batches in order, stopping at the first sign the database
+/// is still unavailable. Scrobbles are de-duplicated on insert, so a batch
+/// interrupted halfway is safe to replay from the start.
+async fn replay(pool: &DbPool, spool: &Spool) -> Result<(), String> {
+  let entries = spool
+    .entries()
+    .await
+    .map_err(|e| format!("Failed to read spool directory: {}", e))?;
+
+  for path in entries {
+    let entry: SpoolEntry = match tokio::fs::read(&path).await.map(|bytes| serde_json::from_slice(&bytes)) {
+      Ok(Ok(entry)) => entry,
+      Ok(Err(e)) => {
+        tracing::error!("Dropping unreadable spool entry {}: {}", path.display(), e);
+        remove(&path).await;
+        continue;
+      }
+      Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+
+    let user = match get_user_by_token(pool, &entry.token, Scope::Scrobble).await {
+      Ok(Some(user)) => user,
+      Ok(None) => {
+        tracing::warn!(
+          "Dropping {} spooled scrobble(s) from {}: token is no longer valid",
+          entry.scrobbles.len(),
+          entry.received_at
+        );
+        remove(&path).await;
+        continue;
+      }
+      Err(e) if db::is_unavailable(&e) => return Err(e.to_string()),
+      Err(e) => {
+        tracing::error!("Failed to resolve spooled token: {}", e);
+        continue;
+      }
+    };
+
+    for scrob in &entry.scrobbles {
+      if let Err(e) = submit_scrobble(pool, user.id, scrob).await {
+        if db::is_unavailable(&e) {
+          return Err(e.to_string());
+        }
+        tracing::error!("Dropping spooled scrobble for user {}: {}", user.id, e);
+      }
+    }
+
+    tracing::info!("Replayed {} spooled scrobble(s) for user {}", entry.scrobbles.len(), user.id);
+    remove(&path).await;
+  }
+
+  Ok(())
+}
+
+async fn remove(path: &Path) {
+  if let Err(e) = tokio::fs::remove_file(path).await {
+    tracing::error!("Failed to remove spool entry {}: {}", path.display(), e);
+  }
+}
@@ -0,0 +1,516 @@
+//! Spotify account linking (authorization code flow) and the poller that
+//! turns linked users' recently played tracks into scrobbles
+
+use std::{sync::Arc, time::Duration};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::Deserialize;
+
+use crate::{
+  config::Config,
+  crypto::SecretBox,
+  db::DbPool,
+  health,
+  routes::scrobble::{insert_scrobbles, validate_scrobble, ScrobbleOutcome, ScrobbleRequest},
+};
+
+const AUTHORIZE_URL: &str = "https://accounts.spotify.com/authorize";
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+const API_ROOT: &str = "https://api.spotify.com/v1";
+const SCOPES: &str = "user-read-recently-played";
+/// How long linking may take between the redirect and the callback
+const LINK_STATE_TTL_SECS: i64 = 10 * 60;
+/// Spotify only remembers the last 50 plays, so poll well within that
+const POLL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const RECENTLY_PLAYED_LIMIT: &str = "50";
+/// Refresh access tokens this long before they expire
+const TOKEN_REFRESH_MARGIN_SECS: i64 = 60;
+/// A scrobble of the same track starting this long before a Spotify play,
+/// or during it, is taken to be the same listen
+const DEDUP_SLACK_SECS: i64 = 2 * 60;
+
+/// App settings, present when `SPOTIFY_CLIENT_ID` is set
+#[derive(Debug, Clone)]
+pub struct SpotifySettings {
+  pub client_id: String,
+  pub client_secret: String,
+  pub redirect_url: String,
+}
+
+impl SpotifySettings {
+  pub fn from_config(config: &Config) -> Option<Self> {
+    Some(Self {
+      client_id: config.spotify_client_id.clone()?,
+      client_secret: config.spotify_client_secret.clone()?,
+      redirect_url: config.spotify_redirect_url.clone()?,
+    })
+  }
+}
+
+#[derive(Debug)]
+pub enum SpotifyError {
+  /// Spotify couldn't be reached or answered with something unusable
+  Provider(String),
+  /// The callback doesn't belong to a link we started
+  Rejected(String),
+  Database(sqlx::Error),
+}
+
+impl std::fmt::Display for SpotifyError {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      SpotifyError::Provider(e) => write!(f, "Spotify error: {}", e),
+      SpotifyError::Rejected(e) => write!(f, "{}", e),
+      SpotifyError::Database(e) => write!(f, "Database error: {}", e),
+    }
+  }
+}
+
+impl From<sqlx::Error> for SpotifyError {
+  fn from(e: sqlx::Error) -> Self {
+    SpotifyError::Database(e)
+  }
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+  access_token: String,
+  expires_in: i64,
+  /// Not always sent on refresh, in which case the old one stays valid
+  refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Profile {
+  id: String,
+  display_name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecentlyPlayed {
+  #[serde(default)]
+  items: Vec<PlayHistory>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayHistory {
+  track: SpotifyTrack,
+  played_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyTrack {
+  name: String,
+  duration_ms: i64,
+  track_number: Option<u32>,
+  #[serde(default)]
+  artists: Vec<Named>,
+  album: Option<SpotifyAlbum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpotifyAlbum {
+  name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Named {
+  name: String,
+}
+
+fn http_client() -> Result<reqwest::Client, SpotifyError> {
+  reqwest::Client::builder()
+    .user_agent(concat!("scrob/", env!("CARGO_PKG_VERSION")))
+    .timeout(Duration::from_secs(10))
+    .build()
+    .map_err(|e| SpotifyError::Provider(format!("Failed to build HTTP client: {}", e)))
+}
+
+/// Record a link attempt for the user and return the Spotify URL to send them to
+pub async fn start_link(pool: &DbPool, settings: &SpotifySettings, user_id: i64) -> Result<String, SpotifyError> {
+  let state = URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>());
+  let now = chrono::Utc::now().timestamp();
+
+  // Abandoned attempts are cleared out whenever a new one starts
+  sqlx::query!(
+    "DELETE FROM spotify_link_states WHERE created_at < $1",
+    now - LINK_STATE_TTL_SECS
+  )
+  .execute(pool)
+  .await?;
+
+  sqlx::query!(
+    "INSERT INTO spotify_link_states (state, user_id, created_at) VALUES ($1, $2, $3)",
+    state,
+    user_id,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  let mut url = reqwest::Url::parse(AUTHORIZE_URL).expect("valid Spotify authorize URL");
+  url
+    .query_pairs_mut()
+    .append_pair("response_type", "code")
+    .append_pair("client_id", &settings.client_id)
+    .append_pair("redirect_uri", &settings.redirect_url)
+    .append_pair("scope", SCOPES)
+    .append_pair("state", &state);
+
+  Ok(url.into())
+}
+
+/// Exchange the callback's code for tokens and link the account to the user
+/// who started the flow, returning their id. Relinking keeps the cursor if
+/// it's the same account.
+pub async fn finish_link(
+  pool: &DbPool,
+  settings: &SpotifySettings,
+  secrets: &SecretBox,
+  code: &str,
+  state: &str,
+) -> Result<i64, SpotifyError> {
+  let now = chrono::Utc::now().timestamp();
+
+  // Each state can only be used once
+  let user_id = sqlx::query!(
+    "DELETE FROM spotify_link_states WHERE state = $1 RETURNING user_id, created_at",
+    state
+  )
+  .fetch_optional(pool)
+  .await?
+  .filter(|link| now - link.created_at <= LINK_STATE_TTL_SECS)
+  .ok_or_else(|| SpotifyError::Rejected("Unknown or expired link request, please start again".to_string()))?
+  .user_id;
+
+  let client = http_client()?;
+  let tokens = request_tokens(
+    &client,
+    settings,
+    &[
+      ("grant_type", "authorization_code"),
+      ("code", code),
+      ("redirect_uri", &settings.redirect_url),
+    ],
+  )
+  .await?;
+  let refresh_token = tokens
+    .refresh_token
+    .as_deref()
+    .ok_or_else(|| SpotifyError::Provider("No refresh token in the token response".to_string()))?;
+
+  let profile: Profile = client
+    .get(format!("{}/me", API_ROOT))
+    .bearer_auth(&tokens.access_token)
+    .send()
+    .await
+    .and_then(|r| r.error_for_status())
+    .map_err(|e| SpotifyError::Provider(format!("Profile request failed: {}", e)))?
+    .json()
+    .await
+    .map_err(|e| SpotifyError::Provider(format!("Invalid profile: {}", e)))?;
+
+  let encrypt = |secret: &str| secrets.encrypt(secret).map_err(SpotifyError::Provider);
+
+  sqlx::query!(
+    r#"
+    INSERT INTO spotify_links (user_id, spotify_user_id, display_name, access_token, refresh_token, expires_at, linked_at)
+    VALUES ($1, $2, $3, $4, $5, $6, $7)
+    ON CONFLICT (user_id) DO UPDATE
+    SET spotify_user_id = $2, display_name = $3, access_token = $4, refresh_token = $5, expires_at = $6,
+        linked_at = $7, last_error = NULL,
+        cursor_ms = CASE WHEN spotify_links.spotify_user_id = $2 THEN spotify_links.cursor_ms END
+    "#,
+    user_id,
+    profile.id,
+    profile.display_name,
+    encrypt(&tokens.access_token)?,
+    encrypt(refresh_token)?,
+    now + tokens.expires_in,
+    now
+  )
+  .execute(pool)
+  .await?;
+
+  tracing::info!("User {} linked Spotify account {}", user_id, profile.id);
+
+  Ok(user_id)
+}
+
+async fn request_tokens(
+  client: &reqwest::Client,
+  settings: &SpotifySettings,
+  form: &[(&str, &str)],
+) -> Result<TokenResponse, SpotifyError> {
+  let response = client
+    .post(TOKEN_URL)
+    .basic_auth(&settings.client_id, Some(&settings.client_secret))
+    .form(form)
+    .send()
+    .await
+    .map_err(|e| SpotifyError::Provider(format!("Token request failed: {}", e)))?;
+
+  if !response.status().is_success() {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    return Err(SpotifyError::Provider(format!(
+      "Token request failed with HTTP {}: {}",
+      status.as_u16(),
+      body.chars().take(200).collect::<String>()
+    )));
+  }
+
+  response
+    .json()
+    .await
+    .map_err(|e| SpotifyError::Provider(format!("Invalid token response: {}", e)))
+}
+
+/// Start the background task that polls every linked account
+pub fn spawn(pool: DbPool, config: &Config, secrets: Option<Arc<SecretBox>>) {
+  let Some(settings) = SpotifySettings::from_config(config) else {
+    return;
+  };
+  let Some(secrets) = secrets else {
+    tracing::warn!("SPOTIFY_CLIENT_ID is set but SECRET_KEY is not; Spotify sync is disabled");
+    return;
+  };
+
+  tokio::spawn(async move {
+    let client = match http_client() {
+      Ok(client) => client,
+      Err(e) => {
+        tracing::error!("Spotify poller not started: {}", e);
+        return;
+      }
+    };
+
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+      interval.tick().await;
+      health::beat("spotify", POLL_INTERVAL);
+      if let Err(e) = poll_all(&pool, &client, &settings, &secrets).await {
+        tracing::error!("Spotify polling failed: {}", e);
+      }
+    }
+  });
+}
+
+async fn poll_all(
+  pool: &DbPool,
+  client: &reqwest::Client,
+  settings: &SpotifySettings,
+  secrets: &SecretBox,
+) -> Result<(), sqlx::Error> {
+  // Banned users' plays are picked up after the ban, if Spotify still has
+  // them. Anonymizing deletes the link; the filter covers one made meanwhile.
+  let user_ids = sqlx::query_scalar!(
+    r#"
+    SELECT l.user_id
+    FROM spotify_links l
+    JOIN users u ON u.id = l.user_id
+    WHERE (u.banned_at IS NULL OR u.banned_until <= $1) AND u.anonymized_at IS NULL
+    ORDER BY l.user_id
+    "#,
+    chrono::Utc::now().timestamp()
+  )
+  .fetch_all(pool)
+  .await?;
+
+  for user_id in user_ids {
+    let error = match poll(pool, client, settings, secrets, user_id).await {
+      Ok(()) => None,
+      Err(SpotifyError::Database(e)) => return Err(e),
+      Err(e) => {
+        tracing::warn!("Spotify poll for user {} failed: {}", user_id, e);
+        Some(e.to_string())
+      }
+    };
+
+    sqlx::query!(
+      "UPDATE spotify_links SET last_polled_at = $1, last_error = $2 WHERE user_id = $3",
+      chrono::Utc::now().timestamp(),
+      error,
+      user_id
+    )
+    .execute(pool)
+    .await?;
+  }
+
+  Ok(())
+}
+
+/// Fetch the user's plays since the cursor and store the new ones
+async fn poll(
+  pool: &DbPool,
+  client: &reqwest::Client,
+  settings: &SpotifySettings,
+  secrets: &SecretBox,
+  user_id: i64,
+) -> Result<(), SpotifyError> {
+  let Some(link) = sqlx::query!(
+    "SELECT access_token, refresh_token, expires_at, cursor_ms FROM spotify_links WHERE user_id = $1",
+    user_id
+  )
+  .fetch_optional(pool)
+  .await?
+  else {
+    // Unlinked since the poll started
+    return Ok(());
+  };
+
+  let decrypt = |secret: &str| secrets.decrypt(secret).map_err(SpotifyError::Provider);
+  let now = chrono::Utc::now().timestamp();
+
+  let access_token = if link.expires_at - TOKEN_REFRESH_MARGIN_SECS > now {
+    decrypt(&link.access_token)?
+  } else {
+    let refresh_token = decrypt(&link.refresh_token)?;
+    let tokens = request_tokens(
+      client,
+      settings,
+      &[("grant_type", "refresh_token"), ("refresh_token", &refresh_token)],
+    )
+    .await?;
+
+    let new_refresh = match &tokens.refresh_token {
+      Some(token) => secrets.encrypt(token).map_err(SpotifyError::Provider)?,
+      None => link.refresh_token.clone(),
+    };
+    sqlx::query!(
+      "UPDATE spotify_links SET access_token = $1, refresh_token = $2, expires_at = $3 WHERE user_id = $4",
+      secrets.encrypt(&tokens.access_token).map_err(SpotifyError::Provider)?,
+      new_refresh,
+      now + tokens.expires_in,
+      user_id
+    )
+    .execute(pool)
+    .await?;
+
+    tokens.access_token
+  };
+
+  let mut request = client
+    .get(format!("{}/me/player/recently-played", API_ROOT))
+    .bearer_auth(&access_token)
+    .query(&[("limit", RECENTLY_PLAYED_LIMIT)]);
+  if let Some(cursor) = link.cursor_ms {
+    request = request.query(&[("after", cursor.to_string())]);
+  }
+
+  let response = request
+    .send()
+    .await
+    .map_err(|e| SpotifyError::Provider(format!("Recently played request failed: {}", e)))?;
+
+  if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+    // Refresh on the next poll
+    sqlx::query!("UPDATE spotify_links SET expires_at = 0 WHERE user_id = $1", user_id)
+      .execute(pool)
+      .await?;
+    return Err(SpotifyError::Provider("Access token was rejected".to_string()));
+  }
+
+  let played: RecentlyPlayed = response
+    .error_for_status()
+    .map_err(|e| SpotifyError::Provider(format!("Recently played request failed: {}", e)))?
+    .json()
+    .await
+    .map_err(|e| SpotifyError::Provider(format!("Invalid recently played response: {}", e)))?;
+
+  let mut cursor = link.cursor_ms;
+  let mut plays: Vec<(ScrobbleRequest, i64)> = Vec::new();
+  for item in played.items {
+    let Ok(played_at) = chrono::DateTime::parse_from_rfc3339(&item.played_at) else {
+      continue;
+    };
+    let played_at_ms = played_at.timestamp_millis();
+    cursor = Some(cursor.map_or(played_at_ms, |c| c.max(played_at_ms)));
+
+    let Some(artist) = item.track.artists.first() else {
+      continue;
+    };
+    let duration = item.track.duration_ms / 1000;
+    let ended_at = played_at.timestamp();
+
+    // Spotify records when playback ended; scrobbles are timestamped at the start
+    let scrob = ScrobbleRequest {
+      artist: artist.name.clone(),
+      track: item.track.name,
+      timestamp: (ended_at - duration).max(0) as u64,
+      album: item.track.album.map(|a| a.name).filter(|a| !a.is_empty()),
+      album_artist: None,
+      duration: (duration > 0).then_some(duration as u64),
+      track_number: item.track.track_number,
+      artist_mbid: None,
+      release_mbid: None,
+      recording_mbid: None,
+      source: Some("spotify".to_string()),
+    };
+    if validate_scrobble(&scrob).is_ok() {
+      plays.push((scrob, ended_at));
+    }
+  }
+
+  let mut fresh = without_known_listens(pool, user_id, plays).await?;
+  if !fresh.is_empty() {
+    let outcomes = insert_scrobbles(pool, user_id, &mut fresh).await?;
+    let accepted = outcomes.iter().filter(|o| matches!(o, ScrobbleOutcome::Accepted(_))).count();
+    tracing::info!("Stored {} of {} new Spotify plays for user {}", accepted, fresh.len(), user_id);
+  }
+
+  if cursor != link.cursor_ms {
+    sqlx::query!("UPDATE spotify_links SET cursor_ms = $1 WHERE user_id = $2", cursor, user_id)
+      .execute(pool)
+      .await?;
+  }
+
+  Ok(())
+}
+
+/// Drop plays the user already scrobbled some other way: a scrobble of the
+/// same track (ignoring case) that started shortly before or during the play
+async fn without_known_listens(
+  pool: &DbPool,
+  user_id: i64,
+  plays: Vec<(ScrobbleRequest, i64)>,
+) -> Result<Vec<ScrobbleRequest>, sqlx::Error> {
+  if plays.is_empty() {
+    return Ok(Vec::new());
+  }
+
+  let artists: Vec<&str> = plays.iter().map(|(s, _)| s.artist.as_str()).collect();
+  let tracks: Vec<&str> = plays.iter().map(|(s, _)| s.track.as_str()).collect();
+  let starts: Vec<i64> = plays.iter().map(|(s, _)| s.timestamp as i64).collect();
+  let ends: Vec<i64> = plays.iter().map(|(_, ended_at)| *ended_at).collect();
+
+  let known = sqlx::query_scalar!(
+    r#"
+    SELECT p.n as "n!"
+    FROM UNNEST($2::TEXT[], $3::TEXT[], $4::BIGINT[], $5::BIGINT[]) WITH ORDINALITY AS p(artist, track, started, ended, n)
+    WHERE EXISTS (
+        SELECT 1 FROM scrobs s
+        WHERE s.user_id = $1
+          AND s.timestamp BETWEEN p.started - $6 AND p.ended
+          AND lower(s.artist) = lower(p.artist) AND lower(s.track) = lower(p.track)
+    )
+    "#,
+    user_id,
+    &artists as &[&str],
+    &tracks as &[&str],
+    &starts,
+    &ends,
+    DEDUP_SLACK_SECS
+  )
+  .fetch_all(pool)
+  .await?;
+
+  Ok(
+    plays
+      .into_iter()
+      .enumerate()
+      .filter(|(i, _)| !known.contains(&(*i as i64 + 1)))
+      .map(|(_, (scrob, _))| scrob)
+      .collect(),
+  )
+}
@@ -0,0 +1,69 @@
+use std::sync::Arc;
+
+use axum::extract::FromRef;
+
+use crate::{
+  config::Config, crypto::SecretBox, db::DbPool, feed::Feed, mail::Mailer, runtime::RuntimeSettings, spool::Spool,
+  storage::Storage,
+};
+
+/// Shared state handed to every handler
+#[derive(Clone)]
+pub struct AppState {
+  pub pool: DbPool,
+  pub config: Arc<Config>,
+  pub secrets: Option<Arc<SecretBox>>,
+  pub mailer: Option<Arc<Mailer>>,
+  pub storage: Arc<dyn Storage>,
+  pub spool: Arc<Spool>,
+  pub feed: Feed,
+  pub runtime: RuntimeSettings,
+}
+
+impl FromRef<AppState> for DbPool {
+  fn from_ref(state: &AppState) -> Self {
+    state.pool.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+  fn from_ref(state: &AppState) -> Self {
+    state.config.clone()
+  }
+}
+
+impl FromRef<AppState> for Option<Arc<SecretBox>> {
+  fn from_ref(state: &AppState) -> Self {
+    state.secrets.clone()
+  }
+}
+
+impl FromRef<AppState> for Option<Arc<Mailer>> {
+  fn from_ref(state: &AppState) -> Self {
+    state.mailer.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<dyn Storage> {
+  fn from_ref(state: &AppState) -> Self {
+    state.storage.clone()
+  }
+}
+
+impl FromRef<AppState> for Arc<Spool> {
+  fn from_ref(state: &AppState) -> Self {
+    state.spool.clone()
+  }
+}
+
+impl FromRef<AppState> for Feed {
+  fn from_ref(state: &AppState) -> Self {
+    state.feed.clone()
+  }
+}
+
+impl FromRef<AppState> for RuntimeSettings {
+  fn from_ref(state: &AppState) -> Self {
+    state.runtime.clone()
+  }
+}
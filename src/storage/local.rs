@@ -0,0 +1,69 @@
+use std::{
+  io::ErrorKind,
+  path::{Path, PathBuf},
+};
+
+use async_trait::async_trait;
+
+use super::{validate_key, Storage};
+
+/// Objects are plain files under a root directory
+pub struct LocalStorage {
+  root: PathBuf,
+}
+
+impl LocalStorage {
+  pub fn new(root: impl AsRef<Path>) -> Self {
+    Self {
+      root: root.as_ref().to_path_buf(),
+    }
+  }
+
+  fn path(&self, key: &str) -> Result<PathBuf, String> {
+    validate_key(key)?;
+    Ok(self.root.join(key))
+  }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+  fn backend(&self) -> &'static str {
+    "local"
+  }
+
+  async fn put(&self, key: &str, bytes: Vec<u8>, _content_type: &str) -> Result<(), String> {
+    let path = self.path(key)?;
+    if let Some(parent) = path.parent() {
+      tokio::fs::create_dir_all(parent)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+
+    // Write next to the target and rename, so readers never see a partial file
+    let tmp = path.with_extension(format!("tmp-{}", rand::random::<u32>()));
+    tokio::fs::write(&tmp, bytes)
+      .await
+      .map_err(|e| format!("Failed to write {}: {}", tmp.display(), e))?;
+    tokio::fs::rename(&tmp, &path)
+      .await
+      .map_err(|e| format!("Failed to move {} into place: {}", path.display(), e))
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+    let path = self.path(key)?;
+    match tokio::fs::read(&path).await {
+      Ok(bytes) => Ok(Some(bytes)),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+      Err(e) => Err(format!("Failed to read {}: {}", path.display(), e)),
+    }
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), String> {
+    let path = self.path(key)?;
+    match tokio::fs::remove_file(&path).await {
+      Ok(()) => Ok(()),
+      Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+      Err(e) => Err(format!("Failed to delete {}: {}", path.display(), e)),
+    }
+  }
+}
@@ -0,0 +1,77 @@
+//! Blob storage for media assets (avatars, cover art, collages, backups).
+//! The backend is picked by `STORAGE_BACKEND`; features only ever see the
+//! `Storage` trait.
+
+mod local;
+mod s3;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::config::Config;
+
+pub use local::LocalStorage;
+pub use s3::S3Storage;
+
+const PROBE_KEY: &str = ".scrob-storage-probe";
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+  /// Short backend name for logs and server info
+  fn backend(&self) -> &'static str;
+
+  /// Store `bytes` under `key`, replacing any existing object
+  async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String>;
+
+  /// Fetch the object under `key`, or `None` if it doesn't exist
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String>;
+
+  /// Remove the object under `key`; missing objects are not an error
+  async fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+/// Build the configured backend
+pub fn from_config(config: &Config) -> Result<Arc<dyn Storage>, String> {
+  match config.storage_backend.as_str() {
+    "local" => Ok(Arc::new(LocalStorage::new(&config.storage_path))),
+    "s3" => Ok(Arc::new(S3Storage::from_config(config)?)),
+    other => Err(format!("Unknown STORAGE_BACKEND '{}' (expected local or s3)", other)),
+  }
+}
+
+/// Keys are relative `/`-separated paths of plain characters, which keeps
+/// them safe as file paths and as S3 object names without escaping
+pub fn validate_key(key: &str) -> Result<(), String> {
+  let valid = !key.is_empty()
+    && key.split('/').all(|segment| {
+      !segment.is_empty()
+        && segment != "."
+        && segment != ".."
+        && segment
+          .chars()
+          .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    });
+
+  if valid {
+    Ok(())
+  } else {
+    Err(format!("Invalid storage key '{}'", key))
+  }
+}
+
+/// Round-trip a small object so misconfigured storage shows up at startup
+/// rather than the first time someone uploads an avatar
+pub async fn check(storage: &dyn Storage) -> Result<(), String> {
+  let payload = chrono::Utc::now().timestamp().to_string().into_bytes();
+
+  storage.put(PROBE_KEY, payload.clone(), "text/plain").await?;
+  let read_back = storage.get(PROBE_KEY).await?;
+  storage.delete(PROBE_KEY).await?;
+
+  if read_back.as_deref() != Some(payload.as_slice()) {
+    return Err("Stored probe object did not read back intact".to_string());
+  }
+
+  Ok(())
+}
@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use hmac::{Hmac, Mac};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use super::{validate_key, Storage};
+use crate::config::Config;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Any S3-compatible object store (AWS, MinIO, Garage, R2, ...), addressed
+/// path-style as `{endpoint}/{bucket}/{key}` and signed with SigV4
+pub struct S3Storage {
+  client: reqwest::Client,
+  endpoint: Url,
+  bucket: String,
+  region: String,
+  access_key: String,
+  secret_key: String,
+}
+
+impl S3Storage {
+  pub fn from_config(config: &Config) -> Result<Self, String> {
+    let required = |value: &Option<String>, name: &str| {
+      value
+        .clone()
+        .ok_or_else(|| format!("{} is required when STORAGE_BACKEND=s3", name))
+    };
+
+    let endpoint = required(&config.s3_endpoint, "S3_ENDPOINT")?;
+    let endpoint = Url::parse(&endpoint).map_err(|e| format!("Invalid S3_ENDPOINT: {}", e))?;
+    if endpoint.host_str().is_none() {
+      return Err("Invalid S3_ENDPOINT: missing host".to_string());
+    }
+
+    let client = reqwest::Client::builder()
+      .timeout(REQUEST_TIMEOUT)
+      .build()
+      .map_err(|e| format!("Failed to build S3 client: {}", e))?;
+
+    Ok(Self {
+      client,
+      endpoint,
+      bucket: required(&config.s3_bucket, "S3_BUCKET")?,
+      region: config.s3_region.clone(),
+      access_key: required(&config.s3_access_key, "S3_ACCESS_KEY")?,
+      secret_key: required(&config.s3_secret_key, "S3_SECRET_KEY")?,
+    })
+  }
+
+  /// Send a signed request for `key`, returning the response status and body
+  async fn send(
+    &self,
+    method: Method,
+    key: &str,
+    body: Vec<u8>,
+    content_type: Option<&str>,
+  ) -> Result<(StatusCode, Vec<u8>), String> {
+    validate_key(key)?;
+
+    let base = self.endpoint.path().trim_end_matches('/');
+    let path = format!("{}/{}/{}", base, self.bucket, key);
+    let mut url = self.endpoint.clone();
+    url.set_path(&path);
+
+    let host = match (url.host_str(), url.port()) {
+      (Some(host), Some(port)) => format!("{}:{}", host, port),
+      (Some(host), None) => host.to_string(),
+      (None, _) => return Err("S3 endpoint has no host".to_string()),
+    };
+
+    let now = chrono::Utc::now();
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let payload_hash = hex::encode(Sha256::digest(&body));
+
+    let canonical_request = format!(
+      "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+      method, path, host, payload_hash, amz_date, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+    let string_to_sign = format!(
+      "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+      amz_date,
+      scope,
+      hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let mut signing_key = format!("AWS4{}", self.secret_key).into_bytes();
+    for part in [date.as_str(), self.region.as_str(), "s3", "aws4_request"] {
+      signing_key = hmac_sha256(&signing_key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+
+    let authorization = format!(
+      "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+      self.access_key, scope, signature
+    );
+
+    let mut request = self
+      .client
+      .request(method, url)
+      .header("x-amz-date", amz_date)
+      .header("x-amz-content-sha256", payload_hash)
+      .header("authorization", authorization);
+    if let Some(content_type) = content_type {
+      request = request.header("content-type", content_type);
+    }
+
+    let response = request
+      .body(body)
+      .send()
+      .await
+      .map_err(|e| format!("S3 request failed: {}", e))?;
+    let status = response.status();
+    let bytes = response
+      .bytes()
+      .await
+      .map_err(|e| format!("Failed to read S3 response: {}", e))?;
+
+    Ok((status, bytes.to_vec()))
+  }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+  let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+  mac.update(data);
+  mac.finalize().into_bytes().to_vec()
+}
+
+fn s3_error(action: &str, key: &str, status: StatusCode, body: &[u8]) -> String {
+  format!(
+    "S3 {} of {} failed with {}: {}",
+    action,
+    key,
+    status,
+    String::from_utf8_lossy(body).chars().take(200).collect::<String>()
+  )
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+  fn backend(&self) -> &'static str {
+    "s3"
+  }
+
+  async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<(), String> {
+    let (status, body) = self.send(Method::PUT, key, bytes, Some(content_type)).await?;
+    if !status.is_success() {
+      return Err(s3_error("upload", key, status, &body));
+    }
+    Ok(())
+  }
+
+  async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+    let (status, body) = self.send(Method::GET, key, Vec::new(), None).await?;
+    match status {
+      StatusCode::NOT_FOUND => Ok(None),
+      status if status.is_success() => Ok(Some(body)),
+      status => Err(s3_error("download", key, status, &body)),
+    }
+  }
+
+  async fn delete(&self, key: &str) -> Result<(), String> {
+    let (status, body) = self.send(Method::DELETE, key, Vec::new(), None).await?;
+    if !status.is_success() && status != StatusCode::NOT_FOUND {
+      return Err(s3_error("delete", key, status, &body));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,65 @@
+//! HTTPS served directly from `TLS_CERT_PATH`/`TLS_KEY_PATH`, for small
+//! deployments without a reverse proxy. The files are checked every minute
+//! and on SIGHUP, so a renewed certificate is picked up without a restart.
+
+use std::{path::Path, time::Duration, time::SystemTime};
+
+use axum_server::tls_rustls::RustlsConfig;
+
+use crate::health;
+
+const RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Load the certificate chain and key, failing startup if they're unusable
+pub async fn load(cert_path: &str, key_path: &str) -> Result<RustlsConfig, String> {
+  // reqwest and lettre already pull in ring; make it the process default so
+  // the server config doesn't depend on which provider features are enabled
+  let _ = rustls::crypto::ring::default_provider().install_default();
+
+  RustlsConfig::from_pem_file(cert_path, key_path)
+    .await
+    .map_err(|e| format!("Failed to load TLS certificate {} / key {}: {}", cert_path, key_path, e))
+}
+
+/// Swap in the certificate when either file changes or on SIGHUP. A failed
+/// reload keeps serving the previous certificate.
+pub fn spawn_reload(tls: RustlsConfig, cert_path: String, key_path: String) {
+  tokio::spawn(async move {
+    let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+      Ok(signal) => Some(signal),
+      Err(e) => {
+        tracing::warn!("Failed to listen for SIGHUP, reloading TLS on file changes only: {}", e);
+        None
+      }
+    };
+
+    let mut loaded = (modified(&cert_path), modified(&key_path));
+    let mut interval = tokio::time::interval(RELOAD_INTERVAL);
+
+    loop {
+      let forced = tokio::select! {
+        _ = interval.tick() => false,
+        Some(()) = async { hangup.as_mut()?.recv().await } => true,
+      };
+      health::beat("tls_reload", RELOAD_INTERVAL);
+
+      let current = (modified(&cert_path), modified(&key_path));
+      if !forced && current == loaded {
+        continue;
+      }
+
+      match tls.reload_from_pem_file(&cert_path, &key_path).await {
+        Ok(()) => {
+          tracing::info!("Reloaded TLS certificate from {}", cert_path);
+          loaded = current;
+        }
+        Err(e) => tracing::warn!("Failed to reload TLS certificate, keeping the current one: {}", e),
+      }
+    }
+  });
+}
+
+/// Follows symlinks, so a swapped certbot `live/` link counts as a change
+fn modified(path: &str) -> Option<SystemTime> {
+  Path::new(path).metadata().and_then(|m| m.modified()).ok()
+}
@@ -0,0 +1,115 @@
+//! ETag / If-Match handling for settings that several devices may edit at
+//! once, and ETag / If-None-Match for responses built from a user's
+//! scrobbles. Versions are plain counters bumped on every successful update;
+//! scrobble versions are bumped by triggers on `scrobs` (migration 048).
+
+use axum::{
+  http::{header, HeaderMap, HeaderName, StatusCode},
+  response::{IntoResponse, Response},
+  Json,
+};
+use sha2::{Digest, Sha256};
+
+use crate::db::DbPool;
+
+/// A response body tagged with the version it reflects
+pub type Versioned<T> = ([(HeaderName, String); 1], Json<T>);
+
+pub fn versioned<T>(version: i64, body: T) -> Versioned<T> {
+  ([(header::ETAG, format!("\"{}\"", version))], Json(body))
+}
+
+/// What an update's `If-Match` header asks for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+  /// No header was sent
+  Missing,
+  /// `If-Match: *`
+  Any,
+  /// `If-Match: "<version>"`
+  Version(i64),
+}
+
+impl Precondition {
+  /// The version an update must match, if any
+  pub fn expected(self) -> Option<i64> {
+    match self {
+      Precondition::Version(version) => Some(version),
+      Precondition::Missing | Precondition::Any => None,
+    }
+  }
+}
+
+/// Parse `If-Match`, accepting quoted, weak (`W/"3"`) or bare versions.
+/// Returns `Err` when the header is present but isn't a version we issued.
+pub fn if_match(headers: &HeaderMap) -> Result<Precondition, String> {
+  let Some(value) = headers.get(header::IF_MATCH) else {
+    return Ok(Precondition::Missing);
+  };
+
+  let value = value
+    .to_str()
+    .map_err(|_| "If-Match must be a settings version".to_string())?
+    .trim();
+
+  if value == "*" {
+    return Ok(Precondition::Any);
+  }
+
+  value
+    .trim_start_matches("W/")
+    .trim_matches('"')
+    .parse()
+    .map(Precondition::Version)
+    .map_err(|_| format!("If-Match '{}' is not a settings version", value))
+}
+
+/// A GET response that may be answered with 304 Not Modified
+pub enum Conditional<T> {
+  NotModified(String),
+  Modified(String, T),
+}
+
+impl<T: IntoResponse> IntoResponse for Conditional<T> {
+  fn into_response(self) -> Response {
+    match self {
+      Conditional::NotModified(etag) => (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response(),
+      Conditional::Modified(etag, body) => ([(header::ETAG, etag)], body).into_response(),
+    }
+  }
+}
+
+/// Weak ETag for a response built from a user's scrobbles: their scrobble
+/// version plus whatever else shaped the body (resolved range, limit, ...)
+pub async fn scrobbles_etag(pool: &DbPool, user_id: i64, parts: &[i64]) -> Result<String, sqlx::Error> {
+  let version = sqlx::query_scalar!("SELECT version FROM scrobble_versions WHERE user_id = $1", user_id)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(0);
+
+  let mut hasher = Sha256::new();
+  for part in [user_id, version].iter().chain(parts) {
+    hasher.update(part.to_le_bytes());
+  }
+  Ok(format!("W/\"{}\"", hex::encode(&hasher.finalize()[..12])))
+}
+
+/// Whether `If-None-Match` lists `etag` (or is `*`), compared weakly
+pub fn not_modified(headers: &HeaderMap, etag: &str) -> bool {
+  let Some(value) = headers.get(header::IF_NONE_MATCH).and_then(|v| v.to_str().ok()) else {
+    return false;
+  };
+
+  let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+  let etag = opaque(etag);
+  value.split(',').any(|tag| tag.trim() == "*" || opaque(tag) == etag)
+}
+
+/// Invalidate every scrobble ETag, for changes the triggers can't see such
+/// as catalogue renames
+pub async fn bump_all_scrobble_versions(pool: &DbPool) -> Result<(), sqlx::Error> {
+  sqlx::query!("UPDATE scrobble_versions SET version = version + 1")
+    .execute(pool)
+    .await?;
+  Ok(())
+}